@@ -1,24 +1,85 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use crate::lexer::Token;
 
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Stmt {
     Expression(Expr),
     VarDeclaration(VarDeclaration),
-    Print(Option<Vec<Expr>>, bool),
+    // `var a, b = value;`: names, whether they are constant and the array
+    // whose elements they take in order
+    VarUnpack(Vec<String>, bool, Expr, usize),
+    // `global x;` or `global x = value;` inside a function: declares `x` in
+    // the global scope if it is not there yet, and assigns the value
+    Global(String, Option<Expr>, usize),
+    Print(Option<Vec<Expr>>, bool, usize),
     IfElse(Vec<(Expr, Vec<Stmt>, usize)>),
-    For((Box<Stmt>, Expr, Expr), Vec<Stmt>, usize),
+    // Initializer, condition and step, each of which may be left out. A
+    // missing condition is always true.
+    For((Option<Box<Stmt>>, Option<Expr>, Option<Expr>), Vec<Stmt>, usize),
+    // One loop variable, or several that each item is unpacked into
+    ForIn(Vec<String>, Expr, Vec<Stmt>, usize),
     While(Expr, Vec<Stmt>, usize),
+    Loop(Vec<Stmt>, usize),
+    Try(Vec<Stmt>, String, Vec<Stmt>, usize),
+    // `match value { case pattern: ... }`: only the first arm whose pattern
+    // fits the value runs
+    Match(Expr, Vec<MatchArm>, usize),
+    // `with value as name { ... }`: the value is closed when the body ends,
+    // however it ends
+    With(Expr, String, Vec<Stmt>, usize),
     Block(Vec<Stmt>),
-    Return(Expr),
-    Break,
-    Continue,
-    Function(FunctionDeclaration),
+    // Whether several values were written without brackets, `return a, b;`,
+    // which returns them as an array
+    Return(Expr, bool),
+    // Hands a value out of a generator and pauses it until it is resumed
+    Yield(Expr),
+    // `break` and `continue` name the loop they leave when it has a label
+    Break(Option<String>),
+    Continue(Option<String>),
+    // A loop with a label: `outer: for ... { ... }`
+    Labeled(String, Box<Stmt>),
+    Function(Rc<FunctionDeclaration>),
     Class(ClassDeclaration),
+    Interface(Rc<InterfaceDeclaration>),
+    Mixin(Rc<MixinDeclaration>),
+    // Only produced when comments are kept. Holds the text after `//` and
+    // whether the comment ends the line of the code before it.
+    Comment(String, bool, usize),
+}
+
+impl Stmt {
+    /// Source line the statement starts on, for statements that record one.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Stmt::Expression(expr) | Stmt::Return(expr, _) | Stmt::Yield(expr) => expr.line(),
+            Stmt::VarDeclaration(declaration) => Some(declaration.line),
+            Stmt::Print(_, _, line)
+            | Stmt::Global(_, _, line)
+            | Stmt::VarUnpack(_, _, _, line)
+            | Stmt::For(_, _, line)
+            | Stmt::ForIn(_, _, _, line)
+            | Stmt::While(_, _, line)
+            | Stmt::Loop(_, line)
+            | Stmt::Try(_, _, _, line)
+            | Stmt::Match(_, _, line)
+            | Stmt::With(_, _, _, line)
+            | Stmt::Comment(_, _, line) => Some(*line),
+            Stmt::IfElse(branches) => branches.first().map(|(_, _, line)| *line),
+            Stmt::Function(declaration) => Some(declaration.line),
+            Stmt::Class(class) => Some(class.line),
+            Stmt::Interface(interface) => Some(interface.line),
+            Stmt::Mixin(mixin) => Some(mixin.line),
+            Stmt::Labeled(_, body) => body.line(),
+            Stmt::Block(_) | Stmt::Break(_) | Stmt::Continue(_) => None,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VarDeclaration {
     pub constant: bool,
     pub identifier: String,
@@ -27,36 +88,127 @@ pub struct VarDeclaration {
 }
 
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Vec<Stmt>,
+    pub line: usize,
+}
+
+/// What a match arm compares the value with. Names in a pattern are bound
+/// to the part of the value they stand for, in a scope of the arm's own.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    // `_` fits anything and binds nothing
+    Wildcard,
+    Binding(String),
+    // A number, string, bool or nil, compared by value
+    Literal(Expr),
+    // Fits arrays of exactly as many elements
+    Array(Vec<Pattern>),
+    // Fits objects that have all of the keys, whatever else they hold. A key
+    // on its own binds the value under it to a variable of the same name.
+    Object(Vec<(String, Pattern)>),
+}
+
+impl Pattern {
+    /// Names the pattern binds, in source order.
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            Pattern::Binding(name) => vec![name],
+            Pattern::Array(elements) => elements.iter().flat_map(Pattern::names).collect(),
+            Pattern::Object(entries) => entries.iter().flat_map(|(_, pattern)| pattern.names()).collect(),
+            Pattern::Wildcard | Pattern::Literal(_) => vec![],
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FunctionDeclaration {
     pub name: String,
     pub parameters: Vec<String>,
     pub body: Vec<Stmt>,
+    // Whether the body yields, making calls return a generator
+    pub generator: bool,
+    // Declared with `async`, making calls return a task for the scheduler
+    pub asynchronous: bool,
     pub line: usize,
 }
 
+/// Name given to class expressions written without one.
+pub const ANONYMOUS_CLASS: &str = "<anonymous>";
+
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClassDeclaration {
     pub name: String,
     pub static_fields: Vec<VarDeclaration>,
-    pub methods: HashMap<String, FunctionDeclaration>,
+    pub instance_fields: Rc<Vec<VarDeclaration>>,
+    pub methods: HashMap<String, Rc<FunctionDeclaration>>,
+    pub static_methods: HashMap<String, Rc<FunctionDeclaration>>,
     pub superclass: Option<String>,
+    // Mixins whose methods the class takes, earlier ones first
+    pub mixins: Vec<String>,
+    // Interfaces the class promises to implement, checked on instantiation
+    pub interfaces: Vec<String>,
+    pub comments: Vec<(String, bool, usize)>,
     pub line: usize,
 }
 
+/// `mixin Movable { fun move(dx) { ... } }`: methods that classes listing
+/// the mixin after `with` take as their own.
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MixinDeclaration {
+    pub name: String,
+    pub methods: HashMap<String, Rc<FunctionDeclaration>>,
+    pub comments: Vec<(String, bool, usize)>,
+    pub line: usize,
+}
+
+/// `interface Drawable { draw(canvas); }`: methods a class implementing it
+/// must have, each with its parameters and line.
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InterfaceDeclaration {
+    pub name: String,
+    pub methods: Vec<(String, Vec<String>, usize)>,
+    pub comments: Vec<(String, bool, usize)>,
+    pub line: usize,
+}
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     NumericLiteral(f64, usize),
     Null(usize),
     BoolLiteral(bool, usize),
-    StringLiteral(String, usize),
+    StringLiteral(Rc<str>, usize),
     Identifier(String, usize),
     This(usize),
     Super(String, usize),
     Array(Vec<Expr>, usize),
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        inclusive: bool,
+        line: usize,
+    },
     Member {
         object: Box<Expr>,
         property: Box<Expr>,
         computed: bool,
+        optional: bool,
+        line: usize,
+    },
+    /// `object[start:end]`, where either bound may be left out
+    Slice {
+        object: Box<Expr>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+        optional: bool,
         line: usize,
     },
     Call {
@@ -89,11 +241,242 @@ pub enum Expr {
         value: Box<Expr>,
         line: usize,
     },
+    /// `if a { x } else if b { y } else { z }`. Each branch is its condition,
+    /// its statements and the expression it ends with. The `else` branch
+    /// is required and stored as `else if true`.
+    If {
+        branches: Vec<(Expr, Vec<Stmt>, Expr)>,
+        line: usize,
+    },
+    /// `class < Base { ... }` used as a value. The name, if given, is only
+    /// bound inside the class.
+    Class(Box<ClassDeclaration>),
+    /// `await task` waits for a task to finish and gives its result. At the
+    /// start of a statement in an async function it pauses only that task.
+    Await {
+        value: Box<Expr>,
+        line: usize,
+    },
+}
+
+impl Expr {
+    /// Source line of the expression. Object literals report the line of
+    /// their first property and empty ones have none.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Expr::NumericLiteral(_, line)
+            | Expr::Null(line)
+            | Expr::BoolLiteral(_, line)
+            | Expr::StringLiteral(_, line)
+            | Expr::Identifier(_, line)
+            | Expr::This(line)
+            | Expr::Super(_, line)
+            | Expr::Array(_, line)
+            | Expr::Range { line, .. }
+            | Expr::Member { line, .. }
+            | Expr::Slice { line, .. }
+            | Expr::Call { line, .. }
+            | Expr::Unary { line, .. }
+            | Expr::BinaryExpr { line, .. }
+            | Expr::ComparisonLiteral { line, .. }
+            | Expr::AssignmentExpr { line, .. }
+            | Expr::If { line, .. }
+            | Expr::Await { line, .. } => Some(*line),
+            Expr::Class(class) => Some(class.line),
+            Expr::ObjectLiteral { properties } => properties.first().map(|property| property.line),
+        }
+    }
 }
 
 #[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Property {
     pub key: String,
     pub value: Option<Box<Expr>>,
     pub line: usize,
 }
+
+/// Sets every line and token position in `stmts` to 0, so two parses can
+/// be compared by what the code says rather than where it is written.
+pub fn clear_positions(stmts: &mut [Stmt]) {
+    for stmt in stmts {
+        clear_stmt(stmt);
+    }
+}
+
+fn clear_stmt(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Expression(expr) | Stmt::Return(expr, _) | Stmt::Yield(expr) => clear_expr(expr),
+        Stmt::VarDeclaration(declaration) => clear_var(declaration),
+        Stmt::VarUnpack(_, _, value, line) => {
+            clear_expr(value);
+            *line = 0;
+        }
+        Stmt::Global(_, value, line) => {
+            value.iter_mut().for_each(clear_expr);
+            *line = 0;
+        }
+        Stmt::Print(values, _, line) => {
+            values.iter_mut().flatten().for_each(clear_expr);
+            *line = 0;
+        }
+        Stmt::IfElse(branches) => {
+            for (condition, body, line) in branches {
+                clear_expr(condition);
+                clear_positions(body);
+                *line = 0;
+            }
+        }
+        Stmt::For((initializer, condition, step), body, line) => {
+            initializer.iter_mut().for_each(|stmt| clear_stmt(stmt));
+            condition.iter_mut().chain(step.iter_mut()).for_each(clear_expr);
+            clear_positions(body);
+            *line = 0;
+        }
+        Stmt::ForIn(_, value, body, line) | Stmt::While(value, body, line) | Stmt::With(value, _, body, line) => {
+            clear_expr(value);
+            clear_positions(body);
+            *line = 0;
+        }
+        Stmt::Loop(body, line) => {
+            clear_positions(body);
+            *line = 0;
+        }
+        Stmt::Try(body, _, handler, line) => {
+            clear_positions(body);
+            clear_positions(handler);
+            *line = 0;
+        }
+        Stmt::Match(value, arms, line) => {
+            clear_expr(value);
+            for arm in arms {
+                clear_pattern(&mut arm.pattern);
+                clear_positions(&mut arm.body);
+                arm.line = 0;
+            }
+            *line = 0;
+        }
+        Stmt::Block(body) => clear_positions(body),
+        Stmt::Labeled(_, body) => clear_stmt(body),
+        Stmt::Function(declaration) => clear_function(declaration),
+        Stmt::Class(class) => clear_class(class),
+        Stmt::Interface(interface) => {
+            let interface = Rc::make_mut(interface);
+            interface.methods.iter_mut().for_each(|(_, _, line)| *line = 0);
+            interface.comments.iter_mut().for_each(|(_, _, line)| *line = 0);
+            interface.line = 0;
+        }
+        Stmt::Mixin(mixin) => {
+            let mixin = Rc::make_mut(mixin);
+            mixin.methods.values_mut().for_each(clear_function);
+            mixin.comments.iter_mut().for_each(|(_, _, line)| *line = 0);
+            mixin.line = 0;
+        }
+        Stmt::Comment(_, _, line) => *line = 0,
+        Stmt::Break(_) | Stmt::Continue(_) => {}
+    }
+}
+
+fn clear_var(declaration: &mut VarDeclaration) {
+    clear_expr(&mut declaration.value);
+    declaration.line = 0;
+}
+
+fn clear_function(declaration: &mut Rc<FunctionDeclaration>) {
+    let declaration = Rc::make_mut(declaration);
+    clear_positions(&mut declaration.body);
+    declaration.line = 0;
+}
+
+fn clear_class(class: &mut ClassDeclaration) {
+    class.static_fields.iter_mut().for_each(clear_var);
+    Rc::make_mut(&mut class.instance_fields).iter_mut().for_each(clear_var);
+    class.methods.values_mut().chain(class.static_methods.values_mut()).for_each(clear_function);
+    class.comments.iter_mut().for_each(|(_, _, line)| *line = 0);
+    class.line = 0;
+}
+
+fn clear_pattern(pattern: &mut Pattern) {
+    match pattern {
+        Pattern::Literal(value) => clear_expr(value),
+        Pattern::Array(elements) => elements.iter_mut().for_each(clear_pattern),
+        Pattern::Object(entries) => entries.iter_mut().for_each(|(_, pattern)| clear_pattern(pattern)),
+        Pattern::Wildcard | Pattern::Binding(_) => {}
+    }
+}
+
+fn clear_token(token: &mut Token) {
+    token.line = 0;
+    token.span = 0..0;
+}
+
+fn clear_expr(expr: &mut Expr) {
+    match expr {
+        Expr::NumericLiteral(_, line)
+        | Expr::Null(line)
+        | Expr::BoolLiteral(_, line)
+        | Expr::StringLiteral(_, line)
+        | Expr::Identifier(_, line)
+        | Expr::This(line)
+        | Expr::Super(_, line) => *line = 0,
+        Expr::Array(elements, line) => {
+            elements.iter_mut().for_each(clear_expr);
+            *line = 0;
+        }
+        Expr::Range { start, end, line, .. } => {
+            clear_expr(start);
+            clear_expr(end);
+            *line = 0;
+        }
+        Expr::Member { object, property, line, .. } => {
+            clear_expr(object);
+            clear_expr(property);
+            *line = 0;
+        }
+        Expr::Slice { object, start, end, line, .. } => {
+            clear_expr(object);
+            start.iter_mut().chain(end.iter_mut()).for_each(|bound| clear_expr(bound));
+            *line = 0;
+        }
+        Expr::Call { args, caller, line } => {
+            args.iter_mut().for_each(clear_expr);
+            clear_expr(caller);
+            *line = 0;
+        }
+        Expr::Unary { operator, right, line } => {
+            clear_token(operator);
+            clear_expr(right);
+            *line = 0;
+        }
+        Expr::BinaryExpr { left, operator, right, line } | Expr::ComparisonLiteral { left, operator, right, line } => {
+            clear_expr(left);
+            clear_token(operator);
+            clear_expr(right);
+            *line = 0;
+        }
+        Expr::ObjectLiteral { properties } => {
+            for property in properties {
+                property.value.iter_mut().for_each(|value| clear_expr(value));
+                property.line = 0;
+            }
+        }
+        Expr::AssignmentExpr { assignee, value, line } => {
+            clear_expr(assignee);
+            clear_expr(value);
+            *line = 0;
+        }
+        Expr::If { branches, line } => {
+            for (condition, body, value) in branches {
+                clear_expr(condition);
+                clear_positions(body);
+                clear_expr(value);
+            }
+            *line = 0;
+        }
+        Expr::Class(class) => clear_class(class),
+        Expr::Await { value, line } => {
+            clear_expr(value);
+            *line = 0;
+        }
+    }
+}
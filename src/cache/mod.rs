@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+
+use crate::ast::Stmt;
+use crate::environment::Mode;
+
+/// Where the parsed form of `source_path` is cached: next to the source,
+/// with a `.loxc` extension.
+pub fn cache_path(source_path: &str) -> PathBuf {
+    Path::new(source_path).with_extension("loxc")
+}
+
+/// The program stored in the cache at `path`, if it was written by this
+/// version of the interpreter from exactly `source` parsed in `mode`.
+/// A missing, stale or unreadable cache gives `None`.
+#[cfg(feature = "cache")]
+pub fn load(path: &Path, source: &str, mode: Mode) -> Option<Vec<Stmt>> {
+    let bytes = std::fs::read(path).ok()?;
+    let (format, hash, main, program): (String, u64, bool, Vec<Stmt>) = postcard::from_bytes(&bytes).ok()?;
+    if format != FORMAT || hash != source_hash(source) || main != (mode == Mode::Main) {
+        return None;
+    }
+    Some(program)
+}
+
+/// Saves `program`, parsed from `source` in `mode`, to the cache at `path`.
+/// The cache only speeds up later runs, so failing to write it is not an
+/// error.
+#[cfg(feature = "cache")]
+pub fn store(path: &Path, source: &str, mode: Mode, program: &[Stmt]) {
+    let entry = (FORMAT, source_hash(source), mode == Mode::Main, program);
+    if let Ok(bytes) = postcard::to_stdvec(&entry) {
+        let _ = std::fs::write(path, bytes);
+    }
+}
+
+#[cfg(not(feature = "cache"))]
+pub fn load(_path: &Path, _source: &str, _mode: Mode) -> Option<Vec<Stmt>> {
+    None
+}
+
+#[cfg(not(feature = "cache"))]
+pub fn store(_path: &Path, _source: &str, _mode: Mode, _program: &[Stmt]) {}
+
+// Caches written by another version of the interpreter may encode the
+// syntax tree differently, so they are never reused.
+#[cfg(feature = "cache")]
+const FORMAT: &str = concat!("loxc ", env!("CARGO_PKG_VERSION"));
+
+// 64-bit FNV-1a. Unlike the standard library's hasher it gives the same
+// result across Rust versions, so a cache stays valid after a rebuild.
+#[cfg(feature = "cache")]
+fn source_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::handle_errors::RuntimeError;
+use crate::interpreter::interpreter::Interpreter;
+use crate::values::*;
+
+/// A value a program handed back to the host, detached from the
+/// interpreter that produced it. Values that only make sense inside a
+/// running program, such as functions, classes and instances, are kept as
+/// their type name and the text `print` shows for them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(HashMap<String, Value>),
+    /// Entries in key order: bools, then numbers, then strings
+    Map(Vec<(Value, Value)>),
+    /// Elements in the same order as map keys
+    Set(Vec<Value>),
+    Other { type_name: String, text: String },
+}
+
+impl Value {
+    /// Name of the value's type, as `type_of` reports it.
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::Nil => "Nil",
+            Value::Bool(_) => "Bool",
+            Value::Number(_) => "Number",
+            Value::String(_) => "String",
+            Value::Array(_) => "Array",
+            Value::Object(_) => "Object",
+            Value::Map(_) => "Map",
+            Value::Set(_) => "Set",
+            Value::Other { type_name, .. } => type_name,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(num: f64) -> Self {
+        Value::Number(num)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(bit: bool) -> Self {
+        Value::Bool(bit)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(elements: Vec<T>) -> Self {
+        Value::Array(elements.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Nil, Into::into)
+    }
+}
+
+impl From<HashMap<String, Value>> for Value {
+    fn from(properties: HashMap<String, Value>) -> Self {
+        Value::Object(properties)
+    }
+}
+
+pub(crate) fn mismatch(expected: &str, found: &Value) -> LoxError {
+    LoxError::Conversion(format!("Expected {}, found {}", expected, found.type_name()))
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = LoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(num) => Ok(num),
+            _ => Err(mismatch("Number", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = LoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(bit) => Ok(bit),
+            _ => Err(mismatch("Bool", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = LoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            _ => Err(mismatch("String", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for Vec<Value> {
+    type Error = LoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Array(elements) => Ok(elements),
+            _ => Err(mismatch("Array", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for HashMap<String, Value> {
+    type Error = LoxError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Object(properties) => Ok(properties),
+            _ => Err(mismatch("Object", &value)),
+        }
+    }
+}
+
+/// Converts any serializable Rust value into a `Value`. Structs and maps
+/// with string keys become objects, sequences become arrays and `None`
+/// becomes `nil`.
+#[cfg(feature = "serde")]
+pub fn to_value<T: serde::Serialize>(value: &T) -> Result<Value, LoxError> {
+    let json = serde_json::to_value(value).map_err(|e| LoxError::Conversion(e.to_string()))?;
+    Ok(from_json(json))
+}
+
+/// Converts a `Value` into any deserializable Rust type, the reverse of
+/// `to_value`. Maps need string keys, and functions, classes and instances
+/// cannot be converted.
+#[cfg(feature = "serde")]
+pub fn from_value<T: serde::de::DeserializeOwned>(value: Value) -> Result<T, LoxError> {
+    serde_json::from_value(into_json(value)?).map_err(|e| LoxError::Conversion(e.to_string()))
+}
+
+#[cfg(feature = "serde")]
+fn from_json(json: serde_json::Value) -> Value {
+    match json {
+        serde_json::Value::Null => Value::Nil,
+        serde_json::Value::Bool(bit) => Value::Bool(bit),
+        serde_json::Value::Number(num) => Value::Number(num.as_f64().unwrap_or(f64::NAN)),
+        serde_json::Value::String(s) => Value::String(s),
+        serde_json::Value::Array(elements) => Value::Array(elements.into_iter().map(from_json).collect()),
+        serde_json::Value::Object(properties) => Value::Object(
+            properties
+                .into_iter()
+                .map(|(key, property)| (key, from_json(property)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(feature = "serde")]
+fn into_json(value: Value) -> Result<serde_json::Value, LoxError> {
+    Ok(match value {
+        Value::Nil => serde_json::Value::Null,
+        Value::Bool(bit) => serde_json::Value::Bool(bit),
+        Value::Number(num) => {
+            // Whole numbers are handed over as integers so they fit integer fields
+            let number = if num.fract() == 0.0 && num.abs() < 9007199254740992.0 {
+                serde_json::Number::from(num as i64)
+            } else {
+                serde_json::Number::from_f64(num)
+                    .ok_or_else(|| LoxError::Conversion(format!("{} is not a finite number", num)))?
+            };
+            serde_json::Value::Number(number)
+        }
+        Value::String(s) => serde_json::Value::String(s),
+        Value::Array(elements) | Value::Set(elements) => serde_json::Value::Array(
+            elements.into_iter().map(into_json).collect::<Result<_, _>>()?,
+        ),
+        Value::Object(properties) => serde_json::Value::Object(
+            properties
+                .into_iter()
+                .map(|(key, property)| Ok((key, into_json(property)?)))
+                .collect::<Result<_, LoxError>>()?,
+        ),
+        Value::Map(entries) => {
+            let mut properties = serde_json::Map::new();
+            for (key, entry) in entries {
+                let Value::String(key) = key else {
+                    return Err(mismatch("String key", &key));
+                };
+                properties.insert(key, into_json(entry)?);
+            }
+            serde_json::Value::Object(properties)
+        }
+        Value::Other { .. } => return Err(mismatch("data", &value)),
+    })
+}
+
+/// Why a program given to `eval_file` did not produce a value, or why a
+/// value could not be converted.
+#[derive(Debug)]
+pub enum LoxError {
+    /// The file could not be read or is not a `.lox` file
+    File(String),
+    /// Lexer and parser errors, rendered as they would be on stderr
+    Compile(String),
+    /// An uncaught runtime error with its kind, as `catch` would see it
+    Runtime { kind: String, message: String, line: usize },
+    /// The program called `exit` with this status
+    Exit(i32),
+    /// A value did not have the shape a conversion expected
+    Conversion(String),
+    /// The interpreter reached a state it should never be in, a bug in Lox
+    /// rather than in the program
+    Internal,
+}
+
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoxError::File(message) => write!(f, "File error: {}", message),
+            LoxError::Compile(diagnostics) => write!(f, "{}", diagnostics.trim_end()),
+            // Line 0 is a call made by the host rather than by the program
+            LoxError::Runtime { kind, message, line: 0 } => write!(f, "{}: {}", kind, message),
+            LoxError::Runtime { kind, message, line } => {
+                write!(f, "{} on line {}: {}", kind, line, message)
+            }
+            LoxError::Exit(code) => write!(f, "Program exited with status {}", code),
+            LoxError::Conversion(message) => write!(f, "Conversion error: {}", message),
+            LoxError::Internal => write!(
+                f,
+                "Internal Error: This should not have happened. Please report this as a bug."
+            ),
+        }
+    }
+}
+
+impl Error for LoxError {}
+
+impl From<RuntimeError> for LoxError {
+    fn from(error: RuntimeError) -> Self {
+        if let RuntimeError::Exit(code) = error {
+            return LoxError::Exit(code);
+        }
+        match error.describe() {
+            Some((kind, message, line)) => LoxError::Runtime {
+                kind: kind.to_string(),
+                message,
+                line,
+            },
+            None => LoxError::Internal,
+        }
+    }
+}
+
+impl Interpreter {
+    /// Copies a runtime value out of the interpreter for the host.
+    pub fn host_value(&mut self, val: &RuntimeVal) -> Result<Value, RuntimeError> {
+        Ok(match val {
+            RuntimeVal::Nil => Value::Nil,
+            RuntimeVal::Bool(bit) => Value::Bool(*bit),
+            RuntimeVal::Number(num) => Value::Number(*num),
+            RuntimeVal::String(s) => Value::String(s.to_string()),
+            RuntimeVal::Array(elements) => {
+                let mut values = vec![];
+                for element in elements {
+                    values.push(self.host_value(element)?);
+                }
+                Value::Array(values)
+            }
+            RuntimeVal::Object(properties) => {
+                let mut values = HashMap::new();
+                for (key, property) in properties {
+                    values.insert(key.clone(), self.host_value(property)?);
+                }
+                Value::Object(values)
+            }
+            RuntimeVal::Map(map) => {
+                let mut entries = vec![];
+                for key in sorted_map_keys(map) {
+                    entries.push((self.host_value(&key.to_value())?, self.host_value(&map[key])?));
+                }
+                Value::Map(entries)
+            }
+            RuntimeVal::Set(set) => {
+                let mut elements = vec![];
+                for element in sorted_set_elements(set) {
+                    elements.push(self.host_value(&element.to_value())?);
+                }
+                Value::Set(elements)
+            }
+            _ => Value::Other {
+                type_name: type_name(val).to_string(),
+                text: self.stringify(val)?,
+            },
+        })
+    }
+}
@@ -1,10 +1,11 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::rc::Rc;
 
 use crate::global_scope::*;
 use crate::handle_errors::EnvironmentError;
+use crate::values::NativeFn;
 use crate::values::RuntimeVal;
 use crate::values::make_native_function;
 
@@ -13,43 +14,407 @@ pub enum Scope {
     Global,
     Class(String),
     Method(String),
+    StaticMethod(String),
     Constructor(String),
     Function(String),
-    Loop,
+    // A loop, with its label if it has one
+    Loop(Option<String>),
+    // A branch of an if expression, which has to end with its value
+    IfExpression,
     VarDeclaration,
 }
 
+/// How a program is run. `Main` programs hold only declarations and start
+/// at `main`, `Script` programs run their top-level statements in order and
+/// `Repl` runs each entered line like a script, echoing expression values.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Main,
+    Script,
+    Repl,
+}
+
+/// Controls which native functions are installed into the global scope.
+/// `Sandboxed` leaves out every native that touches IO (console input,
+/// files, network), `Allowlist` installs exactly the named natives.
+#[derive(Clone, PartialEq)]
+pub enum NativeAccess {
+    All,
+    Sandboxed,
+    Allowlist(Vec<String>),
+}
+
+impl NativeAccess {
+    fn allows(&self, name: &str, io: bool) -> bool {
+        match self {
+            NativeAccess::All => true,
+            NativeAccess::Sandboxed => !io,
+            NativeAccess::Allowlist(names) => names.iter().any(|allowed| allowed == name),
+        }
+    }
+}
+
 pub struct Environment {
     parent: Option<Rc<RefCell<Environment>>>,
-    pub variables: HashMap<String, RuntimeVal>,
-    constants: HashSet<String>,
+    variables: Variables,
+    // Set by `freeze` on the fields of an instance or the static fields of
+    // a class, which can then no longer be assigned or added
+    frozen: bool,
+    // Set on the global scope of the REPL, where entering a declaration
+    // again replaces the earlier one
+    redeclarable: bool,
+    // Set by `track_drop` on the fields of an instance whose class has a
+    // `__drop__` method, see `Drop for Environment`
+    droppable: Option<Droppable>,
+}
+
+/// The class of an instance waiting for its `__drop__` method, and when the
+/// instance was made, so instances released together are finalized in the
+/// order they were made.
+pub struct Droppable {
+    pub order: usize,
+    pub class_name: String,
+    pub class: Rc<RuntimeVal>,
+}
+
+thread_local! {
+    // Counts the classes replaced by a declaration of the same name, such as
+    // one entered again at the REPL
+    static CLASS_GENERATION: Cell<usize> = const { Cell::new(0) };
+    // Fields of droppable instances nothing refers to anymore
+    static RELEASED: RefCell<Vec<(Droppable, Rc<RefCell<Environment>>)>> = const { RefCell::new(vec![]) };
+}
+
+// Once nothing refers to a droppable instance its fields move to a new
+// environment, which keeps them alive until `__drop__` has run on them. The
+// interpreter only has to look at instances that were released this way.
+impl Drop for Environment {
+    fn drop(&mut self) {
+        let Some(droppable) = self.droppable.take() else {
+            return;
+        };
+        let fields = Environment {
+            parent: self.parent.take(),
+            variables: std::mem::replace(&mut self.variables, Variables::Local(vec![])),
+            frozen: self.frozen,
+            redeclarable: false,
+            droppable: None,
+        };
+        // Fails only while the thread exits, when no finalizer can run anyway
+        let _ = RELEASED.try_with(|released| released.borrow_mut().push((droppable, Rc::new(RefCell::new(fields)))));
+    }
+}
+
+/// Has `__drop__` run on the instance with the fields `env` once nothing
+/// refers to it anymore.
+pub fn track_drop(env: &Rc<RefCell<Environment>>, droppable: Droppable) {
+    env.borrow_mut().droppable = Some(droppable);
+}
+
+/// Changes whenever a class is replaced, which can change what the
+/// subclasses of that name inherit.
+pub fn class_generation() -> usize {
+    CLASS_GENERATION.with(Cell::get)
+}
+
+/// Whether droppable instances were released since `take_released`.
+pub fn has_released() -> bool {
+    RELEASED.with(|released| !released.borrow().is_empty())
+}
+
+/// The droppable instances released since the last call, in the order they
+/// were made.
+pub fn take_released() -> Vec<(Droppable, Rc<RefCell<Environment>>)> {
+    let mut released = RELEASED.with(|released| std::mem::take(&mut *released.borrow_mut()));
+    released.sort_by_key(|(droppable, _)| droppable.order);
+    released
+}
+
+// The global scope holds every native and top-level declaration, so its
+// variables are found by hashing their name. Every other scope holds a few
+// variables, kept in declaration order and found by comparing names, which
+// keeps hashing off the path of most variable accesses. A scope that grows
+// past `MAX_SCANNED_VARIABLES` also indexes them by name, so a function
+// declaring thousands of variables does not compare each against the rest.
+enum Variables {
+    Global(HashMap<Rc<str>, Variable>),
+    Local(Vec<(Rc<str>, Variable)>),
+    Indexed(Vec<(Rc<str>, Variable)>, HashMap<Rc<str>, usize>),
+}
+
+const MAX_SCANNED_VARIABLES: usize = 12;
+
+struct Variable {
+    value: RuntimeVal,
+    constant: bool,
+    // Natives and parameters give way to a declaration of the same name in
+    // their own scope, which replaces them
+    shadowable: bool,
+}
+
+impl Variables {
+    fn get(&self, name: &str) -> Option<&Variable> {
+        match self {
+            Variables::Global(variables) => variables.get(name),
+            Variables::Local(variables) => variables
+                .iter()
+                .find(|(own, _)| **own == *name)
+                .map(|(_, variable)| variable),
+            Variables::Indexed(variables, index) => index.get(name).map(|&slot| &variables[slot].1),
+        }
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut Variable> {
+        match self {
+            Variables::Global(variables) => variables.get_mut(name),
+            Variables::Local(variables) => variables
+                .iter_mut()
+                .find(|(own, _)| **own == *name)
+                .map(|(_, variable)| variable),
+            Variables::Indexed(variables, index) => index.get(name).map(|&slot| &mut variables[slot].1),
+        }
+    }
+
+    fn insert(&mut self, name: Rc<str>, variable: Variable) {
+        match self {
+            Variables::Global(variables) => {
+                variables.insert(name, variable);
+            }
+            Variables::Local(variables) if variables.len() < MAX_SCANNED_VARIABLES => {
+                variables.push((name, variable))
+            }
+            Variables::Local(variables) => {
+                let mut variables = std::mem::take(variables);
+                let mut index: HashMap<Rc<str>, usize> = variables
+                    .iter()
+                    .enumerate()
+                    .map(|(slot, (own, _))| (Rc::clone(own), slot))
+                    .collect();
+                index.insert(Rc::clone(&name), variables.len());
+                variables.push((name, variable));
+                *self = Variables::Indexed(variables, index);
+            }
+            Variables::Indexed(variables, index) => {
+                index.insert(Rc::clone(&name), variables.len());
+                variables.push((name, variable));
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Variables::Global(variables) => variables.clear(),
+            Variables::Local(variables) => variables.clear(),
+            Variables::Indexed(..) => *self = Variables::Local(vec![]),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&Rc<str>, &Variable)> + '_> {
+        match self {
+            Variables::Global(variables) => Box::new(variables.iter()),
+            Variables::Local(variables) | Variables::Indexed(variables, _) => {
+                Box::new(variables.iter().map(|(name, variable)| (name, variable)))
+            }
+        }
+    }
 }
 
 impl Environment {
     pub fn new(parent_env: Option<Rc<RefCell<Environment>>>) -> Rc<RefCell<Self>> {
-        let env = Rc::new(RefCell::new(Environment {
+        Rc::new(RefCell::new(Environment {
             parent: parent_env,
-            variables: HashMap::new(),
-            constants: HashSet::new(),
+            variables: Variables::Local(vec![]),
+            frozen: false,
+            redeclarable: false,
+            droppable: None,
+        }))
+    }
+
+    /// Creates the single root environment of a program. Natives are only
+    /// installed here; every other environment reaches them through its
+    /// parent chain.
+    pub fn new_global(access: &NativeAccess) -> Rc<RefCell<Self>> {
+        let env = Rc::new(RefCell::new(Environment {
+            parent: None,
+            variables: Variables::Global(HashMap::new()),
+            frozen: false,
+            redeclarable: false,
+            droppable: None,
         }));
-        set_global_scope(&env);
+        set_global_scope(&env, access);
         env
     }
 }
 
-pub fn set_global_scope(env: &Rc<RefCell<Environment>>) {
-    let _ = declare_var(env, "clock", make_native_function(clock, "clock"), true);
-    let _ = declare_var(env, "scan", make_native_function(scan, "scan"), true);
-    let _ = declare_var(env, "min", make_native_function(min, "min"), true);
-    let _ = declare_var(env, "max", make_native_function(max, "max"), true);
-    let _ = declare_var(env, "number", make_native_function(number, "number"), true);
-    let _ = declare_var(env, "bool", make_native_function(bool, "bool"), true);
-    let _ = declare_var(env, "string", make_native_function(string, "string"), true);
-    let _ = declare_var(env, "len", make_native_function(len, "len"), true);
-    let _ = declare_var(env, "type_of", make_native_function(type_of, "type_of"), true);
-    let _ = declare_var(env, "reverse", make_native_function(reverse, "reverse"), true);
-    let _ = declare_var(env, "append", make_native_function(append, "append"), true);
-    let _ = declare_var(env, "remove", make_native_function(remove, "remove"), true);
+// (name, function, performs IO)
+const NATIVES: &[(&str, NativeFn, bool)] = &[
+    ("clock", clock, false),
+    ("time", time, false),
+    ("sleep", sleep, false),
+    ("now_iso", now_iso, false),
+    ("date_parts", date_parts, false),
+    ("format_time", format_time, false),
+    ("parse_time", parse_time, false),
+    ("random", random, false),
+    ("rand_int", rand_int, false),
+    ("shuffle", shuffle, false),
+    ("choice", choice, false),
+    ("seed", seed, false),
+    ("scan", scan, true),
+    ("tcp_connect", tcp_connect, true),
+    ("tcp_listen", tcp_listen, true),
+    ("unix_connect", unix_connect, true),
+    ("unix_listen", unix_listen, true),
+    ("accept", accept, true),
+    ("send", send, true),
+    ("recv", recv, true),
+    ("close", close, true),
+    ("open_file", open_file, true),
+    ("read_line", read_line, true),
+    ("read_all", read_all, true),
+    ("write", write, true),
+    ("min", min, false),
+    ("max", max, false),
+    ("sum", sum, false),
+    ("avg", avg, false),
+    ("clamp", clamp, false),
+    ("abs", abs, false),
+    ("number", number, false),
+    ("parse_int", parse_int, false),
+    ("parse_float", parse_float, false),
+    ("bool", bool, false),
+    ("string", string, false),
+    ("len", len, false),
+    ("type_of", type_of, false),
+    ("is_number", is_number, false),
+    ("is_string", is_string, false),
+    ("is_bool", is_bool, false),
+    ("is_array", is_array, false),
+    ("is_object", is_object, false),
+    ("is_nil", is_nil, false),
+    ("is_callable", is_callable, false),
+    ("is_instance", is_instance, false),
+    ("implements", implements, false),
+    ("methods_of", methods_of, false),
+    ("fields_of", fields_of, false),
+    ("class_of", class_of, false),
+    ("superclass_of", superclass_of, false),
+    ("weak_ref", weak_ref, false),
+    ("deref", deref, false),
+    ("ord", ord, false),
+    ("chr", chr, false),
+    ("is_digit", is_digit, false),
+    ("is_alpha", is_alpha, false),
+    ("is_space", is_space, false),
+    ("find", find, false),
+    ("count", count, false),
+    ("pad_left", pad_left, false),
+    ("pad_right", pad_right, false),
+    ("repeat", repeat, false),
+    ("reverse", reverse, false),
+    ("append", append, false),
+    ("remove", remove, false),
+    ("range", range, false),
+    ("keys", keys, false),
+    ("values", values, false),
+    ("entries", entries, false),
+    ("has", has, false),
+    ("delete", delete, false),
+    ("merge", merge, false),
+    ("slice", slice, false),
+    ("concat", concat, false),
+    ("index_of", index_of, false),
+    ("contains", contains, false),
+    ("fill", fill, false),
+    ("flatten", flatten, false),
+    ("enumerate", enumerate, false),
+    ("zip", zip, false),
+    ("sort", sort, false),
+    ("sort_by", sort_by, false),
+    ("map", map, false),
+    ("get", get, false),
+    ("set", set, false),
+    ("size", size, false),
+    ("add", add, false),
+    ("union", union, false),
+    ("intersect", intersect, false),
+    ("difference", difference, false),
+    ("to_array", to_array, false),
+    ("bytes", bytes, false),
+    ("read_file_bytes", read_file_bytes, true),
+    ("write_file_bytes", write_file_bytes, true),
+    ("base64_encode", base64_encode, false),
+    ("base64_decode", base64_decode, false),
+    ("hex_encode", hex_encode, false),
+    ("hex_decode", hex_decode, false),
+    ("hash_sha256", hash_sha256, false),
+    ("hash_md5", hash_md5, false),
+    ("next", next, false),
+    ("spawn", spawn, false),
+    ("delay", delay, false),
+    ("is_done", is_done, false),
+    ("spawn_thread", spawn_thread, false),
+    ("join", join, false),
+    ("channel", channel, false),
+    ("channel_send", channel_send, false),
+    ("channel_recv", channel_recv, false),
+    ("channel_close", channel_close, false),
+    ("mutex", mutex, false),
+    ("lock", lock, false),
+    ("atomic_add", atomic_add, false),
+    ("on_interrupt", on_interrupt, false),
+    ("print", print, false),
+    ("println", println, false),
+    ("format", format, false),
+    ("args", args, false),
+    ("freeze", freeze, false),
+    ("deep_copy", deep_copy, false),
+    ("error", error, false),
+    ("panic", panic, false),
+    ("exit", exit, false),
+];
+
+pub fn set_global_scope(env: &Rc<RefCell<Environment>>, access: &NativeAccess) {
+    for &(name, func, io) in NATIVES {
+        if access.allows(name, io) {
+            let _ = declare_shadowable(env, name, make_native_function(func, name), true);
+        }
+    }
+}
+
+/// Names of every native function, whether or not it is installed.
+pub fn native_names() -> impl Iterator<Item = &'static str> {
+    NATIVES.iter().map(|(name, _, _)| *name)
+}
+
+thread_local! {
+    static INTERNED: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+/// Returns the shared copy of an identifier so every environment declaring
+/// the same name reuses a single allocation.
+pub fn intern(name: &str) -> Rc<str> {
+    INTERNED.with(|names| {
+        let mut names = names.borrow_mut();
+        if let Some(existing) = names.get(name) {
+            return Rc::clone(existing);
+        }
+        let name: Rc<str> = Rc::from(name);
+        names.insert(Rc::clone(&name));
+        name
+    })
+}
+
+/// Empties `scope` for the next pass of a loop body, so passes reuse one
+/// allocation. A scope that a closure or generator captured stays theirs,
+/// and the pass gets a new one instead.
+pub fn reuse_scope(scope: &mut Rc<RefCell<Environment>>) {
+    if Rc::strong_count(scope) == 1 {
+        scope.borrow_mut().variables.clear();
+    } else {
+        let parent = scope.borrow().parent.clone();
+        *scope = Environment::new(parent);
+    }
 }
 
 pub fn declare_var(
@@ -57,51 +422,221 @@ pub fn declare_var(
     var_name: &str,
     value: RuntimeVal,
     constant: bool,
+) -> Result<RuntimeVal, EnvironmentError> {
+    declare(env, var_name, value, constant, false)
+}
+
+/// Declares a native or a parameter, which a later declaration of the same
+/// name in `env` replaces instead of failing.
+pub fn declare_shadowable(
+    env: &Rc<RefCell<Environment>>,
+    var_name: &str,
+    value: RuntimeVal,
+    constant: bool,
+) -> Result<RuntimeVal, EnvironmentError> {
+    declare(env, var_name, value, constant, true)
+}
+
+fn declare(
+    env: &Rc<RefCell<Environment>>,
+    var_name: &str,
+    value: RuntimeVal,
+    constant: bool,
+    shadowable: bool,
 ) -> Result<RuntimeVal, EnvironmentError> {
     let mut env = env.borrow_mut();
-    if env.variables.contains_key(var_name) {
-        return Err(EnvironmentError::ReDeclareVar);
-    }
-    env.variables.insert(var_name.to_string(), value.clone());
-    if constant {
-        env.constants.insert(var_name.to_string());
+    let redeclarable = env.redeclarable;
+    let variable = Variable {
+        value: value.clone(),
+        constant,
+        shadowable,
+    };
+    match env.variables.get_mut(var_name) {
+        Some(existing) if existing.shadowable || redeclarable => {
+            if matches!(existing.value, RuntimeVal::Class { .. }) {
+                CLASS_GENERATION.with(|generation| generation.set(generation.get() + 1));
+            }
+            *existing = variable;
+        }
+        Some(_) => return Err(EnvironmentError::ReDeclareVar),
+        None => env.variables.insert(intern(var_name), variable),
     }
     Ok(value)
 }
 
+/// Lets every declaration made directly in `env` be repeated, replacing the
+/// earlier one. The REPL sets it on its global scope so a line can be
+/// entered again after fixing it.
+pub fn allow_redeclaration(env: &Rc<RefCell<Environment>>) {
+    env.borrow_mut().redeclarable = true;
+}
+
 pub fn assign_var(
     env: &Rc<RefCell<Environment>>,
     var_name: &str,
     value: RuntimeVal,
 ) -> Result<RuntimeVal, EnvironmentError> {
-    let final_env = resolve(env, var_name)?;
-    let mut env = final_env.borrow_mut();
+    let mut scope = env.borrow_mut();
+    match scope.variables.get_mut(var_name) {
+        Some(variable) if variable.constant => Err(EnvironmentError::ConstReassign),
+        Some(variable) => {
+            variable.value = value.clone();
+            Ok(value)
+        }
+        None => match &scope.parent {
+            Some(parent) => assign_var(parent, var_name, value),
+            None => Err(EnvironmentError::VarNotDeclared),
+        },
+    }
+}
 
-    if env.constants.contains(var_name) {
-        return Err(EnvironmentError::ConstReassign);
+/// The value of a variable declared in `env` itself, ignoring its parents.
+pub fn lookup_own_var(env: &Rc<RefCell<Environment>>, var_name: &str) -> Option<RuntimeVal> {
+    env.borrow().variables.get(var_name).map(|variable| variable.value.clone())
+}
+
+/// Replaces the value of a variable declared in `env` itself, constant or
+/// not. Subclasses use it to override the default of an inherited field.
+pub fn overwrite_var(env: &Rc<RefCell<Environment>>, var_name: &str, value: RuntimeVal) {
+    if let Some(variable) = env.borrow_mut().variables.get_mut(var_name) {
+        variable.value = value;
     }
-    env.variables.insert(var_name.to_string(), value.clone());
-    Ok(value)
+}
+
+/// Freezes `env` and returns whether it was frozen already.
+pub fn freeze_scope(env: &Rc<RefCell<Environment>>) -> bool {
+    std::mem::replace(&mut env.borrow_mut().frozen, true)
+}
+
+pub fn is_frozen(env: &Rc<RefCell<Environment>>) -> bool {
+    env.borrow().frozen
+}
+
+/// The variables declared in `env` itself, in declaration order for local
+/// scopes.
+pub fn own_vars(env: &Rc<RefCell<Environment>>) -> Vec<(Rc<str>, RuntimeVal)> {
+    env.borrow()
+        .variables
+        .iter()
+        .map(|(name, variable)| (Rc::clone(name), variable.value.clone()))
+        .collect()
+}
+
+/// Whether `var_name` is declared in `env` or a scope enclosing it below the
+/// global scope, where it hides a global variable of the same name.
+pub fn declared_locally(env: &Rc<RefCell<Environment>>, var_name: &str) -> bool {
+    let env = env.borrow();
+    match &env.parent {
+        Some(parent) => env.variables.get(var_name).is_some() || declared_locally(parent, var_name),
+        None => false,
+    }
+}
+
+pub fn is_redeclarable(env: &Rc<RefCell<Environment>>) -> bool {
+    env.borrow().redeclarable
+}
+
+/// A new, unfrozen scope with the same parent and variables as `env`.
+/// Values are cloned, so instances held in it are shared with `env`.
+pub fn copy_scope(env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+    let env = env.borrow();
+    let copy = Environment::new(env.parent.clone());
+    for (name, variable) in env.variables.iter() {
+        let variable = Variable {
+            value: variable.value.clone(),
+            constant: variable.constant,
+            shadowable: variable.shadowable,
+        };
+        copy.borrow_mut().variables.insert(Rc::clone(name), variable);
+    }
+    copy
 }
 
 pub fn lookup_var(
     env: &Rc<RefCell<Environment>>,
     var_name: &str,
 ) -> Result<RuntimeVal, EnvironmentError> {
-    let final_env = resolve(env, var_name)?;
-    let env = final_env.borrow();
-    Ok(env.variables.get(var_name).unwrap().clone())
+    let scope = env.borrow();
+    match scope.variables.get(var_name) {
+        Some(variable) => Ok(variable.value.clone()),
+        None => match &scope.parent {
+            Some(parent) => lookup_var(parent, var_name),
+            None => Err(EnvironmentError::VarNotDeclared),
+        },
+    }
 }
 
-pub fn resolve(
-    env: &Rc<RefCell<Environment>>,
-    var_name: &str,
-) -> Result<Rc<RefCell<Environment>>, EnvironmentError> {
-    if env.borrow().variables.contains_key(var_name) {
-        return Ok(Rc::clone(env));
+/// Every variable visible from `env` as (name, value, is constant), nearest
+/// scope first and sorted by name within a scope. Shadowed names are left out.
+pub fn visible_vars(env: &Rc<RefCell<Environment>>) -> Vec<(Rc<str>, RuntimeVal, bool)> {
+    let mut result: Vec<(Rc<str>, RuntimeVal, bool)> = vec![];
+    let mut current = Some(Rc::clone(env));
+    while let Some(scope) = current {
+        let scope = scope.borrow();
+        let mut found: Vec<_> = scope
+            .variables
+            .iter()
+            .filter(|(name, _)| !result.iter().any(|(seen, _, _)| seen == *name))
+            .map(|(name, variable)| (Rc::clone(name), variable.value.clone(), variable.constant))
+            .collect();
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+        result.extend(found);
+        current = scope.parent.clone();
+    }
+    result
+}
+
+/// Names of every variable visible from `env`, nearest scope first, each
+/// listed once.
+pub fn visible_names(env: &Rc<RefCell<Environment>>) -> Vec<Rc<str>> {
+    let mut names: Vec<Rc<str>> = vec![];
+    let mut current = Some(Rc::clone(env));
+    while let Some(scope) = current {
+        let scope = scope.borrow();
+        // Sorted within a scope, as the global one keeps no order
+        let mut own: Vec<Rc<str>> = scope
+            .variables
+            .iter()
+            .filter(|(name, _)| !names.contains(name))
+            .map(|(name, _)| Rc::clone(name))
+            .collect();
+        own.sort();
+        names.extend(own);
+        current = scope.parent.clone();
+    }
+    names
+}
+
+/// A hint naming the visible variable closest in spelling to `var_name`,
+/// such as " Did you mean 'counter'?", or an empty string when nothing is
+/// close enough to be a likely typo.
+pub fn suggest_name(env: &Rc<RefCell<Environment>>, var_name: &str) -> String {
+    // Allow one edit for short names and about one per three characters
+    let limit = (var_name.chars().count() / 3).max(1);
+    let closest = visible_names(env)
+        .into_iter()
+        .map(|name| (edit_distance(&name, var_name), name))
+        .filter(|(distance, _)| *distance <= limit)
+        // The first of equally close names is in the nearest scope
+        .min_by_key(|(distance, _)| *distance);
+    match closest {
+        Some((_, name)) => format!(" Did you mean '{}'?", name),
+        None => String::new(),
     }
-    match &env.borrow().parent {
-        Some(parent) => resolve(parent, var_name),
-        None => Err(EnvironmentError::VarNotDeclared),
+}
+
+// Levenshtein distance: the fewest insertions, deletions and substitutions
+// turning one string into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
     }
+    previous[b.len()]
 }
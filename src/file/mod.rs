@@ -0,0 +1,90 @@
+use std::cell::RefCell;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// A file opened for reading or writing, behind the `File` value type.
+/// Closing it, or dropping the last copy of the value, closes the file.
+pub struct File {
+    path: String,
+    handle: RefCell<Option<Handle>>,
+}
+
+enum Handle {
+    Read(BufReader<fs::File>),
+    Write(fs::File),
+}
+
+impl File {
+    /// Opens `path` with mode "r" to read it, "w" to replace what it holds
+    /// or "a" to append to it. Writing creates the file if it is missing.
+    pub fn open(path: &str, mode: &str) -> io::Result<File> {
+        let handle = match mode {
+            "r" => Handle::Read(BufReader::new(fs::File::open(path)?)),
+            "w" => Handle::Write(fs::File::create(path)?),
+            "a" => Handle::Write(OpenOptions::new().append(true).create(true).open(path)?),
+            _ => return Err(invalid("the mode must be \"r\", \"w\" or \"a\"")),
+        };
+        Ok(File {
+            path: path.to_string(),
+            handle: RefCell::new(Some(handle)),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Reads the next line without its line ending, or `None` at the end of
+    /// the file.
+    pub fn read_line(&self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        match &mut *self.handle.borrow_mut() {
+            Some(Handle::Read(reader)) => {
+                if reader.read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+            }
+            Some(Handle::Write(_)) => return Err(invalid("a file opened for writing cannot be read")),
+            None => return Err(closed()),
+        }
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+
+    /// Reads everything from the current position to the end of the file.
+    pub fn read_all(&self) -> io::Result<String> {
+        let mut text = String::new();
+        match &mut *self.handle.borrow_mut() {
+            Some(Handle::Read(reader)) => reader.read_to_string(&mut text)?,
+            Some(Handle::Write(_)) => return Err(invalid("a file opened for writing cannot be read")),
+            None => return Err(closed()),
+        };
+        Ok(text)
+    }
+
+    pub fn write(&self, text: &str) -> io::Result<()> {
+        match &mut *self.handle.borrow_mut() {
+            Some(Handle::Write(file)) => file.write_all(text.as_bytes()),
+            Some(Handle::Read(_)) => Err(invalid("a file opened for reading cannot be written")),
+            None => Err(closed()),
+        }
+    }
+
+    /// Closes the file. Closing it again does nothing.
+    pub fn close(&self) {
+        self.handle.borrow_mut().take();
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn closed() -> io::Error {
+    io::Error::other("the file is closed")
+}
@@ -0,0 +1,688 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::lexer::{TokenType, match_keyword};
+
+const INDENT: &str = "    ";
+
+// Stands for a line break inside a string literal until the whole program
+// is formatted, so indenting the lines around it leaves the string as it is
+const LITERAL_NEWLINE: char = '\0';
+
+/// Prints a parsed program back as source with canonical indentation,
+/// spacing and brace placement. Parentheses are only kept where the
+/// grammar needs them.
+pub fn format_program(program: &[Stmt]) -> String {
+    let mut formatter = Formatter {
+        output: String::new(),
+        depth: 0,
+    };
+    for (index, stmt) in program.iter().enumerate() {
+        // Declarations are separated by a blank line at the top level. A
+        // comment on its own line stays with the statement below it.
+        let separated = index > 0
+            && !matches!(stmt, Stmt::Comment(_, true, _))
+            && !matches!(program[index - 1], Stmt::Comment(_, false, _))
+            && (is_declaration(attached(&program[index..]))
+                || program[..index]
+                    .iter()
+                    .rev()
+                    .find(|stmt| !matches!(stmt, Stmt::Comment(..)))
+                    .is_some_and(is_declaration));
+        if separated {
+            formatter.output.push('\n');
+        }
+        formatter.stmt(stmt);
+    }
+    formatter.output.replace(LITERAL_NEWLINE, "\n")
+}
+
+fn is_declaration(stmt: &Stmt) -> bool {
+    matches!(stmt, Stmt::Function(_) | Stmt::Class(_) | Stmt::Interface(_) | Stmt::Mixin(_))
+}
+
+// The statement a run of leading comments belongs to
+fn attached(stmts: &[Stmt]) -> &Stmt {
+    stmts
+        .iter()
+        .find(|stmt| !matches!(stmt, Stmt::Comment(..)))
+        .unwrap_or(&stmts[0])
+}
+
+struct Formatter {
+    output: String,
+    depth: usize,
+}
+
+impl Formatter {
+    fn comment(&mut self, text: &str, trailing: bool) {
+        if trailing && self.output.ends_with('\n') {
+            self.output.pop();
+            self.output.push_str(&format!(" //{}\n", text));
+        } else {
+            self.line(&format!("//{}", text));
+        }
+    }
+
+    // Text spanning several lines, such as an if expression, is indented
+    // as a whole
+    fn line(&mut self, text: &str) {
+        for line in text.split('\n') {
+            // Blank lines of a nested multi-line value stay empty
+            if !line.is_empty() {
+                for _ in 0..self.depth {
+                    self.output.push_str(INDENT);
+                }
+            }
+            self.output.push_str(line);
+            self.output.push('\n');
+        }
+    }
+
+    // Writes `header { ... }`. A continuing block (`else`, `catch`) starts on
+    // the line that closes the previous one.
+    fn body(&mut self, header: &str, stmts: &[Stmt], continues: bool) {
+        let open = if stmts.is_empty() { "{}" } else { "{" };
+        if continues {
+            self.output.pop();
+            self.output.push_str(&format!(" {}{}\n", header, open));
+        } else {
+            self.line(&format!("{}{}", header, open));
+        }
+        if stmts.is_empty() {
+            return;
+        }
+        self.depth += 1;
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) => {
+                let text = statement_expr(expr);
+                self.line(&format!("{};", text));
+            }
+            Stmt::VarDeclaration(declaration) => self.line(&var_declaration(declaration)),
+            Stmt::VarUnpack(names, constant, value, _) => self.line(&var_unpack(names, *constant, value)),
+            Stmt::Global(name, None, _) => self.line(&format!("global {};", name)),
+            Stmt::Global(name, Some(value), _) => {
+                self.line(&format!("global {} = {};", name, expr(value, LOWEST)))
+            }
+            Stmt::Print(values, new_line, _) => {
+                let keyword = if *new_line { "println" } else { "print" };
+                let values: Vec<String> = values
+                    .iter()
+                    .flatten()
+                    .map(|value| expr(value, ASSIGNMENT))
+                    .collect();
+                // `print (a) * b;` stays a statement, only `print (...);` calls the native
+                if values.is_empty() {
+                    self.line(&format!("{};", keyword));
+                } else {
+                    self.line(&format!("{} {};", keyword, values.join(", ")));
+                }
+            }
+            Stmt::IfElse(branches) => {
+                for (index, (condition, body, _)) in branches.iter().enumerate() {
+                    let is_else = index > 0
+                        && index == branches.len() - 1
+                        && matches!(condition, Expr::BoolLiteral(true, _));
+                    let header = if index == 0 {
+                        format!("if {} ", expr(condition, LOWEST))
+                    } else if is_else {
+                        "else ".to_string()
+                    } else {
+                        format!("else if {} ", expr(condition, LOWEST))
+                    };
+                    self.body(&header, body, index > 0);
+                }
+            }
+            Stmt::For((initializer, condition, step), body, _) => {
+                let initializer = match initializer.as_deref() {
+                    Some(Stmt::VarDeclaration(declaration)) => var_declaration(declaration),
+                    Some(Stmt::VarUnpack(names, constant, value, _)) => var_unpack(names, *constant, value),
+                    Some(Stmt::Expression(initializer)) => format!("{};", statement_expr(initializer)),
+                    _ => ";".to_string(),
+                };
+                // Left out clauses keep their ';': `for ;; {`
+                let condition = match condition {
+                    Some(condition) => format!(" {};", expr(condition, LOWEST)),
+                    None => ";".to_string(),
+                };
+                let step = match step {
+                    Some(step) => format!(" {}", expr(step, LOWEST)),
+                    None => String::new(),
+                };
+                let header = format!("for {}{}{} ", initializer, condition, step);
+                self.body(&header, body, false);
+            }
+            Stmt::ForIn(names, iterable, body, _) => {
+                let header = format!("for {} in {} ", names.join(", "), expr(iterable, LOWEST));
+                self.body(&header, body, false);
+            }
+            Stmt::While(condition, body, _) => {
+                let header = format!("while {} ", expr(condition, LOWEST));
+                self.body(&header, body, false);
+            }
+            Stmt::Loop(body, _) => self.body("loop ", body, false),
+            Stmt::Try(body, name, handler, _) => {
+                self.body("try ", body, false);
+                self.body(&format!("catch {} ", name), handler, true);
+            }
+            Stmt::With(value, name, body, _) => {
+                let header = format!("with {} as {} ", expr(value, LOWEST), name);
+                self.body(&header, body, false);
+            }
+            Stmt::Match(value, arms, _) => {
+                if arms.is_empty() {
+                    self.line(&format!("match {} {{}}", expr(value, LOWEST)));
+                    return;
+                }
+                self.line(&format!("match {} {{", expr(value, LOWEST)));
+                self.depth += 1;
+                for arm in arms {
+                    self.line(&format!("case {}:", pattern(&arm.pattern)));
+                    self.depth += 1;
+                    for stmt in &arm.body {
+                        self.stmt(stmt);
+                    }
+                    self.depth -= 1;
+                }
+                self.depth -= 1;
+                self.line("}");
+            }
+            Stmt::Block(stmts) => self.body("", stmts, false),
+            Stmt::Return(Expr::Null(_), _) => self.line("return;"),
+            Stmt::Return(Expr::Array(values, _), true) => {
+                let values: Vec<String> = values.iter().map(|value| expr(value, LOWEST)).collect();
+                self.line(&format!("return {};", values.join(", ")))
+            }
+            Stmt::Return(value, _) => self.line(&format!("return {};", expr(value, LOWEST))),
+            Stmt::Yield(Expr::Null(_)) => self.line("yield;"),
+            Stmt::Yield(value) => self.line(&format!("yield {};", expr(value, LOWEST))),
+            Stmt::Break(None) => self.line("break;"),
+            Stmt::Break(Some(label)) => self.line(&format!("break {};", label)),
+            Stmt::Continue(None) => self.line("continue;"),
+            Stmt::Continue(Some(label)) => self.line(&format!("continue {};", label)),
+            Stmt::Labeled(label, body) => {
+                // The label goes in front of the loop's first line
+                let start = self.output.len() + self.depth * INDENT.len();
+                self.stmt(body);
+                self.output.insert_str(start, &format!("{}: ", label));
+            }
+            Stmt::Function(declaration) => self.function("", declaration),
+            Stmt::Class(class) => self.class(class),
+            Stmt::Interface(interface) => self.interface(interface),
+            Stmt::Mixin(mixin) => self.mixin(mixin),
+            Stmt::Comment(text, trailing, _) => self.comment(text, *trailing),
+        }
+    }
+
+    fn function(&mut self, prefix: &str, declaration: &FunctionDeclaration) {
+        let header = format!(
+            "{}{}fun {}({}) ",
+            prefix,
+            if declaration.asynchronous { "async " } else { "" },
+            declaration.name,
+            declaration.parameters.join(", ")
+        );
+        self.body(&header, &declaration.body, false);
+    }
+
+    fn class(&mut self, class: &ClassDeclaration) {
+        let mut header = "class ".to_string();
+        if class.name != ANONYMOUS_CLASS {
+            header.push_str(&format!("{} ", class.name));
+        }
+        if let Some(superclass) = &class.superclass {
+            header.push_str(&format!("< {} ", superclass));
+        }
+        if !class.mixins.is_empty() {
+            header.push_str(&format!("with {} ", class.mixins.join(", ")));
+        }
+        if !class.interfaces.is_empty() {
+            header.push_str(&format!(": {} ", class.interfaces.join(", ")));
+        }
+        self.members(
+            &header,
+            &class.static_fields,
+            &class.instance_fields,
+            &class.methods,
+            &class.static_methods,
+            &class.comments,
+        );
+    }
+
+    fn mixin(&mut self, mixin: &MixinDeclaration) {
+        let header = format!("mixin {} ", mixin.name);
+        self.members(&header, &[], &[], &mixin.methods, &HashMap::new(), &mixin.comments);
+    }
+
+    // The body of a class or mixin
+    fn members(
+        &mut self,
+        header: &str,
+        static_fields: &[VarDeclaration],
+        instance_fields: &[VarDeclaration],
+        methods: &HashMap<String, Rc<FunctionDeclaration>>,
+        static_methods: &HashMap<String, Rc<FunctionDeclaration>>,
+        comments: &[(String, bool, usize)],
+    ) {
+        // Members keep the order they were declared in
+        enum Member<'a> {
+            Field(&'a str, &'a VarDeclaration),
+            Method(&'a str, &'a FunctionDeclaration),
+            Comment(&'a str, bool),
+        }
+        let mut members: Vec<(usize, &str, Member)> = vec![];
+        for field in static_fields {
+            members.push((field.line, &field.identifier, Member::Field("static ", field)));
+        }
+        for field in instance_fields {
+            members.push((field.line, &field.identifier, Member::Field("", field)));
+        }
+        for method in methods.values() {
+            members.push((method.line, &method.name, Member::Method("", method)));
+        }
+        for method in static_methods.values() {
+            members.push((method.line, &method.name, Member::Method("static ", method)));
+        }
+        for (text, trailing, line) in comments {
+            members.push((*line, "", Member::Comment(text, *trailing)));
+        }
+        // Members sharing a line put fields first, then methods ordered by
+        // name, so the output does not depend on hash map order. A comment
+        // comes after the code it shares a line with.
+        let rank = |member: &Member| match member {
+            Member::Field(..) => 0,
+            Member::Method(..) => 1,
+            Member::Comment(..) => 2,
+        };
+        members.sort_by_key(|(line, name, member)| (*line, rank(member), *name));
+
+        if members.is_empty() {
+            self.line(&format!("{}{{}}", header));
+            return;
+        }
+        self.line(&format!("{}{{", header));
+        self.depth += 1;
+        let mut previous_was_method = None;
+        for (index, (_, _, member)) in members.iter().enumerate() {
+            // Comments on their own line go with the member below them
+            let is_method = members[index..]
+                .iter()
+                .find(|(_, _, member)| !matches!(member, Member::Comment(..)))
+                .is_some_and(|(_, _, member)| matches!(member, Member::Method(..)));
+            match member {
+                Member::Comment(text, true) => {
+                    self.comment(text, true);
+                    continue;
+                }
+                _ => {
+                    // Methods are set apart from each other and from the fields
+                    if previous_was_method.is_some_and(|previous| previous || is_method) {
+                        self.output.push('\n');
+                    }
+                }
+            }
+            match member {
+                Member::Field(prefix, field) => {
+                    let text = format!("{}{}", prefix, var_declaration(field));
+                    self.line(&text);
+                }
+                Member::Method(prefix, method) => self.function(prefix, method),
+                Member::Comment(text, _) => {
+                    self.comment(text, false);
+                    previous_was_method = None;
+                    continue;
+                }
+            }
+            previous_was_method = Some(is_method);
+        }
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn interface(&mut self, interface: &InterfaceDeclaration) {
+        let header = format!("interface {} ", interface.name);
+        if interface.methods.is_empty() && interface.comments.is_empty() {
+            self.line(&format!("{}{{}}", header));
+            return;
+        }
+        self.line(&format!("{}{{", header));
+        self.depth += 1;
+        // Methods and comments keep their order, a comment after the method
+        // it shares a line with
+        let mut members: Vec<(usize, usize, String, bool)> = vec![];
+        for (name, parameters, line) in &interface.methods {
+            members.push((*line, 0, format!("{}({});", name, parameters.join(", ")), false));
+        }
+        for (text, trailing, line) in &interface.comments {
+            members.push((*line, 1, text.clone(), *trailing));
+        }
+        members.sort_by_key(|(line, rank, _, _)| (*line, *rank));
+        for (_, rank, text, trailing) in members {
+            if rank == 0 {
+                self.line(&text);
+            } else {
+                self.comment(&text, trailing);
+            }
+        }
+        self.depth -= 1;
+        self.line("}");
+    }
+}
+
+fn var_declaration(declaration: &VarDeclaration) -> String {
+    match (declaration.constant, declaration.value.as_ref()) {
+        (false, Expr::Null(_)) => format!("var {};", declaration.identifier),
+        (constant, value) => format!(
+            "{} {} = {};",
+            if constant { "const" } else { "var" },
+            declaration.identifier,
+            expr(value, LOWEST)
+        ),
+    }
+}
+
+fn var_unpack(names: &[String], constant: bool, value: &Expr) -> String {
+    format!(
+        "{} {} = {};",
+        if constant { "const" } else { "var" },
+        names.join(", "),
+        expr(value, LOWEST)
+    )
+}
+
+// A branch of an if expression. Branches holding only their value stay on
+// one line: `{ a }`
+fn value_block(body: &[Stmt], value: &Expr) -> String {
+    if body.is_empty() {
+        return format!("{{ {} }}", expr(value, LOWEST));
+    }
+    let mut formatter = Formatter {
+        output: String::new(),
+        depth: 1,
+    };
+    for stmt in body {
+        formatter.stmt(stmt);
+    }
+    formatter.line(&expr(value, LOWEST));
+    format!("{{\n{}}}", formatter.output)
+}
+
+// Expression statements cannot start with a token that begins another
+// statement or is not accepted at the start of one
+fn statement_expr(value: &Expr) -> String {
+    let text = expr(value, LOWEST);
+    if text.starts_with(['[', '!', '{'])
+        || text.starts_with("not ")
+        || text.starts_with("if ")
+        || text.starts_with("class ")
+    {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+// Binding strength of each expression, loosest first. An operand that binds
+// looser than its position allows is wrapped in parentheses.
+const LOWEST: u8 = 0;
+const ASSIGNMENT: u8 = 1;
+const PREFIX: u8 = 2;
+const NIL_COALESCING: u8 = 3;
+const LOGICAL: u8 = 4;
+const EQUALITY: u8 = 5;
+const COMPARISON: u8 = 6;
+const RANGE: u8 = 7;
+const ADDITIVE: u8 = 8;
+const MULTIPLICATIVE: u8 = 9;
+const UNARY: u8 = 10;
+const CALL: u8 = 11;
+const PRIMARY: u8 = 12;
+
+fn precedence(value: &Expr) -> u8 {
+    match value {
+        // An if expression reads as taking everything after it, so it is
+        // wrapped wherever it is an operand
+        Expr::AssignmentExpr { .. } | Expr::If { .. } => ASSIGNMENT,
+        // Object literals are only parsed where a whole expression may
+        // stand, so they need parentheses anywhere else
+        Expr::ObjectLiteral { .. } => PREFIX,
+        Expr::Unary { .. } | Expr::Await { .. } => UNARY,
+        Expr::ComparisonLiteral { operator, .. } => match operator.token_type {
+            TokenType::QUESTIONQUESTION => NIL_COALESCING,
+            TokenType::AND | TokenType::OR => LOGICAL,
+            TokenType::EQUALEQUAL | TokenType::BANGEQUAL => EQUALITY,
+            _ => COMPARISON,
+        },
+        Expr::Range { .. } => RANGE,
+        Expr::BinaryExpr { operator, .. } => match &operator.lexeme[..] {
+            "+" | "-" => ADDITIVE,
+            _ => MULTIPLICATIVE,
+        },
+        Expr::Call { .. } | Expr::Member { .. } | Expr::Slice { .. } => CALL,
+        _ => PRIMARY,
+    }
+}
+
+fn pattern(value: &Pattern) -> String {
+    match value {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Binding(name) => name.clone(),
+        Pattern::Literal(literal) => expr(literal, LOWEST),
+        Pattern::Array(elements) => {
+            let elements: Vec<String> = elements.iter().map(pattern).collect();
+            format!("[{}]", elements.join(", "))
+        }
+        Pattern::Object(entries) => {
+            let entries: Vec<String> = entries
+                .iter()
+                .map(|(key, value)| match value {
+                    Pattern::Binding(name) if name == key => key.clone(),
+                    _ if is_identifier(key) => format!("{}: {}", key, pattern(value)),
+                    _ => format!("{}: {}", string_literal(key), pattern(value)),
+                })
+                .collect();
+            if entries.is_empty() {
+                "{}".to_string()
+            } else {
+                format!("{{ {} }}", entries.join(", "))
+            }
+        }
+    }
+}
+
+fn expr(value: &Expr, min_precedence: u8) -> String {
+    let text = match value {
+        Expr::NumericLiteral(num, _) => num.to_string(),
+        Expr::Null(_) => "nil".to_string(),
+        Expr::BoolLiteral(bit, _) => bit.to_string(),
+        Expr::StringLiteral(text, _) => string_literal(text),
+        Expr::Identifier(name, _) => name.clone(),
+        Expr::This(_) => "this".to_string(),
+        Expr::Super(..) => "super".to_string(),
+        Expr::Array(elements, _) => {
+            let elements: Vec<String> = elements.iter().map(|element| expr(element, ASSIGNMENT)).collect();
+            format!("[{}]", elements.join(", "))
+        }
+        Expr::Range {
+            start,
+            end,
+            inclusive,
+            ..
+        } => format!(
+            "{}{}{}",
+            expr(start, ADDITIVE),
+            if *inclusive { "..=" } else { ".." },
+            expr(end, ADDITIVE)
+        ),
+        Expr::Member {
+            object,
+            property,
+            computed,
+            optional,
+            ..
+        } => {
+            let object = expr(object, CALL);
+            match (computed, optional) {
+                (true, true) => format!("{}?[{}]", object, expr(property, LOWEST)),
+                (true, false) => format!("{}[{}]", object, expr(property, LOWEST)),
+                (false, true) => format!("{}?.{}", object, expr(property, PRIMARY)),
+                (false, false) => format!("{}.{}", object, expr(property, PRIMARY)),
+            }
+        }
+        Expr::Slice {
+            object,
+            start,
+            end,
+            optional,
+            ..
+        } => {
+            let bound = |bound: &Option<Box<Expr>>| bound.as_deref().map_or(String::new(), |bound| expr(bound, LOWEST));
+            format!(
+                "{}{}[{}:{}]",
+                expr(object, CALL),
+                if *optional { "?" } else { "" },
+                bound(start),
+                bound(end)
+            )
+        }
+        Expr::Call { args, caller, .. } => {
+            let args: Vec<String> = args.iter().map(|arg| expr(arg, ASSIGNMENT)).collect();
+            format!("{}({})", expr(caller, CALL), args.join(", "))
+        }
+        Expr::Unary {
+            operator, right, ..
+        } => match &operator.lexeme[..] {
+            "not" => format!("not {}", expr(right, UNARY)),
+            lexeme => format!("{}{}", lexeme, expr(right, UNARY)),
+        },
+        Expr::Await { value, .. } => format!("await {}", expr(value, UNARY)),
+        Expr::BinaryExpr {
+            left,
+            operator,
+            right,
+            ..
+        }
+        | Expr::ComparisonLiteral {
+            left,
+            operator,
+            right,
+            ..
+        } => {
+            let level = precedence(value);
+            format!(
+                "{} {} {}",
+                expr(left, level),
+                operator.lexeme,
+                expr(right, level + 1)
+            )
+        }
+        Expr::ObjectLiteral { properties } => {
+            let properties: Vec<String> = properties
+                .iter()
+                .map(|property| {
+                    let key = if is_identifier(&property.key) {
+                        property.key.clone()
+                    } else {
+                        string_literal(&property.key)
+                    };
+                    match &property.value {
+                        Some(value) => format!("{}: {}", key, expr(value, LOWEST)),
+                        None => key,
+                    }
+                })
+                .collect();
+            if properties.is_empty() {
+                "{}".to_string()
+            } else {
+                format!("{{ {} }}", properties.join(", "))
+            }
+        }
+        Expr::If { branches, .. } => {
+            let mut text = String::new();
+            for (index, (condition, body, value)) in branches.iter().enumerate() {
+                if index == 0 {
+                    text.push_str(&format!("if {} ", expr(condition, LOWEST)));
+                } else if index == branches.len() - 1 {
+                    text.push_str(" else ");
+                } else {
+                    text.push_str(&format!(" else if {} ", expr(condition, LOWEST)));
+                }
+                text.push_str(&value_block(body, value));
+            }
+            text
+        }
+        Expr::Class(class) => {
+            let mut formatter = Formatter {
+                output: String::new(),
+                depth: 0,
+            };
+            formatter.class(class);
+            formatter.output.trim_end().to_string()
+        }
+        Expr::AssignmentExpr {
+            assignee, value, ..
+        } => match value.as_ref() {
+            // `a += b` is parsed as `a = a + b` with the compound operator kept on the `+`
+            Expr::BinaryExpr {
+                operator, right, ..
+            } if matches!(
+                operator.token_type,
+                TokenType::PLUSEQUAL
+                    | TokenType::MINUSEQUAL
+                    | TokenType::STAREQUAL
+                    | TokenType::SLASHEQUAL
+                    | TokenType::MODULUSEQUAL
+            ) =>
+            {
+                format!(
+                    "{} {}= {}",
+                    expr(assignee, NIL_COALESCING),
+                    operator.lexeme,
+                    expr(right, LOWEST)
+                )
+            }
+            _ => format!(
+                "{} = {}",
+                expr(assignee, NIL_COALESCING),
+                expr(value, ASSIGNMENT)
+            ),
+        },
+    };
+    if precedence(value) < min_precedence {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+fn string_literal(text: &str) -> String {
+    quote(text).replace('\n', &LITERAL_NEWLINE.to_string())
+}
+
+fn quote(text: &str) -> String {
+    if !text.contains('"') {
+        format!("\"{}\"", text)
+    } else if !text.contains('\'') {
+        format!("'{}'", text)
+    } else if !text.contains("\"\"\"") && !text.ends_with('"') {
+        format!("\"\"\"{}\"\"\"", text)
+    } else {
+        format!("'''{}'''", text)
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && match_keyword(text) == TokenType::IDENTIFIER
+}
@@ -1,11 +1,35 @@
-use std::io;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
+use std::cmp::Ordering;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::rc::Rc;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::encoding;
+use crate::file::File;
+use crate::environment::{Environment, copy_scope, freeze_scope, overwrite_var, own_vars};
 use crate::handle_errors::RuntimeError;
+use crate::interpreter::expression::{map_key, missing_method};
+use crate::interpreter::interpreter::Interpreter;
+use crate::interpreter::random::Random;
+use crate::interpreter::scheduler::AsyncTask;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::interrupt::interrupt_pending;
+use crate::socket::Socket;
+use crate::threads::{Channel, SharedValue, from_message};
 use crate::values::*;
 
-pub fn clock(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
+pub fn clock(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
     if args.len() > 0 {
         return Err(RuntimeError::InvalidArgumentCount(
             format!(
@@ -15,107 +39,760 @@ pub fn clock(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeErro
             line,
         ));
     }
+    Ok(make_number(now_seconds()))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_seconds() -> f64 {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("Time went backwards");
-    let time = now.as_secs_f64() + now.as_nanos() as f64 * 1e-9;
-    Ok(make_number(time))
+    now.as_secs_f64()
 }
 
-pub fn scan(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
-    if args.len() != 0 {
-        return Err(RuntimeError::InvalidArgumentCount(
-            format!(
-                "Expected 1, found {} arguments provided to native function 'reverse'",
-                args.len()
-            ),
+// The system clock is not available in the browser, JavaScript's is used instead
+#[cfg(target_arch = "wasm32")]
+pub fn now_seconds() -> f64 {
+    js_sys::Date::now() / 1000.0
+}
+
+// Timestamps are UTC seconds since the UNIX epoch, the same as `clock()`.
+// Dates are on the proleptic Gregorian calendar.
+
+pub fn now_iso(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 0, "now_iso", line)?;
+    let parts = DateParts::from_timestamp(now_seconds());
+    Ok(make_string(&parts.format("%Y-%m-%dT%H:%M:%SZ", line)?))
+}
+
+pub fn date_parts(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "date_parts", line)?;
+    let parts = DateParts::from_timestamp(expect_timestamp(&args[0], "date_parts", line)?);
+    let mut fields = HashMap::new();
+    for (name, value) in [
+        ("year", parts.year),
+        ("month", parts.month),
+        ("day", parts.day),
+        ("hour", parts.hour),
+        ("min", parts.minute),
+        ("sec", parts.second),
+        ("weekday", parts.weekday),
+    ] {
+        fields.insert(name.to_string(), make_number(value as f64));
+    }
+    Ok(RuntimeVal::Object(fields))
+}
+
+pub fn format_time(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "format_time", line)?;
+    let parts = DateParts::from_timestamp(expect_timestamp(&args[0], "format_time", line)?);
+    let pattern = expect_string(&args[1], "second", "format_time", line)?;
+    Ok(make_string(&parts.format(pattern, line)?))
+}
+
+pub fn parse_time(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "parse_time", line)?;
+    let text = expect_string(&args[0], "first", "parse_time", line)?;
+    let pattern = expect_string(&args[1], "second", "parse_time", line)?;
+    let parts = DateParts::parse(text, pattern, line)?;
+    Ok(make_number(parts.timestamp()))
+}
+
+fn expect_timestamp(arg: &RuntimeVal, name: &str, line: usize) -> Result<f64, RuntimeError> {
+    match arg {
+        RuntimeVal::Number(num) if num.is_finite() => Ok(*num),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only a timestamp in seconds allowed as first argument in '{}' function", name),
             line,
-        ));
+        )),
+    }
+}
+
+// A moment broken into its calendar fields. `weekday` counts from Sunday = 0.
+struct DateParts {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    weekday: i64,
+}
+
+impl DateParts {
+    // Fractions of a second are dropped
+    fn from_timestamp(timestamp: f64) -> Self {
+        let seconds = timestamp.floor() as i64;
+        let days = seconds.div_euclid(86400);
+        let time = seconds.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        DateParts {
+            year,
+            month,
+            day,
+            hour: time / 3600,
+            minute: time % 3600 / 60,
+            second: time % 60,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7),
+        }
     }
 
-    let mut input = String::new();
-    io::stdin()
-        .read_line(&mut input)
-        .expect("Failed to scan input");
+    fn timestamp(&self) -> f64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        (days * 86400 + self.hour * 3600 + self.minute * 60 + self.second) as f64
+    }
 
-    Ok(make_string(&input[..]))
+    // `%Y` year, `%m` month, `%d` day, `%H` hour, `%M` minute, `%S` second
+    // and `%%` a literal `%`
+    fn format(&self, pattern: &str, line: usize) -> Result<String, RuntimeError> {
+        let mut text = String::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                text.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => text.push_str(&format!("{:04}", self.year)),
+                Some('m') => text.push_str(&format!("{:02}", self.month)),
+                Some('d') => text.push_str(&format!("{:02}", self.day)),
+                Some('H') => text.push_str(&format!("{:02}", self.hour)),
+                Some('M') => text.push_str(&format!("{:02}", self.minute)),
+                Some('S') => text.push_str(&format!("{:02}", self.second)),
+                Some('%') => text.push('%'),
+                other => return Err(unknown_specifier(other, "format_time", line)),
+            }
+        }
+        Ok(text)
+    }
+
+    // Reads `text` laid out as `pattern`, which uses the specifiers of
+    // `format`. Fields the pattern leaves out default to 1970-01-01 00:00:00.
+    fn parse(text: &str, pattern: &str, line: usize) -> Result<Self, RuntimeError> {
+        let mismatch = || {
+            RuntimeError::TypeCastingError(
+                format!("'{}' does not match the format '{}' in 'parse_time' function", text, pattern),
+                line,
+            )
+        };
+        let mut parts = DateParts {
+            year: 1970,
+            month: 1,
+            day: 1,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            weekday: 0,
+        };
+        let mut rest = text.chars().peekable();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            let (field, width) = match c {
+                '%' => match chars.next() {
+                    Some('Y') => (&mut parts.year, 4),
+                    Some('m') => (&mut parts.month, 2),
+                    Some('d') => (&mut parts.day, 2),
+                    Some('H') => (&mut parts.hour, 2),
+                    Some('M') => (&mut parts.minute, 2),
+                    Some('S') => (&mut parts.second, 2),
+                    Some('%') => {
+                        rest.next_if_eq(&'%').ok_or_else(mismatch)?;
+                        continue;
+                    }
+                    other => return Err(unknown_specifier(other, "parse_time", line)),
+                },
+                _ => {
+                    rest.next_if_eq(&c).ok_or_else(mismatch)?;
+                    continue;
+                }
+            };
+            let mut digits = String::new();
+            while digits.len() < width {
+                match rest.next_if(char::is_ascii_digit) {
+                    Some(digit) => digits.push(digit),
+                    None => break,
+                }
+            }
+            *field = digits.parse().map_err(|_| mismatch())?;
+        }
+        let valid = rest.next().is_none()
+            && (1..=12).contains(&parts.month)
+            && (1..=days_in_month(parts.year, parts.month)).contains(&parts.day)
+            && parts.hour < 24
+            && parts.minute < 60
+            && parts.second < 60;
+        if !valid {
+            return Err(mismatch());
+        }
+        Ok(parts)
+    }
+}
+
+fn unknown_specifier(specifier: Option<char>, name: &str, line: usize) -> RuntimeError {
+    let specifier = specifier.map(|c| format!("%{}", c)).unwrap_or_else(|| "%".to_string());
+    RuntimeError::TypeMismatch(
+        format!("Unknown specifier '{}' in the format given to '{}' function", specifier, name),
+        line,
+    )
+}
+
+// Days since 1970-01-01 of a date, and back. Howard Hinnant's algorithms,
+// which count in 400 year eras starting from March 1st.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    days_from_civil(next_year, next_month, 1) - days_from_civil(year, month, 1)
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
 }
 
-pub fn min(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
-    if args.len() < 2 {
+// `time(f, args...)` calls `f` with the remaining arguments and returns how
+// many seconds the call took. What `f` returns is discarded.
+pub fn time(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    let Some((callable, callable_args)) = args.split_first() else {
         return Err(RuntimeError::InvalidArgumentCount(
-            format!(
-                "Expected more than 2, found {} arguments provided to native function 'min'",
-                args.len()
-            ),
+            "Expected at least 1, found 0 arguments provided to native function 'time'".to_string(),
+            line,
+        ));
+    };
+    if !matches!(
+        callable,
+        RuntimeVal::Function { .. }
+            | RuntimeVal::Overloaded { .. }
+            | RuntimeVal::Method { .. }
+            | RuntimeVal::NativeFunction(..)
+            | RuntimeVal::Class { .. }
+    ) {
+        return Err(RuntimeError::TypeMismatch(
+            "Only functions allowed as first argument in 'time' function".to_string(),
             line,
         ));
     }
+    let start = elapsed_seconds();
+    interpreter.call(callable.clone(), callable_args, line)?;
+    Ok(make_number(elapsed_seconds() - start))
+}
+
+// Seconds on a clock that never goes back, for measuring how long
+// something took
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn elapsed_seconds() -> f64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn elapsed_seconds() -> f64 {
+    now_seconds()
+}
 
-    let mut min = match &args[0] {
-        RuntimeVal::Number(num) => *num,
+pub fn sleep(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "sleep", line)?;
+    let milliseconds = match &args[0] {
+        RuntimeVal::Number(num) if *num >= 0.0 && num.is_finite() => *num,
         _ => {
             return Err(RuntimeError::TypeMismatch(
-                "Only type number and array allowed in 'min' function".to_string(),
+                "Only a number of milliseconds of 0 or more allowed as first argument in 'sleep' function".to_string(),
                 line,
             ));
         }
     };
+    pause(milliseconds, "sleep", line)?;
+    Ok(make_nil())
+}
 
-    for arg in &args[1..] {
-        if let RuntimeVal::Number(num) = arg {
-            if *num > min {
-                min = *num;
-            }
-        } else {
-            return Err(RuntimeError::TypeMismatch(
-                "Only type number and array allowed in 'min' function".to_string(),
-                line,
-            ));
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn pause(milliseconds: f64, _name: &str, _line: usize) -> Result<(), RuntimeError> {
+    // Sleeps in slices so Ctrl+C does not wait for the whole pause
+    let until = elapsed_seconds() + milliseconds / 1000.0;
+    while !interrupt_pending() {
+        let left = until - elapsed_seconds();
+        if left <= 0.0 {
+            break;
         }
+        thread::sleep(Duration::from_secs_f64(left.min(0.05)));
+    }
+    Ok(())
+}
+
+// The browser cannot block its only thread
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn pause(_milliseconds: f64, name: &str, line: usize) -> Result<(), RuntimeError> {
+    Err(RuntimeError::IoError(
+        format!("'{}' is not available in WebAssembly", name),
+        line,
+    ))
+}
+
+pub fn random(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 0, "random", line)?;
+    Ok(make_number(interpreter.random.next_f64()))
+}
+
+// `rand_int(lo, hi)` includes both ends.
+pub fn rand_int(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "rand_int", line)?;
+    let low = expect_integer(&args[0], "first", "rand_int", line)?;
+    let high = expect_integer(&args[1], "second", "rand_int", line)?;
+    if low > high {
+        return Err(RuntimeError::TypeMismatch(
+            format!("Lower bound {} is greater than upper bound {} in 'rand_int' function", low, high),
+            line,
+        ));
+    }
+    let span = (high as i64).wrapping_sub(low as i64) as u64;
+    let offset = match span.checked_add(1) {
+        Some(bound) => interpreter.random.below(bound),
+        None => interpreter.random.next_u64(),
+    };
+    Ok(make_number((low as i64).wrapping_add(offset as i64) as f64))
+}
+
+pub fn shuffle(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "shuffle", line)?;
+    let mut array = expect_array(&args[0], "first", "shuffle", line)?.clone();
+    // Fisher-Yates
+    for i in (1..array.len()).rev() {
+        let j = interpreter.random.below(i as u64 + 1) as usize;
+        array.swap(i, j);
+    }
+    Ok(RuntimeVal::Array(array))
+}
+
+pub fn choice(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "choice", line)?;
+    let array = expect_array(&args[0], "first", "choice", line)?;
+    if array.is_empty() {
+        return Err(RuntimeError::ArrayIndexOutOfBounds(
+            "Cannot choose from an empty array in 'choice' function".to_string(),
+            line,
+        ));
     }
+    let index = interpreter.random.below(array.len() as u64) as usize;
+    Ok(array[index].clone())
+}
 
-    Ok(make_number(min))
+// Restarts the sequence `random`, `rand_int`, `shuffle` and `choice` draw
+// from, so a run with the same seed makes the same choices.
+pub fn seed(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "seed", line)?;
+    let seed = expect_integer(&args[0], "first", "seed", line)?;
+    interpreter.random = Random::new(seed as i64 as u64);
+    Ok(make_nil())
 }
 
-pub fn max(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
-    if args.len() < 2 {
+pub fn scan(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() != 0 {
         return Err(RuntimeError::InvalidArgumentCount(
             format!(
-                "Expected more than 2, found {} arguments provided to native function 'max'",
+                "Expected 1, found {} arguments provided to native function 'reverse'",
                 args.len()
             ),
             line,
         ));
     }
 
-    let mut max = match &args[0] {
-        RuntimeVal::Number(num) => *num,
-        _ => {
+    let input = interpreter.read_line(line)?;
+    Ok(make_string(&input[..]))
+}
+
+pub fn tcp_connect(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "tcp_connect", line)?;
+    let host = expect_string(&args[0], "first", "tcp_connect", line)?;
+    let port = expect_port(&args[1], "tcp_connect", line)?;
+    let socket = Socket::tcp_connect(host, port)
+        .map_err(|e| io_error(&format!("connect to {}:{}", host, port), e, line))?;
+    Ok(RuntimeVal::Socket(Rc::new(socket)))
+}
+
+// `tcp_listen(host, 0)` listens on a port the system picks; printing the
+// socket shows which.
+pub fn tcp_listen(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "tcp_listen", line)?;
+    let host = expect_string(&args[0], "first", "tcp_listen", line)?;
+    let port = expect_port(&args[1], "tcp_listen", line)?;
+    let socket = Socket::tcp_listen(host, port)
+        .map_err(|e| io_error(&format!("listen on {}:{}", host, port), e, line))?;
+    Ok(RuntimeVal::Socket(Rc::new(socket)))
+}
+
+pub fn unix_connect(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "unix_connect", line)?;
+    let path = expect_string(&args[0], "first", "unix_connect", line)?;
+    let socket = Socket::unix_connect(path)
+        .map_err(|e| io_error(&format!("connect to {}", path), e, line))?;
+    Ok(RuntimeVal::Socket(Rc::new(socket)))
+}
+
+pub fn unix_listen(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "unix_listen", line)?;
+    let path = expect_string(&args[0], "first", "unix_listen", line)?;
+    let socket = Socket::unix_listen(path)
+        .map_err(|e| io_error(&format!("listen on {}", path), e, line))?;
+    Ok(RuntimeVal::Socket(Rc::new(socket)))
+}
+
+pub fn accept(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "accept", line)?;
+    let listener = expect_socket(&args[0], "accept", line)?;
+    let socket = listener
+        .accept()
+        .map_err(|e| io_error(&format!("accept on {}", listener.address()), e, line))?;
+    Ok(RuntimeVal::Socket(Rc::new(socket)))
+}
+
+pub fn send(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "send", line)?;
+    let socket = expect_socket(&args[0], "send", line)?;
+    let data = expect_string(&args[1], "second", "send", line)?;
+    socket
+        .send(data.as_bytes())
+        .map_err(|e| io_error(&format!("send to {}", socket.address()), e, line))?;
+    Ok(make_nil())
+}
+
+// `recv(socket)` reads up to 4096 bytes, `recv(socket, n)` up to n. An empty
+// string means the other end closed the connection.
+pub fn recv(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 1 | 2, found {} arguments provided to native function 'recv'",
+            args.len()
+        ), line));
+    }
+    let socket = expect_socket(&args[0], "recv", line)?;
+    let max = match args.get(1) {
+        Some(RuntimeVal::Number(num)) if *num >= 1.0 && num.fract() == 0.0 => *num as usize,
+        Some(_) => {
             return Err(RuntimeError::TypeMismatch(
-                "Only type number and array allowed in 'max' function".to_string(),
+                "Only a positive integer allowed as second argument in 'recv' function".to_string(),
                 line,
             ));
         }
+        None => 4096,
     };
+    let data = socket
+        .recv(max)
+        .map_err(|e| io_error(&format!("receive from {}", socket.address()), e, line))?;
+    Ok(make_string(&String::from_utf8_lossy(&data)))
+}
 
-    for arg in &args[1..] {
-        if let RuntimeVal::Number(num) = arg {
-            if *num > max {
-                max = *num;
-            }
-        } else {
-            return Err(RuntimeError::TypeMismatch(
-                "Only type number and array allowed in 'min' function".to_string(),
-                line,
-            ));
-        }
+// `close` takes a socket or a file
+pub fn close(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "close", line)?;
+    match &args[0] {
+        RuntimeVal::File(file) => file.close(),
+        socket => expect_socket(socket, "close", line)?.close(),
+    }
+    Ok(make_nil())
+}
+
+fn expect_socket<'a>(arg: &'a RuntimeVal, name: &str, line: usize) -> Result<&'a Socket, RuntimeError> {
+    match arg {
+        RuntimeVal::Socket(socket) => Ok(socket),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type socket allowed as first argument in '{}' function", name),
+            line,
+        )),
+    }
+}
+
+fn expect_port(arg: &RuntimeVal, name: &str, line: usize) -> Result<u16, RuntimeError> {
+    match arg {
+        RuntimeVal::Number(num) if num.fract() == 0.0 && (0.0..=65535.0).contains(num) => Ok(*num as u16),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only a port number from 0 to 65535 allowed as second argument in '{}' function", name),
+            line,
+        )),
+    }
+}
+
+fn io_error(action: &str, error: std::io::Error, line: usize) -> RuntimeError {
+    RuntimeError::IoError(format!("Failed to {}: {}", action, error), line)
+}
+
+// `open_file(path)` opens a file to read it, `open_file(path, mode)` with
+// mode "r" to read, "w" to replace its contents or "a" to append to it
+pub fn open_file(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 1 | 2, found {} arguments provided to native function 'open_file'",
+            args.len()
+        ), line));
+    }
+    let path = expect_string(&args[0], "first", "open_file", line)?;
+    let mode = match args.get(1) {
+        Some(mode) => expect_string(mode, "second", "open_file", line)?,
+        None => "r",
+    };
+    let file = File::open(path, mode).map_err(|e| io_error(&format!("open '{}'", path), e, line))?;
+    Ok(RuntimeVal::File(Rc::new(file)))
+}
+
+// The next line of a file without its line ending, or nil at the end
+pub fn read_line(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "read_line", line)?;
+    let file = expect_file(&args[0], "read_line", line)?;
+    let text = file
+        .read_line()
+        .map_err(|e| io_error(&format!("read '{}'", file.path()), e, line))?;
+    Ok(text.map_or_else(make_nil, |text| make_string(&text)))
+}
+
+// The rest of a file from where reading stopped
+pub fn read_all(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "read_all", line)?;
+    let file = expect_file(&args[0], "read_all", line)?;
+    let text = file
+        .read_all()
+        .map_err(|e| io_error(&format!("read '{}'", file.path()), e, line))?;
+    Ok(make_string(&text))
+}
+
+pub fn write(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "write", line)?;
+    let file = expect_file(&args[0], "write", line)?;
+    let text = expect_string(&args[1], "second", "write", line)?;
+    file.write(text)
+        .map_err(|e| io_error(&format!("write '{}'", file.path()), e, line))?;
+    Ok(make_nil())
+}
+
+fn expect_file<'a>(arg: &'a RuntimeVal, name: &str, line: usize) -> Result<&'a File, RuntimeError> {
+    match arg {
+        RuntimeVal::File(file) => Ok(file),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type file allowed as first argument in '{}' function", name),
+            line,
+        )),
+    }
+}
+
+// `min`, `max`, `sum` and `avg` take either numbers, `min(3, 1, 2)`, or a
+// single array of numbers, `min([3, 1, 2])`.
+pub fn min(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    let numbers = expect_numbers(args, "min", line)?;
+    match numbers.into_iter().reduce(f64::min) {
+        Some(min) => Ok(make_number(min)),
+        None => Err(no_numbers("min", line)),
+    }
+}
+
+pub fn max(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    let numbers = expect_numbers(args, "max", line)?;
+    match numbers.into_iter().reduce(f64::max) {
+        Some(max) => Ok(make_number(max)),
+        None => Err(no_numbers("max", line)),
+    }
+}
+
+// The sum of no numbers is 0
+pub fn sum(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    let numbers = expect_numbers(args, "sum", line)?;
+    Ok(make_number(numbers.iter().fold(0.0, |total, num| total + num)))
+}
+
+pub fn avg(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    let numbers = expect_numbers(args, "avg", line)?;
+    if numbers.is_empty() {
+        return Err(no_numbers("avg", line));
+    }
+    Ok(make_number(numbers.iter().fold(0.0, |total, num| total + num) / numbers.len() as f64))
+}
+
+pub fn clamp(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 3, "clamp", line)?;
+    let numbers = expect_numbers(args, "clamp", line)?;
+    let (num, low, high) = (numbers[0], numbers[1], numbers[2]);
+    if low > high {
+        return Err(RuntimeError::TypeMismatch(
+            format!("Lower bound {} is greater than upper bound {} in 'clamp' function", low, high),
+            line,
+        ));
+    }
+    Ok(make_number(num.clamp(low, high)))
+}
+
+pub fn abs(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "abs", line)?;
+    match &args[0] {
+        RuntimeVal::Number(num) => Ok(make_number(num.abs())),
+        _ => Err(RuntimeError::TypeMismatch(
+            "Only type number allowed as first argument in 'abs' function".to_string(),
+            line,
+        )),
     }
+}
+
+fn expect_numbers(args: &[RuntimeVal], name: &str, line: usize) -> Result<Vec<f64>, RuntimeError> {
+    let values = match args {
+        [RuntimeVal::Array(arr)] => &arr[..],
+        _ => args,
+    };
+    values
+        .iter()
+        .map(|val| match val {
+            RuntimeVal::Number(num) => Ok(*num),
+            _ => Err(RuntimeError::TypeMismatch(
+                format!("Only numbers or a single array of numbers allowed in '{}' function", name),
+                line,
+            )),
+        })
+        .collect()
+}
 
-    Ok(make_number(max))
+fn no_numbers(name: &str, line: usize) -> RuntimeError {
+    RuntimeError::InvalidArgumentCount(
+        format!("Expected at least 1 number provided to native function '{}'", name),
+        line,
+    )
 }
 
-pub fn number(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
+pub fn number(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
     if args.len() != 1 {
         return Err(RuntimeError::InvalidArgumentCount(
             format!(
@@ -151,7 +828,69 @@ pub fn number(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeErr
     }
 }
 
-pub fn bool(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
+// `parse_int(s)` reads a base 10 integer, `parse_int(s, radix)` one in any
+// base from 2 to 36. Unlike `number` a string that is not a number gives nil
+// rather than an error. Surrounding whitespace and a leading sign are allowed.
+pub fn parse_int(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 1 | 2, found {} arguments provided to native function 'parse_int'",
+            args.len()
+        ), line));
+    }
+    let text = expect_string(&args[0], "first", "parse_int", line)?;
+    let radix = match args.get(1) {
+        Some(RuntimeVal::Number(radix)) if radix.fract() == 0.0 && (2.0..=36.0).contains(radix) => *radix as u32,
+        Some(_) => {
+            return Err(RuntimeError::TypeMismatch(
+                "Only an integer from 2 to 36 allowed as second argument in 'parse_int' function".to_string(),
+                line,
+            ));
+        }
+        None => 10,
+    };
+    let text = text.trim();
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if digits.is_empty() {
+        return Ok(make_nil());
+    }
+    let mut value = 0.0;
+    for c in digits.chars() {
+        match c.to_digit(radix) {
+            Some(digit) => value = value * radix as f64 + digit as f64,
+            None => return Ok(make_nil()),
+        }
+    }
+    Ok(make_number(sign * value))
+}
+
+// Reads a decimal number such as "3.14", "-2" or "1e-3", or gives nil
+pub fn parse_float(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "parse_float", line)?;
+    let text = expect_string(&args[0], "first", "parse_float", line)?;
+    // Rust also reads "inf" and "NaN", which are not Lox numbers
+    match text.trim().parse::<f64>() {
+        Ok(num) if num.is_finite() => Ok(make_number(num)),
+        _ => Ok(make_nil()),
+    }
+}
+
+pub fn bool(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
     if args.len() != 1 {
         return Err(RuntimeError::InvalidArgumentCount(
             format!(
@@ -187,7 +926,11 @@ pub fn bool(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError
     }
 }
 
-pub fn string(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
+pub fn string(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
     if args.len() != 1 {
         return Err(RuntimeError::InvalidArgumentCount(
             format!(
@@ -208,16 +951,22 @@ pub fn string(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeErr
             }
         }
         RuntimeVal::String(str) => Ok(make_string(&str[..])),
+        RuntimeVal::Bytes(bytes) => decoded_text(bytes.clone(), "string", line),
+        RuntimeVal::Instance { .. } => Ok(make_string(&interpreter.stringify(&args[0])?[..])),
         _ => {
             Err(RuntimeError::TypeMismatch(
-                "Only type number, bool and string allowed in 'string' function".to_string(),
+                "Only type number, bool, string, bytes and class instances allowed in 'string' function".to_string(),
                 line,
             ))
         }
     }
 }
 
-pub fn len(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
+pub fn len(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
     if args.len() != 1 {
         return Err(RuntimeError::InvalidArgumentCount(
             format!(
@@ -229,16 +978,23 @@ pub fn len(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError>
     }
 
     match &args[0] {
-        RuntimeVal::String(s) => Ok(make_number(s.len() as f64)),
+        RuntimeVal::String(s) => Ok(make_number(s.chars().count() as f64)),
         RuntimeVal::Array(arr) => Ok(make_number(arr.len() as f64)),
+        RuntimeVal::Bytes(bytes) => Ok(make_number(bytes.len() as f64)),
+        RuntimeVal::Map(map) => Ok(make_number(map.len() as f64)),
+        RuntimeVal::Set(set) => Ok(make_number(set.len() as f64)),
         _ => Err(RuntimeError::TypeMismatch(
-            "Only type string and array allowed in 'len' function".to_string(),
+            "Only type string, array, bytes, map and set allowed in 'len' function".to_string(),
             line,
         )),
     }
 }
 
-pub fn type_of(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
+pub fn type_of(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
     if args.len() != 1 {
         return Err(RuntimeError::InvalidArgumentCount(
             format!(
@@ -249,61 +1005,490 @@ pub fn type_of(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeEr
         ));
     }
 
+    // An instance is reported as its class
     match &args[0] {
-        RuntimeVal::Number(_) => Ok(make_string("Number")),
-        RuntimeVal::Bool(_) => Ok(make_string("Bool")),
-        RuntimeVal::Nil => Ok(make_string("Nil")),
-        RuntimeVal::String(_) => Ok(make_string("String")),
-        RuntimeVal::Object(_) => Ok(make_string("Object")),
-        RuntimeVal::Array(_) => Ok(make_string("Array")),
-        RuntimeVal::Function { .. } => Ok(make_string("Function")),
-        RuntimeVal::NativeFunction(_, _) => Ok(make_string("Native function")),
-        RuntimeVal::Method { .. } => Ok(make_string("Method")),
-        RuntimeVal::Class { .. } => Ok(make_string("Class")),
-        RuntimeVal::Instance { .. } => Ok(make_string("Instance")),
+        RuntimeVal::Instance { class_name, .. } => Ok(make_string(class_name)),
+        other => Ok(make_string(type_name(other))),
     }
 }
 
-pub fn reverse(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
-    if args.len() != 1 {
-        return Err(RuntimeError::InvalidArgumentCount(
-            format!(
-                "Expected 1, found {} arguments provided to native function 'reverse'",
-                args.len()
-            ),
+pub fn is_number(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "is_number", line)?;
+    Ok(make_bool(matches!(args[0], RuntimeVal::Number(_))))
+}
+
+pub fn is_string(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "is_string", line)?;
+    Ok(make_bool(matches!(args[0], RuntimeVal::String(_))))
+}
+
+pub fn is_bool(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "is_bool", line)?;
+    Ok(make_bool(matches!(args[0], RuntimeVal::Bool(_))))
+}
+
+pub fn is_array(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "is_array", line)?;
+    Ok(make_bool(matches!(args[0], RuntimeVal::Array(_))))
+}
+
+pub fn is_object(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "is_object", line)?;
+    Ok(make_bool(matches!(args[0], RuntimeVal::Object(_))))
+}
+
+pub fn is_nil(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "is_nil", line)?;
+    Ok(make_bool(matches!(args[0], RuntimeVal::Nil)))
+}
+
+// Functions, natives, methods and classes can all be called
+pub fn is_callable(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "is_callable", line)?;
+    Ok(make_bool(matches!(
+        args[0],
+        RuntimeVal::Function { .. }
+            | RuntimeVal::Overloaded { .. }
+            | RuntimeVal::NativeFunction(..)
+            | RuntimeVal::Method { .. }
+            | RuntimeVal::Class { .. }
+    )))
+}
+
+// `is_instance(value, Class)` is true for instances of the class and of
+// every class inheriting from it.
+pub fn is_instance(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "is_instance", line)?;
+    let RuntimeVal::Class { name: target, .. } = &args[1] else {
+        return Err(RuntimeError::TypeMismatch(
+            "Only type class allowed as second argument in 'is_instance' function".to_string(),
             line,
         ));
+    };
+    let RuntimeVal::Instance { class, .. } = &args[0] else {
+        return Ok(make_bool(false));
+    };
+    for class in interpreter.class_chain(RuntimeVal::clone(class), line)? {
+        if let RuntimeVal::Class { name, .. } = class
+            && name == *target
+        {
+            return Ok(make_bool(true));
+        }
     }
+    Ok(make_bool(false))
+}
 
-    match &args[0] {
-        RuntimeVal::String(s) => Ok(make_string(&s.chars().rev().collect::<String>()[..])),
-        RuntimeVal::Array(arr) => Ok(make_arr(&arr.clone().into_iter().rev().collect())),
-        _ => Err(RuntimeError::TypeMismatch(
-            "Only type string allowed in 'reverse' function".to_string(),
+// `implements(value, Interface)` is true for classes, and instances of
+// classes, that have every method the interface requires, whether or not
+// they list it.
+pub fn implements(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "implements", line)?;
+    let RuntimeVal::Interface(interface) = &args[1] else {
+        return Err(RuntimeError::TypeMismatch(
+            "Only type interface allowed as second argument in 'implements' function".to_string(),
             line,
-        )),
-    }
+        ));
+    };
+    let class = match &args[0] {
+        RuntimeVal::Class { .. } => args[0].clone(),
+        RuntimeVal::Instance { class, .. } => RuntimeVal::clone(class),
+        _ => return Ok(make_bool(false)),
+    };
+    let chain = interpreter.class_chain(class, line)?;
+    Ok(make_bool(missing_method(&chain, interface).is_none()))
 }
 
-pub fn append(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
-    if args.len() < 2 || args.len() > 3 {
-        return Err(RuntimeError::InvalidArgumentCount(format!(
-            "Expected 2 | 3, found {} arguments provided to native function 'append'",
-            args.len()
-        ), line));
+// Names of the methods instances of a class can call, its own and
+// inherited ones, ordered by name. Static methods are not included.
+pub fn methods_of(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "methods_of", line)?;
+    if !matches!(args[0], RuntimeVal::Class { .. }) {
+        return Err(RuntimeError::TypeMismatch(
+            "Only type class allowed as argument in 'methods_of' function".to_string(),
+            line,
+        ));
     }
-    let mut array = match &args[0] {
-        RuntimeVal::Array(arr) => arr.clone(),
-        _ => return Err(RuntimeError::TypeMismatch(
-            "Only type array allowed as first argument in 'append' function".to_string(),
+    let mut names = vec![];
+    for class in interpreter.class_chain(args[0].clone(), line)? {
+        if let RuntimeVal::Class { methods, .. } = class {
+            names.extend(methods.into_keys());
+        }
+    }
+    names.sort();
+    names.dedup();
+    Ok(RuntimeVal::Array(names.iter().map(|name| make_string(name)).collect()))
+}
+
+// Names of an instance's fields, ordered by name
+pub fn fields_of(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "fields_of", line)?;
+    let RuntimeVal::Instance { instance_env, .. } = &args[0] else {
+        return Err(RuntimeError::TypeMismatch(
+            "Only class instances allowed as argument in 'fields_of' function".to_string(),
             line,
-        )),
+        ));
     };
-    
-    let val = &args[1];
-    
-    let position;
-    if args.len() == 2 {
+    let mut names: Vec<Rc<str>> = own_vars(instance_env).into_iter().map(|(name, _)| name).collect();
+    names.sort();
+    Ok(RuntimeVal::Array(names.iter().map(|name| make_string(name)).collect()))
+}
+
+// The class an instance was made from, or nil for any other value
+pub fn class_of(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "class_of", line)?;
+    let RuntimeVal::Instance { class, .. } = &args[0] else {
+        return Ok(make_nil());
+    };
+    Ok(RuntimeVal::clone(class))
+}
+
+// The class a class inherits from, or nil if it has none
+pub fn superclass_of(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "superclass_of", line)?;
+    if !matches!(args[0], RuntimeVal::Class { .. }) {
+        return Err(RuntimeError::TypeMismatch(
+            "Only type class allowed as argument in 'superclass_of' function".to_string(),
+            line,
+        ));
+    }
+    let chain = interpreter.class_chain(args[0].clone(), line)?;
+    Ok(chain.into_iter().nth(1).unwrap_or_else(make_nil))
+}
+
+// `weak_ref(instance)` refers to an instance without keeping it alive.
+// `deref(weak)` gives the instance back, or nil once it has been dropped.
+pub fn weak_ref(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "weak_ref", line)?;
+    let RuntimeVal::Instance { class_name, class, instance_env } = &args[0] else {
+        return Err(RuntimeError::TypeMismatch(
+            "Only class instances allowed as argument in 'weak_ref' function".to_string(),
+            line,
+        ));
+    };
+    Ok(RuntimeVal::WeakRef {
+        class_name: class_name.clone(),
+        class: Rc::clone(class),
+        instance_env: Rc::downgrade(instance_env),
+    })
+}
+
+pub fn deref(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "deref", line)?;
+    let RuntimeVal::WeakRef { class_name, class, instance_env } = &args[0] else {
+        return Err(RuntimeError::TypeMismatch(
+            "Only weak references allowed as argument in 'deref' function".to_string(),
+            line,
+        ));
+    };
+    Ok(match instance_env.upgrade() {
+        Some(instance_env) => make_instance(class_name, Rc::clone(class), instance_env),
+        None => make_nil(),
+    })
+}
+
+pub fn ord(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "ord", line)?;
+    let text = expect_string(&args[0], "first", "ord", line)?;
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(make_number(c as u32 as f64)),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only a single character allowed as first argument in 'ord' function, found '{}'", text),
+            line,
+        )),
+    }
+}
+
+pub fn chr(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "chr", line)?;
+    let code = expect_integer(&args[0], "first", "chr", line)?;
+    // Surrogates and numbers past U+10FFFF are not characters
+    match char::from_u32(code as u32).filter(|_| (0.0..=u32::MAX as f64).contains(&code)) {
+        Some(c) => Ok(make_string(&c.to_string())),
+        None => Err(RuntimeError::TypeCastingError(
+            format!("{} is not the code of a character", code),
+            line,
+        )),
+    }
+}
+
+// The classification natives are true when the string is not empty and
+// every character in it belongs to the class.
+pub fn is_digit(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    classify(args, "is_digit", line, |c| c.is_ascii_digit())
+}
+
+pub fn is_alpha(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    classify(args, "is_alpha", line, char::is_alphabetic)
+}
+
+pub fn is_space(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    classify(args, "is_space", line, char::is_whitespace)
+}
+
+fn classify(args: &[RuntimeVal], name: &str, line: usize, class: fn(char) -> bool) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, name, line)?;
+    let text = expect_string(&args[0], "first", name, line)?;
+    Ok(make_bool(!text.is_empty() && text.chars().all(class)))
+}
+
+// Returns the character index of the first occurrence of the substring at or
+// after the optional start index, or -1 when there is none.
+pub fn find(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 2 | 3, found {} arguments provided to native function 'find'",
+            args.len()
+        ), line));
+    }
+    let text = expect_string(&args[0], "first", "find", line)?;
+    let sub = expect_string(&args[1], "second", "find", line)?;
+    let start = match args.get(2) {
+        Some(from) => clamp_index(expect_integer(from, "third", "find", line)?, text.chars().count()),
+        None => 0,
+    };
+    // Indices count characters, as they do when indexing a string
+    let offset = text.char_indices().nth(start).map_or(text.len(), |(offset, _)| offset);
+    match text[offset..].find(sub) {
+        Some(found) => Ok(make_number((start + text[offset..offset + found].chars().count()) as f64)),
+        None => Ok(make_number(-1.0)),
+    }
+}
+
+// Counts the occurrences of the substring that do not overlap.
+pub fn count(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "count", line)?;
+    let text = expect_string(&args[0], "first", "count", line)?;
+    let sub = expect_string(&args[1], "second", "count", line)?;
+    if sub.is_empty() {
+        return Err(RuntimeError::TypeMismatch(
+            "Cannot count occurrences of the empty string in 'count' function".to_string(),
+            line,
+        ));
+    }
+    Ok(make_number(text.matches(sub).count() as f64))
+}
+
+pub fn pad_left(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    pad(args, "pad_left", line, true)
+}
+
+pub fn pad_right(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    pad(args, "pad_right", line, false)
+}
+
+// Fills a string up to the given width in characters with the pad character,
+// a space by default. Strings already that wide are returned unchanged.
+fn pad(args: &[RuntimeVal], name: &str, line: usize, left: bool) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() != 2 && args.len() != 3 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 2 | 3, found {} arguments provided to native function '{}'",
+            args.len(),
+            name
+        ), line));
+    }
+    let text = expect_string(&args[0], "first", name, line)?;
+    let width = expect_integer(&args[1], "second", name, line)?;
+    let fill = match args.get(2) {
+        Some(fill) => {
+            let fill = expect_string(fill, "third", name, line)?;
+            let mut chars = fill.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(RuntimeError::TypeMismatch(
+                    format!("Only a single character allowed as third argument in '{}' function, found '{}'", name, fill),
+                    line,
+                )),
+            }
+        }
+        None => ' ',
+    };
+    let missing = (width.max(0.0) as usize).saturating_sub(text.chars().count());
+    check_string_length(missing.checked_mul(fill.len_utf8()).and_then(|bytes| bytes.checked_add(text.len())), name, line)?;
+    let padding: String = std::iter::repeat_n(fill, missing).collect();
+    if left {
+        Ok(make_string(&format!("{}{}", padding, text)))
+    } else {
+        Ok(make_string(&format!("{}{}", text, padding)))
+    }
+}
+
+pub fn repeat(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "repeat", line)?;
+    let text = expect_string(&args[0], "first", "repeat", line)?;
+    let times = expect_integer(&args[1], "second", "repeat", line)?;
+    if times < 0.0 {
+        return Err(RuntimeError::TypeMismatch(
+            format!("Cannot repeat a string {} times in 'repeat' function", times),
+            line,
+        ));
+    }
+    check_string_length(text.len().checked_mul(times as usize), "repeat", line)?;
+    Ok(make_string(&text.repeat(times as usize)))
+}
+
+// Strings natives build are kept to this many bytes, so a mistaken count
+// gives an error instead of exhausting memory
+const MAX_STRING_LENGTH: usize = 1 << 30;
+
+fn check_string_length(length: Option<usize>, name: &str, line: usize) -> Result<(), RuntimeError> {
+    match length {
+        Some(length) if length <= MAX_STRING_LENGTH => Ok(()),
+        _ => Err(RuntimeError::InvalidCall(
+            format!("The string '{}' would build is longer than {} bytes", name, MAX_STRING_LENGTH),
+            line,
+        )),
+    }
+}
+
+pub fn reverse(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() != 1 {
+        return Err(RuntimeError::InvalidArgumentCount(
+            format!(
+                "Expected 1, found {} arguments provided to native function 'reverse'",
+                args.len()
+            ),
+            line,
+        ));
+    }
+
+    match &args[0] {
+        RuntimeVal::String(s) => Ok(make_string(&s.chars().rev().collect::<String>()[..])),
+        RuntimeVal::Array(arr) => Ok(make_arr(&arr.clone().into_iter().rev().collect())),
+        _ => Err(RuntimeError::TypeMismatch(
+            "Only type string allowed in 'reverse' function".to_string(),
+            line,
+        )),
+    }
+}
+
+pub fn append(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 2 | 3, found {} arguments provided to native function 'append'",
+            args.len()
+        ), line));
+    }
+    let mut array = match &args[0] {
+        RuntimeVal::Array(arr) => arr.clone(),
+        _ => return Err(RuntimeError::TypeMismatch(
+            "Only type array allowed as first argument in 'append' function".to_string(),
+            line,
+        )),
+    };
+    
+    let val = &args[1];
+    
+    let position;
+    if args.len() == 2 {
         position = array.len() - 1;
     } else {
         match &args[2] {
@@ -324,17 +1509,43 @@ pub fn append(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeErr
     Ok(RuntimeVal::Array(array))
 }
 
-pub fn remove(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeError> {
+pub fn remove(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
     if args.len() < 1 || args.len() > 2 {
         return Err(RuntimeError::InvalidArgumentCount(format!(
             "Expected 2 | 3, found {} arguments provided to native function 'remove'",
             args.len()
         ), line));
     }
+    if let RuntimeVal::Map(map) = &args[0] {
+        if args.len() != 2 {
+            return Err(RuntimeError::InvalidArgumentCount(
+                "Expected 2 arguments provided to native function 'remove' when removing from a map".to_string(),
+                line,
+            ));
+        }
+        let mut map = map.clone();
+        map.remove(&map_key(&args[1], line)?);
+        return Ok(RuntimeVal::Map(map));
+    }
+    if let RuntimeVal::Set(set) = &args[0] {
+        if args.len() != 2 {
+            return Err(RuntimeError::InvalidArgumentCount(
+                "Expected 2 arguments provided to native function 'remove' when removing from a set".to_string(),
+                line,
+            ));
+        }
+        let mut set = set.clone();
+        set.remove(&map_key(&args[1], line)?);
+        return Ok(RuntimeVal::Set(set));
+    }
     let mut array = match &args[0] {
         RuntimeVal::Array(arr) => arr.clone(),
         _ => return Err(RuntimeError::TypeMismatch(
-            "Only type array allowed as first argument in 'remove' function".to_string(),
+            "Only type array, map and set allowed as first argument in 'remove' function".to_string(),
             line,
         )),
     };
@@ -360,3 +1571,1429 @@ pub fn remove(args: &[RuntimeVal], line: usize) -> Result<RuntimeVal, RuntimeErr
     array.remove(position);
     Ok(RuntimeVal::Array(array))
 }
+
+pub fn range(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 2 | 3, found {} arguments provided to native function 'range'",
+            args.len()
+        ), line));
+    }
+    let mut bounds = vec![];
+    for arg in args {
+        match arg {
+            RuntimeVal::Number(num) => bounds.push(*num),
+            _ => return Err(RuntimeError::TypeMismatch(
+                "Only type number allowed as arguments in 'range' function".to_string(),
+                line,
+            )),
+        }
+    }
+    let (start, end) = (bounds[0], bounds[1]);
+    let step = if bounds.len() == 3 { bounds[2] } else { 1.0 };
+    if step == 0.0 {
+        return Err(RuntimeError::TypeMismatch(
+            "The step of 'range' cannot be 0".to_string(),
+            line,
+        ));
+    }
+
+    let mut array = vec![];
+    let mut i = start;
+    while (step > 0.0 && i < end) || (step < 0.0 && i > end) {
+        array.push(make_number(i));
+        i += step;
+    }
+    Ok(RuntimeVal::Array(array))
+}
+
+// Object keys are returned sorted so enumeration order is stable between runs.
+fn sorted_keys(obj: &HashMap<String, RuntimeVal>) -> Vec<&String> {
+    let mut keys: Vec<&String> = obj.keys().collect();
+    keys.sort();
+    keys
+}
+
+fn expect_object<'a>(
+    arg: &'a RuntimeVal,
+    position: &str,
+    name: &str,
+    line: usize,
+) -> Result<&'a HashMap<String, RuntimeVal>, RuntimeError> {
+    match arg {
+        RuntimeVal::Object(obj) => Ok(obj),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type object allowed as {} argument in '{}' function", position, name),
+            line,
+        )),
+    }
+}
+
+fn expect_key<'a>(arg: &'a RuntimeVal, name: &str, line: usize) -> Result<&'a str, RuntimeError> {
+    match arg {
+        RuntimeVal::String(key) => Ok(key),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type string allowed as second argument in '{}' function", name),
+            line,
+        )),
+    }
+}
+
+fn check_arg_count(args: &[RuntimeVal], expected: usize, name: &str, line: usize) -> Result<(), RuntimeError> {
+    if args.len() != expected {
+        return Err(RuntimeError::InvalidArgumentCount(
+            format!(
+                "Expected {}, found {} arguments provided to native function '{}'",
+                expected,
+                args.len(),
+                name
+            ),
+            line,
+        ));
+    }
+    Ok(())
+}
+
+// The (key, value) pairs of an object or map, ordered by key.
+fn key_value_pairs(arg: &RuntimeVal, name: &str, line: usize) -> Result<Vec<(RuntimeVal, RuntimeVal)>, RuntimeError> {
+    match arg {
+        RuntimeVal::Object(obj) => Ok(sorted_keys(obj)
+            .into_iter()
+            .map(|key| (make_string(key), obj[key].clone()))
+            .collect()),
+        RuntimeVal::Map(map) => Ok(sorted_map_keys(map)
+            .into_iter()
+            .map(|key| (key.to_value(), map[key].clone()))
+            .collect()),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type object and map allowed as first argument in '{}' function", name),
+            line,
+        )),
+    }
+}
+
+pub fn keys(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "keys", line)?;
+    let pairs = key_value_pairs(&args[0], "keys", line)?;
+    Ok(RuntimeVal::Array(pairs.into_iter().map(|(key, _)| key).collect()))
+}
+
+pub fn values(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "values", line)?;
+    let pairs = key_value_pairs(&args[0], "values", line)?;
+    Ok(RuntimeVal::Array(pairs.into_iter().map(|(_, value)| value).collect()))
+}
+
+pub fn entries(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "entries", line)?;
+    let pairs = key_value_pairs(&args[0], "entries", line)?;
+    Ok(RuntimeVal::Array(
+        pairs
+            .into_iter()
+            .map(|(key, value)| RuntimeVal::Array(vec![key, value]))
+            .collect(),
+    ))
+}
+
+pub fn has(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "has", line)?;
+    match &args[0] {
+        RuntimeVal::Map(map) => return Ok(make_bool(map.contains_key(&map_key(&args[1], line)?))),
+        RuntimeVal::Set(set) => return Ok(make_bool(set.contains(&map_key(&args[1], line)?))),
+        _ => {}
+    }
+    let obj = expect_object(&args[0], "first", "has", line)?;
+    let key = expect_key(&args[1], "has", line)?;
+    Ok(make_bool(obj.contains_key(key)))
+}
+
+pub fn delete(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "delete", line)?;
+    let mut obj = expect_object(&args[0], "first", "delete", line)?.clone();
+    let key = expect_key(&args[1], "delete", line)?;
+    obj.remove(key);
+    Ok(RuntimeVal::Object(obj))
+}
+
+pub fn merge(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "merge", line)?;
+    let mut obj = expect_object(&args[0], "first", "merge", line)?.clone();
+    let other = expect_object(&args[1], "second", "merge", line)?;
+    for (key, value) in other {
+        obj.insert(key.clone(), value.clone());
+    }
+    Ok(RuntimeVal::Object(obj))
+}
+
+fn expect_array<'a>(
+    arg: &'a RuntimeVal,
+    position: &str,
+    name: &str,
+    line: usize,
+) -> Result<&'a Vec<RuntimeVal>, RuntimeError> {
+    match arg {
+        RuntimeVal::Array(arr) => Ok(arr),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type array allowed as {} argument in '{}' function", position, name),
+            line,
+        )),
+    }
+}
+
+fn expect_string<'a>(
+    arg: &'a RuntimeVal,
+    position: &str,
+    name: &str,
+    line: usize,
+) -> Result<&'a str, RuntimeError> {
+    match arg {
+        RuntimeVal::String(s) => Ok(s),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type string allowed as {} argument in '{}' function", position, name),
+            line,
+        )),
+    }
+}
+
+fn expect_integer(arg: &RuntimeVal, position: &str, name: &str, line: usize) -> Result<f64, RuntimeError> {
+    match arg {
+        RuntimeVal::Number(num) if num.fract() == 0.0 => Ok(*num),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only integers allowed as {} argument in '{}' function", position, name),
+            line,
+        )),
+    }
+}
+
+// Turns a possibly negative index into a position within 0..=len, counting
+// negative indices from the end.
+fn clamp_index(index: f64, len: usize) -> usize {
+    if index < 0.0 {
+        (len as f64 + index).max(0.0) as usize
+    } else {
+        (index as usize).min(len)
+    }
+}
+
+// Numbers, bools, strings and nil compare by value; nothing else is equal.
+fn same_value(left: &RuntimeVal, right: &RuntimeVal) -> bool {
+    match (left, right) {
+        (RuntimeVal::Number(a), RuntimeVal::Number(b)) => a == b,
+        (RuntimeVal::Bool(a), RuntimeVal::Bool(b)) => a == b,
+        (RuntimeVal::String(a), RuntimeVal::String(b)) => a == b,
+        (RuntimeVal::Nil, RuntimeVal::Nil) => true,
+        _ => false,
+    }
+}
+
+pub fn slice(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 2 | 3, found {} arguments provided to native function 'slice'",
+            args.len()
+        ), line));
+    }
+    if !matches!(args[0], RuntimeVal::Array(_) | RuntimeVal::String(_) | RuntimeVal::Bytes(_)) {
+        return Err(RuntimeError::TypeMismatch(
+            "Only type array, string and bytes allowed as first argument in 'slice' function".to_string(),
+            line,
+        ));
+    }
+    let start = expect_integer(&args[1], "second", "slice", line)?;
+    let end = match args.get(2) {
+        Some(end) => Some(expect_integer(end, "third", "slice", line)?),
+        None => None,
+    };
+    slice_between(&args[0], start, end).ok_or(RuntimeError::InternalError)
+}
+
+/// The part of an array, string or bytes from `start` up to `end` (or its end),
+/// with negative indices counting from the end. `None` for other values.
+pub fn slice_between(value: &RuntimeVal, start: f64, end: Option<f64>) -> Option<RuntimeVal> {
+    let len = match value {
+        RuntimeVal::Array(arr) => arr.len(),
+        RuntimeVal::String(s) => s.chars().count(),
+        RuntimeVal::Bytes(bytes) => bytes.len(),
+        _ => return None,
+    };
+    let start = clamp_index(start, len);
+    let end = end.map_or(len, |end| clamp_index(end, len)).max(start);
+    match value {
+        RuntimeVal::Array(arr) => Some(RuntimeVal::Array(arr[start..end].to_vec())),
+        RuntimeVal::String(s) => {
+            let sliced: String = s.chars().skip(start).take(end - start).collect();
+            Some(make_string(&sliced))
+        }
+        RuntimeVal::Bytes(bytes) => Some(RuntimeVal::Bytes(bytes[start..end].to_vec())),
+        _ => None,
+    }
+}
+
+pub fn concat(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    // Bytes join into bytes, anything else has to be arrays
+    if let Some(RuntimeVal::Bytes(_)) = args.first() {
+        let mut joined = vec![];
+        for arg in args {
+            joined.extend_from_slice(expect_bytes(arg, "every", "concat", line)?);
+        }
+        return Ok(RuntimeVal::Bytes(joined));
+    }
+    let mut array = vec![];
+    for arg in args {
+        array.extend(expect_array(arg, "every", "concat", line)?.iter().cloned());
+    }
+    Ok(RuntimeVal::Array(array))
+}
+
+pub fn index_of(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "index_of", line)?;
+    let array = expect_array(&args[0], "first", "index_of", line)?;
+    match array.iter().position(|val| same_value(val, &args[1])) {
+        Some(index) => Ok(make_number(index as f64)),
+        None => Ok(make_number(-1.0)),
+    }
+}
+
+pub fn contains(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "contains", line)?;
+    let array = expect_array(&args[0], "first", "contains", line)?;
+    Ok(make_bool(array.iter().any(|val| same_value(val, &args[1]))))
+}
+
+pub fn fill(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() < 2 || args.len() > 4 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 2 | 3 | 4, found {} arguments provided to native function 'fill'",
+            args.len()
+        ), line));
+    }
+    let mut array = expect_array(&args[0], "first", "fill", line)?.clone();
+    let len = array.len();
+    let start = match args.get(2) {
+        Some(start) => clamp_index(expect_integer(start, "third", "fill", line)?, len),
+        None => 0,
+    };
+    let end = match args.get(3) {
+        Some(end) => clamp_index(expect_integer(end, "fourth", "fill", line)?, len),
+        None => len,
+    };
+    for val in array.iter_mut().take(end).skip(start) {
+        *val = args[1].clone();
+    }
+    Ok(RuntimeVal::Array(array))
+}
+
+pub fn flatten(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "flatten", line)?;
+    let mut array = vec![];
+    for val in expect_array(&args[0], "first", "flatten", line)? {
+        match val {
+            RuntimeVal::Array(inner) => array.extend(inner.iter().cloned()),
+            _ => array.push(val.clone()),
+        }
+    }
+    Ok(RuntimeVal::Array(array))
+}
+
+// Pairs each element with its index, `[[0, a], [1, b], ...]`, for loops
+// such as `for i, x in enumerate(arr)`
+pub fn enumerate(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "enumerate", line)?;
+    let array = expect_array(&args[0], "first", "enumerate", line)?;
+    Ok(RuntimeVal::Array(
+        array
+            .iter()
+            .enumerate()
+            .map(|(index, element)| RuntimeVal::Array(vec![make_number(index as f64), element.clone()]))
+            .collect(),
+    ))
+}
+
+pub fn zip(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "zip", line)?;
+    let first = expect_array(&args[0], "first", "zip", line)?;
+    let second = expect_array(&args[1], "second", "zip", line)?;
+    Ok(RuntimeVal::Array(
+        first
+            .iter()
+            .zip(second.iter())
+            .map(|(a, b)| RuntimeVal::Array(vec![a.clone(), b.clone()]))
+            .collect(),
+    ))
+}
+
+// Orders two sort keys; only numbers with numbers and strings with strings
+// can be compared.
+fn compare_keys(left: &RuntimeVal, right: &RuntimeVal, name: &str, line: usize) -> Result<Ordering, RuntimeError> {
+    match (left, right) {
+        (RuntimeVal::Number(a), RuntimeVal::Number(b)) => Ok(a.partial_cmp(b).unwrap_or(Ordering::Equal)),
+        (RuntimeVal::String(a), RuntimeVal::String(b)) => Ok(a.cmp(b)),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("'{}' can only order numbers with numbers or strings with strings", name),
+            line,
+        )),
+    }
+}
+
+// Stable merge sort whose comparison may fail, so errors raised by Lox
+// callbacks stop the sort instead of being swallowed.
+fn merge_sort<T: Clone>(
+    items: Vec<T>,
+    compare: &mut dyn FnMut(&T, &T) -> Result<Ordering, RuntimeError>,
+) -> Result<Vec<T>, RuntimeError> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let left = merge_sort(left, compare)?;
+    let right = merge_sort(right, compare)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        if compare(&right[j], &left[i])? == Ordering::Less {
+            merged.push(right[j].clone());
+            j += 1;
+        } else {
+            merged.push(left[i].clone());
+            i += 1;
+        }
+    }
+    merged.extend_from_slice(&left[i..]);
+    merged.extend_from_slice(&right[j..]);
+    Ok(merged)
+}
+
+pub fn sort(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "sort", line)?;
+    let array = expect_array(&args[0], "first", "sort", line)?.clone();
+    let sorted = merge_sort(array, &mut |a, b| compare_keys(a, b, "sort", line))?;
+    Ok(RuntimeVal::Array(sorted))
+}
+
+pub fn sort_by(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "sort_by", line)?;
+    let array = expect_array(&args[0], "first", "sort_by", line)?.clone();
+    let callback = args[1].clone();
+    let parameters = match &callback {
+        RuntimeVal::Function { declaration, .. } | RuntimeVal::Method { declaration, .. } => {
+            declaration.parameters.len()
+        }
+        // An overload taking two parameters makes it a comparator
+        RuntimeVal::Overloaded { declarations, .. } => {
+            if declarations.iter().any(|declaration| declaration.parameters.len() == 2) { 2 } else { 1 }
+        }
+        RuntimeVal::NativeFunction(..) => 1,
+        _ => {
+            return Err(RuntimeError::TypeMismatch(
+                "Only functions allowed as second argument in 'sort_by' function".to_string(),
+                line,
+            ));
+        }
+    };
+
+    if parameters == 2 {
+        // A comparator returns a negative number, zero or a positive number.
+        let sorted = merge_sort(array, &mut |a, b| {
+            match interpreter.call(callback.clone(), &[a.clone(), b.clone()], line)? {
+                RuntimeVal::Number(num) => Ok(num.partial_cmp(&0.0).unwrap_or(Ordering::Equal)),
+                _ => Err(RuntimeError::TypeMismatch(
+                    "The comparator given to 'sort_by' must return a number".to_string(),
+                    line,
+                )),
+            }
+        })?;
+        return Ok(RuntimeVal::Array(sorted));
+    }
+
+    // Otherwise the callback maps each element to the key it is sorted by.
+    let mut keyed = vec![];
+    for val in array {
+        let key = interpreter.call(callback.clone(), std::slice::from_ref(&val), line)?;
+        keyed.push((key, val));
+    }
+    let sorted = merge_sort(keyed, &mut |a, b| compare_keys(&a.0, &b.0, "sort_by", line))?;
+    Ok(RuntimeVal::Array(sorted.into_iter().map(|(_, val)| val).collect()))
+}
+
+fn expect_map<'a>(
+    arg: &'a RuntimeVal,
+    name: &str,
+    line: usize,
+) -> Result<&'a HashMap<MapKey, RuntimeVal>, RuntimeError> {
+    match arg {
+        RuntimeVal::Map(map) => Ok(map),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type map allowed as first argument in '{}' function", name),
+            line,
+        )),
+    }
+}
+
+pub fn map(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() > 1 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 0 | 1, found {} arguments provided to native function 'map'",
+            args.len()
+        ), line));
+    }
+    let mut map = HashMap::new();
+    if let Some(pairs) = args.first() {
+        for pair in expect_array(pairs, "first", "map", line)? {
+            match pair {
+                RuntimeVal::Array(pair) if pair.len() == 2 => {
+                    map.insert(map_key(&pair[0], line)?, pair[1].clone());
+                }
+                _ => {
+                    return Err(RuntimeError::TypeMismatch(
+                        "'map' expects an array of [key, value] pairs".to_string(),
+                        line,
+                    ));
+                }
+            }
+        }
+    }
+    Ok(RuntimeVal::Map(map))
+}
+
+pub fn get(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 2 | 3, found {} arguments provided to native function 'get'",
+            args.len()
+        ), line));
+    }
+    let map = expect_map(&args[0], "get", line)?;
+    match map.get(&map_key(&args[1], line)?) {
+        Some(value) => Ok(value.clone()),
+        None => Ok(args.get(2).cloned().unwrap_or(RuntimeVal::Nil)),
+    }
+}
+
+// `set()` and `set(array)` create a set, `set(map, key, value)` updates a map.
+pub fn set(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() <= 1 {
+        let mut set = HashSet::new();
+        if let Some(elements) = args.first() {
+            for element in expect_array(elements, "first", "set", line)? {
+                set.insert(map_key(element, line)?);
+            }
+        }
+        return Ok(RuntimeVal::Set(set));
+    }
+    if args.len() != 3 {
+        return Err(RuntimeError::InvalidArgumentCount(format!(
+            "Expected 0 | 1 | 3, found {} arguments provided to native function 'set'",
+            args.len()
+        ), line));
+    }
+    let mut map = expect_map(&args[0], "set", line)?.clone();
+    map.insert(map_key(&args[1], line)?, args[2].clone());
+    Ok(RuntimeVal::Map(map))
+}
+
+pub fn size(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "size", line)?;
+    if let RuntimeVal::Set(set) = &args[0] {
+        return Ok(make_number(set.len() as f64));
+    }
+    Ok(make_number(expect_map(&args[0], "size", line)?.len() as f64))
+}
+
+fn expect_set<'a>(
+    arg: &'a RuntimeVal,
+    position: &str,
+    name: &str,
+    line: usize,
+) -> Result<&'a HashSet<MapKey>, RuntimeError> {
+    match arg {
+        RuntimeVal::Set(set) => Ok(set),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type set allowed as {} argument in '{}' function", position, name),
+            line,
+        )),
+    }
+}
+
+pub fn add(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "add", line)?;
+    let mut set = expect_set(&args[0], "first", "add", line)?.clone();
+    set.insert(map_key(&args[1], line)?);
+    Ok(RuntimeVal::Set(set))
+}
+
+pub fn union(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "union", line)?;
+    let first = expect_set(&args[0], "first", "union", line)?;
+    let second = expect_set(&args[1], "second", "union", line)?;
+    Ok(RuntimeVal::Set(first.union(second).cloned().collect()))
+}
+
+pub fn intersect(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "intersect", line)?;
+    let first = expect_set(&args[0], "first", "intersect", line)?;
+    let second = expect_set(&args[1], "second", "intersect", line)?;
+    Ok(RuntimeVal::Set(first.intersection(second).cloned().collect()))
+}
+
+pub fn difference(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "difference", line)?;
+    let first = expect_set(&args[0], "first", "difference", line)?;
+    let second = expect_set(&args[1], "second", "difference", line)?;
+    Ok(RuntimeVal::Set(first.difference(second).cloned().collect()))
+}
+
+// Sets give their elements in order, generators run until they finish.
+pub fn to_array(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "to_array", line)?;
+    if let RuntimeVal::Generator(generator) = &args[0] {
+        let mut elements = vec![];
+        while let Some(element) = interpreter.resume_generator(generator, line)? {
+            elements.push(element);
+        }
+        return Ok(RuntimeVal::Array(elements));
+    }
+    if let RuntimeVal::Bytes(bytes) = &args[0] {
+        return Ok(RuntimeVal::Array(bytes.iter().map(|byte| make_number(*byte as f64)).collect()));
+    }
+    let set = expect_set(&args[0], "first", "to_array", line)?;
+    Ok(RuntimeVal::Array(
+        sorted_set_elements(set).into_iter().map(MapKey::to_value).collect(),
+    ))
+}
+
+// `bytes("text")` holds the UTF-8 encoding of the text, `bytes([72, 105])`
+// the given byte values.
+pub fn bytes(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "bytes", line)?;
+    match &args[0] {
+        RuntimeVal::String(s) => Ok(RuntimeVal::Bytes(s.as_bytes().to_vec())),
+        RuntimeVal::Bytes(bytes) => Ok(RuntimeVal::Bytes(bytes.clone())),
+        RuntimeVal::Array(arr) => {
+            let mut bytes = vec![];
+            for element in arr {
+                match element {
+                    RuntimeVal::Number(num) if num.fract() == 0.0 && (0.0..=255.0).contains(num) => {
+                        bytes.push(*num as u8)
+                    }
+                    _ => {
+                        return Err(RuntimeError::TypeMismatch(
+                            "Only integers from 0 to 255 allowed in the array given to 'bytes' function".to_string(),
+                            line,
+                        ));
+                    }
+                }
+            }
+            Ok(RuntimeVal::Bytes(bytes))
+        }
+        _ => Err(RuntimeError::TypeMismatch(
+            "Only type string, bytes and array allowed as first argument in 'bytes' function".to_string(),
+            line,
+        )),
+    }
+}
+
+pub fn read_file_bytes(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "read_file_bytes", line)?;
+    let path = expect_string(&args[0], "first", "read_file_bytes", line)?;
+    let bytes = fs::read(path)
+        .map_err(|e| RuntimeError::IoError(format!("Failed to read '{}': {}", path, e), line))?;
+    Ok(RuntimeVal::Bytes(bytes))
+}
+
+pub fn write_file_bytes(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "write_file_bytes", line)?;
+    let path = expect_string(&args[0], "first", "write_file_bytes", line)?;
+    let bytes = expect_bytes(&args[1], "second", "write_file_bytes", line)?;
+    fs::write(path, bytes)
+        .map_err(|e| RuntimeError::IoError(format!("Failed to write '{}': {}", path, e), line))?;
+    Ok(make_nil())
+}
+
+fn expect_bytes<'a>(
+    arg: &'a RuntimeVal,
+    position: &str,
+    name: &str,
+    line: usize,
+) -> Result<&'a [u8], RuntimeError> {
+    match arg {
+        RuntimeVal::Bytes(bytes) => Ok(bytes),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type bytes allowed as {} argument in '{}' function", position, name),
+            line,
+        )),
+    }
+}
+
+pub fn base64_encode(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "base64_encode", line)?;
+    let data = expect_data(&args[0], "base64_encode", line)?;
+    Ok(make_string(&encoding::base64_encode(data)))
+}
+
+pub fn base64_decode(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "base64_decode", line)?;
+    let text = expect_string(&args[0], "first", "base64_decode", line)?;
+    let data = encoding::base64_decode(text)
+        .ok_or_else(|| decode_error(text, "valid base64", line))?;
+    decoded_text(data, "base64_decode", line)
+}
+
+pub fn hex_encode(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "hex_encode", line)?;
+    let data = expect_data(&args[0], "hex_encode", line)?;
+    Ok(make_string(&encoding::hex_encode(data)))
+}
+
+pub fn hex_decode(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "hex_decode", line)?;
+    let text = expect_string(&args[0], "first", "hex_decode", line)?;
+    let data = encoding::hex_decode(text)
+        .ok_or_else(|| decode_error(text, "valid hex", line))?;
+    decoded_text(data, "hex_decode", line)
+}
+
+// Strings are encoded and hashed as their UTF-8 bytes
+fn expect_data<'a>(arg: &'a RuntimeVal, name: &str, line: usize) -> Result<&'a [u8], RuntimeError> {
+    match arg {
+        RuntimeVal::String(s) => Ok(s.as_bytes()),
+        RuntimeVal::Bytes(bytes) => Ok(bytes),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type string and bytes allowed as first argument in '{}' function", name),
+            line,
+        )),
+    }
+}
+
+fn decode_error(text: &str, expected: &str, line: usize) -> RuntimeError {
+    RuntimeError::TypeCastingError(format!("'{}' is not {}", text, expected), line)
+}
+
+// Strings hold text, so decoded bytes have to be valid UTF-8
+fn decoded_text(data: Vec<u8>, name: &str, line: usize) -> Result<RuntimeVal, RuntimeError> {
+    match String::from_utf8(data) {
+        Ok(text) => Ok(make_string(&text)),
+        Err(_) => Err(RuntimeError::TypeCastingError(
+            format!("The data decoded by '{}' is not valid UTF-8 text", name),
+            line,
+        )),
+    }
+}
+
+// Digests are returned as lowercase hex
+pub fn hash_sha256(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "hash_sha256", line)?;
+    let data = expect_data(&args[0], "hash_sha256", line)?;
+    Ok(make_string(&encoding::hex_encode(&encoding::sha256(data))))
+}
+
+pub fn hash_md5(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "hash_md5", line)?;
+    let data = expect_data(&args[0], "hash_md5", line)?;
+    Ok(make_string(&encoding::hex_encode(&encoding::md5(data))))
+}
+
+// Resumes a generator for its next value, or nil once it has finished.
+pub fn next(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "next", line)?;
+    match &args[0] {
+        RuntimeVal::Generator(generator) => {
+            Ok(interpreter.resume_generator(generator, line)?.unwrap_or(make_nil()))
+        }
+        _ => Err(RuntimeError::TypeMismatch(
+            "Only type generator allowed as first argument in 'next' function".to_string(),
+            line,
+        )),
+    }
+}
+
+// `spawn(task)` starts a task without waiting for it. It runs whenever an
+// `await` lets other tasks go on, and the program waits for it to end.
+pub fn spawn(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "spawn", line)?;
+    let task = expect_task(&args[0], "spawn", line)?;
+    interpreter.spawn_task(task);
+    Ok(args[0].clone())
+}
+
+// `delay(ms)` returns a task that finishes after the given milliseconds.
+// Awaiting it lets other tasks run meanwhile, unlike `sleep`.
+pub fn delay(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "delay", line)?;
+    match &args[0] {
+        RuntimeVal::Number(num) if *num >= 0.0 && num.is_finite() => {
+            Ok(RuntimeVal::Task(Rc::new(AsyncTask::delay(*num))))
+        }
+        _ => Err(RuntimeError::TypeMismatch(
+            "Only a number of milliseconds of 0 or more allowed as first argument in 'delay' function".to_string(),
+            line,
+        )),
+    }
+}
+
+pub fn is_done(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "is_done", line)?;
+    let task = expect_task(&args[0], "is_done", line)?;
+    Ok(make_bool(task.is_done()))
+}
+
+fn expect_task<'a>(arg: &'a RuntimeVal, name: &str, line: usize) -> Result<&'a Rc<AsyncTask>, RuntimeError> {
+    match arg {
+        RuntimeVal::Task(task) => Ok(task),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type task allowed as first argument in '{}' function", name),
+            line,
+        )),
+    }
+}
+
+// `spawn_thread(fun, args...)` calls a function declared at the top of the
+// program on a new thread, with deep copies of the arguments
+pub fn spawn_thread(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.is_empty() {
+        return Err(RuntimeError::InvalidArgumentCount(
+            "Expected at least 1, found 0 arguments provided to native function 'spawn_thread'".to_string(),
+            line,
+        ));
+    }
+    let thread = interpreter.spawn_thread(&args[0], &args[1..], line)?;
+    Ok(RuntimeVal::Thread(thread))
+}
+
+pub fn join(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "join", line)?;
+    match &args[0] {
+        RuntimeVal::Thread(thread) => interpreter.join_thread(thread),
+        _ => Err(RuntimeError::TypeMismatch(
+            "Only type thread allowed as first argument in 'join' function".to_string(),
+            line,
+        )),
+    }
+}
+
+pub fn channel(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 0, "channel", line)?;
+    Ok(RuntimeVal::Channel(Arc::new(Channel::new())))
+}
+
+// Channels do no IO, so unlike `send`, `recv` and `close` these natives
+// stay available to sandboxed programs
+
+// `channel_send` hands a copy of any value to the threads receiving from a
+// channel
+pub fn channel_send(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "channel_send", line)?;
+    let channel = expect_channel(&args[0], "channel_send", line)?;
+    let message = interpreter.message(&args[1], line)?;
+    if !channel.send(message) {
+        return Err(RuntimeError::InvalidCall("Cannot send to a closed channel".to_string(), line));
+    }
+    Ok(make_nil())
+}
+
+// `channel_recv` waits for the next value sent to a channel, and gives nil
+// once it is closed and every value was received
+pub fn channel_recv(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "channel_recv", line)?;
+    let channel = expect_channel(&args[0], "channel_recv", line)?;
+    Ok(channel.recv().map_or(make_nil(), |message| from_message(&message)))
+}
+
+pub fn channel_close(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "channel_close", line)?;
+    expect_channel(&args[0], "channel_close", line)?.close();
+    Ok(make_nil())
+}
+
+fn expect_channel<'a>(arg: &'a RuntimeVal, name: &str, line: usize) -> Result<&'a Channel, RuntimeError> {
+    match arg {
+        RuntimeVal::Channel(channel) => Ok(channel),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type channel allowed as first argument in '{}' function", name),
+            line,
+        )),
+    }
+}
+
+// `on_interrupt(fun() { ... })` calls the function when Ctrl+C is pressed
+// instead of stopping the program with an `Interrupted` error, which the
+// function can still raise. `on_interrupt(nil)` brings the error back.
+pub fn on_interrupt(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "on_interrupt", line)?;
+    interpreter.on_interrupt = match &args[0] {
+        RuntimeVal::Nil => None,
+        RuntimeVal::Function { .. }
+        | RuntimeVal::Overloaded { .. }
+        | RuntimeVal::Method { .. }
+        | RuntimeVal::NativeFunction(..)
+        | RuntimeVal::Class { .. } => Some(args[0].clone()),
+        _ => {
+            return Err(RuntimeError::TypeMismatch(
+                "Only functions or nil allowed as first argument in 'on_interrupt' function".to_string(),
+                line,
+            ));
+        }
+    };
+    Ok(make_nil())
+}
+
+// `mutex(value)` returns a mutex holding a copy of the value, which threads
+// it is passed to share
+pub fn mutex(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "mutex", line)?;
+    let message = interpreter.message(&args[0], line)?;
+    Ok(RuntimeVal::Mutex(Arc::new(SharedValue::new(message))))
+}
+
+// `lock(m, fun(value) { ... })` calls the function with the value the mutex
+// holds while no other thread can lock it. What the function returns
+// replaces the value, and is returned.
+pub fn lock(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "lock", line)?;
+    let shared = expect_mutex(&args[0], "lock", line)?;
+    if !matches!(
+        args[1],
+        RuntimeVal::Function { .. }
+            | RuntimeVal::Overloaded { .. }
+            | RuntimeVal::Method { .. }
+            | RuntimeVal::NativeFunction(..)
+            | RuntimeVal::Class { .. }
+    ) {
+        return Err(RuntimeError::TypeMismatch(
+            "Only functions allowed as second argument in 'lock' function".to_string(),
+            line,
+        ));
+    }
+    interpreter.lock_value(shared, line, |interpreter, value| {
+        interpreter.call(args[1].clone(), &[value], line)
+    })
+}
+
+// `atomic_add(m, n)` adds n to the number a mutex holds and returns the sum
+pub fn atomic_add(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 2, "atomic_add", line)?;
+    let shared = expect_mutex(&args[0], "atomic_add", line)?;
+    let RuntimeVal::Number(amount) = args[1] else {
+        return Err(RuntimeError::TypeMismatch(
+            "Only type number allowed as second argument in 'atomic_add' function".to_string(),
+            line,
+        ));
+    };
+    interpreter.lock_value(shared, line, |_, value| match value {
+        RuntimeVal::Number(num) => Ok(make_number(num + amount)),
+        value => Err(RuntimeError::TypeMismatch(
+            format!("'atomic_add' needs a mutex holding a number, found {}", type_name(&value)),
+            line,
+        )),
+    })
+}
+
+fn expect_mutex<'a>(arg: &'a RuntimeVal, name: &str, line: usize) -> Result<&'a SharedValue, RuntimeError> {
+    match arg {
+        RuntimeVal::Mutex(shared) => Ok(shared),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only type mutex allowed as first argument in '{}' function", name),
+            line,
+        )),
+    }
+}
+
+fn write_values(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    new_line: bool,
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    let mut text = String::new();
+    for arg in args {
+        text.push_str(&interpreter.stringify(arg)?);
+    }
+    if new_line {
+        text.push('\n');
+    }
+    interpreter.write_output(&text, line)?;
+    Ok(make_nil())
+}
+
+pub fn print(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    write_values(interpreter, args, false, line)
+}
+
+pub fn println(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    write_values(interpreter, args, true, line)
+}
+
+// Applies a `{:width.precision}` spec. Precision only applies to numbers
+fn format_value(
+    interpreter: &mut Interpreter,
+    value: &RuntimeVal,
+    spec: &str,
+    line: usize,
+) -> Result<String, RuntimeError> {
+    let invalid = || {
+        RuntimeError::TypeMismatch(
+            format!("Invalid format specifier '{{:{}}}' in native function 'format'", spec),
+            line,
+        )
+    };
+    let (width, precision) = match spec.split_once('.') {
+        Some((width, precision)) => (width, Some(precision.parse::<usize>().map_err(|_| invalid())?)),
+        None => (spec, None),
+    };
+    let width = if width.is_empty() {
+        0
+    } else {
+        width.parse::<usize>().map_err(|_| invalid())?
+    };
+
+    let text = match (value, precision) {
+        (RuntimeVal::Number(num), Some(precision)) => format!("{:.*}", precision, num),
+        (_, Some(_)) => {
+            return Err(RuntimeError::TypeMismatch(
+                "Precision in native function 'format' can only be applied to numbers".to_string(),
+                line,
+            ));
+        }
+        (_, None) => interpreter.stringify(value)?,
+    };
+    Ok(format!("{:>width$}", text, width = width))
+}
+
+pub fn format(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.is_empty() {
+        return Err(RuntimeError::InvalidArgumentCount(
+            "Expected a format string as first argument to native function 'format'".to_string(),
+            line,
+        ));
+    }
+    let RuntimeVal::String(template) = &args[0] else {
+        return Err(RuntimeError::TypeMismatch(
+            "First argument to native function 'format' must be a string".to_string(),
+            line,
+        ));
+    };
+    let values = &args[1..];
+
+    let mut result = String::new();
+    let mut next = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => {
+                            return Err(RuntimeError::TypeMismatch(
+                                "Unclosed '{' in format string of native function 'format'".to_string(),
+                                line,
+                            ));
+                        }
+                    }
+                }
+                let (position, spec) = placeholder.split_once(':').unwrap_or((&placeholder, ""));
+                let index = if position.is_empty() {
+                    next += 1;
+                    next - 1
+                } else {
+                    position.parse::<usize>().map_err(|_| {
+                        RuntimeError::TypeMismatch(
+                            format!("Invalid placeholder '{{{}}}' in native function 'format'", placeholder),
+                            line,
+                        )
+                    })?
+                };
+                let Some(value) = values.get(index) else {
+                    return Err(RuntimeError::InvalidArgumentCount(
+                        format!(
+                            "Placeholder {} has no matching argument in native function 'format', only {} provided",
+                            index,
+                            values.len()
+                        ),
+                        line,
+                    ));
+                };
+                result.push_str(&format_value(interpreter, value, spec, line)?);
+            }
+            '}' => {
+                return Err(RuntimeError::TypeMismatch(
+                    "Unmatched '}' in format string of native function 'format'. Use '}}' for a literal brace".to_string(),
+                    line,
+                ));
+            }
+            c => result.push(c),
+        }
+    }
+    Ok(make_string(&result[..]))
+}
+
+pub fn args(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 0, "args", line)?;
+    Ok(RuntimeVal::Array(
+        interpreter
+            .command_line_args
+            .iter()
+            .map(|arg| RuntimeVal::String(Rc::clone(arg)))
+            .collect(),
+    ))
+}
+
+// `freeze(value)` stops the fields of an instance, and the static fields of
+// a class, from being assigned, and does the same for every instance and
+// class reachable from their fields or from the elements of an array,
+// object or map. Arrays, objects, maps and sets are copied whenever they are
+// assigned or passed, so on their own they are kept from changing with
+// `const`. Returns the value.
+pub fn freeze(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "freeze", line)?;
+    freeze_value(&args[0]);
+    Ok(args[0].clone())
+}
+
+fn freeze_value(val: &RuntimeVal) {
+    let scope = match val {
+        RuntimeVal::Instance { instance_env, .. } => instance_env,
+        RuntimeVal::Class { static_fields, .. } => static_fields,
+        RuntimeVal::Array(arr) => return arr.iter().for_each(freeze_value),
+        RuntimeVal::Object(obj) => return obj.values().for_each(freeze_value),
+        RuntimeVal::Map(map) => return map.values().for_each(freeze_value),
+        _ => return,
+    };
+    // A scope frozen already has had its fields visited, which also ends cycles
+    if !freeze_scope(scope) {
+        for (_, field) in own_vars(scope) {
+            freeze_value(&field);
+        }
+    }
+}
+
+// `deep_copy(value)` copies a value together with every instance reachable
+// from it, so the copy shares no fields with the original. The copied
+// instances are not frozen. Functions, classes, generators and sockets are
+// shared rather than copied.
+pub fn deep_copy(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "deep_copy", line)?;
+    let mut copies = vec![];
+    let copy = copy_value(&args[0], &mut copies);
+    // Copied instances are dropped on their own
+    for (_, instance) in &copies {
+        interpreter.track_drop(instance, line)?;
+    }
+    Ok(copy)
+}
+
+// Each instance scope copied so far, paired with the copied instance, so an
+// instance reached twice is copied once and cycles end
+type Copies = Vec<(Rc<RefCell<Environment>>, RuntimeVal)>;
+
+fn copy_value(val: &RuntimeVal, copies: &mut Copies) -> RuntimeVal {
+    match val {
+        RuntimeVal::Instance { instance_env, class_name, class } => {
+            if let Some((_, copy)) = copies.iter().find(|(original, _)| Rc::ptr_eq(original, instance_env)) {
+                return copy.clone();
+            }
+            let copy = copy_scope(instance_env);
+            copies.push((Rc::clone(instance_env), make_instance(class_name, Rc::clone(class), Rc::clone(&copy))));
+            for (name, field) in own_vars(instance_env) {
+                let field = copy_value(&field, copies);
+                overwrite_var(&copy, &name, field);
+            }
+            make_instance(class_name, Rc::clone(class), copy)
+        }
+        RuntimeVal::Array(arr) => RuntimeVal::Array(arr.iter().map(|element| copy_value(element, copies)).collect()),
+        RuntimeVal::Object(obj) => RuntimeVal::Object(
+            obj.iter().map(|(key, property)| (key.clone(), copy_value(property, copies))).collect(),
+        ),
+        RuntimeVal::Map(map) => RuntimeVal::Map(
+            map.iter().map(|(key, entry)| (key.clone(), copy_value(entry, copies))).collect(),
+        ),
+        _ => val.clone(),
+    }
+}
+
+// `error(message)` raises an error that try/catch can handle, with kind
+// "Error". `panic(message)` stops the program with the message however many
+// try blocks surround it. Messages that are not strings are printed as
+// `print` would show them.
+pub fn error(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "error", line)?;
+    Err(RuntimeError::Error(error_message(interpreter, &args[0])?, line))
+}
+
+pub fn panic(
+    interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    check_arg_count(args, 1, "panic", line)?;
+    Err(RuntimeError::Panic(error_message(interpreter, &args[0])?, line))
+}
+
+fn error_message(interpreter: &mut Interpreter, message: &RuntimeVal) -> Result<String, RuntimeError> {
+    match message {
+        RuntimeVal::String(s) => Ok(s.to_string()),
+        _ => interpreter.stringify(message),
+    }
+}
+
+pub fn exit(
+    _interpreter: &mut Interpreter,
+    args: &[RuntimeVal],
+    line: usize,
+) -> Result<RuntimeVal, RuntimeError> {
+    if args.len() > 1 {
+        return Err(RuntimeError::InvalidArgumentCount(
+            format!(
+                "Expected 0 or 1, found {} arguments provided to native function 'exit'",
+                args.len()
+            ),
+            line,
+        ));
+    }
+    let code = match args.first() {
+        Some(arg) => expect_integer(arg, "first", "exit", line)?,
+        None => 0.0,
+    };
+    if !(0.0..=255.0).contains(&code) {
+        return Err(RuntimeError::TypeMismatch(
+            format!("Exit code must be between 0 and 255, found {}", code),
+            line,
+        ));
+    }
+    Err(RuntimeError::Exit(code as i32))
+}
@@ -1,3 +1,10 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 pub enum ParserError {
     EOF,
     UnExpectedToken(String, usize),
@@ -7,14 +14,17 @@ pub enum ParserError {
     ConstValueNull(usize),
     ForLoopDeclaration(String, usize),
     ScopeError(String, usize),
+    NestingTooDeep(usize),
 }
 
+#[derive(Clone)]
 pub enum RuntimeError {
     TypeMismatch(String, usize),
 
     TypeCastingError(String, usize),
 
     InvalidArgumentCount(String, usize),
+    ArityMismatch(String, usize, usize),
 
     ArrayIndexOutOfBounds(String, usize),
     InvalidArrayIndex(String, usize),
@@ -24,9 +34,21 @@ pub enum RuntimeError {
     InvalidMemberAccess(String, usize),
     UndefinedField(String, usize),
     UndefinedProperty(String, usize),
+    PrivateAccess(String, usize),
 
     EnvironmentError(String, usize),
 
+    StackOverflow(String, usize),
+
+    IoError(String, usize),
+
+    Error(String, usize), // Raised by the 'error' native
+    Panic(String, usize), // Raised by the 'panic' native, which try/catch lets through
+
+    Interrupted(String, usize), // Raised when Ctrl+C is pressed and no 'on_interrupt' handler is set
+
+    Exit(i32), // Raised by the 'exit' native, unwinds the program with the given status code
+
     InternalError, // Error should not occur but made to satisfy rust compiler
 }
 
@@ -37,113 +59,435 @@ pub enum EnvironmentError {
     VarNotDeclared,
 }
 
-pub fn handle_lexer_error(line: usize, message: &str, code: &str) {
-    eprintln!("Line {}: {}", line, code);
-    eprintln!("Error: {}", message);
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
 }
 
-pub fn handle_parser_error(error: ParserError, code: &[&str]) {
-    match error {
-        ParserError::EOF => eprintln!("Unexpected end of file: incomplete program structure"),
-
-        ParserError::UnExpectedToken(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: {}", s);
-        }
-
-        ParserError::ObjectKey(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!(
-                "Error: Expected string or identifier for object keys. {}",
-                s
-            );
+impl Severity {
+    fn label(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
         }
+    }
 
-        ParserError::ConstValueNull(line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: Constant variable is not initialized.")
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+            Severity::Note => "\x1b[1;36m",
         }
+    }
+}
 
-        ParserError::ForLoopDeclaration(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: Invalid for loop declaration. {}", s);
-        }
+/// How diagnostics are written: for people reading a terminal, or as one
+/// JSON object per line for editors and CI tools.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
 
-        ParserError::MemberExpr(line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!(
-                "Error: Expected identifier or 'this' and 'super' keywords before dot operator"
-            );
-        }
+/// An error or warning as tools receive it, rather than rendered as text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Names the kind of problem, as `code` in JSON diagnostics
+    pub code: String,
+    pub message: String,
+    pub line: usize,
+    /// Byte offsets of the offending source
+    pub span: Range<usize>,
+}
 
-        ParserError::PrimaryExpr(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: Invalid expression. Found '{}'", s);
-        }
+/// The characters of its line a diagnostic points at: the column they
+/// start at, counted from 1, and how many there are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Columns {
+    pub start: usize,
+    pub width: usize,
+}
 
-        ParserError::ScopeError(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: {}", s);
-        }
+impl Columns {
+    /// The columns of the byte offsets `span` of `source`, up to the end of
+    /// the line the span starts on.
+    pub fn of(source: &str, span: &Range<usize>) -> Option<Columns> {
+        let before = source.get(..span.start)?;
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        let covered = source.get(span.start..span.end.max(span.start))?;
+        let covered = covered.split('\n').next().unwrap_or_default().trim_end_matches('\r');
+        Some(Columns {
+            start: before[line_start..].chars().count() + 1,
+            width: covered.chars().count().max(1),
+        })
     }
 }
 
-pub fn handle_runtime_error(error: RuntimeError, code: &[&str]) {
-    match error {
-        RuntimeError::TypeMismatch(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: {}", s);
+/// A program file that could not be read, with the path it was read from.
+#[derive(Debug)]
+pub struct FileError {
+    pub path: String,
+    pub kind: FileErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileErrorKind {
+    NotFound,
+    PermissionDenied,
+    IsADirectory,
+    /// The file is not UTF-8 text
+    InvalidEncoding,
+    /// The file is not named like a Lox program
+    InvalidType,
+    Other(String),
+}
+
+impl FileError {
+    pub fn new(path: &str, kind: FileErrorKind) -> Self {
+        FileError {
+            path: path.to_string(),
+            kind,
         }
+    }
 
-        RuntimeError::TypeCastingError(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: {}", s);
+    pub fn from_io(path: &str, error: &io::Error) -> Self {
+        let kind = match error.kind() {
+            io::ErrorKind::NotFound => FileErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied => FileErrorKind::PermissionDenied,
+            io::ErrorKind::IsADirectory => FileErrorKind::IsADirectory,
+            io::ErrorKind::InvalidData => FileErrorKind::InvalidEncoding,
+            _ => FileErrorKind::Other(error.to_string()),
+        };
+        FileError::new(path, kind)
+    }
+
+    /// Names the error for tools, as `code` in JSON diagnostics.
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            FileErrorKind::NotFound => "FileNotFound",
+            FileErrorKind::PermissionDenied => "PermissionDenied",
+            FileErrorKind::IsADirectory => "IsADirectory",
+            FileErrorKind::InvalidEncoding => "InvalidEncoding",
+            FileErrorKind::InvalidType => "InvalidFileType",
+            FileErrorKind::Other(_) => "FileError",
         }
+    }
 
-        RuntimeError::InvalidArgumentCount(s, line) => {
-            if line != 0 {
-                eprintln!("Line {}: {}", line, code[line - 1]);
+    /// Writes the error to stderr like any other diagnostic, naming the file.
+    pub fn report(&self) {
+        with_file(Some(&self.path), || {
+            render(&mut io::stderr(), Severity::Error, self.code(), &self.to_string(), 0, None, &[]);
+        });
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            FileErrorKind::NotFound => write!(f, "File '{}' does not exist", self.path),
+            FileErrorKind::PermissionDenied => write!(f, "Permission denied to read '{}'", self.path),
+            FileErrorKind::IsADirectory => write!(f, "'{}' is a directory, not a file", self.path),
+            FileErrorKind::InvalidEncoding => write!(f, "File '{}' is not UTF-8 text", self.path),
+            FileErrorKind::InvalidType => {
+                write!(f, "Invalid file type '{}', expected a .lox file", self.path)
             }
-            eprintln!("Error: {}", s);
+            FileErrorKind::Other(message) => write!(f, "Cannot read '{}': {}", self.path, message),
         }
+    }
+}
 
-        RuntimeError::ArrayIndexOutOfBounds(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: {}", s);
-        }
+impl Error for FileError {}
 
-        RuntimeError::InvalidArrayIndex(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: {}", s);
+static COLOR: AtomicBool = AtomicBool::new(false);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static FILE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Turns ANSI colors in rendered diagnostics on or off for the whole
+/// process. Colors are off until this is called.
+pub fn set_color(enabled: bool) {
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Selects how diagnostics are written for the whole process. The human
+/// format is used until this is called.
+pub fn set_error_format(format: ErrorFormat) {
+    JSON.store(format == ErrorFormat::Json, Ordering::Relaxed);
+}
+
+/// Whether diagnostics are written as JSON. Status messages meant for people
+/// are left out then, so the error output stays machine readable.
+pub fn json_errors() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Runs `task` with diagnostics on this thread naming `file` as the source
+/// they point into.
+pub fn with_file<R>(file: Option<&str>, task: impl FnOnce() -> R) -> R {
+    let previous = FILE.with(|current| current.replace(file.map(str::to_string)));
+    let result = task();
+    FILE.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Writes one diagnostic: its severity and message, where it happened and
+/// the offending source line underlined. Every lexer, parser and runtime
+/// error and every lint warning is rendered here. `kind` names the error,
+/// such as `TypeMismatch`, for tools. Line 0 stands for no location, such
+/// as errors raised while calling `main`. Lexer and parser errors also
+/// know the `columns` of the offending token.
+pub fn render(
+    err: &mut dyn Write,
+    severity: Severity,
+    kind: &str,
+    message: &str,
+    line: usize,
+    columns: Option<Columns>,
+    code: &[&str],
+) {
+    if json_errors() {
+        render_json(err, severity, kind, message, line, columns);
+        return;
+    }
+    let color = COLOR.load(Ordering::Relaxed);
+    let paint = |style: &str, text: &str| {
+        if color {
+            format!("{}{}\x1b[0m", style, text)
+        } else {
+            text.to_string()
         }
-        
-        RuntimeError::InvalidCall(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: {}", s);            
+    };
+    let _ = writeln!(
+        err,
+        "{} {}",
+        paint(severity.color(), &format!("{}:", severity.label())),
+        paint("\x1b[1m", message)
+    );
+    let Some(source) = line.checked_sub(1).and_then(|index| code.get(index)) else {
+        return;
+    };
+    let number = line.to_string();
+    let gutter = " ".repeat(number.len());
+    let location = FILE.with(|file| match &*file.borrow() {
+        Some(file) => format!("{}:{}", file, line),
+        None => format!("line {}", line),
+    });
+    let bar = paint("\x1b[1;34m", "|");
+    let _ = writeln!(err, "{}{} {}", gutter, paint("\x1b[1;34m", "-->"), location);
+    let _ = writeln!(err, "{} {}", gutter, bar);
+    let _ = writeln!(err, "{} {} {}", paint("\x1b[1;34m", &number), bar, source);
+    // Errors that only record their line underline all the code on it
+    let (start, width) = match columns {
+        Some(columns) => (columns.start - 1, columns.width),
+        None => {
+            let indent = source.chars().take_while(|c| c.is_whitespace()).count();
+            (indent, source.trim().chars().count().max(1))
         }
+    };
+    // Tabs are kept so the carets line up however wide they are shown
+    let offset: String = source
+        .chars()
+        .chain(std::iter::repeat(' '))
+        .take(start)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let carets = "^".repeat(width);
+    let _ = writeln!(err, "{} {} {}{}", gutter, bar, offset, paint(severity.color(), &carets));
+}
+
+// Runtime errors and warnings only record their line, so their column is null
+fn render_json(
+    err: &mut dyn Write,
+    severity: Severity,
+    kind: &str,
+    message: &str,
+    line: usize,
+    columns: Option<Columns>,
+) {
+    let file = FILE.with(|file| match &*file.borrow() {
+        Some(file) => json_string(file),
+        None => "null".to_string(),
+    });
+    let line = if line == 0 { "null".to_string() } else { line.to_string() };
+    let column = columns.map_or("null".to_string(), |columns| columns.start.to_string());
+    let _ = writeln!(
+        err,
+        "{{\"file\":{},\"line\":{},\"column\":{},\"code\":{},\"message\":{},\"severity\":{}}}",
+        file,
+        line,
+        column,
+        json_string(kind),
+        json_string(message),
+        json_string(severity.label())
+    );
+}
 
-        RuntimeError::InvalidMemberAccess(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: Invalid use of '{}' for member expression", s);
+fn json_string(text: &str) -> String {
+    let mut result = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
         }
-        RuntimeError::UndefinedField(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: {}", s);
+    }
+    result.push('"');
+    result
+}
+
+pub fn handle_lexer_error(
+    err: &mut dyn Write,
+    line: usize,
+    columns: Option<Columns>,
+    message: &str,
+    code: &[&str],
+) {
+    render(err, Severity::Error, "LexerError", message, line, columns, code);
+}
+
+pub fn handle_warning(err: &mut dyn Write, kind: &str, message: &str, line: usize, code: &[&str]) {
+    render(err, Severity::Warning, kind, message, line, None, code);
+}
+
+pub fn handle_parser_error(
+    err: &mut dyn Write,
+    error: ParserError,
+    columns: Option<Columns>,
+    code: &[&str],
+) {
+    let (kind, message, line) = match error {
+        ParserError::EOF => (
+            "UnexpectedEof",
+            "Unexpected end of file: incomplete program structure".to_string(),
+            0,
+        ),
+        ParserError::UnExpectedToken(s, line) => ("UnexpectedToken", s, line),
+        ParserError::ObjectKey(s, line) => (
+            "ObjectKey",
+            format!("Expected string or identifier for object keys. {}", s),
+            line,
+        ),
+        ParserError::ConstValueNull(line) => (
+            "ConstValueNull",
+            "Constant variable is not initialized.".to_string(),
+            line,
+        ),
+        ParserError::ForLoopDeclaration(s, line) => (
+            "ForLoopDeclaration",
+            format!("Invalid for loop declaration. {}", s),
+            line,
+        ),
+        ParserError::MemberExpr(line) => (
+            "MemberExpr",
+            "Expected identifier or 'this' and 'super' keywords before dot operator".to_string(),
+            line,
+        ),
+        ParserError::PrimaryExpr(s, line) => (
+            "PrimaryExpr",
+            format!("Invalid expression. Found '{}'", s),
+            line,
+        ),
+        ParserError::ScopeError(s, line) => ("ScopeError", s, line),
+        ParserError::NestingTooDeep(line) => (
+            "NestingTooDeep",
+            format!(
+                "Code is nested too deeply. At most {} levels of nesting are allowed.",
+                crate::parser::parser::MAX_NESTING_DEPTH
+            ),
+            line,
+        ),
+    };
+    render(err, Severity::Error, kind, &message, line, columns, code);
+}
+
+impl ParserError {
+    /// The line the error is reported on, 0 for the end of the file.
+    pub fn line(&self) -> usize {
+        match self {
+            ParserError::EOF => 0,
+            ParserError::UnExpectedToken(_, line)
+            | ParserError::ObjectKey(_, line)
+            | ParserError::PrimaryExpr(_, line)
+            | ParserError::ForLoopDeclaration(_, line)
+            | ParserError::ScopeError(_, line) => *line,
+            ParserError::MemberExpr(line) | ParserError::ConstValueNull(line) | ParserError::NestingTooDeep(line) => {
+                *line
+            }
         }
-        RuntimeError::UndefinedProperty(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: {}", s);
+    }
+}
+
+impl RuntimeError {
+    /// Kind, message and line of the error as it is reported. Internal
+    /// errors are interpreter bugs and a call to `exit` is not an error, so
+    /// neither has one.
+    pub fn describe(&self) -> Option<(&'static str, String, usize)> {
+        Some(match self {
+            RuntimeError::TypeMismatch(s, line) => ("TypeMismatch", s.clone(), *line),
+            RuntimeError::TypeCastingError(s, line) => ("TypeCastingError", s.clone(), *line),
+            RuntimeError::InvalidArgumentCount(s, line) => ("InvalidArgumentCount", s.clone(), *line),
+            RuntimeError::ArityMismatch(s, line, _) => ("ArityMismatch", s.clone(), *line),
+            RuntimeError::ArrayIndexOutOfBounds(s, line) => ("ArrayIndexOutOfBounds", s.clone(), *line),
+            RuntimeError::InvalidArrayIndex(s, line) => ("InvalidArrayIndex", s.clone(), *line),
+            RuntimeError::InvalidCall(s, line) => ("InvalidCall", s.clone(), *line),
+            RuntimeError::InvalidMemberAccess(s, line) => (
+                "InvalidMemberAccess",
+                format!("Invalid use of '{}' for member expression", s),
+                *line,
+            ),
+            RuntimeError::UndefinedField(s, line) => ("UndefinedField", s.clone(), *line),
+            RuntimeError::UndefinedProperty(s, line) => ("UndefinedProperty", s.clone(), *line),
+            RuntimeError::PrivateAccess(s, line) => ("PrivateAccess", s.clone(), *line),
+            RuntimeError::EnvironmentError(s, line) => ("EnvironmentError", s.clone(), *line),
+            RuntimeError::StackOverflow(s, line) => ("StackOverflow", s.clone(), *line),
+            RuntimeError::IoError(s, line) => ("IoError", s.clone(), *line),
+            RuntimeError::Error(s, line) => ("Error", s.clone(), *line),
+            RuntimeError::Panic(s, line) => ("Panic", s.clone(), *line),
+            RuntimeError::Interrupted(s, line) => ("Interrupted", s.clone(), *line),
+            RuntimeError::Exit(_) | RuntimeError::InternalError => return None,
+        })
+    }
+
+    /// Kind, message and line of the error as a `catch` block sees it, or
+    /// `None` for the errors try/catch lets through: a panic, a call to
+    /// `exit` and internal errors.
+    pub fn caught(&self) -> Option<(&'static str, String, usize)> {
+        match self {
+            RuntimeError::Panic(..) => None,
+            _ => self.describe(),
         }
+    }
+}
 
-        RuntimeError::EnvironmentError(s, line) => {
-            eprintln!("Line {}: {}", line, code[line - 1]);
-            eprintln!("Error: {}", s);
+pub fn handle_runtime_error(err: &mut dyn Write, error: RuntimeError, code: &[&str]) {
+    match error {
+        RuntimeError::ArityMismatch(s, line, declaration_line) => {
+            render(err, Severity::Error, "ArityMismatch", &s, line, None, code);
+            render(err, Severity::Note, "ArityMismatch", "Declared here", declaration_line, None, code);
         }
 
+        RuntimeError::Exit(_) => {}
+
         RuntimeError::InternalError => {
             unreachable!(
                 "Internal Error: This should not have happened. Please report this as a bug."
             );
         }
+
+        error => {
+            if let Some((kind, message, line)) = error.describe() {
+                render(err, Severity::Error, kind, &message, line, None, code);
+            }
+        }
     }
 }
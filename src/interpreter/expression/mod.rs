@@ -4,225 +4,1378 @@ use std::rc::Rc;
 
 use crate::ast::*;
 use crate::environment::*;
+use crate::global_scope::slice_between;
 use crate::handle_errors::EnvironmentError;
 use crate::handle_errors::RuntimeError;
+use crate::interpreter::generator::Generator;
+use crate::interpreter::scheduler::AsyncTask;
 use crate::interpreter::interpreter::*;
 use crate::lexer::*;
 use crate::values::*;
 
-pub fn evaluate_expr(
-    expr: &Expr,
-    env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeVal, RuntimeError> {
-    match expr {
-        Expr::NumericLiteral(num, _) => Ok(make_number(*num)),
-        Expr::Null(_) => Ok(make_nil()),
-        Expr::BoolLiteral(bit, _) => Ok(make_bool(*bit)),
-        Expr::StringLiteral(str, _) => Ok(make_string(&str[..])),
-        Expr::Identifier(symbol, line) => evaluate_identifier(&symbol[..], env, *line),
-        Expr::This(line) => evaluate_identifier("this", env, *line),
-        Expr::Super(class_name, line) => evaluate_super_expr(class_name, env, *line),
-        Expr::Array(array, _) => evaluate_array_expr(array, env),
-        Expr::Member {
-            object,
-            property,
-            computed,
-            line,
-        } => evaluate_member_expr(object, property, *computed, env, *line),
-        Expr::Call { args, caller, line } => evaluate_function_call(args, caller, env, *line),
-        Expr::Unary {
-            operator,
-            right,
-            line,
-        } => evaluate_unary_expr(operator, right, env, *line),
-        Expr::BinaryExpr {
-            left,
-            operator,
-            right,
-            line,
-        } => evaluate_binary_expr(left, operator, right, env, *line),
-        Expr::ComparisonLiteral {
-            left,
-            operator,
-            right,
-            line,
-        } => evaluate_compare_expr(left, operator, right, env, *line),
-        Expr::ObjectLiteral { properties } => evaluate_object_expr(properties, env),
-        Expr::AssignmentExpr {
-            assignee,
-            value,
-            line,
-        } => evaluate_assignment(assignee, value, env, *line),
+impl Interpreter {
+    pub fn evaluate_expr(
+        &mut self,
+        expr: &Expr,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        match expr {
+            Expr::NumericLiteral(num, _) => Ok(make_number(*num)),
+            Expr::Null(_) => Ok(make_nil()),
+            Expr::BoolLiteral(bit, _) => Ok(make_bool(*bit)),
+            Expr::StringLiteral(str, _) => Ok(make_shared_string(str)),
+            Expr::Identifier(symbol, line) => self.evaluate_identifier(&symbol[..], env, *line),
+            Expr::This(line) => self.evaluate_identifier("this", env, *line),
+            Expr::Super(class_name, line) => self.evaluate_super_expr(class_name, env, *line),
+            Expr::Array(array, _) => self.evaluate_array_expr(array, env),
+            Expr::Range {
+                start,
+                end,
+                inclusive,
+                line,
+            } => self.evaluate_range_expr(start, end, *inclusive, env, *line),
+            Expr::Member { .. } | Expr::Slice { .. } | Expr::Call { .. } => {
+                Ok(self.evaluate_chain(expr, env)?.unwrap_or_else(make_nil))
+            }
+            Expr::Unary {
+                operator,
+                right,
+                line,
+            } => self.evaluate_unary_expr(operator, right, env, *line),
+            Expr::BinaryExpr {
+                left,
+                operator,
+                right,
+                line,
+            } => self.evaluate_binary_expr(left, operator, right, env, *line),
+            Expr::ComparisonLiteral {
+                left,
+                operator,
+                right,
+                ..
+            } if operator.token_type == TokenType::QUESTIONQUESTION => {
+                match self.evaluate_expr(left, env)? {
+                    RuntimeVal::Nil => self.evaluate_expr(right, env),
+                    value => Ok(value),
+                }
+            }
+            Expr::ComparisonLiteral {
+                left,
+                operator,
+                right,
+                line,
+            } => self.evaluate_compare_expr(left, operator, right, env, *line),
+            Expr::ObjectLiteral { properties } => self.evaluate_object_expr(properties, env),
+            Expr::AssignmentExpr {
+                assignee,
+                value,
+                line,
+            } => self.evaluate_assignment(assignee, value, env, *line),
+            Expr::If { branches, line } => self.evaluate_if_expr(branches, env, *line),
+            Expr::Class(class) => self.evaluate_class_expr(class, env),
+            Expr::Await { value, line } => self.evaluate_await_expr(value, env, *line),
+        }
     }
-}
 
-fn evaluate_unary_expr(
-    operator: &Token,
-    right: &Expr,
-    env: &Rc<RefCell<Environment>>,
-    line: usize,
-) -> Result<RuntimeVal, RuntimeError> {
-    let value = evaluate_expr(right, env)?;
-    if operator.token_type == TokenType::BANG {
-        if let RuntimeVal::Bool(bit) = value {
-            return Ok(make_bool(!bit));
+    // Evaluates a chain of calls, member reads and slices, such as
+    // `a?.b.c()`. `None` means a `?.` or `?[` found nil, which skips the rest
+    // of the chain.
+    fn evaluate_chain(
+        &mut self,
+        expr: &Expr,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<Option<RuntimeVal>, RuntimeError> {
+        match expr {
+            Expr::Member {
+                object,
+                property,
+                computed,
+                optional,
+                line,
+            } => self.evaluate_member_expr(object, property, *computed, *optional, env, *line),
+            Expr::Slice {
+                object,
+                start,
+                end,
+                optional,
+                line,
+            } => self.evaluate_slice_expr(object, start, end, *optional, env, *line),
+            Expr::Call { args, caller, line } => self.evaluate_function_call(args, caller, env, *line),
+            _ => self.evaluate_expr(expr, env).map(Some),
         }
-        Err(RuntimeError::TypeMismatch(
-            "'!' NOT operator is only valid for bools".to_string(),
-            line,
-        ))
-    } else {
-        if let RuntimeVal::Number(num) = value {
-            return Ok(make_number(-num));
+    }
+
+    // A task resumed after pausing at this `await` takes the result it
+    // waited for. Anywhere else the other tasks run until the awaited one
+    // has finished.
+    fn evaluate_await_expr(
+        &mut self,
+        value: &Expr,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        if let Some(result) = self.awaited.take() {
+            return Ok(result);
+        }
+        let task = self.awaited_task(value, env, line)?;
+        self.run_until(&task, line)?;
+        task.result()
+    }
+
+    // The class is declared in a scope of its own, so its name is only seen
+    // by its methods, which need it for `super`
+    fn evaluate_class_expr(
+        &mut self,
+        class: &ClassDeclaration,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let class_env = Environment::new(Some(Rc::clone(env)));
+        self.class_declaration(class, &class_env)?;
+        match lookup_var(&class_env, &class.name) {
+            Ok(class) => Ok(class),
+            Err(_) => Err(RuntimeError::InternalError),
+        }
+    }
+
+    fn evaluate_if_expr(
+        &mut self,
+        branches: &[(Expr, Vec<Stmt>, Expr)],
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        for (condition, statements, value) in branches {
+            match self.evaluate_expr(condition, env)? {
+                RuntimeVal::Bool(true) => {
+                    let local_env = Environment::new(Some(Rc::clone(env)));
+                    // The parser keeps `return`, `break` and `continue` from
+                    // leaving a branch, so only errors end it early
+                    for statement in statements {
+                        self.evaluate(statement, &local_env)?;
+                    }
+                    return self.evaluate_expr(value, &local_env);
+                }
+                RuntimeVal::Bool(false) => {}
+                _ => {
+                    return Err(RuntimeError::TypeMismatch(
+                        "Conditions of if expressions must be of type bool".to_string(),
+                        condition.line().unwrap_or(line),
+                    ));
+                }
+            }
+        }
+        Ok(make_nil())
+    }
+
+    fn evaluate_range_expr(
+        &mut self,
+        start: &Expr,
+        end: &Expr,
+        inclusive: bool,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        match (self.evaluate_expr(start, env)?, self.evaluate_expr(end, env)?) {
+            (RuntimeVal::Number(start), RuntimeVal::Number(end)) => {
+                Ok(make_range(start, end, inclusive))
+            }
+            _ => Err(RuntimeError::TypeMismatch(
+                "Range bounds must be numbers".to_string(),
+                line,
+            )),
+        }
+    }
+
+    fn evaluate_unary_expr(
+        &mut self,
+        operator: &Token,
+        right: &Expr,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let value = self.evaluate_expr(right, env)?;
+        if operator.token_type == TokenType::BANG {
+            if let RuntimeVal::Bool(bit) = value {
+                return Ok(make_bool(!bit));
+            }
+            Err(RuntimeError::TypeMismatch(
+                "'!' NOT operator is only valid for bools".to_string(),
+                line,
+            ))
+        } else {
+            if let RuntimeVal::Number(num) = value {
+                return Ok(make_number(-num));
+            }
+            Err(RuntimeError::TypeMismatch(
+                "'-' negation operator is only valid for numbers".to_string(),
+                line,
+            ))
+        }
+    }
+
+    fn evaluate_binary_expr(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let left_hand_side = self.evaluate_expr(left, env)?;
+        let right_hand_side = self.evaluate_expr(right, env)?;
+        if let RuntimeVal::Number(lhs) = left_hand_side {
+            if let RuntimeVal::Number(rhs) = right_hand_side {
+                return Ok(evaluate_numeric_binary_expr(lhs, rhs, &operator.lexeme[..]));
+            }
         }
         Err(RuntimeError::TypeMismatch(
-            "'-' negation operator is only valid for numbers".to_string(),
+            format!(
+                "{} operation is not valid for two non-numbers",
+                operator.lexeme
+            ),
             line,
         ))
     }
-}
 
-fn evaluate_numeric_binary_expr(lhs: f64, rhs: f64, operator: &str) -> RuntimeVal {
-    make_number(match operator {
-        "+" => lhs + rhs,
-        "-" => lhs - rhs,
-        "*" => lhs * rhs,
-        "/" => lhs / rhs,
-        _ => lhs % rhs,
-    })
-}
+    fn evaluate_identifier(
+        &mut self,
+        ident: &str,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        match lookup_var(env, ident) {
+            Ok(val) => Ok(val),
+            Err(_) => Err(RuntimeError::EnvironmentError(
+                format!("'{}' is not declared.{}", ident, suggest_name(env, ident)),
+                line,
+            )),
+        }
+    }
 
-fn evaluate_binary_expr(
-    left: &Expr,
-    operator: &Token,
-    right: &Expr,
-    env: &Rc<RefCell<Environment>>,
-    line: usize,
-) -> Result<RuntimeVal, RuntimeError> {
-    let left_hand_side = evaluate_expr(left, env)?;
-    let right_hand_side = evaluate_expr(right, env)?;
-    if let RuntimeVal::Number(lhs) = left_hand_side {
-        if let RuntimeVal::Number(rhs) = right_hand_side {
-            return Ok(evaluate_numeric_binary_expr(lhs, rhs, &operator.lexeme[..]));
+    fn evaluate_super_expr(
+        &mut self,
+        class_name: &str,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        if let Ok(class) = lookup_var(env, class_name) {
+            if let RuntimeVal::Class {
+                name, superclass, closure, ..
+            } = class
+            {
+                if let Some(parent_class) = superclass {
+                    return match lookup_var(&closure, &parent_class) {
+                        Ok(val) => Ok(val),
+                        Err(_) => {
+                            Err(RuntimeError::EnvironmentError(
+                                format!(
+                                    "Cannot use 'super' in '{}' class as parent class '{}' is not declared",
+                                    name, parent_class
+                                ),
+                                line,
+                            ))
+                        }
+                    }
+                }
+                return Err(RuntimeError::EnvironmentError(
+                    format!("Cannot use 'super' in '{}' class as it has no parent class", name),
+                    line,
+                ));
+            }
+            if let RuntimeVal::Mixin { declaration, .. } = class {
+                return Err(RuntimeError::EnvironmentError(
+                    format!("Cannot use 'super' in mixin '{}' as mixins have no parent class", declaration.name),
+                    line,
+                ));
+            }
         }
+        Err(RuntimeError::InternalError)
     }
-    Err(RuntimeError::TypeMismatch(
-        format!(
-            "{} operation is not valid for two non-numbers",
-            operator.lexeme
-        ),
-        line,
-    ))
-}
 
-fn evaluate_identifier(
-    ident: &str,
-    env: &Rc<RefCell<Environment>>,
-    line: usize,
-) -> Result<RuntimeVal, RuntimeError> {
-    match lookup_var(env, ident) {
-        Ok(val) => Ok(val),
-        Err(_) => Err(RuntimeError::EnvironmentError(
-            format!("'{}' is not declared.", ident),
-            line,
-        )),
+    fn evaluate_object_expr(
+        &mut self,
+        obj: &[Property],
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let mut map = HashMap::new();
+
+        for prop in obj {
+            let runtime_val;
+            if let Some(expr) = &prop.value {
+                runtime_val = self.evaluate_expr(&expr, env)?;
+            } else {
+                runtime_val = match lookup_var(env, &prop.key[..]) {
+                    Ok(val) => val,
+                    Err(_) => {
+                        return Err(RuntimeError::EnvironmentError(
+                            format!(
+                                "{} is not declared yet.{}",
+                                prop.key,
+                                suggest_name(env, &prop.key)
+                            ),
+                            prop.line,
+                        ));
+                    }
+                }
+            }
+            map.insert(prop.key.clone(), runtime_val);
+        }
+        Ok(make_obj(&map))
     }
-}
 
-fn evaluate_super_expr(
-    class_name: &str,
-    env: &Rc<RefCell<Environment>>,
-    line: usize,
-) -> Result<RuntimeVal, RuntimeError> {
-    if let Ok(class) = lookup_var(env, class_name) {
-        if let RuntimeVal::Class {
-            name, superclass, ..
-        } = class
+    fn evaluate_array_expr(
+        &mut self,
+        array: &[Expr],
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let mut val = vec![];
+
+        for arr in array {
+            val.push(self.evaluate_expr(arr, env)?);
+        }
+
+        Ok(make_arr(&val))
+    }
+
+    fn evaluate_compare_expr(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let left_hand_side = self.evaluate_expr(left, env)?;
+        let right_hand_side = self.evaluate_expr(right, env)?;
+
+        if operator.token_type == TokenType::AND || operator.token_type == TokenType::OR {
+            evaluate_logical_expr(left_hand_side, right_hand_side, &operator.lexeme[..], line)
+        } else if operator.token_type == TokenType::EQUALEQUAL
+            || operator.token_type == TokenType::BANGEQUAL
         {
-            if let Some(parent_class) = superclass {
-                return match lookup_var(env, &parent_class) {
-                    Ok(val) => Ok(val),
-                    Err(_) => {
-                        Err(RuntimeError::EnvironmentError(
+            evaluate_equality_expr(left_hand_side, right_hand_side, &operator.lexeme[..], line)
+        } else {
+            evaluate_comparison_expr(
+                left_hand_side,
+                right_hand_side,
+                &operator.lexeme[..],
+                line,
+            )
+        }
+    }
+
+    fn evaluate_assignment(
+        &mut self,
+        assignee: &Expr,
+        value: &Expr,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        match assignee {
+            Expr::Identifier(ident, line) => {
+                let value = self.evaluate_expr(value, env)?;
+                match assign_var(env, &ident[..], value) {
+                    Ok(val) => {
+                        Ok(val)
+                    }
+                    Err(err) => match err {
+                        EnvironmentError::ConstReassign => {
+                            Err(RuntimeError::EnvironmentError(
+                                format!(
+                                    "{} is a constant. Constant values cannot be reassigned",
+                                    ident
+                                ),
+                                *line,
+                            ))
+                        }
+                        EnvironmentError::VarNotDeclared => {
+                            Err(RuntimeError::EnvironmentError(
+                                format!(
+                                    "{} has not been declared yet.{}",
+                                    ident,
+                                    suggest_name(env, ident)
+                                ),
+                                *line,
+                            ))
+                        }
+                        EnvironmentError::ReDeclareVar => {
+                            Err(RuntimeError::InternalError)
+                        }
+                    },
+                }
+            }
+            Expr::Member {
+                object,
+                property,
+                computed,
+                line,
+                ..
+            } => {
+                self.equate_member_expr(object, property, *computed, value, env, *line)
+            }
+            _ => Err(RuntimeError::TypeMismatch(
+                "Only variables and member expressions can be assigned values".into(),
+                line,
+            )),
+        }
+    }
+
+    fn evaluate_function_body(
+        &mut self,
+        declaration: &Rc<FunctionDeclaration>,
+        args: &[RuntimeVal],
+        local_env: &Rc<RefCell<Environment>>,
+        index: usize,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let callable = ["function", "method", "constructor"];
+        let params = &declaration.parameters;
+
+        if args.len() != params.len() {
+            return Err(RuntimeError::ArityMismatch(
+                format!(
+                    "Expected {}, found {} arguments provided to {} {}",
+                    params.len(),
+                    args.len(),
+                    callable[index],
+                    declaration.name
+                ),
+                line,
+                declaration.line,
+            ));
+        }
+
+        // The parser rejects repeated parameter names. The body may declare
+        // a variable named after a parameter, which then replaces it
+        for i in 0..args.len() {
+            let _ = declare_shadowable(&local_env, &params[i][..], args[i].clone(), false);
+        }
+
+        // The body of an async function runs as the scheduler gets to it
+        if declaration.asynchronous {
+            let task = AsyncTask::new(declaration, Rc::clone(local_env));
+            return Ok(RuntimeVal::Task(Rc::new(task)));
+        }
+
+        // The body of a generator only runs as it is resumed
+        if declaration.generator {
+            let generator = Generator::new(declaration, Rc::clone(local_env));
+            return Ok(RuntimeVal::Generator(Rc::new(generator)));
+        }
+
+        for stmt in &declaration.body {
+            match self.evaluate(&stmt, local_env)? {
+                EvalResult::Return(val) => return Ok(val),
+                _ => continue,
+            }
+        }
+
+        Ok(make_nil())
+    }
+
+    fn evaluate_function_call(
+        &mut self,
+        args: &[Expr],
+        caller: &Expr,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<Option<RuntimeVal>, RuntimeError> {
+        let call = match caller {
+            Expr::Super(class_name, _) => {
+                let values = self.evaluate_args(args, env)?;
+                return self.evaluate_super_call(class_name, &values, env, line).map(Some);
+            }
+            // `obj?.method()` skips the call when `obj` is nil
+            _ => match self.evaluate_chain(caller, env)? {
+                Some(call) => call,
+                None => return Ok(None),
+            },
+        };
+        let values = self.evaluate_args(args, env)?;
+        self.call(call, &values, line).map(Some)
+    }
+
+    fn evaluate_args(
+        &mut self,
+        args: &[Expr],
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<Vec<RuntimeVal>, RuntimeError> {
+        let mut values = vec![];
+        for arg in args {
+            values.push(self.evaluate_expr(arg, env)?);
+        }
+        Ok(values)
+    }
+
+    /// Calls a function, method, class or native with already evaluated
+    /// arguments. Natives use this to call back into Lox code.
+    pub fn call(
+        &mut self,
+        call: RuntimeVal,
+        args: &[RuntimeVal],
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::StackOverflow(
+                format!("Maximum call depth of {} exceeded", MAX_CALL_DEPTH),
+                line,
+            ));
+        }
+        self.call_depth += 1;
+        let result = if self.profile.is_some() {
+            self.call_profiled(call, args, line)
+        } else {
+            self.call_unprofiled(call, args, line)
+        };
+        self.call_depth -= 1;
+        // Instances the call left unreachable are finalized before its result is used
+        if result.is_ok() && has_released() {
+            self.run_finalizers(line)?;
+        }
+        result
+    }
+
+    /// Registers an instance whose class has a `__drop__` method, so the
+    /// method runs once nothing else refers to the instance.
+    pub(crate) fn track_drop(&mut self, instance: &RuntimeVal, line: usize) -> Result<(), RuntimeError> {
+        if let RuntimeVal::Instance { class_name, class, instance_env } = instance
+            && self.resolve_method(class, "__drop__", line)?.is_some()
+        {
+            let droppable = Droppable {
+                order: self.droppable,
+                class_name: class_name.clone(),
+                class: Rc::clone(class),
+            };
+            self.droppable += 1;
+            track_drop(instance_env, droppable);
+        }
+        Ok(())
+    }
+
+    /// Calls `__drop__` on every tracked instance nothing refers to anymore,
+    /// including those its finalizer let go of in turn.
+    pub(crate) fn run_finalizers(&mut self, line: usize) -> Result<(), RuntimeError> {
+        while has_released() {
+            for (droppable, fields) in take_released() {
+                let instance = make_instance(&droppable.class_name, droppable.class, fields);
+                if let Some(method) = self.find_method(&instance, "__drop__")? {
+                    self.call(method, &[], line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn call_unprofiled(
+        &mut self,
+        call: RuntimeVal,
+        args: &[RuntimeVal],
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        if self.trace {
+            self.call_traced(call, args, line)
+        } else {
+            self.call_value(call, args, line)
+        }
+    }
+
+    pub fn call_value(
+        &mut self,
+        call: RuntimeVal,
+        args: &[RuntimeVal],
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        match call {
+            RuntimeVal::Class { ref name, .. } => {
+                self.check_interfaces(call.clone(), line)?;
+                let instance_env = Environment::new(None);
+                self.initialize_fields(call.clone(), &instance_env, line)?;
+                let instance = make_instance(&name[..], Rc::new(call.clone()), instance_env);
+                if let Some(constructor) = self.find_constructor(call.clone(), line)? {
+                    self.call_constructor(constructor, instance.clone(), args, line)?;
+                }
+                self.track_drop(&instance, line)?;
+                Ok(instance)
+            }
+
+            RuntimeVal::Method { declaration, closure } => {
+                let local_env = Environment::new(Some(Rc::clone(&closure)));
+                if declaration.name == "init" {
+                    // Calling an initializer directly, or through `super`, hands back the instance.
+                    self.evaluate_function_body(&declaration, args, &local_env, 2, line)?;
+                    return self.evaluate_identifier("this", &closure, line);
+                }
+                self.evaluate_function_body(&declaration, args, &local_env, 1, line)
+            }
+
+            RuntimeVal::Function {
+                declaration,
+                closure,
+            } => {
+                let local_env = Environment::new(Some(Rc::clone(&closure)));
+                self.evaluate_function_body(&declaration, args, &local_env, 0, line)
+            }
+
+            RuntimeVal::Overloaded { declarations, closure } => {
+                match declarations.iter().find(|declaration| declaration.parameters.len() == args.len()) {
+                    Some(declaration) => {
+                        let local_env = Environment::new(Some(Rc::clone(&closure)));
+                        self.evaluate_function_body(declaration, args, &local_env, 0, line)
+                    }
+                    None => {
+                        let arities: Vec<String> = declarations
+                            .iter()
+                            .map(|declaration| declaration.parameters.len().to_string())
+                            .collect();
+                        Err(RuntimeError::ArityMismatch(
                             format!(
-                                "Cannot use 'super' in '{}' class as parent class '{}' is not declared",
-                                name, parent_class
+                                "Expected {}, found {} arguments provided to function {}",
+                                arities.join(" | "),
+                                args.len(),
+                                declarations[0].name
                             ),
                             line,
+                            declarations[0].line,
                         ))
                     }
                 }
-            }
+            }
+
+            RuntimeVal::NativeFunction(func, ..) => func(self, args, line),
+            _ => Err(RuntimeError::InvalidCall("Expected function, method or class type for call expression".to_string(), line))
+        }
+    }
+
+    /// Returns the class followed by each of its superclasses, nearest first.
+    /// A superclass is looked up in the scope its subclass was declared in.
+    pub(crate) fn class_chain(
+        &mut self,
+        class: RuntimeVal,
+        line: usize,
+    ) -> Result<Vec<RuntimeVal>, RuntimeError> {
+        let mut chain = vec![class];
+        loop {
+            let (name, superclass, closure) = match chain.last() {
+                Some(RuntimeVal::Class {
+                    name, superclass, closure, ..
+                }) => (name.clone(), superclass.clone(), Rc::clone(closure)),
+                _ => return Err(RuntimeError::InternalError),
+            };
+            match superclass {
+                Some(parent) => match lookup_var(&closure, &parent[..]) {
+                    Ok(val) => chain.push(val),
+                    Err(_) => {
+                        return Err(RuntimeError::EnvironmentError(
+                            format!(
+                                "'{}' superclass is not defined but is inherited by class '{}'.",
+                                parent, name
+                            ),
+                            line,
+                        ));
+                    }
+                },
+                None => return Ok(chain),
+            }
+        }
+    }
+
+    /// Checks that a class has every method required by the interfaces it
+    /// and its superclasses implement, before it is instantiated.
+    fn check_interfaces(&mut self, class: RuntimeVal, line: usize) -> Result<(), RuntimeError> {
+        let chain = self.class_chain(class, line)?;
+        let RuntimeVal::Class { name: class_name, .. } = &chain[0] else {
+            return Err(RuntimeError::InternalError);
+        };
+        for class in &chain {
+            let RuntimeVal::Class { name, interfaces, closure, .. } = class else {
+                return Err(RuntimeError::InternalError);
+            };
+            for interface in interfaces.iter() {
+                let interface = match lookup_var(closure, &interface[..]) {
+                    Ok(RuntimeVal::Interface(interface)) => interface,
+                    Ok(_) => {
+                        return Err(RuntimeError::TypeMismatch(
+                            format!("'{}' is not an interface but is implemented by class '{}'", interface, name),
+                            line,
+                        ));
+                    }
+                    Err(_) => {
+                        return Err(RuntimeError::EnvironmentError(
+                            format!(
+                                "'{}' interface is not defined but is implemented by class '{}'.",
+                                interface, name
+                            ),
+                            line,
+                        ));
+                    }
+                };
+                if let Some((method, parameters, _)) = missing_method(&chain, &interface) {
+                    return Err(RuntimeError::TypeMismatch(
+                        format!(
+                            "Class '{}' cannot be instantiated as it does not implement method '{}({})' of interface '{}'",
+                            class_name,
+                            method,
+                            parameters.join(", "),
+                            interface.name
+                        ),
+                        line,
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up a method on an instance's class or its superclasses and
+    /// binds it to the instance.
+    pub fn find_method(
+        &mut self,
+        instance: &RuntimeVal,
+        method_name: &str,
+    ) -> Result<Option<RuntimeVal>, RuntimeError> {
+        let RuntimeVal::Instance { class, .. } = instance else {
+            return Ok(None);
+        };
+        Ok(self.resolve_method(class, method_name, 0)?.map(|method| bind_method(method, instance.clone())))
+    }
+
+    /// Finds the instance method `name` declared by a class or its nearest
+    /// superclass declaring one. A class remembers each method it resolves,
+    /// so only the first call walks up the superclass chain, until a class
+    /// is replaced by one of the same name.
+    fn resolve_method(
+        &mut self,
+        class: &RuntimeVal,
+        name: &str,
+        line: usize,
+    ) -> Result<Option<RuntimeVal>, RuntimeError> {
+        let RuntimeVal::Class { method_cache, .. } = class else {
+            return Ok(None);
+        };
+        let generation = class_generation();
+        {
+            let mut cache = method_cache.borrow_mut();
+            if cache.generation != generation {
+                cache.methods.clear();
+                cache.generation = generation;
+            } else if let Some(method) = cache.methods.get(name) {
+                return Ok(Some(method.clone()));
+            }
+        }
+        for ancestor in self.class_chain(class.clone(), line)? {
+            if let RuntimeVal::Class { methods, .. } = ancestor
+                && let Some(method) = methods.get(name)
+            {
+                method_cache.borrow_mut().methods.insert(name.to_string(), method.clone());
+                return Ok(Some(method.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds the constructor a class runs on instantiation, falling back to the
+    /// nearest superclass that declares one.
+    fn find_constructor(
+        &mut self,
+        class: RuntimeVal,
+        line: usize,
+    ) -> Result<Option<RuntimeVal>, RuntimeError> {
+        for class in self.class_chain(class, line)? {
+            if let RuntimeVal::Class { name, methods, .. } = class
+                && let Some(constructor) = methods.get("init").or_else(|| methods.get(name.as_str()))
+            {
+                return Ok(Some(constructor.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Declares the instance fields of a class and its superclasses on a new
+    /// instance, superclass fields first so subclasses can override defaults.
+    fn initialize_fields(
+        &mut self,
+        class: RuntimeVal,
+        instance_env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<(), RuntimeError> {
+        for class in self.class_chain(class, line)?.into_iter().rev() {
+            if let RuntimeVal::Class {
+                instance_fields,
+                closure,
+                ..
+            } = class
+            {
+                for field in instance_fields.iter() {
+                    let value = self.evaluate_expr(&field.value, &closure)?;
+                    if declare_var(instance_env, &field.identifier[..], value.clone(), field.constant).is_err() {
+                        overwrite_var(instance_env, &field.identifier[..], value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn call_constructor(
+        &mut self,
+        constructor: RuntimeVal,
+        instance: RuntimeVal,
+        args: &[RuntimeVal],
+        line: usize,
+    ) -> Result<(), RuntimeError> {
+        if let RuntimeVal::Function {
+            declaration,
+            closure,
+        } = constructor
+            && let RuntimeVal::Method { closure, .. } = make_method(&declaration, &closure, instance)
+        {
+            let local_env = Environment::new(Some(closure));
+            let _ = self.evaluate_function_body(&declaration, args, &local_env, 2, line)?;
+        }
+        Ok(())
+    }
+
+    fn evaluate_super_call(
+        &mut self,
+        class_name: &str,
+        args: &[RuntimeVal],
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let parent = self.evaluate_super_expr(class_name, env, line)?;
+        let instance = self.evaluate_identifier("this", env, line)?;
+        match self.find_constructor(parent, line)? {
+            Some(constructor) => {
+                self.call_constructor(constructor, instance.clone(), args, line)?
+            }
+            None if !args.is_empty() => {
+                return Err(RuntimeError::InvalidArgumentCount(
+                    format!(
+                        "Expected 0, found {} arguments provided to 'super' as no superclass of '{}' declares a constructor",
+                        args.len(),
+                        class_name
+                    ),
+                    line,
+                ));
+            }
+            None => {}
+        }
+        Ok(instance)
+    }
+
+    fn evaluate_member_expr(
+        &mut self,
+        object: &Expr,
+        property: &Expr,
+        computed: bool,
+        optional: bool,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<Option<RuntimeVal>, RuntimeError> {
+        let obj = match self.evaluate_chain(object, env)? {
+            Some(RuntimeVal::Nil) if optional => return Ok(None),
+            Some(obj) => obj,
+            None => return Ok(None),
+        };
+        let key = self.member_key(property, computed, env)?;
+        self.read_member(obj, key, object, env, line).map(Some)
+    }
+
+    fn evaluate_slice_expr(
+        &mut self,
+        object: &Expr,
+        start: &Option<Box<Expr>>,
+        end: &Option<Box<Expr>>,
+        optional: bool,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<Option<RuntimeVal>, RuntimeError> {
+        let obj = match self.evaluate_chain(object, env)? {
+            Some(RuntimeVal::Nil) if optional => return Ok(None),
+            Some(obj) => obj,
+            None => return Ok(None),
+        };
+        let mut bounds = [None, None];
+        for (bound, expr) in bounds.iter_mut().zip([start, end]) {
+            let Some(expr) = expr else { continue };
+            *bound = match self.evaluate_expr(expr, env)? {
+                RuntimeVal::Number(num) if num.fract() == 0.0 => Some(num),
+                other => {
+                    return Err(RuntimeError::InvalidArrayIndex(
+                        format!("'{}' is an invalid slice bound. Slices can only be taken with integers", self.stringify(&other)?),
+                        line,
+                    ));
+                }
+            };
+        }
+        match slice_between(&obj, bounds[0].unwrap_or(0.0), bounds[1]) {
+            Some(sliced) => Ok(Some(sliced)),
+            None => Err(RuntimeError::InvalidMemberAccess("[:]".into(), line)),
+        }
+    }
+
+    fn member_key<'a>(
+        &mut self,
+        property: &'a Expr,
+        computed: bool,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<Key<'a>, RuntimeError> {
+        if computed {
+            return Ok(Key::Index(self.evaluate_expr(property, env)?));
+        }
+        match property {
+            Expr::Identifier(name, _) => Ok(Key::Name(name)),
+            _ => Err(RuntimeError::InternalError),
+        }
+    }
+
+    // `object` is the expression `obj` came from, which decides whether
+    // private members may be read
+    fn read_member(
+        &mut self,
+        mut obj: RuntimeVal,
+        key: Key,
+        object: &Expr,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        match key {
+            Key::Index(key) => match (obj, key) {
+                (RuntimeVal::Object(map), RuntimeVal::String(str)) => {
+                    let value = map.get(&str[..]);
+                    match value {
+                        Some(val) => Ok(val.clone()),
+                        None => Ok(make_nil()),
+                    }
+                }
+
+                (RuntimeVal::Map(map), key) => match map.get(&map_key(&key, line)?) {
+                    Some(val) => Ok(val.clone()),
+                    None => Ok(make_nil()),
+                },
+
+                (RuntimeVal::String(str), RuntimeVal::Number(num)) => {
+                    if num < 0.0 || num.fract() != 0.0 {
+                        return Err(RuntimeError::InvalidArrayIndex(format!("'{}' is an invalid type. Arrays can only be accessed with positive integers", num), line));
+                    }
+                    // Strings are indexed by character, as `len` counts them
+                    match str.chars().nth(num as usize) {
+                        Some(c) => Ok(make_string(&c.to_string())),
+                        None => Err(RuntimeError::ArrayIndexOutOfBounds("Array index is out of bounds".to_string(), line)),
+                    }
+                }
+
+                (RuntimeVal::Array(arr), RuntimeVal::Number(num)) => {
+                    if num < 0.0 || num.fract() != 0.0 {
+                        return Err(RuntimeError::InvalidArrayIndex(format!("'{}' is an invalid type. Arrays can only be accessed with positive integers", num), line));
+                    }
+                    let pos_num = num as usize;
+                    if pos_num >= arr.len() {
+                        return Err(RuntimeError::ArrayIndexOutOfBounds("Array index is out of bounds".to_string(), line));
+                    }
+                    Ok(arr[pos_num].clone())
+                }
+
+                (RuntimeVal::Bytes(bytes), RuntimeVal::Number(num)) => {
+                    if num < 0.0 || num.fract() != 0.0 {
+                        return Err(RuntimeError::InvalidArrayIndex(format!("'{}' is an invalid type. Bytes can only be accessed with positive integers", num), line));
+                    }
+                    let pos_num = num as usize;
+                    if pos_num >= bytes.len() {
+                        return Err(RuntimeError::ArrayIndexOutOfBounds("Bytes index is out of bounds".to_string(), line));
+                    }
+                    Ok(make_number(bytes[pos_num] as f64))
+                }
+
+                _ => Err(RuntimeError::InvalidMemberAccess("[]".into(), line)),
+            },
+            Key::Name(lexeme) => {
+                // Methods reached through `super` still belong to the current instance.
+                let mut method_exists = match object {
+                    Expr::Super(..) => {
+                        let instance = self.evaluate_identifier("this", env, line)?;
+                        if let Some(method) = self.resolve_method(&obj, lexeme, line)? {
+                            return Ok(bind_method(method, instance));
+                        }
+                        Some(instance)
+                    }
+                    _ => None,
+                };
+                loop {
+                    match obj {
+                        RuntimeVal::Object(map) => {
+                            let res = map.get(lexeme);
+                            return match res {
+                                Some(value) => Ok(value.clone()),
+                                None => {
+                                    Err(RuntimeError::UndefinedField(
+                                        format!("Object has no field named '{}'", lexeme),
+                                        line,
+                                    ))
+                                }
+                            }
+                        }
+
+                        RuntimeVal::Class {
+                            name,
+                            static_fields,
+                            methods,
+                            static_methods,
+                            superclass,
+                            closure,
+                            ..
+                        } => {
+                            check_private(object, lexeme, &name, line)?;
+                            if let Some(method) = static_methods.get(lexeme) {
+                                return Ok(method.clone());
+                            }
+                            let method = methods.get(lexeme);
+                            if let Some(method) = method {
+                                return match (method_exists, method) {
+                                    (Some(val), RuntimeVal::Function { declaration, closure }) => {
+                                        Ok(make_method(declaration, closure, val))
+                                    }
+                                    _ => Err(RuntimeError::TypeMismatch(
+                                        format!(
+                                            "'{}' is an instance method of class '{}' and needs an instance. Declare it 'static' to call it on the class",
+                                            lexeme, name
+                                        ),
+                                        line,
+                                    )),
+                                };
+                            }
+                            if let Ok(static_field) = lookup_var(&static_fields, lexeme) {
+                                return Ok(static_field);
+                            }
+
+                            match superclass {
+                                Some(parent) => {
+                                    obj = match lookup_var(&closure, &parent[..]) {
+                                        Ok(val) => val,
+                                        Err(_) => {
+                                            return Err(RuntimeError::EnvironmentError(
+                                                format!(
+                                                    "'{}' superclass is not defined but is inherited by class '{}'.",
+                                                    parent, name
+                                                ),
+                                                line,
+                                            ));
+                                        }
+                                    };
+                                }
+                                None => {
+                                    return Err(RuntimeError::UndefinedProperty(
+                                        format!(
+                                            "Property '{}' is not defined in class '{}' or superclasses",
+                                            lexeme, name
+                                        ),
+                                        line,
+                                    ));
+                                }
+                            }
+                        }
+
+                        RuntimeVal::Instance {
+                            class_name,
+                            class,
+                            instance_env,
+                        } => {
+                            check_private(object, lexeme, &class_name, line)?;
+                            match lookup_var(&instance_env, lexeme) {
+                                Ok(value) => return Ok(value),
+                                Err(_) => {
+                                    let instance = make_instance(&class_name[..], Rc::clone(&class), instance_env);
+                                    if let Some(method) = self.resolve_method(&class, lexeme, line)? {
+                                        return Ok(bind_method(method, instance));
+                                    }
+                                    // Static methods and fields are found on the class
+                                    obj = RuntimeVal::clone(&class);
+                                    method_exists = Some(instance);
+                                    continue;
+                                }
+                            }
+                        }
+
+                        _ => return Err(RuntimeError::InvalidMemberAccess(".".into(), line)),
+                    }
+                }
+            }
+        }
+    }
+
+    fn equate_member_expr(
+        &mut self,
+        object: &Expr,
+        property: &Expr,
+        computed: bool,
+        value: &Expr,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let result = self.evaluate_expr(value, env)?;
+
+        // Every container on the way to the target with the key into it,
+        // innermost first: `a.b[i] = v` gives `a` with `.b`, then `a.b` with `[i]`
+        let mut path = vec![(object, property, computed, line)];
+        let mut root = object;
+        while let Expr::Member {
+            object,
+            property,
+            computed,
+            line,
+            ..
+        } = root
+        {
+            path.push((object, property, *computed, *line));
+            root = object;
+        }
+        path.reverse();
+
+        // Each key is evaluated once, in source order
+        let mut containers = vec![];
+        let mut obj = self.evaluate_expr(root, env)?;
+        for (index, (object, property, computed, line)) in path.iter().enumerate() {
+            let key = self.member_key(property, *computed, env)?;
+            let next = if index + 1 < path.len() {
+                self.read_member(obj.clone(), key.clone(), object, env, *line)?
+            } else {
+                make_nil()
+            };
+            containers.push((std::mem::replace(&mut obj, next), key, *object, *line));
+        }
+
+        // Containers are values, so each updated copy is written into the
+        // one holding it, up to the variable at the root. Instances and
+        // classes are updated in place, which ends the walk.
+        let mut value = result.clone();
+        for (obj, key, object, line) in containers.into_iter().rev() {
+            match self.write_member(obj, key, value, object, line)? {
+                Some(updated) => value = updated,
+                None => return Ok(result),
+            }
+        }
+        store_container(root, value, env, line)?;
+        Ok(result)
+    }
+
+    // Sets `key` of `obj` to `result`. Gives the updated copy of a value
+    // container, or `None` when an instance or class was updated in place.
+    fn write_member(
+        &mut self,
+        obj: RuntimeVal,
+        key: Key,
+        result: RuntimeVal,
+        object: &Expr,
+        line: usize,
+    ) -> Result<Option<RuntimeVal>, RuntimeError> {
+        match key {
+            Key::Index(key) => match (obj, key) {
+                (RuntimeVal::Object(mut map), RuntimeVal::String(str)) => {
+                    map.insert(str.to_string(), result);
+                    Ok(Some(make_obj(&map)))
+                }
+
+                (RuntimeVal::Map(mut map), key) => {
+                    map.insert(map_key(&key, line)?, result);
+                    Ok(Some(RuntimeVal::Map(map)))
+                }
+
+                (RuntimeVal::String(str), RuntimeVal::Number(num)) => {
+                    if num < 0.0 || num.fract() != 0.0 {
+                        return Err(RuntimeError::InvalidArrayIndex(format!("'{}' is an invalid type. Arrays can only be accessed with positive integers", num), line));
+                    }
+                    let Some((offset, c)) = str.char_indices().nth(num as usize) else {
+                        return Err(RuntimeError::ArrayIndexOutOfBounds("Array index is out of bounds".to_string(), line));
+                    };
+                    let res = match result {
+                        RuntimeVal::String(ref s) => s,
+                        _ => return Err(RuntimeError::TypeMismatch("Cannot assign non-string type value to string index".to_string(), line))
+                    };
+                    let new_str = format!("{}{}{}", &str[..offset], res, &str[offset + c.len_utf8()..]);
+                    Ok(Some(make_string(&new_str)))
+                }
+
+                (RuntimeVal::Array(mut arr), RuntimeVal::Number(num)) => {
+                    if num < 0.0 || num.fract() != 0.0 {
+                        return Err(RuntimeError::InvalidArrayIndex(format!("'{}' is an invalid type. Arrays can only be accessed with positive integers", num), line));
+                    }
+                    let pos_num = num as usize;
+                    if pos_num >= arr.len() {
+                        return Err(RuntimeError::ArrayIndexOutOfBounds("Array index is out of bounds".to_string(), line));
+                    }
+                    arr[pos_num] = result;
+                    Ok(Some(make_arr(&arr)))
+                }
+
+                (RuntimeVal::Bytes(mut bytes), RuntimeVal::Number(num)) => {
+                    if num < 0.0 || num.fract() != 0.0 {
+                        return Err(RuntimeError::InvalidArrayIndex(format!("'{}' is an invalid type. Bytes can only be accessed with positive integers", num), line));
+                    }
+                    let pos_num = num as usize;
+                    if pos_num >= bytes.len() {
+                        return Err(RuntimeError::ArrayIndexOutOfBounds("Bytes index is out of bounds".to_string(), line));
+                    }
+                    bytes[pos_num] = match result {
+                        RuntimeVal::Number(byte) if byte.fract() == 0.0 && (0.0..=255.0).contains(&byte) => byte as u8,
+                        _ => return Err(RuntimeError::TypeMismatch("Only integers from 0 to 255 can be assigned to a bytes index".to_string(), line)),
+                    };
+                    Ok(Some(RuntimeVal::Bytes(bytes)))
+                }
+
+                _ => Err(RuntimeError::InvalidMemberAccess("[]".into(), line)),
+            },
+            Key::Name(lexeme) => match obj {
+                RuntimeVal::Object(mut map) => {
+                    map.insert(lexeme.to_string(), result);
+                    Ok(Some(make_obj(&map)))
+                }
+
+                RuntimeVal::Class {
+                    name,
+                    static_fields,
+                    methods,
+                    static_methods,
+                    ..
+                } => {
+                    check_private(object, lexeme, &name, line)?;
+                    if methods.contains_key(lexeme) || static_methods.contains_key(lexeme) {
+                        return Err(RuntimeError::TypeMismatch(
+                            format!(
+                                "Cannot assign value to method '{}' of class '{}'",
+                                lexeme, name
+                            ),
+                            line,
+                        ));
+                    }
+                    if is_frozen(&static_fields) {
+                        return Err(RuntimeError::EnvironmentError(
+                            format!("Cannot assign to static field '{}' of frozen class '{}'", lexeme, name),
+                            line,
+                        ));
+                    }
+                    match assign_var(&static_fields, lexeme, result.clone()) {
+                        Ok(_) => {}
+                        Err(EnvironmentError::VarNotDeclared) => {
+                            let _ = declare_var(&static_fields, lexeme, result.clone(), false);
+                        }
+                        Err(_) => {
+                            return Err(RuntimeError::EnvironmentError(
+                                format!(
+                                    "'{}' is a constant static field of class '{}'. Constant values cannot be reassigned.",
+                                    lexeme, name
+                                ),
+                                line,
+                            ));
+                        }
+                    }
+                    Ok(None)
+                }
+
+                RuntimeVal::Instance { instance_env, class_name, .. } => {
+                    check_private(object, lexeme, &class_name, line)?;
+                    if is_frozen(&instance_env) {
+                        return Err(RuntimeError::EnvironmentError(
+                            format!("Cannot assign to field '{}' of a frozen instance of class '{}'", lexeme, class_name),
+                            line,
+                        ));
+                    }
+                    if let Err(_) = declare_var(&instance_env, lexeme, result.clone(), false) {
+                        if let Err(_) = assign_var(&instance_env, lexeme, result.clone()) {
+                            return Err(RuntimeError::EnvironmentError(
+                                format!(
+                                    "'{}' is a constant field of class '{}'. Constant values cannot be reassigned.",
+                                    lexeme, class_name
+                                ),
+                                line,
+                            ));
+                        }
+                    }
+                    Ok(None)
+                }
+
+                _ => Err(RuntimeError::InvalidMemberAccess(".".into(), line)),
+            },
         }
     }
-    Err(RuntimeError::InternalError)
 }
 
-fn evaluate_object_expr(
-    obj: &[Property],
-    env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeVal, RuntimeError> {
-    let mut map = HashMap::new();
-
-    for prop in obj {
-        let runtime_val;
-        if let Some(expr) = &prop.value {
-            runtime_val = evaluate_expr(&expr, env)?;
-        } else {
-            runtime_val = match lookup_var(env, &prop.key[..]) {
-                Ok(val) => val,
-                Err(_) => {
-                    return Err(RuntimeError::EnvironmentError(
-                        format!("{} is not declared yet.", prop.key),
-                        prop.line,
-                    ));
-                }
-            }
-        }
-        map.insert(prop.key.clone(), runtime_val);
+pub fn map_key(key: &RuntimeVal, line: usize) -> Result<MapKey, RuntimeError> {
+    match MapKey::from_value(key) {
+        Some(key) => Ok(key),
+        None => Err(RuntimeError::TypeMismatch(
+            "Map keys must be numbers, strings or bools".to_string(),
+            line,
+        )),
     }
-    Ok(make_obj(&map))
 }
 
-fn evaluate_array_expr(
-    array: &[Expr],
-    env: &Rc<RefCell<Environment>>,
-) -> Result<RuntimeVal, RuntimeError> {
-    let mut val = vec![];
-
-    for arr in array {
-        val.push(evaluate_expr(arr, env)?);
+// Members whose name starts with '_' are private to their class and can only
+// be reached through `this` or `super`.
+fn check_private(object: &Expr, member: &str, class_name: &str, line: usize) -> Result<(), RuntimeError> {
+    if member.starts_with('_') && !matches!(object, Expr::This(_) | Expr::Super(..)) {
+        return Err(RuntimeError::PrivateAccess(
+            format!(
+                "'{}' is a private member of class '{}' and can only be accessed through 'this'",
+                member, class_name
+            ),
+            line,
+        ));
     }
+    Ok(())
+}
 
-    Ok(make_arr(&val))
+// The member an access or assignment refers to: `.name` or `[key]`
+#[derive(Clone)]
+enum Key<'a> {
+    Name(&'a str),
+    Index(RuntimeVal),
 }
 
-fn evaluate_compare_expr(
-    left: &Expr,
-    operator: &Token,
-    right: &Expr,
+// Containers are values, so an updated copy has to be written back to the
+// variable that holds it.
+fn store_container(
+    object: &Expr,
+    value: RuntimeVal,
     env: &Rc<RefCell<Environment>>,
     line: usize,
-) -> Result<RuntimeVal, RuntimeError> {
-    let left_hand_side = evaluate_expr(left, env)?;
-    let right_hand_side = evaluate_expr(right, env)?;
-
-    if operator.token_type == TokenType::AND || operator.token_type == TokenType::OR {
-        evaluate_logical_expr(left_hand_side, right_hand_side, &operator.lexeme[..], line)
-    } else if operator.token_type == TokenType::EQUALEQUAL
-        || operator.token_type == TokenType::BANGEQUAL
-    {
-        evaluate_equality_expr(left_hand_side, right_hand_side, &operator.lexeme[..], line)
-    } else {
-        evaluate_comparison_expr(
-            left_hand_side,
-            right_hand_side,
-            &operator.lexeme[..],
+) -> Result<(), RuntimeError> {
+    let name = match object {
+        Expr::Identifier(name, _) => name,
+        _ => {
+            return Err(RuntimeError::TypeMismatch(
+                "Only variables holding objects, maps, arrays or strings can have their members assigned".to_string(),
+                line,
+            ));
+        }
+    };
+    // A constant holds its container deeply, so changing any element of it
+    // is rejected like rebinding the name
+    let kind = type_name(&value).to_lowercase();
+    match assign_var(env, &name[..], value) {
+        Ok(_) => Ok(()),
+        Err(EnvironmentError::ConstReassign) => Err(RuntimeError::EnvironmentError(
+            format!(
+                "'{}' is a constant. The contents of a constant {} cannot be modified.",
+                name, kind
+            ),
             line,
-        )
+        )),
+        Err(_) => Err(RuntimeError::EnvironmentError(
+            format!("{} has not been declared yet.{}", name, suggest_name(env, name)),
+            line,
+        )),
     }
 }
 
+fn evaluate_numeric_binary_expr(lhs: f64, rhs: f64, operator: &str) -> RuntimeVal {
+    make_number(match operator {
+        "+" => lhs + rhs,
+        "-" => lhs - rhs,
+        "*" => lhs * rhs,
+        "/" => lhs / rhs,
+        _ => lhs % rhs,
+    })
+}
+
 fn evaluate_logical_expr(
     left: RuntimeVal,
     right: RuntimeVal,
@@ -267,8 +1420,8 @@ fn evaluate_equality_expr(
         }
     }
 
-    if let RuntimeVal::String(str1) = left {
-        if let RuntimeVal::String(str2) = right {
+    if let RuntimeVal::String(ref str1) = left {
+        if let RuntimeVal::String(ref str2) = right {
             return Ok(make_bool(match operator {
                 "==" => str1 == str2,
                 _ => str1 != str2,
@@ -276,15 +1429,93 @@ fn evaluate_equality_expr(
         }
     }
 
+    if let (RuntimeVal::Bytes(bytes1), RuntimeVal::Bytes(bytes2)) = (&left, &right) {
+        return Ok(make_bool(match operator {
+            "==" => bytes1 == bytes2,
+            _ => bytes1 != bytes2,
+        }));
+    }
+
+    if let Some(same) = same_callable(&left, &right) {
+        return Ok(make_bool(match operator {
+            "==" => same,
+            _ => !same,
+        }));
+    }
+
     Err(RuntimeError::TypeMismatch(
         format!(
-            "{} equality operation is only valid for numbers, bools and strings",
+            "{} equality operation is only valid for numbers, bools, strings, bytes and callables",
             operator
         ),
         line,
     ))
 }
 
+/// The first method of `interface` that a class, given with its
+/// superclasses by `class_chain`, lacks or declares with a different number
+/// of parameters. The nearest class declaring a method is the one that counts.
+pub fn missing_method<'a>(
+    chain: &[RuntimeVal],
+    interface: &'a InterfaceDeclaration,
+) -> Option<&'a (String, Vec<String>, usize)> {
+    interface.methods.iter().find(|(name, parameters, _)| {
+        let method = chain.iter().find_map(|class| match class {
+            RuntimeVal::Class { methods, .. } => methods.get(name),
+            _ => None,
+        });
+        !matches!(method, Some(RuntimeVal::Function { declaration, .. }) if declaration.parameters.len() == parameters.len())
+    })
+}
+
+// Functions, natives, methods and classes are equal when they are the same
+// callable. Methods also need to be bound to the same instance. `None` when
+// either value cannot be called.
+fn same_callable(left: &RuntimeVal, right: &RuntimeVal) -> Option<bool> {
+    let callable = |val: &RuntimeVal| {
+        matches!(
+            val,
+            RuntimeVal::Function { .. }
+                | RuntimeVal::Overloaded { .. }
+                | RuntimeVal::NativeFunction(..)
+                | RuntimeVal::Method { .. }
+                | RuntimeVal::Class { .. }
+        )
+    };
+    if !callable(left) || !callable(right) {
+        return None;
+    }
+    Some(match (left, right) {
+        (
+            RuntimeVal::Function { declaration: a, closure: a_closure },
+            RuntimeVal::Function { declaration: b, closure: b_closure },
+        ) => Rc::ptr_eq(a, b) && Rc::ptr_eq(a_closure, b_closure),
+        (RuntimeVal::Overloaded { declarations: a, .. }, RuntimeVal::Overloaded { declarations: b, .. }) => {
+            Rc::ptr_eq(a, b)
+        }
+        // Natives are installed once each, under their own name
+        (RuntimeVal::NativeFunction(_, a), RuntimeVal::NativeFunction(_, b)) => a == b,
+        (
+            RuntimeVal::Method { declaration: a, closure: a_closure },
+            RuntimeVal::Method { declaration: b, closure: b_closure },
+        ) => {
+            let instance = |closure| match lookup_var(closure, "this") {
+                Ok(RuntimeVal::Instance { instance_env, .. }) => Some(instance_env),
+                _ => None,
+            };
+            Rc::ptr_eq(a, b)
+                && match (instance(a_closure), instance(b_closure)) {
+                    (Some(a), Some(b)) => Rc::ptr_eq(&a, &b),
+                    _ => false,
+                }
+        }
+        (RuntimeVal::Class { static_fields: a, .. }, RuntimeVal::Class { static_fields: b, .. }) => {
+            Rc::ptr_eq(a, b)
+        }
+        _ => false,
+    })
+}
+
 fn evaluate_comparison_expr(
     left: RuntimeVal,
     right: RuntimeVal,
@@ -332,451 +1563,3 @@ fn evaluate_comparison_expr(
         line,
     ))
 }
-
-fn evaluate_assignment(
-    assignee: &Expr,
-    value: &Expr,
-    env: &Rc<RefCell<Environment>>,
-    line: usize,
-) -> Result<RuntimeVal, RuntimeError> {
-    match assignee {
-        Expr::Identifier(ident, line) => {
-            let value = evaluate_expr(value, env)?;
-            match assign_var(env, &ident[..], value) {
-                Ok(val) => {
-                    Ok(val)
-                }
-                Err(err) => match err {
-                    EnvironmentError::ConstReassign => {
-                        Err(RuntimeError::EnvironmentError(
-                            format!(
-                                "{} is a constant. Constant values cannot be reassigned",
-                                ident
-                            ),
-                            *line,
-                        ))
-                    }
-                    EnvironmentError::VarNotDeclared => {
-                        Err(RuntimeError::EnvironmentError(
-                            format!("{} has not been declared yet.", ident),
-                            *line,
-                        ))
-                    }
-                    EnvironmentError::ReDeclareVar => {
-                        Err(RuntimeError::InternalError)
-                    }
-                },
-            }
-        }
-        Expr::Member {
-            object,
-            property,
-            computed,
-            line,
-        } => {
-            let _ = equate_member_expr(object, property, *computed, value, env, *line);
-            evaluate_expr(value, env)
-        }
-        _ => Err(RuntimeError::TypeMismatch(
-            "Only variables and member expressions can be assigned values".into(),
-            line,
-        )),
-    }
-}
-
-fn evaluate_function_body(
-    name: &str,
-    args: &[Expr],
-    params: &[String],
-    body: &[Stmt],
-    env: &Rc<RefCell<Environment>>,
-    local_env: &Rc<RefCell<Environment>>,
-    index: usize,
-    line: usize,
-) -> Result<RuntimeVal, RuntimeError> {
-    let callable = ["function", "method", "constructor"];
-
-    if args.len() != params.len() {
-        return Err(RuntimeError::InvalidArgumentCount(
-            format!(
-                "Expected {}, found {} arguments provided to {} {}",
-                args.len(),
-                params.len(),
-                callable[index],
-                name
-            ),
-            line,
-        ));
-    }
-
-    for i in 0..args.len() {
-        let value = evaluate_expr(&args[i], env)?;
-        if let Err(_) = declare_var(&local_env, &params[i][..], value, false) {
-            return Err(RuntimeError::EnvironmentError(
-                format!(
-                    "{} is already declared. Cannot redeclare variable with same name",
-                    params[i]
-                ),
-                line,
-            ));
-        }
-    }
-
-    for stmt in body {
-        match evaluate(&stmt, local_env)? {
-            EvalResult::Return(val) => return Ok(val),
-            _ => continue,
-        }
-    }
-
-    Ok(make_nil())
-}
-
-fn evaluate_function_call(
-    args: &[Expr],
-    caller: &Expr,
-    env: &Rc<RefCell<Environment>>,
-    line: usize,
-) -> Result<RuntimeVal, RuntimeError> {
-    let call = evaluate_expr(caller, env)?;
-    match call {
-        RuntimeVal::Class { name, methods, .. } => {
-            let instance_env = Environment::new(None);
-            let class_constructor = methods.get(name.as_str());
-            let instance = make_instance(&name[..], instance_env);
-            if let Some(func) = class_constructor {
-                if let RuntimeVal::Function {
-                    name,
-                    params,
-                    body,
-                    closure,
-                } = func
-                {
-                    let local_env = Environment::new(Some(Rc::clone(&closure)));
-                    if let Err(_) = declare_var(&local_env, "this", instance.clone(), false) {
-                        return Err(RuntimeError::InternalError);
-                    }
-                    let _ = evaluate_function_body(
-                        &name[..],
-                        args,
-                        &params,
-                        &body,
-                        env,
-                        &local_env,
-                        2,
-                        line,
-                    )?;
-                }
-            }
-            Ok(instance)
-        }
-
-        RuntimeVal::Method { name, params, body, closure, instance } => {
-            let local_env = Environment::new(Some(Rc::clone(&closure)));
-            if let Err(_) = declare_var(&local_env, "this", *instance, true) {
-                return Err(RuntimeError::InternalError);
-            }
-            evaluate_function_body(
-                &name[..],
-                args,
-                &params,
-                &body,
-                env,
-                &local_env,
-                1,
-                line,
-            )
-        }
-
-        RuntimeVal::Function {
-            name,
-            params,
-            body,
-            closure,
-        } => {
-            let local_env = Environment::new(Some(Rc::clone(&closure)));
-            evaluate_function_body(&name[..], args, &params, &body, env, &local_env, 0, line)
-        }
-
-        RuntimeVal::NativeFunction(func, ..) => {
-            let mut values = vec![];
-            for arg in args {
-                values.push(evaluate_expr(&arg, env)?);
-            }
-            func(&values, line)
-        }
-        _ => Err(RuntimeError::InvalidCall("Expected function, method or class type for call expression".to_string(), line))
-    }
-}
-
-fn evaluate_member_expr(
-    object: &Expr,
-    property: &Expr,
-    computed: bool,
-    env: &Rc<RefCell<Environment>>,
-    line: usize,
-) -> Result<RuntimeVal, RuntimeError> {
-    let mut obj = evaluate_expr(object, env)?;
-
-    if computed {
-        let key = evaluate_expr(property, env)?;
-        match (obj, key) {
-            (RuntimeVal::Object(map), RuntimeVal::String(str)) => {
-                let value = map.get(str.as_str());
-                match value {
-                    Some(val) => Ok(val.clone()),
-                    None => Ok(make_nil()),
-                }
-            }
-
-            (RuntimeVal::String(str), RuntimeVal::Number(num)) => {
-                if num < 0.0 || num.fract() != 0.0 {
-                    return Err(RuntimeError::InvalidArrayIndex(format!("'{}' is an invalid type. Arrays can only be accessed with positive integers", num), line));
-                }
-                let pos_num = num as usize;
-                if pos_num >= str.len() {
-                    return Err(RuntimeError::ArrayIndexOutOfBounds("Array index is out of bounds".to_string(), line));
-                }
-                Ok(make_string(&str.chars().nth(pos_num).unwrap().to_string()[..]))
-            }
-
-            (RuntimeVal::Array(arr), RuntimeVal::Number(num)) => {
-                if num < 0.0 || num.fract() != 0.0 {
-                    return Err(RuntimeError::InvalidArrayIndex(format!("'{}' is an invalid type. Arrays can only be accessed with positive integers", num), line));
-                }
-                let pos_num = num as usize;
-                if pos_num >= arr.len() {
-                    return Err(RuntimeError::ArrayIndexOutOfBounds("Array index is out of bounds".to_string(), line));
-                }
-                Ok(arr[pos_num].clone())
-            }
-
-            _ => Err(RuntimeError::InvalidMemberAccess("[]".into(), line)),
-        }
-    } else {
-        let lexeme = match property {
-            Expr::Identifier(name, _) => name,
-            _ => return Err(RuntimeError::InternalError),
-        };
-        let mut method_exists = None;
-        loop {
-            match obj {
-                RuntimeVal::Object(map) => {
-                    let res = map.get(lexeme.as_str());
-                    return match res {
-                        Some(value) => Ok(value.clone()),
-                        None => {
-                            Err(RuntimeError::UndefinedField(
-                                format!("Object has no field named '{}'", lexeme),
-                                line,
-                            ))
-                        }
-                    }
-                }
-
-                RuntimeVal::Class {
-                    name,
-                    static_fields,
-                    methods,
-                    superclass,
-                    ..
-                } => {
-                    let method = methods.get(lexeme);
-                    if let Some(method) = method {
-                        if let Some(val) = method_exists {
-                            if let RuntimeVal::Function {name, params, body, closure} = method {
-                                return Ok(make_method(name, params, body, closure, val));
-                            }
-                        }
-                        return Ok(method.clone());
-                    }
-                    let static_field = static_fields.get(lexeme);
-                    if let Some(static_field) = static_field {
-                        return Ok(static_field.clone());
-                    }
-
-                    match superclass {
-                        Some(parent) => {
-                            obj = match lookup_var(env, &parent[..]) {
-                                Ok(val) => val,
-                                Err(_) => {
-                                    return Err(RuntimeError::EnvironmentError(
-                                        format!(
-                                            "'{}' superclass is not defined but is inherited by class '{}'.",
-                                            parent, name
-                                        ),
-                                        line,
-                                    ));
-                                }
-                            };
-                        }
-                        None => {
-                            return Err(RuntimeError::UndefinedProperty(
-                                format!(
-                                    "Property '{}' is not defined in class '{}' or superclasses",
-                                    lexeme, name
-                                ),
-                                line,
-                            ));
-                        }
-                    }
-                }
-
-                RuntimeVal::Instance {
-                    class_name,
-                    instance_env,
-                } => match lookup_var(&instance_env, &lexeme[..]) {
-                    Ok(value) => return Ok(value),
-                    Err(_) => match lookup_var(&env, &class_name[..]) {
-                        Ok(class) => {
-                            method_exists =
-                                Some(make_instance(&class_name[..], Rc::clone(&instance_env)));
-                            obj = class;
-                            continue;
-                        }
-                        Err(_) => return Err(RuntimeError::InternalError),
-                    },
-                },
-
-                _ => return Err(RuntimeError::InvalidMemberAccess(".".into(), line)),
-            }
-        }
-    }
-}
-
-fn equate_member_expr(
-    object: &Expr,
-    property: &Expr,
-    computed: bool,
-    value: &Expr,
-    env: &Rc<RefCell<Environment>>,
-    line: usize,
-) -> Result<RuntimeVal, RuntimeError> {
-    let result = evaluate_expr(value, env)?;
-    let obj = evaluate_expr(object, env)?;
-    let lexeme_name = match object {
-        Expr::Identifier(s, _) => s,
-        _ => return Err(RuntimeError::InternalError),
-    };
-
-    if computed {
-        let key = evaluate_expr(property, env)?;
-        match (obj, key) {
-            (RuntimeVal::Object(mut map), RuntimeVal::String(str)) => {
-                map.insert(str, result.clone());
-                let val = make_obj(&map);
-                if let Err(_) = assign_var(env, &lexeme_name[..], val) {
-                    return Err(RuntimeError::EnvironmentError(
-                        format!(
-                            "'{}' is a constant. Constant values cannot be reassigned.",
-                            lexeme_name
-                        ),
-                        line,
-                    ));
-                }
-            }
-
-            (RuntimeVal::String(str), RuntimeVal::Number(num)) => {
-                if num < 0.0 || num.fract() != 0.0 {
-                    return Err(RuntimeError::InvalidArrayIndex(format!("'{}' is an invalid type. Arrays can only be accessed with positive integers", num), line));
-                }
-                let pos_num = num as usize;
-                if pos_num >= str.len() {
-                    return Err(RuntimeError::ArrayIndexOutOfBounds("Array index is out of bounds".to_string(), line));
-                }
-                let res = match result {
-                    RuntimeVal::String(ref s) => s,
-                    _ => return Err(RuntimeError::TypeMismatch("Cannot assign non-string type value to string index".to_string(), line))
-                };
-                let new_str = format!("{}{}{}", &str[..pos_num], res, &str[pos_num+1..]);
-                let val = make_string(&new_str);
-                if let Err(_) = assign_var(env, &lexeme_name[..], val) {
-                    return Err(RuntimeError::EnvironmentError(
-                        format!(
-                            "'{}' is a constant. Constant values cannot be reassigned.",
-                            lexeme_name
-                        ),
-                        line,
-                    ));
-                }
-            }
-
-            (RuntimeVal::Array(mut arr), RuntimeVal::Number(num)) => {
-                if num < 0.0 || num.fract() != 0.0 {
-                    return Err(RuntimeError::InvalidArrayIndex(format!("'{}' is an invalid type. Arrays can only be accessed with positive integers", num), line));
-                }
-                let pos_num = num as usize;
-                if pos_num >= arr.len() {
-                    return Err(RuntimeError::ArrayIndexOutOfBounds("Array index is out of bounds".to_string(), line));
-                }
-                arr[pos_num] = result.clone();
-                let val = make_arr(&arr);
-                if let Err(_) = assign_var(env, &lexeme_name[..], val) {
-                    return Err(RuntimeError::EnvironmentError(
-                        format!(
-                            "'{}' is a constant. Constant values cannot be reassigned.",
-                            lexeme_name
-                        ),
-                        line,
-                    ));
-                }
-            }
-
-            _ => return Err(RuntimeError::InvalidMemberAccess("[]".into(), line)),
-        }
-    } else {
-        let lexeme = match property {
-            Expr::Identifier(name, _) => name,
-            _ => return Err(RuntimeError::InternalError),
-        };
-        match obj {
-            RuntimeVal::Object(mut map) => {
-                map.insert(lexeme.clone(), result.clone());
-                let val = make_obj(&map);
-                if let Err(_) = assign_var(env, &lexeme_name[..], val) {
-                    return Err(RuntimeError::EnvironmentError(
-                        format!(
-                            "'{}' is a constant. Constant values cannot be reassigned.",
-                            lexeme_name
-                        ),
-                        line,
-                    ));
-                }
-            }
-
-            RuntimeVal::Class {
-                name,
-                mut static_fields,
-                methods,
-                superclass,
-            } => {
-                let method = methods.get(lexeme);
-                if let Some(_) = method {
-                    return Err(RuntimeError::TypeMismatch(
-                        format!(
-                            "Cannot assign value to method '{}' of class '{}'",
-                            lexeme, name
-                        ),
-                        line,
-                    ));
-                }
-                static_fields.insert(lexeme.clone(), result.clone());
-                let val = make_class(&name, static_fields, methods, superclass);
-                if let Err(_) = assign_var(env, &name[..], val) {
-                    return Err(RuntimeError::InternalError);
-                }
-            }
-
-            RuntimeVal::Instance { instance_env, .. } => {
-                if let Err(_) = declare_var(&instance_env, &lexeme[..], result.clone(), false) {
-                    if let Err(_) = assign_var(&instance_env, &lexeme[..], result.clone()) {
-                        return Err(RuntimeError::InternalError);
-                    }
-                }
-            }
-
-            _ => return Err(RuntimeError::InvalidMemberAccess(".".into(), line)),
-        }
-    }
-    Ok(result)
-}
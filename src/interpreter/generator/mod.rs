@@ -0,0 +1,483 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::environment::*;
+use crate::handle_errors::RuntimeError;
+use crate::interpreter::interpreter::{Interpreter, MAX_CALL_DEPTH};
+use crate::interpreter::scheduler::AsyncTask;
+use crate::interpreter::statement::{LoopItems, catch_scope, declare_loop_vars, loop_condition, matching_arm};
+use crate::values::*;
+
+/// A paused call of a function that yields, or of an async function. Its
+/// body runs one step at a time: `tasks` holds what is left to do,
+/// innermost last, so a `yield` or `await` deep inside loops and blocks can
+/// pause the body and later pick up exactly where it stopped. A generator
+/// that finished or failed has no tasks left.
+pub struct Generator {
+    declaration: Rc<FunctionDeclaration>,
+    tasks: RefCell<Vec<Task>>,
+}
+
+// A statement list is found by its path from the function body: for each
+// enclosing statement, its index and which of its bodies holds the list
+type Path = Vec<(usize, usize)>;
+
+enum Task {
+    // Statements of a list still to run, in the scope they run in
+    Run {
+        path: Path,
+        next: usize,
+        env: Rc<RefCell<Environment>>,
+    },
+    // A loop about to start its next pass, `path` leading to its body
+    Loop {
+        path: Path,
+        env: Rc<RefCell<Environment>>,
+        label: Option<String>,
+        kind: LoopKind,
+    },
+    // Errors raised by the tasks above are handed to the catch block at
+    // `path`, with the error bound to `name`
+    Try {
+        path: Path,
+        name: String,
+        env: Rc<RefCell<Environment>>,
+    },
+    // The value of a `with` statement, closed once the tasks above are done
+    // or dropped
+    With {
+        resource: RuntimeVal,
+        line: usize,
+    },
+    // The statement at `path` waits for `target` to finish, then runs with
+    // its result in place of the `await`
+    Await {
+        target: Rc<AsyncTask>,
+        path: Path,
+        env: Rc<RefCell<Environment>>,
+    },
+}
+
+enum LoopKind {
+    While,
+    Loop,
+    // The step runs before every condition check but the first
+    For { started: bool },
+    ForIn { names: Vec<String>, items: LoopItems, line: usize },
+}
+
+// Where one step of a generator's body left it
+enum Step {
+    Next,
+    Yield(RuntimeVal),
+    Await(Rc<AsyncTask>),
+    Finish(RuntimeVal),
+}
+
+/// Where the body of an async function stopped when it handed control back
+/// to the scheduler: waiting for another task, or finished with a value.
+pub(crate) enum Pause {
+    Await(Rc<AsyncTask>),
+    Finish(RuntimeVal),
+}
+
+impl Generator {
+    pub fn new(declaration: &Rc<FunctionDeclaration>, env: Rc<RefCell<Environment>>) -> Self {
+        let start = Task::Run { path: vec![], next: 0, env };
+        Generator {
+            declaration: Rc::clone(declaration),
+            tasks: RefCell::new(vec![start]),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.declaration.name
+    }
+}
+
+impl Interpreter {
+    /// Runs a generator up to its next `yield` and gives the yielded value,
+    /// or `None` once the body has finished.
+    pub fn resume_generator(
+        &mut self,
+        generator: &Generator,
+        line: usize,
+    ) -> Result<Option<RuntimeVal>, RuntimeError> {
+        match self.resume(generator, line)? {
+            Step::Yield(value) => Ok(Some(value)),
+            Step::Finish(_) => Ok(None),
+            _ => Err(RuntimeError::InternalError),
+        }
+    }
+
+    /// Runs the body of an async function up to its next `await` of a task
+    /// that has not finished, or to its end.
+    pub(crate) fn resume_body(&mut self, body: &Generator, line: usize) -> Result<Pause, RuntimeError> {
+        match self.resume(body, line)? {
+            Step::Await(target) => Ok(Pause::Await(target)),
+            Step::Finish(value) => Ok(Pause::Finish(value)),
+            _ => Err(RuntimeError::InternalError),
+        }
+    }
+
+    fn resume(&mut self, generator: &Generator, line: usize) -> Result<Step, RuntimeError> {
+        let Ok(mut tasks) = generator.tasks.try_borrow_mut() else {
+            return Err(RuntimeError::InvalidCall(
+                format!("Generator '{}' cannot be resumed while it is running", generator.name()),
+                line,
+            ));
+        };
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::StackOverflow(
+                format!("Maximum call depth of {} exceeded", MAX_CALL_DEPTH),
+                line,
+            ));
+        }
+        self.call_depth += 1;
+        let declaration = &generator.declaration;
+        let mut result = self.run_tasks(&declaration.body, declaration.asynchronous, &mut tasks);
+        if !matches!(result, Ok(Step::Yield(_) | Step::Await(_))) {
+            // Values of `with` statements the body was still inside are closed
+            let closed = self.unwind(&mut tasks, 0);
+            if let (Ok(_), Err(error)) = (&result, closed) {
+                result = Err(error);
+            }
+        }
+        self.call_depth -= 1;
+        result
+    }
+
+    // Steps through the body until it pauses or finishes. In async
+    // functions an `await` starting a statement pauses the body.
+    fn run_tasks(
+        &mut self,
+        body: &[Stmt],
+        asynchronous: bool,
+        tasks: &mut Vec<Task>,
+    ) -> Result<Step, RuntimeError> {
+        loop {
+            match self.step(body, asynchronous, tasks) {
+                Ok(Step::Next) => continue,
+                Ok(step) => return Ok(step),
+                Err(error) => self.catch(tasks, error)?,
+            }
+        }
+    }
+
+    fn step(&mut self, body: &[Stmt], asynchronous: bool, tasks: &mut Vec<Task>) -> Result<Step, RuntimeError> {
+        let (path, index, env) = match tasks.last_mut() {
+            None => return Ok(Step::Finish(make_nil())),
+            Some(Task::Loop { .. }) => return self.next_pass(body, tasks),
+            // The try body finished without an error
+            Some(Task::Try { .. }) => {
+                tasks.pop();
+                return Ok(Step::Next);
+            }
+            // The with body finished, so its value is closed
+            Some(Task::With { .. }) => {
+                self.unwind(tasks, tasks.len() - 1)?;
+                return Ok(Step::Next);
+            }
+            Some(Task::Await { target, .. }) if !target.is_done() => {
+                return Ok(Step::Await(Rc::clone(target)));
+            }
+            Some(Task::Await { .. }) => return self.finish_await(body, tasks),
+            Some(Task::Run { path, next, env }) => {
+                *next += 1;
+                (path.clone(), *next - 1, Rc::clone(env))
+            }
+        };
+        let Some(stmt) = stmts_at(body, &path).get(index) else {
+            tasks.pop();
+            return Ok(Step::Next);
+        };
+        if pauses(stmt, asynchronous) {
+            return self.enter(stmt, child(&path, index, 0), env, tasks, None);
+        }
+        match self.evaluate(stmt, &env)? {
+            // What a generator returns is not handed out
+            EvalResult::Return(value) => return Ok(Step::Finish(value)),
+            EvalResult::Break(label) => self.leave_loop(tasks, label, true)?,
+            EvalResult::Continue(label) => self.leave_loop(tasks, label, false)?,
+            _ => {}
+        }
+        Ok(Step::Next)
+    }
+
+    // Starts a statement that yields somewhere inside. `path` leads to its
+    // first body.
+    fn enter(
+        &mut self,
+        stmt: &Stmt,
+        path: Path,
+        env: Rc<RefCell<Environment>>,
+        tasks: &mut Vec<Task>,
+        label: Option<String>,
+    ) -> Result<Step, RuntimeError> {
+        let kind = match stmt {
+            Stmt::Yield(value) => {
+                let value = self.evaluate_expr(value, &env)?;
+                if self.trace {
+                    self.trace_yield(stmt.line(), &value);
+                }
+                return Ok(Step::Yield(value));
+            }
+            Stmt::Labeled(label, body) => return self.enter(body, path, env, tasks, Some(label.clone())),
+            Stmt::Block(_) => {
+                let local_env = Environment::new(Some(env));
+                tasks.push(Task::Run { path, next: 0, env: local_env });
+                return Ok(Step::Next);
+            }
+            Stmt::IfElse(branches) => {
+                let local_env = Environment::new(Some(env));
+                if let Some(branch) = self.chosen_branch(branches, &local_env)? {
+                    let (index, _) = path[path.len() - 1];
+                    let path = child(&path[..path.len() - 1], index, branch);
+                    tasks.push(Task::Run { path, next: 0, env: local_env });
+                }
+                return Ok(Step::Next);
+            }
+            Stmt::Match(value, arms, _) => {
+                let value = self.evaluate_expr(value, &env)?;
+                if let Some((arm, local_env)) = matching_arm(&value, arms, &env) {
+                    let (index, _) = path[path.len() - 1];
+                    let path = child(&path[..path.len() - 1], index, arm);
+                    tasks.push(Task::Run { path, next: 0, env: local_env });
+                }
+                return Ok(Step::Next);
+            }
+            Stmt::Try(_, name, _, _) => {
+                let (index, _) = path[path.len() - 1];
+                let handler = child(&path[..path.len() - 1], index, 1);
+                tasks.push(Task::Try { path: handler, name: name.clone(), env: Rc::clone(&env) });
+                let local_env = Environment::new(Some(env));
+                tasks.push(Task::Run { path, next: 0, env: local_env });
+                return Ok(Step::Next);
+            }
+            Stmt::With(value, name, _, line) => {
+                let resource = self.evaluate_expr(value, &env)?;
+                self.check_closable(&resource, *line)?;
+                let local_env = Environment::new(Some(env));
+                let _ = declare_var(&local_env, name, resource.clone(), true);
+                tasks.push(Task::With { resource, line: *line });
+                tasks.push(Task::Run { path, next: 0, env: local_env });
+                return Ok(Step::Next);
+            }
+            Stmt::Expression(_) | Stmt::VarDeclaration(_) | Stmt::Return(..) => {
+                let Some(Expr::Await { value, line }) = awaited(stmt) else {
+                    return Err(RuntimeError::InternalError);
+                };
+                let target = self.awaited_task(value, &env, *line)?;
+                tasks.push(Task::Await { target, path, env });
+                return Ok(Step::Next);
+            }
+            Stmt::While(..) => LoopKind::While,
+            Stmt::Loop(..) => LoopKind::Loop,
+            Stmt::For((initializer, _, _), _, _) => {
+                let local_env = Environment::new(Some(env));
+                if let Some(initializer) = initializer {
+                    let _ = self.evaluate(initializer, &local_env)?;
+                }
+                let kind = LoopKind::For { started: false };
+                tasks.push(Task::Loop { path, env: local_env, label, kind });
+                return Ok(Step::Next);
+            }
+            Stmt::ForIn(names, iterable, _, line) => {
+                let items = LoopItems::new(self.evaluate_expr(iterable, &env)?, *line)?;
+                LoopKind::ForIn { names: names.clone(), items, line: *line }
+            }
+            // Nothing else holds statements that could yield
+            _ => return Err(RuntimeError::InternalError),
+        };
+        tasks.push(Task::Loop { path, env, label, kind });
+        Ok(Step::Next)
+    }
+
+    // Checks whether the loop on top of `tasks` goes round again, and
+    // starts the pass if it does
+    fn next_pass(&mut self, body: &[Stmt], tasks: &mut Vec<Task>) -> Result<Step, RuntimeError> {
+        if let Some(Task::Loop { path, .. }) = tasks.last() {
+            self.check_interrupt(loop_at(body, path).line().unwrap_or(0))?;
+        }
+        let Some(Task::Loop { path, env, kind, .. }) = tasks.last_mut() else {
+            return Err(RuntimeError::InternalError);
+        };
+        // Each pass gets a scope of its own, as it does outside generators
+        let pass_env = Environment::new(Some(Rc::clone(env)));
+        let again = match (kind, loop_at(body, path)) {
+            (LoopKind::While, Stmt::While(condition, _, line)) => {
+                let condition = self.evaluate_expr(condition, env)?;
+                loop_condition(condition, *line)?
+            }
+            (LoopKind::Loop, _) => true,
+            (LoopKind::For { started }, Stmt::For((_, condition, step), _, line)) => {
+                if let (true, Some(step)) = (*started, step) {
+                    let _ = self.evaluate_expr(step, env)?;
+                }
+                *started = true;
+                match condition {
+                    Some(condition) => loop_condition(self.evaluate_expr(condition, env)?, *line)?,
+                    None => true,
+                }
+            }
+            (LoopKind::ForIn { names, items, line }, _) => match self.next_item(items)? {
+                Some(item) => {
+                    declare_loop_vars(&pass_env, names, item, *line)?;
+                    true
+                }
+                None => false,
+            },
+            _ => return Err(RuntimeError::InternalError),
+        };
+        let path = path.clone();
+        if again {
+            tasks.push(Task::Run { path, next: 0, env: pass_env });
+        } else {
+            tasks.pop();
+        }
+        Ok(Step::Next)
+    }
+
+    // Runs the statement on top of `tasks` that waited for a task, now that
+    // the task has finished
+    fn finish_await(&mut self, body: &[Stmt], tasks: &mut Vec<Task>) -> Result<Step, RuntimeError> {
+        let Some(Task::Await { target, path, env }) = tasks.pop() else {
+            return Err(RuntimeError::InternalError);
+        };
+        let (index, _) = path[path.len() - 1];
+        let stmt = &stmts_at(body, &path[..path.len() - 1])[index];
+        if awaited(stmt).is_none() {
+            return Err(RuntimeError::InternalError);
+        }
+        self.awaited = Some(target.result()?);
+        let result = self.evaluate(stmt, &env);
+        self.awaited = None;
+        match result? {
+            EvalResult::Return(value) => Ok(Step::Finish(value)),
+            _ => Ok(Step::Next),
+        }
+    }
+
+    // Drops tasks until `len` are left, closing the values of the `with`
+    // statements they leave, innermost first. The first error raised while
+    // closing is returned once all of them are closed.
+    fn unwind(&mut self, tasks: &mut Vec<Task>, len: usize) -> Result<(), RuntimeError> {
+        let mut result = Ok(());
+        while tasks.len() > len {
+            if let Some(Task::With { resource, line }) = tasks.pop() {
+                let closed = self.close_resource(&resource, line);
+                if result.is_ok() {
+                    result = closed;
+                }
+            }
+        }
+        result
+    }
+
+    // Ends passes of loops up to the one `label` names, or the innermost one.
+    // `break` drops that loop too, `continue` leaves it to start its next pass.
+    fn leave_loop(&mut self, tasks: &mut Vec<Task>, label: Option<String>, leave: bool) -> Result<(), RuntimeError> {
+        let position = tasks.iter().rposition(|task| {
+            matches!(task, Task::Loop { label: own, .. } if label.is_none() || label == *own)
+        });
+        match position {
+            Some(position) if !leave => self.unwind(tasks, position + 1),
+            Some(position) => self.unwind(tasks, position),
+            None => self.unwind(tasks, 0),
+        }
+    }
+
+    // Hands an error to the innermost try inside the generator's body, or
+    // passes it on when there is none. An error raised while closing the
+    // values of `with` statements the error leaves takes its place.
+    fn catch(&mut self, tasks: &mut Vec<Task>, error: RuntimeError) -> Result<(), RuntimeError> {
+        let Some(position) = tasks.iter().rposition(|task| matches!(task, Task::Try { .. })) else {
+            return Err(error);
+        };
+        if error.caught().is_none() {
+            return Err(error);
+        }
+        let error = self.unwind(tasks, position + 1).err().unwrap_or(error);
+        let Some(Task::Try { path, name, env }) = tasks.pop() else {
+            return Err(RuntimeError::InternalError);
+        };
+        let local_env = catch_scope(error, &name, &env)?;
+        tasks.push(Task::Run { path, next: 0, env: local_env });
+        Ok(())
+    }
+}
+
+fn child(path: &[(usize, usize)], index: usize, body: usize) -> Path {
+    let mut path = path.to_vec();
+    path.push((index, body));
+    path
+}
+
+fn stmts_at<'a>(body: &'a [Stmt], path: &[(usize, usize)]) -> &'a [Stmt] {
+    path.iter()
+        .fold(body, |stmts, &(index, body)| bodies(&stmts[index], body))
+}
+
+// The loop whose body `path` leads to
+fn loop_at<'a>(body: &'a [Stmt], path: &[(usize, usize)]) -> &'a Stmt {
+    let (index, _) = path[path.len() - 1];
+    let mut stmt = &stmts_at(body, &path[..path.len() - 1])[index];
+    while let Stmt::Labeled(_, inner) = stmt {
+        stmt = inner;
+    }
+    stmt
+}
+
+// Statement lists held by a statement, counted from 0 in source order
+fn bodies(stmt: &Stmt, body: usize) -> &[Stmt] {
+    match stmt {
+        Stmt::IfElse(branches) => &branches[body].1,
+        Stmt::Try(stmts, _, handler, _) => if body == 0 { stmts } else { handler },
+        Stmt::Match(_, arms, _) => &arms[body].body,
+        Stmt::With(_, _, stmts, _) => stmts,
+        Stmt::For(_, stmts, _)
+        | Stmt::ForIn(_, _, stmts, _)
+        | Stmt::While(_, stmts, _)
+        | Stmt::Loop(stmts, _)
+        | Stmt::Block(stmts) => stmts,
+        Stmt::Labeled(_, inner) => bodies(inner, body),
+        _ => &[],
+    }
+}
+
+// Whether a statement yields, itself or from a statement nested in it, or
+// in an async function awaits at the start of one. Functions declared
+// inside pause only their own calls.
+fn pauses(stmt: &Stmt, asynchronous: bool) -> bool {
+    let any = |body: &[Stmt]| body.iter().any(|stmt| pauses(stmt, asynchronous));
+    match stmt {
+        Stmt::Yield(_) => true,
+        Stmt::IfElse(branches) => branches.iter().any(|(_, body, _)| any(body)),
+        Stmt::Try(body, _, handler, _) => any(body) || any(handler),
+        Stmt::Match(_, arms, _) => arms.iter().any(|arm| any(&arm.body)),
+        Stmt::With(_, _, body, _) => any(body),
+        Stmt::For(_, body, _)
+        | Stmt::ForIn(_, _, body, _)
+        | Stmt::While(_, body, _)
+        | Stmt::Loop(body, _)
+        | Stmt::Block(body) => any(body),
+        Stmt::Labeled(_, body) => pauses(body, asynchronous),
+        _ => asynchronous && awaited(stmt).is_some(),
+    }
+}
+
+// The `await` a statement starts with: the whole of an expression
+// statement, of a returned value, of a variable's value or of an assigned
+// value. Assignments evaluate their value before their target, so it is
+// always the first `await` its statement evaluates. Anywhere else an
+// `await` waits without pausing its function.
+fn awaited(stmt: &Stmt) -> Option<&Expr> {
+    let value = match stmt {
+        Stmt::Expression(Expr::AssignmentExpr { value, .. }) => value,
+        Stmt::Expression(value) | Stmt::Return(value, _) => value,
+        Stmt::VarDeclaration(declaration) => &declaration.value,
+        _ => return None,
+    };
+    matches!(value, Expr::Await { .. }).then_some(value)
+}
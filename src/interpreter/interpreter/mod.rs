@@ -1,167 +1,292 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io;
+use std::io::{BufRead, Write};
 use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::ast::*;
 use crate::environment::*;
 use crate::handle_errors::RuntimeError;
-use crate::interpreter::expression::*;
-use crate::interpreter::statement::*;
+use crate::interpreter::profile::Profile;
+use crate::interpreter::random::Random;
+use crate::interpreter::scheduler::AsyncTask;
+use crate::threads::{ProgramSource, Thread};
 use crate::values::*;
 
-pub fn evaluate_program(
-    program: &[Stmt],
-    env: &Rc<RefCell<Environment>>,
-    command_line_args: &[&str],
-    is_repl: bool,
-) -> Result<(), RuntimeError> {
-    let _ = evaluate_first_pass(program, env, is_repl)?;
-    if is_repl {
-        for statement in program {
-            if let EvalResult::Value(val) = evaluate(&statement, env)? {
-                print_runtime_val(val);
-                println!();
-            }
+/// Deepest chain of nested calls a program may build before it is stopped
+/// with a runtime error. The host thread's stack is sized so this limit is
+/// always reached before the native stack runs out.
+pub const MAX_CALL_DEPTH: usize = 2048;
+
+/// Where a program's output goes. Everything the program prints is written
+/// to `stdout`, lexer, parser and runtime errors to `stderr`. The default
+/// writes to the process's own stdout and stderr.
+pub struct Output {
+    pub stdout: Box<dyn Write + Send>,
+    pub stderr: Box<dyn Write + Send>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
         }
-    } else {
-        let mut args = vec![];
-        args.extend(
-            command_line_args
-                .iter()
-                .map(|s| Expr::StringLiteral(s.to_string(), 0)),
-        );
-        let main_stmt = Stmt::Expression(Expr::Call {
-            args,
-            caller: Box::new(Expr::Identifier(String::from("main"), 0)),
-            line: 0,
-        }); // Calling main function happens outside the code, thus denoted by line 0. NOT A MISTAKE
-        evaluate(&main_stmt, env)?;
     }
-    Ok(())
 }
 
-fn evaluate_first_pass(
-    program: &[Stmt],
-    env: &Rc<RefCell<Environment>>,
-    is_repl: bool,
-) -> Result<(), RuntimeError> {
-    for statement in program {
-        match statement {
-            Stmt::Function(function) => {
-                let func = make_function(
-                    &function.name[..],
-                    &function.parameters,
-                    &function.body,
-                    env,
-                );
-                if let Err(_) = declare_var(env, &function.name[..], func, true) {
-                    return Err(RuntimeError::EnvironmentError(
-                        format!(
-                            "{} is already declared. Cannot redeclare variable with same name",
-                            function.name
-                        ),
-                        function.line,
-                    ));
+pub struct Interpreter {
+    pub(crate) call_depth: usize,
+    pub(crate) globals: Rc<RefCell<Environment>>,
+    pub(crate) output: Output,
+    pub(crate) input: Box<dyn BufRead + Send>,
+    pub(crate) command_line_args: Vec<Rc<str>>,
+    pub(crate) trace: bool,
+    pub(crate) profile: Option<Profile>,
+    pub(crate) warnings: bool,
+    pub(crate) random: Random,
+    // How many instances with a `__drop__` method were made so far
+    pub(crate) droppable: usize,
+    // Tasks waiting for their turn, see `run_until`
+    pub(crate) scheduled: VecDeque<Rc<AsyncTask>>,
+    // Tasks that failed, reported at the end unless an `await` saw the error
+    pub(crate) failed: Vec<Rc<AsyncTask>>,
+    // Result for the `await` a resumed task stopped at, which is the first
+    // one its statement evaluates
+    pub(crate) awaited: Option<RuntimeVal>,
+    // What threads rebuild the program from, when it was run from source
+    pub(crate) source: Option<Arc<ProgramSource>>,
+    // Threads started and not joined yet, waited for when the program ends
+    pub(crate) threads: Vec<Rc<Thread>>,
+    // Whether Ctrl+C stops this interpreter, false on threads
+    pub(crate) interruptible: bool,
+    // Called instead of raising `Interrupted`, see `on_interrupt`
+    pub(crate) on_interrupt: Option<RuntimeVal>,
+}
+
+impl Interpreter {
+    pub fn new(
+        globals: &Rc<RefCell<Environment>>,
+        output: Output,
+        input: Box<dyn BufRead + Send>,
+    ) -> Self {
+        // Left by an earlier program on this thread, which is not there to finalize them
+        take_released();
+        Interpreter {
+            call_depth: 0,
+            globals: Rc::clone(globals),
+            output,
+            input,
+            command_line_args: vec![],
+            trace: false,
+            profile: None,
+            warnings: false,
+            random: Random::from_clock(),
+            droppable: 0,
+            scheduled: VecDeque::new(),
+            failed: vec![],
+            awaited: None,
+            source: None,
+            threads: vec![],
+            interruptible: true,
+            on_interrupt: None,
+        }
+    }
+
+    /// Writes program output to the configured stdout and flushes it, so
+    /// prompts without a newline show up before input is read.
+    /// Reads one line, including its line ending, from the configured input.
+    /// Returns an empty string once the input is exhausted.
+    pub fn read_line(&mut self, line: usize) -> Result<String, RuntimeError> {
+        let mut text = String::new();
+        self.input
+            .read_line(&mut text)
+            .map_err(|e| RuntimeError::IoError(format!("Failed to read input: {}", e), line))?;
+        Ok(text)
+    }
+
+    pub fn write_output(&mut self, text: &str, line: usize) -> Result<(), RuntimeError> {
+        self.output
+            .stdout
+            .write_all(text.as_bytes())
+            .and_then(|_| self.output.stdout.flush())
+            .map_err(|e| RuntimeError::IoError(format!("Failed to write output: {}", e), line))
+    }
+
+    /// Runs a whole program. Returns what `main` returned, or in a script
+    /// the value of the last top-level expression statement.
+    pub fn evaluate_program(
+        &mut self,
+        program: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+        command_line_args: &[&str],
+        mode: Mode,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        self.command_line_args = command_line_args.iter().map(|s| Rc::from(*s)).collect();
+        if mode != Mode::Main {
+            // Scripts and REPL lines run top to bottom, declarations included
+            let mut last_value = RuntimeVal::Nil;
+            for statement in program {
+                let result = self.evaluate(statement, env)?;
+                if has_released() {
+                    self.run_finalizers(statement.line().unwrap_or(0))?;
                 }
-            }
-            Stmt::Class(class) => {
-                let mut fields = HashMap::new();
-                for var in &class.static_fields {
-                    let _ = var_declaration(var, env)?;
-                    let res = evaluate_expr(&var.value, env)?;
-                    fields.insert(var.identifier.clone(), res);
+                if let (Stmt::Expression(_), EvalResult::Value(val)) = (statement, &result) {
+                    last_value = val.clone();
                 }
-                let mut methods = HashMap::new();
-                for (name, func) in &class.methods {
-                    let res = make_function(&func.name[..], &func.parameters, &func.body, env);
-                    methods.insert(name.clone(), res);
+                if mode != Mode::Repl {
+                    continue;
                 }
-                let class_val =
-                    make_class(&class.name[..], fields, methods, class.superclass.clone());
-                if let Err(_) = declare_var(env, &class.name[..], class_val, true) {
-                    return Err(RuntimeError::EnvironmentError(
-                        format!(
-                            "{} is already declared. Cannot redeclare variable with same name",
-                            class.name
-                        ),
-                        class.line,
-                    ));
+                if let EvalResult::Value(val) = result {
+                    // Calls that return nothing, such as `println(...)`, show no result.
+                    if matches!(val, RuntimeVal::Nil)
+                        && matches!(statement, Stmt::Expression(Expr::Call { .. }))
+                    {
+                        continue;
+                    }
+                    let text = self.stringify(&val)?;
+                    self.write_output(&format!("{}\n", text), 0)?;
                 }
             }
-            _ => {
-                if !is_repl {
-                    return Err(RuntimeError::InternalError);
-                }
+            self.finish_tasks(0)?;
+            self.finish_threads()?;
+            Ok(last_value)
+        } else {
+            self.evaluate_first_pass(program, env)?;
+            // `main()` ignores the command line, `main(args)` receives it as an array
+            let takes_args = matches!(
+                lookup_var(env, "main"),
+                Ok(RuntimeVal::Function { declaration, .. }) if !declaration.parameters.is_empty()
+            );
+            let mut args = vec![];
+            if takes_args {
+                args.push(Expr::Array(
+                    command_line_args
+                        .iter()
+                        .map(|s| Expr::StringLiteral(Rc::from(*s), 0))
+                        .collect(),
+                    0,
+                ));
             }
+            let main_stmt = Stmt::Expression(Expr::Call {
+                args,
+                caller: Box::new(Expr::Identifier(String::from("main"), 0)),
+                line: 0,
+            }); // Calling main function happens outside the code, thus denoted by line 0. NOT A MISTAKE
+            let value = match self.evaluate(&main_stmt, env)? {
+                // `async fun main` runs as a task the program waits for
+                EvalResult::Value(RuntimeVal::Task(task)) => {
+                    self.run_until(&task, 0)?;
+                    task.result()?
+                }
+                EvalResult::Value(val) => val,
+                _ => RuntimeVal::Nil,
+            };
+            self.finish_tasks(0)?;
+            self.finish_threads()?;
+            Ok(value)
         }
     }
-    Ok(())
-}
 
-pub fn evaluate(
-    ast_node: &Stmt,
-    env: &Rc<RefCell<Environment>>,
-) -> Result<EvalResult, RuntimeError> {
-    match ast_node {
-        Stmt::Expression(expr) => Ok(EvalResult::Value(evaluate_expr(expr, env)?)),
-        Stmt::VarDeclaration(declaration) => var_declaration(declaration, env),
-        Stmt::Print(value, new_line) => print_stmt(value, env, *new_line),
-        Stmt::IfElse(if_collection) => if_else_stmt(if_collection, env),
-        Stmt::While(expr, stmt, line) => while_stmt(expr, stmt, env, *line),
-        Stmt::For((var_stmt, expr1, expr2), statement, line) => {
-            for_stmt(var_stmt, expr1, expr2, statement, env, *line)
+    /// Declares a program's functions and classes without calling `main`,
+    /// so the host can call them afterwards. Scripts run their top-level
+    /// statements as usual.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_program(
+        &mut self,
+        program: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+        command_line_args: &[&str],
+        mode: Mode,
+    ) -> Result<(), RuntimeError> {
+        if mode != Mode::Main {
+            return self.evaluate_program(program, env, command_line_args, mode).map(|_| ());
         }
-        Stmt::Block(stmts) => block_stmt(stmts.clone(), env),
-        Stmt::Return(expr) => Ok(make_return(evaluate_expr(expr, env)?)),
-        Stmt::Break => Ok(make_break()),
-        Stmt::Continue => Ok(make_continue()),
-        Stmt::Function(FunctionDeclaration {
-            name,
-            parameters,
-            body,
-            line,
-        }) => {
-            let function = make_function(name, parameters, body, env);
-            if let Err(_) = declare_var(env, &name[..], function, true) {
-                return Err(RuntimeError::EnvironmentError(
-                    format!(
-                        "{} is already declared. Cannot redeclare variable with same name",
-                        name
-                    ),
-                    *line,
-                ));
+        self.command_line_args = command_line_args.iter().map(|s| Rc::from(*s)).collect();
+        self.evaluate_first_pass(program, env)
+    }
+
+    pub(crate) fn evaluate_first_pass(
+        &mut self,
+        program: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), RuntimeError> {
+        // Mixins come first, so classes can take the methods of mixins
+        // declared below them
+        for statement in program {
+            if let Stmt::Mixin(mixin) = statement {
+                let _ = self.mixin_declaration(mixin, env)?;
             }
-            Ok(make_none())
         }
-        Stmt::Class(ClassDeclaration {
-            name,
-            static_fields,
-            methods,
-            superclass,
-            line,
-        }) => {
-            let mut fields = HashMap::new();
-            for var in static_fields {
-                let _ = var_declaration(var, env)?;
-                let value = evaluate_expr(&var.value, env)?;
-                fields.insert(var.identifier.clone(), value);
+        for statement in program {
+            match statement {
+                Stmt::Function(function) => {
+                    let _ = self.function_declaration(function, env)?;
+                }
+                Stmt::Class(class) => {
+                    let _ = self.class_declaration(class, env)?;
+                }
+                Stmt::Interface(interface) => {
+                    let _ = self.interface_declaration(interface, env)?;
+                }
+                // Only scripts hold other statements, which threads
+                // rebuilding the program's declarations leave out
+                _ => {}
             }
-            let mut method = HashMap::new();
-            for (name, func) in methods {
-                let res = make_function(&func.name[..], &func.parameters, &func.body, env);
-                method.insert(name.clone(), res);
+        }
+        Ok(())
+    }
+
+    pub fn evaluate(
+        &mut self,
+        ast_node: &Stmt,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        self.check_interrupt(ast_node.line().unwrap_or(0))?;
+        if self.trace {
+            return self.evaluate_traced(ast_node, env);
+        }
+        self.evaluate_stmt(ast_node, env)
+    }
+
+    pub fn evaluate_stmt(
+        &mut self,
+        ast_node: &Stmt,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        match ast_node {
+            Stmt::Expression(expr) => Ok(EvalResult::Value(self.evaluate_expr(expr, env)?)),
+            Stmt::VarDeclaration(declaration) => self.var_declaration(declaration, env),
+            Stmt::VarUnpack(names, constant, value, line) => {
+                self.var_unpack(names, *constant, value, env, *line)
             }
-            let class_val = make_class(&name[..], fields, method, superclass.clone());
-            if let Err(_) = declare_var(env, &name[..], class_val, true) {
-                return Err(RuntimeError::EnvironmentError(
-                    format!(
-                        "{} is already declared. Cannot redeclare variable with same name",
-                        name
-                    ),
-                    *line,
-                ));
+            Stmt::Global(name, value, line) => self.global_stmt(name, value.as_ref(), env, *line),
+            Stmt::Print(value, new_line, line) => self.print_stmt(value, env, *new_line, *line),
+            Stmt::IfElse(if_collection) => self.if_else_stmt(if_collection, env),
+            Stmt::ForIn(names, iterable, stmt, line) => {
+                self.for_in_stmt(names, iterable, stmt, env, *line, None)
+            }
+            Stmt::Try(body, name, handler, _) => self.try_stmt(body, name, handler, env),
+            Stmt::Match(value, arms, _) => self.match_stmt(value, arms, env),
+            Stmt::With(value, name, body, line) => self.with_stmt(value, name, body, env, *line),
+            Stmt::While(expr, stmt, line) => self.while_stmt(expr, stmt, env, *line, None),
+            Stmt::Loop(stmt, line) => self.loop_stmt(stmt, env, *line, None),
+            Stmt::For(clauses, statement, line) => {
+                self.for_stmt(clauses, statement, env, *line, None)
             }
-            Ok(make_none())
+            Stmt::Labeled(label, body) => self.labeled_stmt(label, body, env),
+            Stmt::Block(stmts) => self.block_stmt(stmts, env),
+            Stmt::Return(expr, _) => Ok(make_return(self.evaluate_expr(expr, env)?)),
+            // Generators run their own yields, see `resume_generator`
+            Stmt::Yield(_) => Err(RuntimeError::InternalError),
+            Stmt::Break(label) => Ok(make_break(label)),
+            Stmt::Continue(label) => Ok(make_continue(label)),
+            Stmt::Comment(..) => Ok(make_none()),
+            Stmt::Function(declaration) => self.function_declaration(declaration, env),
+            Stmt::Class(class) => self.class_declaration(class, env),
+            Stmt::Interface(interface) => self.interface_declaration(interface, env),
+            Stmt::Mixin(mixin) => self.mixin_declaration(mixin, env),
         }
     }
 }
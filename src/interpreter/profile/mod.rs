@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::handle_errors::RuntimeError;
+use crate::interpreter::interpreter::Interpreter;
+use crate::values::*;
+
+#[derive(Default)]
+struct FunctionStats {
+    calls: usize,
+    total: Duration,
+    // Calls of this function currently on the stack. Only the outermost one
+    // adds to `total`, so recursion is not counted twice.
+    active: usize,
+}
+
+/// Call counts and cumulative time of every function called while profiling.
+#[derive(Default)]
+pub struct Profile {
+    functions: HashMap<String, FunctionStats>,
+}
+
+impl Interpreter {
+    pub fn call_profiled(
+        &mut self,
+        call: RuntimeVal,
+        args: &[RuntimeVal],
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let name = callable_name(&call);
+        if let Some(profile) = &mut self.profile {
+            let stats = profile.functions.entry(name.clone()).or_default();
+            stats.calls += 1;
+            stats.active += 1;
+        }
+        let start = Instant::now();
+        let result = self.call_unprofiled(call, args, line);
+        let elapsed = start.elapsed();
+        if let Some(profile) = &mut self.profile
+            && let Some(stats) = profile.functions.get_mut(&name)
+        {
+            stats.active -= 1;
+            if stats.active == 0 {
+                stats.total += elapsed;
+            }
+        }
+        result
+    }
+
+    /// Writes the collected profile to stderr, slowest function first.
+    pub fn write_profile(&mut self) {
+        let Some(profile) = &self.profile else {
+            return;
+        };
+        let mut functions: Vec<(&String, &FunctionStats)> = profile.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.total.cmp(&a.1.total).then_with(|| a.0.cmp(b.0)));
+
+        let mut report = format!(
+            "{:<24} {:>10} {:>14} {:>14}\n",
+            "function", "calls", "total ms", "avg ms"
+        );
+        for (name, stats) in functions {
+            let total = stats.total.as_secs_f64() * 1000.0;
+            report.push_str(&format!(
+                "{:<24} {:>10} {:>14.3} {:>14.3}\n",
+                name,
+                stats.calls,
+                total,
+                total / stats.calls as f64
+            ));
+        }
+        let _ = self.output.stderr.write_all(report.as_bytes());
+    }
+}
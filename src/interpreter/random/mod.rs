@@ -0,0 +1,46 @@
+use crate::global_scope::now_seconds;
+
+/// The pseudo-random generator behind `random`, `rand_int`, `shuffle` and
+/// `choice`. Every interpreter owns one, so embedded programs draw from
+/// independent sequences. It is SplitMix64: fast, seedable with any number
+/// and not suitable for cryptography.
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random { state: seed }
+    }
+
+    /// A generator seeded from the clock, so each run gets a new sequence
+    /// until the program calls `seed`.
+    pub fn from_clock() -> Self {
+        Random::new(now_seconds().to_bits())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in [0, 1), using the 53 bits an f64 can hold exactly.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number in [0, bound). Draws falling in the last, partial stretch
+    /// of the u64 range are redrawn so every result is equally likely.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        let limit = u64::MAX - u64::MAX % bound;
+        loop {
+            let draw = self.next_u64();
+            if draw < limit {
+                return draw % bound;
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::environment::*;
+use crate::global_scope::{elapsed_seconds, pause};
+use crate::handle_errors::RuntimeError;
+use crate::interpreter::generator::{Generator, Pause};
+use crate::interpreter::interpreter::Interpreter;
+use crate::values::*;
+
+/// A call of an async function, or a delay, that the scheduler runs
+/// alongside other tasks. Tasks take turns on the one thread: a task runs
+/// until it awaits a task that has not finished, and the next scheduled
+/// task goes on from there.
+pub struct AsyncTask {
+    name: String,
+    work: Work,
+    state: RefCell<State>,
+    // Set once an `await` has seen the result, so a failure nothing awaited
+    // is reported when the program ends
+    observed: Cell<bool>,
+}
+
+enum Work {
+    Body(Generator),
+    // Finishes once the clock passes this many seconds
+    Delay(f64),
+}
+
+enum State {
+    // Returned by a call, not yet handed to the scheduler
+    Created,
+    // Queued, and waiting for the task it holds to finish when there is one
+    Scheduled(Option<Rc<AsyncTask>>),
+    Done(RuntimeVal),
+    Failed(RuntimeError),
+}
+
+impl AsyncTask {
+    pub fn new(declaration: &Rc<FunctionDeclaration>, env: Rc<RefCell<Environment>>) -> Self {
+        AsyncTask::with_work(&declaration.name, Work::Body(Generator::new(declaration, env)))
+    }
+
+    /// A task that does nothing but finish `milliseconds` from now.
+    pub fn delay(milliseconds: f64) -> Self {
+        AsyncTask::with_work("delay", Work::Delay(elapsed_seconds() + milliseconds / 1000.0))
+    }
+
+    fn with_work(name: &str, work: Work) -> Self {
+        AsyncTask {
+            name: name.to_string(),
+            work,
+            state: RefCell::new(State::Created),
+            observed: Cell::new(false),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(*self.state.borrow(), State::Done(_) | State::Failed(_))
+    }
+
+    /// The value the task finished with, or the error it failed with.
+    pub fn result(&self) -> Result<RuntimeVal, RuntimeError> {
+        self.observed.set(true);
+        match &*self.state.borrow() {
+            State::Done(value) => Ok(value.clone()),
+            State::Failed(error) => Err(error.clone()),
+            _ => Err(RuntimeError::InternalError),
+        }
+    }
+}
+
+impl Interpreter {
+    /// Hands a task to the scheduler, unless it already has it.
+    pub(crate) fn spawn_task(&mut self, task: &Rc<AsyncTask>) {
+        let mut state = task.state.borrow_mut();
+        if let State::Created = *state {
+            *state = State::Scheduled(None);
+            self.scheduled.push_back(Rc::clone(task));
+        }
+    }
+
+    /// Evaluates the operand of an `await` and schedules the task it gives.
+    pub(crate) fn awaited_task(
+        &mut self,
+        value: &Expr,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<Rc<AsyncTask>, RuntimeError> {
+        match self.evaluate_expr(value, env)? {
+            RuntimeVal::Task(task) => {
+                self.spawn_task(&task);
+                Ok(task)
+            }
+            value => Err(RuntimeError::TypeMismatch(
+                format!("Only tasks can be awaited, found {}", type_name(&value)),
+                line,
+            )),
+        }
+    }
+
+    /// Runs scheduled tasks in turn until `task` has finished.
+    pub(crate) fn run_until(&mut self, task: &Rc<AsyncTask>, line: usize) -> Result<(), RuntimeError> {
+        self.spawn_task(task);
+        while !task.is_done() {
+            if !self.run_round(line)? {
+                return Err(RuntimeError::InvalidCall(
+                    format!("Task '{}' can never finish, as every task is waiting for another", task.name()),
+                    line,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs every scheduled task to its end, then reports the first failed
+    /// task whose error no `await` saw.
+    pub(crate) fn finish_tasks(&mut self, line: usize) -> Result<(), RuntimeError> {
+        while !self.scheduled.is_empty() {
+            if !self.run_round(line)? {
+                let names: Vec<&str> = self.scheduled.iter().map(|task| task.name()).collect();
+                return Err(RuntimeError::InvalidCall(
+                    format!("Tasks {} can never finish, as every task is waiting for another", names.join(", ")),
+                    line,
+                ));
+            }
+        }
+        let failed = std::mem::take(&mut self.failed);
+        match failed.iter().find(|task| !task.observed.get()) {
+            Some(task) => task.result().map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    // Gives every scheduled task one turn. When none of them could go on,
+    // waits for the earliest delay to pass, and reports false if there is
+    // none to wait for.
+    fn run_round(&mut self, line: usize) -> Result<bool, RuntimeError> {
+        self.check_interrupt(line)?;
+        let mut progress = false;
+        let mut wake_at = f64::INFINITY;
+        for _ in 0..self.scheduled.len() {
+            // Tasks awaiting in the middle of an expression run rounds of
+            // their own, which may have emptied the queue
+            let Some(task) = self.scheduled.pop_front() else {
+                break;
+            };
+            progress |= self.run_turn(&task, &mut wake_at, line)?;
+        }
+        if !progress && wake_at.is_finite() {
+            pause((wake_at - elapsed_seconds()).max(0.0) * 1000.0, "delay", line)?;
+            return Ok(true);
+        }
+        Ok(progress)
+    }
+
+    // Runs a task until it pauses, unless it is waiting for something that
+    // has not finished. Reports whether the task went on.
+    fn run_turn(&mut self, task: &Rc<AsyncTask>, wake_at: &mut f64, line: usize) -> Result<bool, RuntimeError> {
+        if let State::Scheduled(Some(target)) = &*task.state.borrow()
+            && !target.is_done()
+        {
+            self.scheduled.push_back(Rc::clone(task));
+            return Ok(false);
+        }
+        let outcome = match &task.work {
+            Work::Delay(at) if elapsed_seconds() < *at => {
+                *wake_at = wake_at.min(*at);
+                self.scheduled.push_back(Rc::clone(task));
+                return Ok(false);
+            }
+            Work::Delay(_) => Ok(Pause::Finish(make_nil())),
+            Work::Body(body) => self.resume_body(body, line),
+        };
+        let state = match outcome {
+            Ok(Pause::Await(target)) => {
+                self.spawn_task(&target);
+                self.scheduled.push_back(Rc::clone(task));
+                State::Scheduled(Some(target))
+            }
+            Ok(Pause::Finish(value)) => State::Done(value),
+            // A panic or a call to `exit` ends the whole program
+            Err(error) if error.caught().is_none() => return Err(error),
+            Err(error) => {
+                self.failed.push(Rc::clone(task));
+                State::Failed(error)
+            }
+        };
+        *task.state.borrow_mut() = state;
+        Ok(true)
+    }
+}
@@ -1,205 +1,842 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
-use std::io;
-use std::io::Write;
 
 use crate::ast::*;
 use crate::environment::*;
 use crate::handle_errors::*;
-use crate::interpreter::expression::*;
+use crate::interpreter::generator::Generator;
 use crate::interpreter::interpreter::*;
 use crate::values::*;
 
-pub fn var_declaration(
-    declaration: &VarDeclaration,
-    env: &Rc<RefCell<Environment>>,
-) -> Result<EvalResult, RuntimeError> {
-    let value = evaluate_expr(&declaration.value, env)?;
-    if let Err(err) = declare_var(
-        env,
-        &declaration.identifier[..],
-        value.clone(),
-        declaration.constant,
-    ) {
-        if err == EnvironmentError::ReDeclareVar {
+impl Interpreter {
+    pub fn var_declaration(
+        &mut self,
+        declaration: &VarDeclaration,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let value = self.evaluate_expr(&declaration.value, env)?;
+        if let Err(err) = declare_var(
+            env,
+            &declaration.identifier[..],
+            value.clone(),
+            declaration.constant,
+        ) {
+            if err == EnvironmentError::ReDeclareVar {
+                return Err(RuntimeError::EnvironmentError(
+                    format!(
+                        "{} is already declared. Cannot redeclare variable with same name",
+                        declaration.identifier
+                    ),
+                    declaration.line,
+                ));
+            }
+        }
+        Ok(make_none())
+    }
+
+    pub fn var_unpack(
+        &mut self,
+        names: &[String],
+        constant: bool,
+        value: &Expr,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<EvalResult, RuntimeError> {
+        let value = self.evaluate_expr(value, env)?;
+        for (name, element) in names.iter().zip(unpack(value, names.len(), "variables", line)?) {
+            if let Err(EnvironmentError::ReDeclareVar) = declare_var(env, name, element, constant) {
+                return Err(RuntimeError::EnvironmentError(
+                    format!(
+                        "{} is already declared. Cannot redeclare variable with same name",
+                        name
+                    ),
+                    line,
+                ));
+            }
+        }
+        Ok(make_none())
+    }
+
+    pub fn global_stmt(
+        &mut self,
+        name: &str,
+        value: Option<&Expr>,
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<EvalResult, RuntimeError> {
+        // The name has to lead to the global scope once this has run
+        if declared_locally(env, name) {
             return Err(RuntimeError::EnvironmentError(
                 format!(
-                    "{} is already declared. Cannot redeclare variable with same name",
-                    declaration.identifier
+                    "'{}' is declared in an enclosing scope, which hides the global variable of the same name",
+                    name
                 ),
-                declaration.line,
+                line,
             ));
         }
+        let value = match value {
+            Some(value) => Some(self.evaluate_expr(value, env)?),
+            None => None,
+        };
+        let globals = Rc::clone(&self.globals);
+        match (lookup_var(&globals, name), value) {
+            (Ok(_), None) => {}
+            (Ok(_), Some(value)) => {
+                if let Err(EnvironmentError::ConstReassign) = assign_var(&globals, name, value) {
+                    return Err(RuntimeError::EnvironmentError(
+                        format!("'{}' is a constant. Constant values cannot be reassigned.", name),
+                        line,
+                    ));
+                }
+            }
+            (Err(_), value) => {
+                let _ = declare_var(&globals, name, value.unwrap_or(RuntimeVal::Nil), false);
+            }
+        }
+        Ok(make_none())
     }
-    Ok(make_none())
-}
 
-pub fn print_stmt(
-    value: &Option<Vec<Expr>>,
-    env: &Rc<RefCell<Environment>>,
-    new_line: bool,
-) -> Result<EvalResult, RuntimeError> {
-    if let Some(expr) = value {
-        for expr in expr {
-            let runtime_val = evaluate_expr(expr, env)?;
-            print_runtime_val(runtime_val);
+    pub fn function_declaration(
+        &mut self,
+        declaration: &Rc<FunctionDeclaration>,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let name = &declaration.name[..];
+        // Declaring a function again in the same scope with a different
+        // number of parameters overloads it. The REPL replaces the overload
+        // taking as many parameters.
+        let mut overloads = false;
+        if let Some(existing) = lookup_own_var(env, name) {
+            overloads = matches!(existing, RuntimeVal::Function { .. } | RuntimeVal::Overloaded { .. });
+            if let Some(overloaded) = add_overload(existing, declaration, env, is_redeclarable(env)) {
+                overwrite_var(env, name, overloaded);
+                return Ok(make_none());
+            }
         }
+        if declare_var(env, name, make_function(declaration, env), true).is_err() {
+            let message = if overloads {
+                format!(
+                    "Function {} taking {} arguments is already declared. Functions of the same name must take different numbers of arguments",
+                    name,
+                    declaration.parameters.len()
+                )
+            } else {
+                format!("{} is already declared. Cannot redeclare variable with same name", name)
+            };
+            return Err(RuntimeError::EnvironmentError(message, declaration.line));
+        }
+        Ok(make_none())
     }
-    if new_line {
-        println!();
+
+    pub fn class_declaration(
+        &mut self,
+        class: &ClassDeclaration,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        // Static fields live in their own environment so every copy of the
+        // class value sees the same fields.
+        let fields = Environment::new(None);
+        for var in &class.static_fields {
+            let value = self.evaluate_expr(&var.value, env)?;
+            if let Err(_) = declare_var(&fields, &var.identifier[..], value, var.constant) {
+                return Err(RuntimeError::EnvironmentError(
+                    format!(
+                        "Static field '{}' is declared more than once in class '{}'",
+                        var.identifier, class.name
+                    ),
+                    var.line,
+                ));
+            }
+        }
+        let mut methods = HashMap::new();
+        for (name, func) in &class.methods {
+            methods.insert(name.clone(), make_function(func, env));
+        }
+        // Methods of the class itself win over those of its mixins, and an
+        // earlier mixin wins over a later one
+        for mixin_name in &class.mixins {
+            let (mixin, closure) = match lookup_var(env, mixin_name) {
+                Ok(RuntimeVal::Mixin { declaration, closure }) => (declaration, closure),
+                Ok(_) => {
+                    return Err(RuntimeError::TypeMismatch(
+                        format!("'{}' is not a mixin but is used by class '{}'", mixin_name, class.name),
+                        class.line,
+                    ));
+                }
+                Err(_) => {
+                    return Err(RuntimeError::EnvironmentError(
+                        format!(
+                            "'{}' mixin is not defined but is used by class '{}'.",
+                            mixin_name, class.name
+                        ),
+                        class.line,
+                    ));
+                }
+            };
+            for (name, func) in &mixin.methods {
+                if !methods.contains_key(name) {
+                    methods.insert(name.clone(), make_function(func, &closure));
+                }
+            }
+        }
+        let mut static_methods = HashMap::new();
+        for (name, func) in &class.static_methods {
+            static_methods.insert(name.clone(), make_function(func, env));
+        }
+        let class_val = make_class(class, fields, methods, static_methods, env);
+        if let Err(_) = declare_var(env, &class.name[..], class_val, true) {
+            return Err(RuntimeError::EnvironmentError(
+                format!(
+                    "{} is already declared. Cannot redeclare variable with same name",
+                    class.name
+                ),
+                class.line,
+            ));
+        }
+        Ok(make_none())
     }
-    io::stdout().flush().unwrap();
-    Ok(make_none())
-}
 
-pub fn print_runtime_val(runtime_val: RuntimeVal) {
-    match runtime_val {
-        RuntimeVal::Number(num) => print!("{}", num),
-        RuntimeVal::Bool(bit) => print!("{}", bit),
-        RuntimeVal::Nil => print!("nil"),
-        RuntimeVal::String(s) => print!("{}", s),
-        RuntimeVal::Object(obj) => print_obj(obj),
-        RuntimeVal::Array(arr) => print_arr(arr),
-        RuntimeVal::Function { name, .. } => print!("Function: '{}'", name),
-        RuntimeVal::NativeFunction(_, name) => print!("Native Function: '{}'", name),
-        RuntimeVal::Method { name, .. } => print!("Method '{}'", name),
-        RuntimeVal::Class { name, .. } => print!("Class: '{}'", name),
-        RuntimeVal::Instance { class_name, .. } => print!("Class Instance: '{}'", class_name),
+    pub fn mixin_declaration(
+        &mut self,
+        mixin: &Rc<MixinDeclaration>,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let value = RuntimeVal::Mixin { declaration: Rc::clone(mixin), closure: Rc::clone(env) };
+        if declare_var(env, &mixin.name[..], value, true).is_err() {
+            return Err(RuntimeError::EnvironmentError(
+                format!(
+                    "{} is already declared. Cannot redeclare variable with same name",
+                    mixin.name
+                ),
+                mixin.line,
+            ));
+        }
+        Ok(make_none())
     }
-}
 
-fn print_obj(obj: HashMap<String, RuntimeVal>) {
-    println!("{{");
-    for (key, value) in obj.iter() {
-        print!("    \"{}\": ", key);
-        print_runtime_val(value.clone());
-        println!(",");
+    pub fn interface_declaration(
+        &mut self,
+        interface: &Rc<InterfaceDeclaration>,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let value = RuntimeVal::Interface(Rc::clone(interface));
+        if declare_var(env, &interface.name[..], value, true).is_err() {
+            return Err(RuntimeError::EnvironmentError(
+                format!(
+                    "{} is already declared. Cannot redeclare variable with same name",
+                    interface.name
+                ),
+                interface.line,
+            ));
+        }
+        Ok(make_none())
     }
-    println!("}}");
-}
 
-fn print_arr(arr: Vec<RuntimeVal>) {
-    print!("[");
-    for val in arr {
-        print_runtime_val(val);
-        print!(", ");
+    pub fn print_stmt(
+        &mut self,
+        value: &Option<Vec<Expr>>,
+        env: &Rc<RefCell<Environment>>,
+        new_line: bool,
+        line: usize,
+    ) -> Result<EvalResult, RuntimeError> {
+        let mut text = String::new();
+        if let Some(expr) = value {
+            for expr in expr {
+                let runtime_val = self.evaluate_expr(expr, env)?;
+                text.push_str(&self.stringify(&runtime_val)?);
+            }
+        }
+        if new_line {
+            text.push('\n');
+        }
+        self.write_output(&text, line)?;
+        Ok(make_none())
     }
-    println!("]");
-}
 
-pub fn if_else_stmt(
-    collection: &[(Expr, Vec<Stmt>, usize)],
-    env: &Rc<RefCell<Environment>>,
-) -> Result<EvalResult, RuntimeError> {
-    let local_env = Environment::new(Some(Rc::clone(env)));
-    let mut is_if_stmt = true;
-    for (expr, statements, line) in collection {
-        let condition = evaluate_expr(expr, &local_env)?;
-        if let RuntimeVal::Bool(bit) = condition {
-            is_if_stmt = false;
-            if !bit {
-                continue;
-            } else {
-                for statement in statements {
-                    match evaluate(&statement, &local_env)? {
-                        EvalResult::Return(val) => return Ok(EvalResult::Return(val)),
-                        EvalResult::Break => return Ok(EvalResult::Break),
-                        EvalResult::Continue => return Ok(EvalResult::Continue),
-                        _ => continue,
-                    }
+    pub fn if_else_stmt(
+        &mut self,
+        collection: &[(Expr, Vec<Stmt>, usize)],
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let local_env = Environment::new(Some(Rc::clone(env)));
+        let Some(branch) = self.chosen_branch(collection, &local_env)? else {
+            return Ok(make_none());
+        };
+        for statement in &collection[branch].1 {
+            match self.evaluate(statement, &local_env)? {
+                EvalResult::Return(val) => return Ok(EvalResult::Return(val)),
+                result @ (EvalResult::Break(_) | EvalResult::Continue(_)) => {
+                    return Ok(result);
                 }
-                break;
+                _ => continue,
             }
         }
-        let str: &str = if is_if_stmt { "if" } else { "else-if" };
-        return Err(RuntimeError::TypeMismatch(
-            format!("Expressions of {} statements must be of type bool", str),
-            *line,
-        ));
+        Ok(make_none())
     }
-    Ok(make_none())
-}
 
-pub fn for_stmt(
-    stmt: &Stmt,
-    expr1: &Expr,
-    expr2: &Expr,
-    statements: &[Stmt],
-    env: &Rc<RefCell<Environment>>,
-    line: usize,
-) -> Result<EvalResult, RuntimeError> {
-    let local_env = Environment::new(Some(Rc::clone(env)));
-    let _ = evaluate(&stmt, &local_env)?;
+    /// Evaluates the conditions of an if statement in turn and gives the
+    /// index of the first branch whose condition holds, if any.
+    pub(crate) fn chosen_branch(
+        &mut self,
+        collection: &[(Expr, Vec<Stmt>, usize)],
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<Option<usize>, RuntimeError> {
+        for (branch, (expr, _, line)) in collection.iter().enumerate() {
+            match self.evaluate_expr(expr, env)? {
+                RuntimeVal::Bool(true) => return Ok(Some(branch)),
+                RuntimeVal::Bool(false) => continue,
+                _ => {
+                    let str: &str = if branch == 0 { "if" } else { "else-if" };
+                    return Err(RuntimeError::TypeMismatch(
+                        format!("Expressions of {} statements must be of type bool", str),
+                        *line,
+                    ));
+                }
+            }
+        }
+        Ok(None)
+    }
 
-    loop {
-        if let RuntimeVal::Bool(bit) = evaluate_expr(expr1, &local_env)? {
-            if !bit {
+    pub fn for_stmt(
+        &mut self,
+        (stmt, expr1, expr2): &(Option<Box<Stmt>>, Option<Expr>, Option<Expr>),
+        statements: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+        label: Option<&str>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let local_env = Environment::new(Some(Rc::clone(env)));
+        if let Some(stmt) = stmt {
+            let _ = self.evaluate(stmt, &local_env)?;
+        }
+
+        let mut body_env = Environment::new(Some(Rc::clone(&local_env)));
+        loop {
+            let again = match expr1 {
+                Some(expr1) => loop_condition(self.evaluate_expr(expr1, &local_env)?, line)?,
+                None => true,
+            };
+            if !again {
                 break;
             }
+            // The loop variables live in `local_env`, shared by every
+            // pass, while each pass of the body gets a scope of its own
+            self.check_interrupt(line)?;
+            reuse_scope(&mut body_env);
             for statement in statements {
-                match evaluate(&statement, &local_env)? {
-                    EvalResult::Return(val) => return Ok(EvalResult::Return(val)),
-                    EvalResult::Break => return Ok(make_none()),
-                    EvalResult::Continue => break,
-                    _ => continue,
+                match loop_flow(self.evaluate(statement, &body_env)?, label) {
+                    Flow::Stop(result) => return Ok(result),
+                    Flow::NextIteration => break,
+                    Flow::Next => continue,
                 }
             }
-            let _ = evaluate(&Stmt::Expression(expr2.clone()), &local_env)?;
-        } else {
-            return Err(RuntimeError::TypeMismatch(
-                "Only bool type allowed in for loop condition statement".into(),
-                line,
-            ));
+            if let Some(expr2) = expr2 {
+                let _ = self.evaluate(&Stmt::Expression(expr2.clone()), &local_env)?;
+            }
         }
+
+        Ok(make_none())
     }
 
-    Ok(make_none())
-}
+    pub fn for_in_stmt(
+        &mut self,
+        names: &[String],
+        iterable: &Expr,
+        statements: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+        label: Option<&str>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let mut local_env = Environment::new(Some(Rc::clone(env)));
+        let mut items = LoopItems::new(self.evaluate_expr(iterable, env)?, line)?;
+        while let Some(item) = self.next_item(&mut items)? {
+            if let Some(result) = self.for_in_iteration(names, item, statements, &mut local_env, line, label)? {
+                return Ok(result);
+            }
+        }
+        Ok(make_none())
+    }
 
-pub fn while_stmt(
-    expr: &Expr,
-    statements: &[Stmt],
-    env: &Rc<RefCell<Environment>>,
-    line: usize,
-) -> Result<EvalResult, RuntimeError> {
-    let local_env = Environment::new(Some(Rc::clone(env)));
-    loop {
-        if let RuntimeVal::Bool(bit) = evaluate_expr(expr, &local_env)? {
-            if !bit {
-                break;
+    /// The next item a for-in loop gets from `items`, or `None` once there
+    /// are no more.
+    pub(crate) fn next_item(&mut self, items: &mut LoopItems) -> Result<Option<RuntimeVal>, RuntimeError> {
+        Ok(match items {
+            LoopItems::Range { next, end, inclusive } => {
+                if *next < *end || (*inclusive && *next == *end) {
+                    *next += 1.0;
+                    Some(make_number(*next - 1.0))
+                } else {
+                    None
+                }
             }
+            LoopItems::Values(values) => values.next(),
+            LoopItems::Generator(generator, line) => self.resume_generator(generator, *line)?,
+        })
+    }
+
+    // Runs one pass of a for-in body with the loop variables bound to `item`
+    // in `local_env`. Returns the result the loop should stop with, if any.
+    fn for_in_iteration(
+        &mut self,
+        names: &[String],
+        item: RuntimeVal,
+        statements: &[Stmt],
+        local_env: &mut Rc<RefCell<Environment>>,
+        line: usize,
+        label: Option<&str>,
+    ) -> Result<Option<EvalResult>, RuntimeError> {
+        self.check_interrupt(line)?;
+        reuse_scope(local_env);
+        declare_loop_vars(local_env, names, item, line)?;
+        for statement in statements {
+            match loop_flow(self.evaluate(statement, local_env)?, label) {
+                Flow::Stop(result) => return Ok(Some(result)),
+                Flow::NextIteration => break,
+                Flow::Next => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn while_stmt(
+        &mut self,
+        expr: &Expr,
+        statements: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+        label: Option<&str>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let mut local_env = Environment::new(Some(Rc::clone(env)));
+        while loop_condition(self.evaluate_expr(expr, env)?, line)? {
+            // Each pass gets a scope of its own, so closures made in one
+            // pass keep that pass's variables
+            self.check_interrupt(line)?;
+            reuse_scope(&mut local_env);
             for statement in statements {
-                match evaluate(&statement, &local_env)? {
-                    EvalResult::Return(val) => return Ok(EvalResult::Return(val)),
-                    EvalResult::Break => return Ok(make_none()),
-                    EvalResult::Continue => break,
-                    _ => continue,
+                match loop_flow(self.evaluate(statement, &local_env)?, label) {
+                    Flow::Stop(result) => return Ok(result),
+                    Flow::NextIteration => break,
+                    Flow::Next => continue,
                 }
             }
-        } else {
-            return Err(RuntimeError::TypeMismatch(
-                "Only bool type allowed in for loop condition statement".into(),
+        }
+
+        Ok(make_none())
+    }
+
+    pub fn loop_stmt(
+        &mut self,
+        statements: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+        label: Option<&str>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let mut local_env = Environment::new(Some(Rc::clone(env)));
+        loop {
+            // Each pass gets a scope of its own, so declarations in the body
+            // start afresh
+            self.check_interrupt(line)?;
+            reuse_scope(&mut local_env);
+            for statement in statements {
+                match loop_flow(self.evaluate(statement, &local_env)?, label) {
+                    Flow::Stop(result) => return Ok(result),
+                    Flow::NextIteration => break,
+                    Flow::Next => continue,
+                }
+            }
+        }
+    }
+
+    pub fn labeled_stmt(
+        &mut self,
+        label: &str,
+        body: &Stmt,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let label = Some(label);
+        match body {
+            Stmt::While(expr, stmts, line) => self.while_stmt(expr, stmts, env, *line, label),
+            Stmt::Loop(stmts, line) => self.loop_stmt(stmts, env, *line, label),
+            Stmt::For(clauses, stmts, line) => self.for_stmt(clauses, stmts, env, *line, label),
+            Stmt::ForIn(names, iterable, stmts, line) => {
+                self.for_in_stmt(names, iterable, stmts, env, *line, label)
+            }
+            // The parser only puts labels on loops
+            _ => Err(RuntimeError::InternalError),
+        }
+    }
+
+    pub fn try_stmt(
+        &mut self,
+        body: &[Stmt],
+        name: &str,
+        handler: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let error = match self.block_stmt(body, env) {
+            Ok(result) => return Ok(result),
+            Err(error) => error,
+        };
+        let local_env = catch_scope(error, name, env)?;
+        for stmt in handler {
+            match self.evaluate(stmt, &local_env)? {
+                EvalResult::Return(val) => return Ok(EvalResult::Return(val)),
+                result @ (EvalResult::Break(_) | EvalResult::Continue(_)) => return Ok(result),
+                _ => continue,
+            }
+        }
+        Ok(make_none())
+    }
+
+    pub fn with_stmt(
+        &mut self,
+        value: &Expr,
+        name: &str,
+        body: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+        line: usize,
+    ) -> Result<EvalResult, RuntimeError> {
+        let resource = self.evaluate_expr(value, env)?;
+        self.check_closable(&resource, line)?;
+        let resource_env = Environment::new(Some(Rc::clone(env)));
+        let _ = declare_var(&resource_env, name, resource.clone(), true);
+        let result = self.block_stmt(body, &resource_env);
+        // An error raised by the body wins over one raised while closing
+        let closed = self.close_resource(&resource, line);
+        let result = result?;
+        closed?;
+        Ok(result)
+    }
+
+    /// Makes sure a `with` statement can close its value: a socket, a file
+    /// or an instance with a `close()` method.
+    pub(crate) fn check_closable(&mut self, resource: &RuntimeVal, line: usize) -> Result<(), RuntimeError> {
+        let closable = match resource {
+            RuntimeVal::Socket(_) | RuntimeVal::File(_) | RuntimeVal::Channel(_) => true,
+            RuntimeVal::Instance { .. } => self.find_method(resource, "close")?.is_some(),
+            _ => false,
+        };
+        if closable {
+            return Ok(());
+        }
+        Err(RuntimeError::TypeMismatch(
+            format!(
+                "Only sockets, files, channels and instances with a 'close' method can be used in a with statement, found {}",
+                type_name(resource)
+            ),
+            line,
+        ))
+    }
+
+    pub(crate) fn close_resource(&mut self, resource: &RuntimeVal, line: usize) -> Result<(), RuntimeError> {
+        match resource {
+            RuntimeVal::Socket(socket) => socket.close(),
+            RuntimeVal::File(file) => file.close(),
+            RuntimeVal::Channel(channel) => channel.close(),
+            _ => {
+                if let Some(close) = self.find_method(resource, "close")? {
+                    self.call(close, &[], line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn match_stmt(
+        &mut self,
+        value: &Expr,
+        arms: &[MatchArm],
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let value = self.evaluate_expr(value, env)?;
+        let Some((arm, local_env)) = matching_arm(&value, arms, env) else {
+            return Ok(make_none());
+        };
+        for stmt in &arms[arm].body {
+            match self.evaluate(stmt, &local_env)? {
+                EvalResult::Return(val) => return Ok(EvalResult::Return(val)),
+                result @ (EvalResult::Break(_) | EvalResult::Continue(_)) => return Ok(result),
+                _ => continue,
+            }
+        }
+        Ok(make_none())
+    }
+
+    pub fn block_stmt(
+        &mut self,
+        stmts: &[Stmt],
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let local_env = Environment::new(Some(Rc::clone(env)));
+        for stmt in stmts {
+            match self.evaluate(stmt, &local_env)? {
+                EvalResult::Return(val) => return Ok(EvalResult::Return(val)),
+                result @ (EvalResult::Break(_) | EvalResult::Continue(_)) => return Ok(result),
+                _ => continue,
+            }
+        }
+        Ok(make_none())
+    }
+}
+
+impl Interpreter {
+    /// Renders a value the way `print` shows it. Instances whose class
+    /// defines `to_string()` are rendered by calling that method.
+    pub fn stringify(&mut self, runtime_val: &RuntimeVal) -> Result<String, RuntimeError> {
+        Ok(match runtime_val {
+            RuntimeVal::Number(num) => num.to_string(),
+            RuntimeVal::Bool(bit) => bit.to_string(),
+            RuntimeVal::Nil => "nil".to_string(),
+            RuntimeVal::String(s) => s.to_string(),
+            RuntimeVal::Object(obj) => self.stringify_obj(obj)?,
+            RuntimeVal::Map(map) => self.stringify_map(map)?,
+            RuntimeVal::Set(set) => {
+                let mut elements = vec![];
+                for element in sorted_set_elements(set) {
+                    elements.push(match element {
+                        MapKey::String(s) => format!("\"{}\"", s),
+                        _ => self.stringify(&element.to_value())?,
+                    });
+                }
+                format!("Set {{{}}}", elements.join(", "))
+            }
+            RuntimeVal::Array(arr) => self.stringify_arr(arr)?,
+            RuntimeVal::Bytes(bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(u8::to_string).collect();
+                format!("Bytes [{}]", bytes.join(", "))
+            }
+            RuntimeVal::Range { start, end, inclusive } => {
+                format!("{}{}{}", start, if *inclusive { "..=" } else { ".." }, end)
+            }
+            RuntimeVal::Function { declaration, .. } => format!("Function: '{}'", declaration.name),
+            RuntimeVal::Overloaded { declarations, .. } => format!("Function: '{}'", declarations[0].name),
+            RuntimeVal::NativeFunction(_, name) => format!("Native Function: '{}'", name),
+            RuntimeVal::Method { declaration, .. } => format!("Method '{}'", declaration.name),
+            RuntimeVal::Class { name, .. } => format!("Class: '{}'", name),
+            RuntimeVal::Interface(interface) => format!("Interface: '{}'", interface.name),
+            RuntimeVal::Mixin { declaration, .. } => format!("Mixin: '{}'", declaration.name),
+            RuntimeVal::Instance { class_name, .. } => {
+                match self.find_method(runtime_val, "to_string")? {
+                    Some(method) => self.call_to_string(method, class_name)?,
+                    None => format!("Class Instance: '{}'", class_name),
+                }
+            }
+            RuntimeVal::WeakRef { class_name, .. } => format!("WeakRef: '{}'", class_name),
+            RuntimeVal::Generator(generator) => format!("Generator: '{}'", generator.name()),
+            RuntimeVal::Task(task) => format!("Task: '{}'", task.name()),
+            RuntimeVal::Socket(socket) => format!("Socket: '{}'", socket.address()),
+            RuntimeVal::File(file) => format!("File: '{}'", file.path()),
+            RuntimeVal::Channel(_) => "Channel".to_string(),
+            RuntimeVal::Mutex(_) => "Mutex".to_string(),
+            RuntimeVal::Thread(thread) => format!("Thread: '{}'", thread.name()),
+        })
+    }
+
+    fn call_to_string(&mut self, method: RuntimeVal, class_name: &str) -> Result<String, RuntimeError> {
+        let line = match &method {
+            RuntimeVal::Method { declaration, .. } => declaration.line,
+            _ => return Err(RuntimeError::InternalError),
+        };
+        match self.call(method, &[], line)? {
+            RuntimeVal::String(s) => Ok(s.to_string()),
+            _ => Err(RuntimeError::TypeMismatch(
+                format!("'to_string' of class '{}' must return a string", class_name),
                 line,
-            ));
+            )),
+        }
+    }
+
+    fn stringify_obj(&mut self, obj: &HashMap<String, RuntimeVal>) -> Result<String, RuntimeError> {
+        let mut result = String::from("{\n");
+        for (key, value) in obj.iter() {
+            result.push_str(&format!("    \"{}\": {},\n", key, self.stringify(value)?));
+        }
+        result.push_str("}\n");
+        Ok(result)
+    }
+
+    fn stringify_map(&mut self, map: &HashMap<MapKey, RuntimeVal>) -> Result<String, RuntimeError> {
+        let mut entries = vec![];
+        for key in sorted_map_keys(map) {
+            let key_text = match key {
+                MapKey::String(s) => format!("\"{}\"", s),
+                _ => self.stringify(&key.to_value())?,
+            };
+            entries.push(format!("{}: {}", key_text, self.stringify(&map[key])?));
+        }
+        Ok(format!("Map {{{}}}", entries.join(", ")))
+    }
+
+    fn stringify_arr(&mut self, arr: &[RuntimeVal]) -> Result<String, RuntimeError> {
+        let mut result = String::from("[");
+        for val in arr {
+            result.push_str(&self.stringify(val)?);
+            result.push_str(", ");
         }
+        result.push_str("]\n");
+        Ok(result)
     }
+}
 
-    Ok(make_none())
+/// What a for-in loop walks over. Ranges and generators give their items
+/// one at a time instead of being expanded into an array.
+pub(crate) enum LoopItems {
+    Range { next: f64, end: f64, inclusive: bool },
+    Values(std::vec::IntoIter<RuntimeVal>),
+    Generator(Rc<Generator>, usize),
 }
 
-pub fn block_stmt(
-    stmts: Vec<Stmt>,
+impl LoopItems {
+    pub(crate) fn new(iterable: RuntimeVal, line: usize) -> Result<LoopItems, RuntimeError> {
+        Ok(match iterable {
+            RuntimeVal::Range { start, end, inclusive } => LoopItems::Range { next: start, end, inclusive },
+            RuntimeVal::Generator(generator) => LoopItems::Generator(generator, line),
+            iterable => LoopItems::Values(iteration_items(iterable, line)?.into_iter()),
+        })
+    }
+}
+
+/// Whether a loop whose condition gave `condition` goes round again.
+pub(crate) fn loop_condition(condition: RuntimeVal, line: usize) -> Result<bool, RuntimeError> {
+    match condition {
+        RuntimeVal::Bool(bit) => Ok(bit),
+        _ => Err(RuntimeError::TypeMismatch(
+            "Only bool type allowed in for loop condition statement".into(),
+            line,
+        )),
+    }
+}
+
+/// The scope a catch block runs in, with the error bound to `name`, or the
+/// error itself when it is one try/catch lets through.
+pub(crate) fn catch_scope(
+    error: RuntimeError,
+    name: &str,
     env: &Rc<RefCell<Environment>>,
-) -> Result<EvalResult, RuntimeError> {
+) -> Result<Rc<RefCell<Environment>>, RuntimeError> {
+    let Some((kind, message, line)) = error.caught() else {
+        return Err(error);
+    };
     let local_env = Environment::new(Some(Rc::clone(env)));
-    for stmt in stmts {
-        match evaluate(&stmt, &local_env)? {
-            EvalResult::Return(val) => return Ok(EvalResult::Return(val)),
-            EvalResult::Break => return Ok(EvalResult::Break),
-            EvalResult::Continue => return Ok(EvalResult::Continue),
-            _ => continue,
+    let _ = declare_var(&local_env, name, make_error(kind, &message, line), false);
+    Ok(local_env)
+}
+
+/// The first arm of a match statement that `value` fits, with the scope
+/// holding the names its pattern binds.
+pub(crate) fn matching_arm(
+    value: &RuntimeVal,
+    arms: &[MatchArm],
+    env: &Rc<RefCell<Environment>>,
+) -> Option<(usize, Rc<RefCell<Environment>>)> {
+    arms.iter().enumerate().find_map(|(arm, MatchArm { pattern, .. })| {
+        let local_env = Environment::new(Some(Rc::clone(env)));
+        fits(pattern, value, &local_env).then_some((arm, local_env))
+    })
+}
+
+/// Items a for-in loop walks over an array, string, bytes, map or set in.
+pub fn iteration_items(iterable: RuntimeVal, line: usize) -> Result<Vec<RuntimeVal>, RuntimeError> {
+    Ok(match iterable {
+        RuntimeVal::Array(arr) => arr,
+        RuntimeVal::String(s) => s.chars().map(|c| make_string(&c.to_string())).collect(),
+        RuntimeVal::Bytes(bytes) => bytes.into_iter().map(|byte| make_number(byte as f64)).collect(),
+        RuntimeVal::Map(map) => sorted_map_keys(&map).into_iter().map(MapKey::to_value).collect(),
+        RuntimeVal::Set(set) => sorted_set_elements(&set).into_iter().map(MapKey::to_value).collect(),
+        _ => {
+            return Err(RuntimeError::TypeMismatch(
+                "Only ranges, arrays, strings, bytes, maps, sets and generators can be iterated over in a for loop".into(),
+                line,
+            ));
+        }
+    })
+}
+
+/// Declares the variables of a for-in loop in `env` for one item. A single
+/// variable holds the item itself; several take the elements of an array
+/// with exactly that many elements, in order.
+pub fn declare_loop_vars(
+    env: &Rc<RefCell<Environment>>,
+    names: &[String],
+    item: RuntimeVal,
+    line: usize,
+) -> Result<(), RuntimeError> {
+    if let [name] = names {
+        let _ = declare_var(env, name, item, false);
+        return Ok(());
+    }
+    for (name, element) in names.iter().zip(unpack(item, names.len(), "loop variables", line)?) {
+        let _ = declare_var(env, name, element, false);
+    }
+    Ok(())
+}
+
+/// Whether `value` fits a match arm's pattern, declaring the names the
+/// pattern binds in `env` along the way. A pattern that does not fit may
+/// leave some of them declared, so `env` should be thrown away then.
+pub fn fits(pattern: &Pattern, value: &RuntimeVal, env: &Rc<RefCell<Environment>>) -> bool {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Binding(name), _) => {
+            let _ = declare_var(env, name, value.clone(), false);
+            true
+        }
+        (Pattern::Literal(Expr::NumericLiteral(expected, _)), RuntimeVal::Number(num)) => num == expected,
+        (Pattern::Literal(Expr::StringLiteral(expected, _)), RuntimeVal::String(str)) => str == expected,
+        (Pattern::Literal(Expr::BoolLiteral(expected, _)), RuntimeVal::Bool(bit)) => bit == expected,
+        (Pattern::Literal(Expr::Null(_)), RuntimeVal::Nil) => true,
+        (Pattern::Array(patterns), RuntimeVal::Array(elements)) => {
+            patterns.len() == elements.len()
+                && patterns.iter().zip(elements).all(|(pattern, element)| fits(pattern, element, env))
+        }
+        (Pattern::Object(entries), RuntimeVal::Object(properties)) => {
+            entries.iter().all(|(key, pattern)| {
+                properties.get(key).is_some_and(|property| fits(pattern, property, env))
+            })
+        }
+        _ => false,
+    }
+}
+
+// The elements of an array that has to hold exactly `count` of them, one
+// for each of the variables it is unpacked into
+fn unpack(value: RuntimeVal, count: usize, targets: &str, line: usize) -> Result<Vec<RuntimeVal>, RuntimeError> {
+    match value {
+        RuntimeVal::Array(elements) if elements.len() == count => Ok(elements),
+        RuntimeVal::Array(elements) => Err(RuntimeError::TypeMismatch(
+            format!(
+                "Cannot unpack an array of {} elements into {} {}",
+                elements.len(),
+                count,
+                targets
+            ),
+            line,
+        )),
+        _ => Err(RuntimeError::TypeMismatch(
+            format!("Only arrays can be unpacked into {} {}", count, targets),
+            line,
+        )),
+    }
+}
+
+// What a loop does after one statement of its body ran
+enum Flow {
+    Next,
+    NextIteration,
+    Stop(EvalResult),
+}
+
+fn loop_flow(result: EvalResult, label: Option<&str>) -> Flow {
+    match result {
+        EvalResult::Break(None) => Flow::Stop(make_none()),
+        EvalResult::Continue(None) => Flow::NextIteration,
+        EvalResult::Break(Some(target)) if Some(&target[..]) == label => Flow::Stop(make_none()),
+        EvalResult::Continue(Some(target)) if Some(&target[..]) == label => Flow::NextIteration,
+        // Returns, and labels of enclosing loops, end this loop and travel on
+        result @ (EvalResult::Return(_) | EvalResult::Break(_) | EvalResult::Continue(_)) => {
+            Flow::Stop(result)
         }
+        _ => Flow::Next,
     }
-    Ok(make_none())
 }
@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::environment::*;
+use crate::handle_errors::RuntimeError;
+use crate::interpreter::interpreter::Interpreter;
+use crate::values::*;
+
+// Tracing writes one line per statement and per call to the error output,
+// indented by call depth, so it never mixes with what the program prints.
+impl Interpreter {
+    pub fn evaluate_traced(
+        &mut self,
+        ast_node: &Stmt,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<EvalResult, RuntimeError> {
+        let line = ast_node.line();
+        // The call to `main` is made by the interpreter, not the program
+        if line == Some(0) || matches!(ast_node, Stmt::Comment(..)) {
+            return self.evaluate_stmt(ast_node, env);
+        }
+        // Compound statements are logged before their bodies run, so the
+        // statements inside them appear underneath
+        let compound = match ast_node {
+            Stmt::IfElse(_) => Some("if".to_string()),
+            Stmt::For(..) => Some("for".to_string()),
+            Stmt::ForIn(names, ..) => Some(format!("for {} in", names.join(", "))),
+            Stmt::While(..) => Some("while".to_string()),
+            Stmt::Loop(..) => Some("loop".to_string()),
+            Stmt::Try(..) => Some("try".to_string()),
+            Stmt::Match(..) => Some("match".to_string()),
+            Stmt::With(_, name, ..) => Some(format!("with {}", name)),
+            Stmt::Block(_) => Some("block".to_string()),
+            Stmt::Break(None) => Some("break".to_string()),
+            Stmt::Break(Some(label)) => Some(format!("break {}", label)),
+            Stmt::Continue(None) => Some("continue".to_string()),
+            Stmt::Continue(Some(label)) => Some(format!("continue {}", label)),
+            Stmt::Function(declaration) => Some(format!("fun {}", declaration.name)),
+            Stmt::Class(class) => Some(format!("class {}", class.name)),
+            Stmt::Interface(interface) => Some(format!("interface {}", interface.name)),
+            Stmt::Mixin(mixin) => Some(format!("mixin {}", mixin.name)),
+            _ => None,
+        };
+        if let Some(text) = compound {
+            self.trace(self.call_depth, line, &text);
+            return self.evaluate_stmt(ast_node, env);
+        }
+
+        let result = self.evaluate_stmt(ast_node, env)?;
+        let text = match (ast_node, &result) {
+            (Stmt::VarDeclaration(declaration), _) => {
+                let kind = if declaration.constant { "const" } else { "var" };
+                let value = lookup_var(env, &declaration.identifier[..])
+                    .map(|value| self.trace_value(&value))
+                    .unwrap_or_default();
+                format!("{} {} = {}", kind, declaration.identifier, value)
+            }
+            (Stmt::VarUnpack(names, constant, _, _), _) => {
+                let kind = if *constant { "const" } else { "var" };
+                let values: Vec<String> = names
+                    .iter()
+                    .map(|name| lookup_var(env, name).map(|value| self.trace_value(&value)).unwrap_or_default())
+                    .collect();
+                format!("{} {} = {}", kind, names.join(", "), values.join(", "))
+            }
+            (Stmt::Global(name, _, _), _) => {
+                let value = lookup_var(&self.globals, name)
+                    .map(|value| self.trace_value(&value))
+                    .unwrap_or_default();
+                format!("global {} = {}", name, value)
+            }
+            (Stmt::Print(..), _) => "print".to_string(),
+            (_, EvalResult::Return(value)) => format!("return {}", self.trace_value(value)),
+            (_, EvalResult::Value(value)) => format!("expression => {}", self.trace_value(value)),
+            _ => "statement".to_string(),
+        };
+        self.trace(self.call_depth, line, &text);
+        Ok(result)
+    }
+
+    pub fn call_traced(
+        &mut self,
+        call: RuntimeVal,
+        args: &[RuntimeVal],
+        line: usize,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let name = callable_name(&call);
+        // Calls are logged at the caller's depth, the callee's statements one level deeper
+        let depth = self.call_depth - 1;
+        let arguments: Vec<String> = args.iter().map(|arg| self.trace_value(arg)).collect();
+        self.trace(depth, Some(line), &format!("call {}({})", name, arguments.join(", ")));
+        let result = self.call_value(call, args, line);
+        let text = match &result {
+            Ok(value) => format!("{} returned {}", name, self.trace_value(value)),
+            Err(_) => format!("{} failed", name),
+        };
+        self.trace(depth, Some(line), &text);
+        result
+    }
+
+    /// Logs a value a generator hands out. The statements leading up to a
+    /// `yield` are run by the generator and only the simple ones are logged.
+    pub fn trace_yield(&mut self, line: Option<usize>, value: &RuntimeVal) {
+        let text = format!("yield {}", self.trace_value(value));
+        self.trace(self.call_depth, line, &text);
+    }
+
+    fn trace(&mut self, depth: usize, line: Option<usize>, text: &str) {
+        let indent = "  ".repeat(depth);
+        let location = match line {
+            Some(line) if line != 0 => format!("[line {}] ", line),
+            _ => String::new(),
+        };
+        let _ = writeln!(self.output.stderr, "{}{}{}", indent, location, text);
+    }
+
+    // Stringifying may call a `to_string` method, which is not traced itself
+    fn trace_value(&mut self, value: &RuntimeVal) -> String {
+        if let RuntimeVal::String(text) = value {
+            return format!("\"{}\"", text);
+        }
+        self.trace = false;
+        let text = self
+            .stringify(value)
+            .unwrap_or_else(|_| format!("<{}>", type_name(value)));
+        self.trace = true;
+        text.trim_end().to_string()
+    }
+}
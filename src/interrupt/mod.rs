@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::handle_errors::RuntimeError;
+use crate::interpreter::interpreter::Interpreter;
+
+// Set by Ctrl+C, cleared once an interpreter has seen it
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Turns Ctrl+C into an `Interrupted` error raised in the running program,
+/// or a call to the function it passed to `on_interrupt`. Pressing Ctrl+C
+/// again before the program got to see the first one ends the process, so
+/// a program blocked reading input or joining a thread can still be
+/// stopped. Hosts embedding the interpreter keep their own handling unless
+/// they call this.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn catch_interrupts() {
+    // Fails only when another handler is installed, which then stays
+    let _ = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(crate::EXIT_INTERRUPTED);
+        }
+    });
+}
+
+/// True while a Ctrl+C waits for the program to see it.
+pub(crate) fn interrupt_pending() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Forgets a Ctrl+C nothing saw, such as one pressed at the REPL prompt.
+pub(crate) fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+impl Interpreter {
+    /// Raises `Interrupted`, or calls the `on_interrupt` handler, when Ctrl+C
+    /// was pressed since the last check. Threads leave it to the main thread.
+    pub(crate) fn check_interrupt(&mut self, line: usize) -> Result<(), RuntimeError> {
+        if !self.interruptible || !interrupt_pending() || !INTERRUPTED.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        match self.on_interrupt.clone() {
+            Some(handler) => self.call(handler, &[], line).map(|_| ()),
+            None => Err(RuntimeError::Interrupted("Interrupted by Ctrl+C".to_string(), line)),
+        }
+    }
+}
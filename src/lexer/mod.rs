@@ -1,6 +1,10 @@
-use crate::handle_errors::handle_lexer_error;
+use std::io::Write;
+use std::ops::Range;
 
-#[derive(Clone, PartialEq)]
+use crate::handle_errors::{Columns, Diagnostic, Severity, handle_lexer_error};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TokenType {
     // Single-Character Tokens
     LEFTPAREN,
@@ -12,6 +16,8 @@ pub enum TokenType {
     COLON,
     COMMA,
     DOT,
+    DOTDOT,
+    DOTDOTEQUAL,
     MINUS,
     MODULUS,
     PLUS,
@@ -33,6 +39,9 @@ pub enum TokenType {
     PLUSEQUAL,
     SLASHEQUAL,
     STAREQUAL,
+    QUESTIONQUESTION,
+    QUESTIONDOT,
+    QUESTIONBRACKET,
 
     // Literals
     IDENTIFIER,
@@ -41,7 +50,12 @@ pub enum TokenType {
 
     // Keywords
     AND,
+    AS,
+    ASYNC,
+    AWAIT,
     BREAK,
+    CASE,
+    CATCH,
     CLASS,
     CONST,
     CONTINUE,
@@ -49,35 +63,54 @@ pub enum TokenType {
     FALSE,
     FUN,
     FOR,
+    GLOBAL,
     IF,
+    IN,
+    INTERFACE,
+    LOOP,
+    MATCH,
+    MIXIN,
     NIL,
+    NOT,
     OR,
     PRINT,
     PRINTLN,
     RETURN,
+    STATIC,
     SUPER,
     THIS,
     TRUE,
+    TRY,
     VAR,
     WHILE,
+    WITH,
+    YIELD,
+
+    // Only emitted when the tokenizer keeps comments
+    COMMENT,
 
     // End of File
     EOF,
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub line: usize,
+    /// Byte offsets of the token in the source, quotes of a string and
+    /// slashes of a comment included
+    pub span: Range<usize>,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, line: usize) -> Self {
+    pub fn new(token_type: TokenType, lexeme: String, line: usize, span: Range<usize>) -> Self {
         Self {
             token_type,
             lexeme,
             line,
+            span,
         }
     }
 }
@@ -86,9 +119,12 @@ pub struct Tokenizer {
     tokens: Vec<Token>,
     source_code: String,
     start: usize,
+    // The line `start` is on, which a string spanning lines moves `line` past
+    start_line: usize,
     current: usize,
     line: usize,
-    had_error: bool,
+    diagnostics: Vec<Diagnostic>,
+    keep_comments: bool,
 }
 
 impl Tokenizer {
@@ -97,24 +133,54 @@ impl Tokenizer {
             tokens: vec![],
             source_code: source_code.to_string(),
             start: 0,
+            start_line: 1,
             current: 0,
             line: 1,
-            had_error: false,
+            diagnostics: vec![],
+            keep_comments: false,
         }
     }
 
-    pub fn scan_tokens(mut self, code: &[&str]) -> (Vec<Token>, bool) {
+    /// Emits a `COMMENT` token for every `//` comment instead of skipping
+    /// it. The lexeme is the comment text following the slashes.
+    pub fn keep_comments(mut self) -> Tokenizer {
+        self.keep_comments = true;
+        self
+    }
+
+    pub fn scan_tokens(self, code: &[&str], err: &mut dyn Write) -> (Vec<Token>, bool) {
+        let (tokens, diagnostics) = self.scan(code, err);
+        (tokens, !diagnostics.is_empty())
+    }
+
+    /// Scans the whole source, reporting every error to `err` as it goes,
+    /// and returns the tokens along with the errors found.
+    pub fn scan(mut self, code: &[&str], err: &mut dyn Write) -> (Vec<Token>, Vec<Diagnostic>) {
         while !&self.is_at_end() {
             self.start = self.current;
-            self.scan_token(code);
+            self.start_line = self.line;
+            self.scan_token(code, err);
         }
 
+        let end = self.source_code.len();
         self.tokens
-            .push(Token::new(TokenType::EOF, String::from("EOF"), self.line));
-        (self.tokens, self.had_error)
+            .push(Token::new(TokenType::EOF, String::from("EOF"), self.line, end..end));
+        (self.tokens, self.diagnostics)
     }
 
-    fn scan_token(&mut self, code: &[&str]) {
+    fn error(&mut self, message: &str, code: &[&str], err: &mut dyn Write) {
+        let columns = Columns::of(&self.source_code, &(self.start..self.current));
+        handle_lexer_error(err, self.start_line, columns, message, code);
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            code: "LexerError".to_string(),
+            message: message.to_string(),
+            line: self.start_line,
+            span: self.start..self.current,
+        });
+    }
+
+    fn scan_token(&mut self, code: &[&str], err: &mut dyn Write) {
         let c = self.advance();
 
         match c {
@@ -125,8 +191,25 @@ impl Tokenizer {
             '[' => self.add_token(TokenType::LEFTBRACKET),
             ']' => self.add_token(TokenType::RIGHTBRACKET),
             ':' => self.add_token(TokenType::COLON),
+            // A `#!` line at the very start lets the file run as an executable
+            '#' if self.start == 0 && self.match_char('!') => {
+                while self.peek() != '\n' && !self.is_at_end() {
+                    self.advance();
+                }
+            }
             ',' => self.add_token(TokenType::COMMA),
-            '.' => self.add_token(TokenType::DOT),
+            '.' => {
+                if self.match_char('.') {
+                    let inclusive = self.match_char('=');
+                    self.add_token(if inclusive {
+                        TokenType::DOTDOTEQUAL
+                    } else {
+                        TokenType::DOTDOT
+                    });
+                } else {
+                    self.add_token(TokenType::DOT);
+                }
+            }
             '-' => {
                 let matched = self.match_char('=');
                 self.add_token(if matched {
@@ -157,6 +240,11 @@ impl Tokenizer {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    if self.keep_comments {
+                        let text = self.source_code[self.start + 2..self.current].trim_end();
+                        self.tokens
+                            .push(Token::new(TokenType::COMMENT, text.to_string(), self.line, self.start..self.current));
+                    }
                 } else if self.match_char('=') {
                     self.add_token(TokenType::SLASHEQUAL);
                 } else {
@@ -203,21 +291,36 @@ impl Tokenizer {
                     TokenType::GREATER
                 });
             }
+            '?' => {
+                if self.match_char('?') {
+                    self.add_token(TokenType::QUESTIONQUESTION);
+                } else if self.match_char('.') {
+                    self.add_token(TokenType::QUESTIONDOT);
+                } else if self.match_char('[') {
+                    self.add_token(TokenType::QUESTIONBRACKET);
+                } else {
+                    self.error("Unexpected character ?. Expected '??', '?.' or '?['", code, err);
+                }
+            }
 
             ' ' | '\r' | '\t' => {}
             '\n' => {
                 self.line += 1;
             }
-            '"' | '\'' => self.string(c, code),
+            '"' | '\'' => self.string(c, code, err),
 
             _ => {
-                if is_digit(c) {
+                // r"..." is a raw string, which reads the same as a plain one
+                // since Lox strings have no escape sequences
+                if c == 'r' && (self.peek() == '"' || self.peek() == '\'') {
+                    let quote = self.advance();
+                    self.string(quote, code, err);
+                } else if is_digit(c) {
                     self.number();
                 } else if is_alpha(c) {
                     self.identifier();
                 } else {
-                    handle_lexer_error(self.line, &format!("Unexpected character {c}."), &code[self.line-1][..]);
-                    self.had_error = true;
+                    self.error(&format!("Unexpected character {c}."), code, err);
                 }
             }
         };
@@ -248,20 +351,39 @@ impl Tokenizer {
         self.add_token(TokenType::NUMBER);
     }
 
-    fn string(&mut self, c: char, code: &[&str]) {
-        while self.peek() != c && !self.is_at_end() {
+    // Scans a string after its opening quote. Three quotes in a row open a
+    // string that only three of the same quotes close, so it can hold single
+    // quotes of either kind. Any string may span several lines.
+    fn string(&mut self, c: char, code: &[&str], err: &mut dyn Write) {
+        let triple = self.peek() == c && self.peek_next() == c;
+        if triple {
+            self.current += 2;
+        }
+        let delimiter = if triple { 3 } else { 1 };
+        let content_start = self.current;
+        while !self.is_at_end() && !self.closes_string(c, triple) {
             if self.peek() == '\n' {
                 self.line += 1;
             }
             self.advance();
         }
         if self.is_at_end() {
-            handle_lexer_error(self.line, "Unterminated string.", &code[self.line-1][..]);
-            self.had_error = true;
+            self.error("Unterminated string.", code, err);
             return;
         }
-        self.advance();
-        self.add_token(TokenType::STRING);
+        let text = &self.source_code[content_start..self.current];
+        let span = self.start..self.current + delimiter;
+        self.tokens
+            .push(Token::new(TokenType::STRING, text.to_string(), self.line, span));
+        self.current += delimiter;
+    }
+
+    fn closes_string(&self, c: char, triple: bool) -> bool {
+        if triple {
+            self.source_code.as_bytes()[self.current..].starts_with(c.to_string().repeat(3).as_bytes())
+        } else {
+            self.peek() == c
+        }
     }
 
     fn get_current_char(&self, buf: usize) -> char {
@@ -304,13 +426,9 @@ impl Tokenizer {
     }
 
     fn add_token(&mut self, token_type: TokenType) {
-        let mut buf = 0;
-        if token_type == TokenType::STRING {
-            buf = 1;
-        }
-        let text = &self.source_code[self.start + buf..self.current - buf];
+        let text = &self.source_code[self.start..self.current];
         self.tokens
-            .push(Token::new(token_type, text.to_string(), self.line));
+            .push(Token::new(token_type, text.to_string(), self.line, self.start..self.current));
     }
 }
 
@@ -326,10 +444,15 @@ fn is_digit(c: char) -> bool {
     c.is_ascii_digit()
 }
 
-fn match_keyword(s: &str) -> TokenType {
+pub fn match_keyword(s: &str) -> TokenType {
     match s {
         "and" => TokenType::AND,
+        "as" => TokenType::AS,
+        "async" => TokenType::ASYNC,
+        "await" => TokenType::AWAIT,
         "break" => TokenType::BREAK,
+        "case" => TokenType::CASE,
+        "catch" => TokenType::CATCH,
         "class" => TokenType::CLASS,
         "const" => TokenType::CONST,
         "continue" => TokenType::CONTINUE,
@@ -337,17 +460,28 @@ fn match_keyword(s: &str) -> TokenType {
         "false" => TokenType::FALSE,
         "for" => TokenType::FOR,
         "fun" => TokenType::FUN,
+        "global" => TokenType::GLOBAL,
         "if" => TokenType::IF,
+        "in" => TokenType::IN,
+        "interface" => TokenType::INTERFACE,
+        "loop" => TokenType::LOOP,
+        "match" => TokenType::MATCH,
+        "mixin" => TokenType::MIXIN,
         "nil" => TokenType::NIL,
+        "not" => TokenType::NOT,
         "or" => TokenType::OR,
         "print" => TokenType::PRINT,
         "println" => TokenType::PRINTLN,
         "return" => TokenType::RETURN,
+        "static" => TokenType::STATIC,
         "super" => TokenType::SUPER,
         "this" => TokenType::THIS,
         "true" => TokenType::TRUE,
+        "try" => TokenType::TRY,
         "var" => TokenType::VAR,
         "while" => TokenType::WHILE,
+        "with" => TokenType::WITH,
+        "yield" => TokenType::YIELD,
         _ => TokenType::IDENTIFIER,
     }
 }
@@ -2,19 +2,42 @@ use std::cell::RefCell;
 use std::error::Error;
 use std::fs;
 use std::io;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Read;
+use std::path::Path;
 use std::rc::Rc;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
 
+use crate::ast::Stmt;
 use crate::environment::*;
 use crate::handle_errors::*;
+use crate::interpreter::interpreter::Interpreter;
+use crate::interpreter::profile::Profile;
+use crate::threads::ProgramSource;
+use crate::values::{RuntimeVal, type_name};
 
-mod ast;
+pub mod ast;
+mod cache;
+mod embed;
+mod encoding;
 mod environment;
+mod file;
 mod handle_errors;
+mod interrupt;
 mod interpreter {
     pub mod expression;
+    pub mod generator;
     pub mod interpreter;
+    pub mod profile;
+    pub mod random;
+    pub mod scheduler;
     pub mod statement;
+    pub mod trace;
 }
 mod lexer;
 mod parser {
@@ -22,77 +45,804 @@ mod parser {
     pub mod statement;
     pub mod parser;
 }
+mod formatter;
 mod global_scope;
+mod linter;
+#[cfg(not(target_arch = "wasm32"))]
+mod program;
+mod lsp {
+    pub mod json;
+    pub mod server;
+}
+mod socket;
+mod threads;
 mod values;
+#[cfg(target_arch = "wasm32")]
+mod wasm;
+
+pub use crate::embed::{LoxError, Value};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::program::Program;
+#[cfg(feature = "serde")]
+pub use crate::embed::{from_value, to_value};
+pub use crate::environment::{Mode, NativeAccess};
+pub use crate::handle_errors::{Diagnostic, ErrorFormat, FileError, FileErrorKind, Severity, set_color, set_error_format};
+#[cfg(not(target_arch = "wasm32"))]
+pub use crate::interrupt::catch_interrupts;
+pub use crate::interpreter::interpreter::Output;
+pub use crate::lexer::{Token, TokenType};
+
+// Interpretation recurses on the native stack, so it runs on a dedicated
+// thread large enough for `MAX_CALL_DEPTH` nested calls.
+const INTERPRETER_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// How often `watch_file` checks whether the file was saved
+#[cfg(not(target_arch = "wasm32"))]
+const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Settings for running a program. The default runs a program that starts
+/// at `main`, with every native installed and no diagnostics.
+#[derive(Clone)]
+pub struct RunOptions {
+    pub access: NativeAccess,
+    pub mode: Mode,
+    /// Log every statement and call, with its result, to stderr
+    pub trace: bool,
+    /// Count calls and time spent per function, reported to stderr at exit
+    pub profile: bool,
+    /// Report lint warnings to stderr before the program runs
+    pub warnings: bool,
+    /// Reuse the parsed program saved in a `.loxc` file next to the source
+    /// while the source is unchanged, and save it there otherwise. Only has
+    /// an effect with the `cache` feature and when running a file.
+    pub cache: bool,
+    /// Run files whatever their extension. `.lox` files and files without
+    /// an extension, such as scripts starting with `#!`, always run.
+    pub any_extension: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            access: NativeAccess::All,
+            mode: Mode::Main,
+            trace: false,
+            profile: false,
+            warnings: false,
+            cache: false,
+            any_extension: false,
+        }
+    }
+}
+
+// Hosts share these between threads
+#[cfg(not(target_arch = "wasm32"))]
+const _: () = {
+    const fn thread_safe<T: Send + Sync>() {}
+    thread_safe::<Program>();
+    thread_safe::<Value>();
+    thread_safe::<LoxError>();
+};
+
+/// Process exit status of a program that failed to lex or parse.
+pub const EXIT_COMPILE_ERROR: i32 = 65;
+/// Process exit status of a program stopped by a runtime error.
+pub const EXIT_RUNTIME_ERROR: i32 = 70;
+/// Process exit status when the program file could not be read.
+pub const EXIT_FILE_ERROR: i32 = 66;
+/// Process exit status of a program stopped by Ctrl+C.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn with_interpreter_stack<R: Send, F: FnOnce() -> R + Send>(task: F) -> R {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(INTERPRETER_STACK_SIZE)
+            .spawn_scoped(scope, task)
+            .expect("Failed to spawn interpreter thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+// WebAssembly has no threads to spawn, programs run on the caller's stack
+#[cfg(target_arch = "wasm32")]
+fn with_interpreter_stack<R: Send, F: FnOnce() -> R + Send>(task: F) -> R {
+    task()
+}
+
+enum Status {
+    Success,
+    CompileError,
+    RuntimeError,
+    Interrupted,
+    Exit(i32),
+}
 
-pub fn run_file(file_path: &str, command_line_args: &[&str]) -> Result<(), Box<dyn Error>> {
-    if !file_path.ends_with(".lox") {
-        return Err("Invalid file type, expected a .lox file".into());
+impl Status {
+    fn code(&self) -> i32 {
+        match self {
+            Status::Success => 0,
+            Status::CompileError => EXIT_COMPILE_ERROR,
+            Status::RuntimeError => EXIT_RUNTIME_ERROR,
+            Status::Interrupted => EXIT_INTERRUPTED,
+            Status::Exit(code) => *code,
+        }
     }
-    let contents = fs::read_to_string(file_path)?;
-    let mut env = Environment::new(None);
-    run(&contents[..], &mut env, command_line_args, false);
-    Ok(())
 }
 
-pub fn run_prompt() {
-    let mut statement = String::new();
-    let mut env = Environment::new(None);
+/// Runs a `.lox` file and returns the exit status the process should end
+/// with: 0 on success, `EXIT_COMPILE_ERROR`, `EXIT_RUNTIME_ERROR`, or the
+/// code the script passed to `exit`. A file that cannot be read is returned
+/// as a `FileError`.
+pub fn run_file(file_path: &str, command_line_args: &[&str]) -> Result<i32, Box<dyn Error>> {
+    run_file_with_options(file_path, command_line_args, RunOptions::default())
+}
+
+/// Runs a `.lox` file whose top-level statements execute in order, without
+/// a `main` function.
+pub fn run_script(file_path: &str, command_line_args: &[&str]) -> Result<i32, Box<dyn Error>> {
+    let options = RunOptions {
+        mode: Mode::Script,
+        ..RunOptions::default()
+    };
+    run_file_with_options(file_path, command_line_args, options)
+}
+
+pub fn run_file_with_options(
+    file_path: &str,
+    command_line_args: &[&str],
+    options: RunOptions,
+) -> Result<i32, Box<dyn Error>> {
+    let contents = read_program(file_path, &options)?;
+    Ok(run_named_source(
+        &contents[..],
+        Some(file_path),
+        command_line_args,
+        options,
+        Output::default(),
+        Box::new(BufReader::new(io::stdin())),
+    ))
+}
+
+// Whether a file may be run as a Lox program under `options`
+fn runnable_file(file_path: &str, options: &RunOptions) -> bool {
+    options.any_extension || file_path.ends_with(".lox") || Path::new(file_path).extension().is_none()
+}
+
+// Reads a program file, if `options` allow running it
+fn read_program(file_path: &str, options: &RunOptions) -> Result<String, FileError> {
+    if !runnable_file(file_path, options) {
+        return Err(FileError::new(file_path, FileErrorKind::InvalidType));
+    }
+    read_source(file_path)
+}
+
+fn read_source(path: &str) -> Result<String, FileError> {
+    fs::read_to_string(path).map_err(|e| FileError::from_io(path, &e))
+}
+
+/// Runs a program read from stdin to its end, as `lox -` does. Errors
+/// point into `<stdin>`, and `scan` finds the input used up by then.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_stdin(command_line_args: &[&str], options: RunOptions) -> Result<i32, Box<dyn Error>> {
+    let mut contents = String::new();
+    io::stdin().read_to_string(&mut contents)?;
+    // There is no file to keep a cache next to
+    let options = RunOptions {
+        cache: false,
+        ..options
+    };
+    Ok(run_named_source(
+        &contents,
+        Some("<stdin>"),
+        command_line_args,
+        options,
+        Output::default(),
+        Box::new(BufReader::new(io::stdin())),
+    ))
+}
+
+/// Runs code given on the command line, as `lox -e` does. Statements run
+/// in order and the value of every expression is printed, as at the REPL.
+pub fn run_inline(code: &str, command_line_args: &[&str], options: RunOptions) -> i32 {
+    let options = RunOptions {
+        mode: Mode::Repl,
+        cache: false,
+        ..options
+    };
+    let input = Box::new(BufReader::new(io::stdin()));
+    run_named_source(code, Some("<command line>"), command_line_args, options, Output::default(), input)
+}
+
+/// Runs a `.lox` file and hands its result to the host: the value `main`
+/// returned or, in script mode, the value of the last top-level expression
+/// statement. Program output still goes to stdout and warnings to stderr,
+/// errors are returned instead of printed.
+pub fn eval_file(
+    file_path: &str,
+    command_line_args: &[&str],
+    options: RunOptions,
+) -> Result<Value, LoxError> {
+    let contents = read_program(file_path, &options).map_err(|e| LoxError::File(e.to_string()))?;
+    eval_named_source(
+        &contents,
+        Some(file_path),
+        command_line_args,
+        options,
+        Output::default(),
+        Box::new(BufReader::new(io::stdin())),
+    )
+}
+
+/// Runs a program given as source text and hands its result to the host,
+/// as `eval_file` does, with its output going to `output` and `scan`
+/// reading from `input`. Every call gets an interpreter of its own, so
+/// servers can evaluate scripts from any number of threads at once.
+pub fn eval_source(
+    source: &str,
+    command_line_args: &[&str],
+    options: RunOptions,
+    output: Output,
+    input: Box<dyn BufRead + Send>,
+) -> Result<Value, LoxError> {
+    eval_named_source(source, None, command_line_args, options, output, input)
+}
+
+fn eval_named_source(
+    source: &str,
+    file: Option<&str>,
+    command_line_args: &[&str],
+    options: RunOptions,
+    output: Output,
+    input: Box<dyn BufRead + Send>,
+) -> Result<Value, LoxError> {
+    with_interpreter_stack(|| {
+        with_file(file, || {
+            let serialized_code = serialize_source_code(source);
+            let mut diagnostics = vec![];
+            let Some(program) = parse(source, &serialized_code, &mut diagnostics, options.mode) else {
+                return Err(LoxError::Compile(String::from_utf8_lossy(&diagnostics).into_owned()));
+            };
+            let env = Environment::new_global(&options.access);
+            let mut interpreter = Interpreter::new(&env, output, input);
+            configure(&mut interpreter, &options, Some(source));
+            if interpreter.warnings {
+                report_warnings(&program, &serialized_code, &mut interpreter.output.stderr);
+            }
+            let result = interpreter
+                .evaluate_program(&program, &env, command_line_args, options.mode)
+                .and_then(|value| interpreter.host_value(&value));
+            interpreter.write_profile();
+            result.map_err(LoxError::from)
+        })
+    })
+}
+
+/// Runs a `.lox` file, then runs it again from a fresh interpreter every
+/// time it is saved. Returns only if the file cannot be run the first time.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn watch_file(
+    file_path: &str,
+    command_line_args: &[&str],
+    options: RunOptions,
+) -> Result<i32, Box<dyn Error>> {
+    let modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    let mut last_run = modified(file_path);
+    run_file_with_options(file_path, command_line_args, options.clone())?;
     loop {
-        print!("> ");
-        io::stdout().flush().unwrap();
-        io::stdin()
-            .read_line(&mut statement)
-            .expect("Failed to read line");
+        if !json_errors() {
+            eprintln!("Watching {} for changes", file_path);
+        }
+        // Editors may briefly remove a file while saving it, so a missing
+        // file is waited out rather than reported
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let current = modified(file_path);
+            if current.is_some() && current != last_run {
+                last_run = current;
+                break;
+            }
+        }
+        if let Err(e) = run_file_with_options(file_path, command_line_args, options.clone()) {
+            match e.downcast_ref::<FileError>() {
+                Some(error) => error.report(),
+                None => eprintln!("File error: {e}"),
+            }
+        }
+    }
+}
 
-        if statement.trim() == "exit" {
+/// How long `bench_file` keeps running a program.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy)]
+pub enum BenchLimit {
+    /// Run the program exactly this many times
+    Runs(usize),
+    /// Start new runs until this much time has passed, running at least once
+    Time(Duration),
+}
+
+/// Runs a `.lox` file over and over, each time from a fresh interpreter,
+/// and prints the fastest, mean and standard deviation of the wall time a
+/// run took. What the program prints is discarded, errors still go to
+/// stderr. A run that fails stops the benchmark with its exit status.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn bench_file(
+    file_path: &str,
+    command_line_args: &[&str],
+    options: RunOptions,
+    limit: BenchLimit,
+) -> Result<i32, Box<dyn Error>> {
+    let contents = read_program(file_path, &options)?;
+    let started = Instant::now();
+    let mut times: Vec<f64> = vec![];
+    loop {
+        let done = match limit {
+            BenchLimit::Runs(runs) => times.len() >= runs,
+            BenchLimit::Time(time) => !times.is_empty() && started.elapsed() >= time,
+        };
+        if done {
             break;
         }
-        run(&statement[..], &mut env, &vec![], true);
-        statement.clear();
+        let output = Output {
+            stdout: Box::new(io::sink()),
+            stderr: Box::new(io::stderr()),
+        };
+        let input = Box::new(BufReader::new(io::stdin()));
+        let run_started = Instant::now();
+        let code = run_named_source(&contents, Some(file_path), command_line_args, options.clone(), output, input);
+        if code != 0 {
+            return Ok(code);
+        }
+        times.push(run_started.elapsed().as_secs_f64() * 1000.0);
+    }
+
+    let count = times.len() as f64;
+    let mean = times.iter().sum::<f64>() / count;
+    let min = times.iter().copied().fold(f64::INFINITY, f64::min);
+    // Sample standard deviation, 0 for a single run
+    let variance = if times.len() > 1 {
+        times.iter().map(|time| (time - mean).powi(2)).sum::<f64>() / (count - 1.0)
+    } else {
+        0.0
+    };
+    let runs = if times.len() == 1 { "run" } else { "runs" };
+    println!("{}: {} {}", file_path, times.len(), runs);
+    println!("  min    {:>10.3} ms", min);
+    println!("  mean   {:>10.3} ms", mean);
+    println!("  stddev {:>10.3} ms", variance.sqrt());
+    Ok(0)
+}
+
+/// Runs a program given as source text, writing everything it prints and
+/// every error it reports to `output` and reading `scan` input from `input`.
+/// Lets host applications and tests feed a script and capture its output
+/// instead of going through the console. Returns the exit status, as
+/// `run_file` does.
+pub fn run_source(
+    source: &str,
+    command_line_args: &[&str],
+    options: RunOptions,
+    output: Output,
+    input: Box<dyn BufRead + Send>,
+) -> i32 {
+    run_named_source(source, None, command_line_args, options, output, input)
+}
+
+// Errors point into `file` when the source was read from one
+fn run_named_source(
+    source: &str,
+    file: Option<&str>,
+    command_line_args: &[&str],
+    options: RunOptions,
+    output: Output,
+    input: Box<dyn BufRead + Send>,
+) -> i32 {
+    with_interpreter_stack(|| {
+        with_file(file, || {
+            let mut env = Environment::new_global(&options.access);
+            let mut interpreter = Interpreter::new(&env, output, input);
+            configure(&mut interpreter, &options, Some(source));
+            let cache_file = file.filter(|_| options.cache).map(cache::cache_path);
+            let status = run(
+                source,
+                &mut env,
+                &mut interpreter,
+                command_line_args,
+                options.mode,
+                cache_file.as_deref(),
+            );
+            interpreter.write_profile();
+            status.code()
+        })
+    })
+}
+
+// Threads started by the program rebuild it from `source`, so the REPL,
+// which has none, cannot start them
+fn configure(interpreter: &mut Interpreter, options: &RunOptions, source: Option<&str>) {
+    interpreter.trace = options.trace;
+    interpreter.warnings = options.warnings;
+    if options.profile {
+        interpreter.profile = Some(Profile::default());
+    }
+    interpreter.source = source.map(|text| {
+        Arc::new(ProgramSource {
+            text: text.to_string(),
+            mode: options.mode,
+            access: options.access.clone(),
+        })
+    });
+}
+
+/// Runs the REPL until `exit`, end of input or a call to the `exit` native.
+/// Returns the exit status the process should end with.
+pub fn run_prompt() -> i32 {
+    run_prompt_with_options(RunOptions::default())
+}
+
+/// Runs the REPL with the given natives and diagnostics. The mode is
+/// ignored, the prompt always runs in `Mode::Repl`.
+pub fn run_prompt_with_options(options: RunOptions) -> i32 {
+    with_interpreter_stack(|| {
+        let mut statement = String::new();
+        let mut env = Environment::new_global(&options.access);
+        allow_redeclaration(&env);
+        // The prompt reads through the interpreter's input so lines typed
+        // for `scan` and lines typed at the prompt come from the same buffer
+        let mut interpreter =
+            Interpreter::new(&env, Output::default(), Box::new(BufReader::new(io::stdin())));
+        configure(&mut interpreter, &options, None);
+        let code = loop {
+            print!("> ");
+            io::stdout().flush().unwrap();
+            match interpreter.input.read_line(&mut statement) {
+                Ok(0) => break 0,
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to read line: {e}");
+                    break EXIT_RUNTIME_ERROR;
+                }
+            }
+
+            if statement.trim() == "exit" {
+                break 0;
+            }
+            // Ctrl+C at the prompt stops nothing
+            interrupt::clear_interrupt();
+            // Errors are reported and the session goes on, only `exit(code)` ends it
+            let status = match statement.trim().strip_prefix(':') {
+                Some(command) => repl_command(command, &mut env, &mut interpreter, &options.access),
+                None => run(&statement[..], &mut env, &mut interpreter, &vec![], Mode::Repl, None),
+            };
+            if let Status::Exit(code) = status {
+                break code;
+            }
+            statement.clear();
+        };
+        interpreter.write_profile();
+        code
+    })
+}
+
+const REPL_HELP: &str = "\
+:env          list the variables in scope and their types
+:type <expr>  evaluate an expression and show the type of its value
+:load <file>  run a .lox file as a script in the current session
+:reset        forget every declaration made in this session
+:help         show this message
+exit          leave the REPL";
+
+fn repl_command(
+    command: &str,
+    env: &mut Rc<RefCell<Environment>>,
+    interpreter: &mut Interpreter,
+    access: &NativeAccess,
+) -> Status {
+    let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+    let argument = argument.trim();
+    match name {
+        "env" => {
+            for (var_name, value, constant) in visible_vars(env) {
+                if let RuntimeVal::NativeFunction(_, _) = value {
+                    continue;
+                }
+                let kind = if constant { "const" } else { "var" };
+                println!("{} {}: {}", kind, var_name, type_name(&value));
+            }
+            Status::Success
+        }
+        "type" => {
+            let serialized_code = serialize_source_code(argument);
+            let tokenizer = lexer::Tokenizer::new(argument);
+            let (tokens, had_error) =
+                tokenizer.scan_tokens(&serialized_code, &mut interpreter.output.stderr);
+            if had_error {
+                return Status::CompileError;
+            }
+            let mut parser = parser::parser::Parser::new(tokens, Mode::Repl);
+            let expr = match parser.parse_expr() {
+                Ok(expr) if !parser.not_eof() => expr,
+                Ok(_) => {
+                    let message = "':type' expects a single expression";
+                    render(&mut interpreter.output.stderr, Severity::Error, "InvalidCommand", message, 0, None, &[]);
+                    return Status::CompileError;
+                }
+                Err(e) => {
+                    let columns = error_columns(&parser, &e, argument);
+                    handle_parser_error(&mut interpreter.output.stderr, e, columns, &serialized_code);
+                    return Status::CompileError;
+                }
+            };
+            match interpreter.evaluate_expr(&expr, env) {
+                Ok(value) => {
+                    println!("{}", type_name(&value));
+                    Status::Success
+                }
+                Err(RuntimeError::Exit(code)) => Status::Exit(code),
+                Err(e) => {
+                    handle_runtime_error(&mut interpreter.output.stderr, e, &serialized_code);
+                    Status::RuntimeError
+                }
+            }
+        }
+        "load" => match read_source(argument) {
+            Ok(contents) => run(&contents[..], env, interpreter, &[], Mode::Script, None),
+            Err(e) => {
+                e.report();
+                Status::RuntimeError
+            }
+        },
+        "reset" => {
+            *env = Environment::new_global(access);
+            allow_redeclaration(env);
+            interpreter.globals = Rc::clone(env);
+            Status::Success
+        }
+        "help" => {
+            println!("{}", REPL_HELP);
+            Status::Success
+        }
+        _ => {
+            eprintln!("Unknown command ':{}'. Enter ':help' to list the commands", name);
+            Status::Success
+        }
+    }
+}
+
+/// Parses a program into its syntax tree without running it, for tools that
+/// inspect or transform programs. Errors are rendered to `err`. Returns
+/// `None` if the program has a compile error.
+pub fn parse_program(source: &str, mode: Mode, err: &mut dyn Write) -> Option<Vec<Stmt>> {
+    parse(source, &serialize_source_code(source), err, mode)
+}
+
+/// Lexes and parses a program, reporting any error to `err`. Returns `None`
+/// if the program has a compile error.
+fn parse(
+    source_code: &str,
+    serialized_code: &[&str],
+    err: &mut dyn Write,
+    mode: Mode,
+) -> Option<Vec<Stmt>> {
+    parse_tokens(lexer::Tokenizer::new(source_code), source_code, serialized_code, err, mode)
+}
+
+fn parse_tokens(
+    tokenizer: lexer::Tokenizer,
+    source_code: &str,
+    serialized_code: &[&str],
+    err: &mut dyn Write,
+    mode: Mode,
+) -> Option<Vec<Stmt>> {
+    let (tokens, had_error) = tokenizer.scan_tokens(serialized_code, err);
+
+    if had_error {
+        return None;
+    }
+
+    let mut program = parser::parser::Parser::new(tokens, mode);
+    match program.produce_ast() {
+        Ok(s) => Some(s),
+        Err(e) => {
+            let columns = error_columns(&program, &e, source_code);
+            handle_parser_error(err, e, columns, serialized_code);
+            None
+        }
     }
 }
 
+// Where on its line the token a parser error points at is
+fn error_columns(parser: &parser::parser::Parser, error: &ParserError, source_code: &str) -> Option<Columns> {
+    let span = parser.error_span(error.line())?;
+    Columns::of(source_code, &span)
+}
+
+/// Whether the source parses and declares a top-level `main` function to
+/// start at, so tools can tell programs from scripts.
+fn declares_main(source_code: &str) -> bool {
+    let serialized_code = serialize_source_code(source_code);
+    parse(source_code, &serialized_code, &mut io::sink(), Mode::Script).is_some_and(|program| {
+        program
+            .iter()
+            .any(|stmt| matches!(stmt, Stmt::Function(declaration) if declaration.name == "main"))
+    })
+}
+
 fn run(
     source_code: &str,
     env: &mut Rc<RefCell<Environment>>,
+    interpreter: &mut Interpreter,
     command_line_args: &[&str],
-    is_repl: bool,
-) {
+    mode: Mode,
+    cache_file: Option<&Path>,
+) -> Status {
     let serialized_code = serialize_source_code(source_code);
+    let cached = cache_file.and_then(|path| cache::load(path, source_code, mode));
+    let parsed_program = match cached {
+        Some(program) => program,
+        None => {
+            let Some(program) = parse(source_code, &serialized_code, &mut interpreter.output.stderr, mode) else {
+                return Status::CompileError;
+            };
+            if let Some(path) = cache_file {
+                cache::store(path, source_code, mode, &program);
+            }
+            program
+        }
+    };
+    if interpreter.warnings {
+        report_warnings(&parsed_program, &serialized_code, &mut interpreter.output.stderr);
+    }
 
-    let tokenizer = lexer::Tokenizer::new(source_code);
-    let (tokens, had_error) = tokenizer.scan_tokens(&serialized_code);
+    match interpreter.evaluate_program(&parsed_program, env, command_line_args, mode) {
+        Ok(_) => Status::Success,
+        Err(RuntimeError::Exit(code)) => Status::Exit(code),
+        // The REPL goes back to the prompt, a file ends with its own status
+        Err(e @ RuntimeError::Interrupted(..)) => {
+            handle_runtime_error(&mut interpreter.output.stderr, e, &serialized_code);
+            Status::Interrupted
+        }
+        Err(e) => {
+            handle_runtime_error(&mut interpreter.output.stderr, e, &serialized_code);
+            Status::RuntimeError
+        }
+    }
+}
 
-    if had_error {
-        return;
+fn report_warnings(program: &[Stmt], serialized_code: &[&str], err: &mut dyn Write) -> usize {
+    let warnings = linter::lint(program);
+    for (kind, message, line) in &warnings {
+        handle_warning(err, kind, message, *line, serialized_code);
     }
+    warnings.len()
+}
 
-    let mut program = parser::parser::Parser::new(tokens, is_repl);
-    let parsed_program = match program.produce_ast() {
-        Ok(s) => s,
-        Err(e) => {
-            handle_parser_error(e, &serialized_code);
-            return;
+/// Runs a language server for editors on stdin and stdout until the client
+/// exits. Returns the exit status the process should end with.
+pub fn run_language_server() -> i32 {
+    with_interpreter_stack(|| {
+        let mut input = BufReader::new(io::stdin());
+        lsp::server::serve(&mut input, &mut io::stdout())
+    })
+}
+
+/// Parses `.lox` files without running them and reports their compile
+/// errors and lint warnings to stderr. Returns `EXIT_COMPILE_ERROR` if a
+/// file does not parse and 0 otherwise, warnings do not fail the check.
+pub fn check_files(paths: &[&str], mode: Mode) -> Result<i32, Box<dyn Error>> {
+    let mut status = 0;
+    for path in paths {
+        if !path.ends_with(".lox") {
+            return Err(FileError::new(path, FileErrorKind::InvalidType).into());
         }
-    };
+        let contents = read_source(path)?;
+        let serialized_code = serialize_source_code(&contents[..]);
+        let count = with_file(Some(path), || {
+            let program = parse(&contents[..], &serialized_code, &mut io::stderr(), mode)?;
+            Some(report_warnings(&program, &serialized_code, &mut io::stderr()))
+        });
+        let Some(count) = count else {
+            if !json_errors() {
+                eprintln!("{} has errors", path);
+            }
+            status = EXIT_COMPILE_ERROR;
+            continue;
+        };
+        if count > 0 && !json_errors() {
+            eprintln!("{}: {} warning{}", path, count, if count == 1 { "" } else { "s" });
+        }
+    }
+    Ok(status)
+}
+
+/// Formats `.lox` files in place. With `check` the files are left untouched
+/// and the ones that are not formatted are listed instead. Returns the exit
+/// status: 1 if `check` found unformatted files and `EXIT_COMPILE_ERROR` if a
+/// file could not be parsed.
+pub fn format_files(paths: &[&str], check: bool) -> Result<i32, Box<dyn Error>> {
+    let mut status = 0;
+    for path in paths {
+        if !path.ends_with(".lox") {
+            return Err(FileError::new(path, FileErrorKind::InvalidType).into());
+        }
+        let contents = read_source(path)?;
+        let Some(formatted) = with_file(Some(path), || format_source(&contents[..])) else {
+            if !json_errors() {
+                eprintln!("Could not format {}", path);
+            }
+            status = EXIT_COMPILE_ERROR;
+            continue;
+        };
+        if formatted == contents {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            if status == 0 {
+                status = 1;
+            }
+        } else {
+            fs::write(path, formatted)?;
+        }
+    }
+    Ok(status)
+}
 
-    if let Err(e) =
-        interpreter::interpreter::evaluate_program(&parsed_program, env, command_line_args, is_repl)
-    {
-        handle_runtime_error(e, &serialized_code);
+/// Splits source code into tokens for editors and syntax highlighters.
+/// Comments are kept as `COMMENT` tokens and the last token is `EOF`. Each
+/// token has its line and the byte range it covers. Source that does not
+/// lex gives every error found instead, nothing is written to stderr.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, Vec<Diagnostic>> {
+    let serialized_code = serialize_source_code(source);
+    let tokenizer = lexer::Tokenizer::new(source).keep_comments();
+    let (tokens, diagnostics) = tokenizer.scan(&serialized_code, &mut io::sink());
+    if diagnostics.is_empty() { Ok(tokens) } else { Err(diagnostics) }
+}
+
+/// Returns the canonical formatting of a program, or `None` after reporting
+/// why it cannot be formatted to stderr.
+pub fn format_source(source: &str) -> Option<String> {
+    let serialized_code = serialize_source_code(source);
+    let tokenizer = lexer::Tokenizer::new(source).keep_comments();
+    let program = parse_tokens(tokenizer, source, &serialized_code, &mut io::stderr(), Mode::Script)?;
+    let formatted = formatter::format_program(&program);
+
+    // Formatting must not change what the program means. The output has to
+    // parse to the same program, positions aside, and reformatting it has to
+    // reproduce it exactly, otherwise the original is kept.
+    let serialized_output = serialize_source_code(&formatted);
+    let tokenizer = lexer::Tokenizer::new(&formatted).keep_comments();
+    let reparsed = parse_tokens(tokenizer, &formatted, &serialized_output, &mut io::sink(), Mode::Script);
+    let unchanged = reparsed.is_some_and(|mut reparsed| {
+        let stable = formatter::format_program(&reparsed) == formatted;
+        let mut original = program;
+        ast::clear_positions(&mut original);
+        ast::clear_positions(&mut reparsed);
+        stable && reparsed == original
+    });
+    if !unchanged {
+        let message = "Formatting this file would change its meaning. Please report this as a bug.";
+        render(&mut io::stderr(), Severity::Error, "FormatterError", message, 0, None, &[]);
+        return None;
+    }
+    // The lexer skips a `#!` line, which has to stay first
+    match source.lines().next().filter(|line| line.starts_with("#!")) {
+        Some(shebang) => Some(format!("{}\n{}", shebang.trim_end(), formatted)),
+        None => Some(formatted),
     }
 }
 
 fn serialize_source_code(code: &str) -> Vec<&str> {
     let mut result = vec![];
 
+    // Indentation is kept so the carets under an error line up with it
     for line in code.lines() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
+        if line.trim().is_empty() {
             result.push("...");
         } else {
-            result.push(trimmed);
+            result.push(line.trim_end());
         }
     }
 
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::environment::native_names;
+
+/// Looks for code that is valid but most likely a mistake: variables that
+/// are never read, statements that can never run, declarations hiding a
+/// parameter or a native function, and conditions that never change.
+/// Returns each warning's kind and message with the line it refers to, in
+/// source order.
+pub fn lint(program: &[Stmt]) -> Vec<(&'static str, String, usize)> {
+    let mut linter = Linter {
+        scopes: vec![],
+        parameters: vec![],
+        warnings: vec![],
+        line: 1,
+    };
+    linter.stmts(program);
+    linter.warnings.sort_by(|(_, a, a_line), (_, b, b_line)| (a_line, a).cmp(&(b_line, b)));
+    linter.warnings
+}
+
+struct Variable {
+    line: usize,
+    read: bool,
+}
+
+struct Linter {
+    // Local scopes, innermost last. Globals are not tracked since functions
+    // may read them before they are declared.
+    scopes: Vec<HashMap<String, Variable>>,
+    // Parameters of each enclosing function, innermost last
+    parameters: Vec<Vec<String>>,
+    warnings: Vec<(&'static str, String, usize)>,
+    // Line of the last statement that recorded one
+    line: usize,
+}
+
+impl Linter {
+    fn warn(&mut self, kind: &'static str, message: String, line: usize) {
+        self.warnings.push((kind, message, line));
+    }
+
+    fn enter(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn exit(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        for (name, variable) in scope {
+            // A leading underscore marks a variable as unused on purpose
+            if !variable.read && !name.starts_with('_') {
+                self.warn("UnusedVariable", format!("'{}' is declared but never read", name), variable.line);
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &str, line: usize) {
+        if native_names().any(|native| native == name) {
+            self.warn("Shadowing", format!("'{}' shadows the native function of the same name", name), line);
+        } else if self.parameters.last().is_some_and(|parameters| parameters.iter().any(|parameter| parameter == name)) {
+            self.warn("Shadowing", format!("'{}' shadows a parameter of the enclosing function", name), line);
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), Variable { line, read: false });
+        }
+    }
+
+    fn read(&mut self, name: &str) {
+        if let Some(variable) = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name)) {
+            variable.read = true;
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        let mut terminator = None;
+        for stmt in stmts {
+            if matches!(stmt, Stmt::Comment(..)) {
+                continue;
+            }
+            // Only the first dead statement of a block is reported
+            if let Some(keyword) = terminator {
+                let line = stmt.line().unwrap_or(self.line);
+                self.warn("UnreachableCode", format!("Unreachable code after '{}'", keyword), line);
+            }
+            self.stmt(stmt);
+            terminator = match stmt {
+                Stmt::Return(..) => Some("return"),
+                Stmt::Break(_) => Some("break"),
+                Stmt::Continue(_) => Some("continue"),
+                _ => None,
+            };
+        }
+    }
+
+    fn body(&mut self, stmts: &[Stmt]) {
+        self.enter();
+        self.stmts(stmts);
+        self.exit();
+    }
+
+    fn constant_condition(&mut self, condition: &Expr, line: usize) {
+        if let Expr::BoolLiteral(bit, _) = condition {
+            self.warn("ConstantCondition", format!("Condition is always {}", bit), line);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        if let Some(line) = stmt.line() {
+            self.line = line;
+        }
+        match stmt {
+            Stmt::Expression(value) | Stmt::Return(value, _) | Stmt::Yield(value) => self.expr(value),
+            Stmt::VarDeclaration(declaration) => {
+                self.expr(&declaration.value);
+                self.declare(&declaration.identifier, declaration.line);
+            }
+            Stmt::VarUnpack(names, _, value, line) => {
+                self.expr(value);
+                for name in names {
+                    self.declare(name, *line);
+                }
+            }
+            Stmt::Global(_, value, _) => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            Stmt::Print(values, _, _) => {
+                for value in values.iter().flatten() {
+                    self.expr(value);
+                }
+            }
+            Stmt::IfElse(branches) => {
+                for (index, (condition, body, line)) in branches.iter().enumerate() {
+                    // A plain `else` is stored as `else if true`
+                    let is_else = index > 0
+                        && index == branches.len() - 1
+                        && matches!(condition, Expr::BoolLiteral(true, _));
+                    if !is_else {
+                        self.constant_condition(condition, *line);
+                    }
+                    self.expr(condition);
+                    self.body(body);
+                }
+            }
+            Stmt::For((initializer, condition, step), body, line) => {
+                self.enter();
+                if let Some(initializer) = initializer {
+                    self.stmt(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.constant_condition(condition, *line);
+                    self.expr(condition);
+                }
+                if let Some(step) = step {
+                    self.expr(step);
+                }
+                self.body(body);
+                self.exit();
+            }
+            Stmt::ForIn(names, iterable, body, line) => {
+                self.expr(iterable);
+                self.enter();
+                for name in names {
+                    self.declare(name, *line);
+                }
+                self.body(body);
+                self.exit();
+            }
+            Stmt::While(condition, body, line) => {
+                // `while true` is the usual way to write an endless loop
+                if matches!(condition, Expr::BoolLiteral(false, _)) {
+                    self.constant_condition(condition, *line);
+                }
+                self.expr(condition);
+                self.body(body);
+            }
+            Stmt::Loop(body, _) => self.body(body),
+            Stmt::Try(body, name, handler, line) => {
+                self.body(body);
+                self.enter();
+                self.declare(name, *line);
+                // Catching an error without looking at it is common
+                self.read(name);
+                self.stmts(handler);
+                self.exit();
+            }
+            Stmt::Match(value, arms, _) => {
+                self.expr(value);
+                for arm in arms {
+                    self.enter();
+                    for name in arm.pattern.names() {
+                        self.declare(name, arm.line);
+                    }
+                    self.stmts(&arm.body);
+                    self.exit();
+                }
+            }
+            Stmt::With(value, name, body, line) => {
+                self.expr(value);
+                self.enter();
+                self.declare(name, *line);
+                // The value may only be held so it is closed after the body
+                self.read(name);
+                self.stmts(body);
+                self.exit();
+            }
+            Stmt::Block(stmts) => self.body(stmts),
+            Stmt::Labeled(_, body) => self.stmt(body),
+            Stmt::Break(_) | Stmt::Continue(_) | Stmt::Comment(..) => {}
+            Stmt::Interface(interface) => self.declare(&interface.name, interface.line),
+            Stmt::Mixin(mixin) => {
+                self.declare(&mixin.name, mixin.line);
+                for method in mixin.methods.values() {
+                    self.function(method);
+                }
+            }
+            Stmt::Function(declaration) => {
+                self.declare(&declaration.name, declaration.line);
+                self.function(declaration);
+            }
+            Stmt::Class(class) => {
+                self.declare(&class.name, class.line);
+                self.class(class);
+            }
+        }
+    }
+
+    fn class(&mut self, class: &ClassDeclaration) {
+        if let Some(superclass) = &class.superclass {
+            self.read(superclass);
+        }
+        for name in class.mixins.iter().chain(&class.interfaces) {
+            self.read(name);
+        }
+        for field in class.static_fields.iter().chain(class.instance_fields.iter()) {
+            self.expr(&field.value);
+        }
+        for method in class.methods.values().chain(class.static_methods.values()) {
+            self.function(method);
+        }
+    }
+
+    fn function(&mut self, declaration: &FunctionDeclaration) {
+        self.enter();
+        for parameter in &declaration.parameters {
+            self.declare(parameter, declaration.line);
+            // Unused parameters are often required by the caller's signature
+            self.read(parameter);
+        }
+        self.parameters.push(declaration.parameters.clone());
+        self.stmts(&declaration.body);
+        self.parameters.pop();
+        self.exit();
+    }
+
+    fn expr(&mut self, value: &Expr) {
+        match value {
+            Expr::Identifier(name, _) => self.read(name),
+            Expr::NumericLiteral(..)
+            | Expr::Null(_)
+            | Expr::BoolLiteral(..)
+            | Expr::StringLiteral(..)
+            | Expr::This(_)
+            | Expr::Super(..) => {}
+            Expr::Array(elements, _) => {
+                for element in elements {
+                    self.expr(element);
+                }
+            }
+            Expr::Range { start, end, .. } => {
+                self.expr(start);
+                self.expr(end);
+            }
+            Expr::Member {
+                object,
+                property,
+                computed,
+                ..
+            } => {
+                self.expr(object);
+                if *computed {
+                    self.expr(property);
+                }
+            }
+            Expr::Slice {
+                object, start, end, ..
+            } => {
+                self.expr(object);
+                for bound in start.iter().chain(end) {
+                    self.expr(bound);
+                }
+            }
+            Expr::If { branches, line } => {
+                for (index, (condition, body, value)) in branches.iter().enumerate() {
+                    if index < branches.len() - 1 {
+                        self.constant_condition(condition, condition.line().unwrap_or(*line));
+                    }
+                    self.expr(condition);
+                    self.enter();
+                    self.stmts(body);
+                    self.expr(value);
+                    self.exit();
+                }
+            }
+            Expr::Call { args, caller, .. } => {
+                self.expr(caller);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Unary { right, .. } | Expr::Await { value: right, .. } => self.expr(right),
+            Expr::BinaryExpr { left, right, .. } | Expr::ComparisonLiteral { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::ObjectLiteral { properties } => {
+                for property in properties {
+                    match &property.value {
+                        Some(value) => self.expr(value),
+                        // `{ name }` reads the variable `name`
+                        None => self.read(&property.key),
+                    }
+                }
+            }
+            Expr::AssignmentExpr { assignee, value, .. } => {
+                // Assigning to a variable does not read it
+                if !matches!(assignee.as_ref(), Expr::Identifier(..)) {
+                    self.expr(assignee);
+                }
+                self.expr(value);
+            }
+            Expr::Class(class) => self.class(class),
+        }
+    }
+}
@@ -0,0 +1,712 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::ast::*;
+use crate::environment::{Mode, native_names};
+use crate::handle_errors::{ErrorFormat, set_error_format};
+use crate::linter::lint;
+use crate::lsp::json::Json;
+
+// Error code JSON-RPC answers requests for unknown methods with
+const METHOD_NOT_FOUND: f64 = -32601.0;
+
+// LSP symbol kinds
+const CLASS: f64 = 5.0;
+const METHOD: f64 = 6.0;
+const FIELD: f64 = 8.0;
+const INTERFACE: f64 = 11.0;
+const FUNCTION: f64 = 12.0;
+const VARIABLE: f64 = 13.0;
+const CONSTANT: f64 = 14.0;
+
+/// Serves the Language Server Protocol over `input` and `output` until the
+/// client sends `exit`. Documents are re-parsed on every change, which
+/// keeps diagnostics, definitions, hovers and symbols in step with the
+/// editor. Returns the exit status: 0 if the client asked for a shutdown
+/// first, 1 otherwise.
+pub fn serve(input: &mut dyn BufRead, output: &mut dyn Write) -> i32 {
+    // Errors are collected as JSON and turned into LSP diagnostics
+    set_error_format(ErrorFormat::Json);
+    let mut server = Server {
+        documents: HashMap::new(),
+        shut_down: false,
+        output,
+    };
+    while let Some(message) = read_message(input) {
+        let Some(message) = Json::parse(&message) else {
+            continue;
+        };
+        let method = message.get("method").as_str().unwrap_or_default().to_string();
+        if method == "exit" {
+            break;
+        }
+        let params = message.get("params");
+        let id = message.get("id");
+        match server.handle(&method, params) {
+            Some(result) if *id != Json::Null => server.send(Json::object(vec![
+                ("jsonrpc", Json::string("2.0")),
+                ("id", id.clone()),
+                ("result", result),
+            ])),
+            None if *id != Json::Null => server.send(Json::object(vec![
+                ("jsonrpc", Json::string("2.0")),
+                ("id", id.clone()),
+                (
+                    "error",
+                    Json::object(vec![
+                        ("code", Json::Number(METHOD_NOT_FOUND)),
+                        ("message", Json::String(format!("Unknown method '{}'", method))),
+                    ]),
+                ),
+            ])),
+            // Notifications get no answer
+            _ => {}
+        }
+    }
+    if server.shut_down { 0 } else { 1 }
+}
+
+// Reads one message framed by a `Content-Length` header
+fn read_message(input: &mut dyn BufRead) -> Option<String> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    String::from_utf8(body).ok()
+}
+
+struct Server<'a> {
+    documents: HashMap<String, String>,
+    shut_down: bool,
+    output: &'a mut dyn Write,
+}
+
+impl Server<'_> {
+    fn send(&mut self, message: Json) {
+        let body = message.to_string();
+        let _ = write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = self.output.flush();
+    }
+
+    fn notify(&mut self, method: &str, params: Json) {
+        self.send(Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("method", Json::string(method)),
+            ("params", params),
+        ]));
+    }
+
+    // Returns the result of a request, `Json::Null` for handled
+    // notifications and `None` for methods the server does not know
+    fn handle(&mut self, method: &str, params: &Json) -> Option<Json> {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or_default().to_string();
+        match method {
+            "initialize" => Some(Json::object(vec![
+                (
+                    "capabilities",
+                    Json::object(vec![
+                        // The whole document is sent on every change
+                        ("textDocumentSync", Json::Number(1.0)),
+                        ("definitionProvider", Json::Bool(true)),
+                        ("hoverProvider", Json::Bool(true)),
+                        ("documentSymbolProvider", Json::Bool(true)),
+                    ]),
+                ),
+                ("serverInfo", Json::object(vec![("name", Json::string("lox"))])),
+            ])),
+            "initialized" | "$/cancelRequest" | "$/setTrace" => Some(Json::Null),
+            "shutdown" => {
+                self.shut_down = true;
+                Some(Json::Null)
+            }
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+                self.publish_diagnostics(&uri);
+                Some(Json::Null)
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").as_array().unwrap_or_default();
+                if let Some(text) = changes.last().and_then(|change| change.get("text").as_str()) {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                self.publish_diagnostics(&uri);
+                Some(Json::Null)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                let params = Json::object(vec![
+                    ("uri", Json::String(uri)),
+                    ("diagnostics", Json::Array(vec![])),
+                ]);
+                self.notify("textDocument/publishDiagnostics", params);
+                Some(Json::Null)
+            }
+            "textDocument/definition" => Some(self.definition(&uri, params.get("position"))),
+            "textDocument/hover" => Some(self.hover(&uri, params.get("position"))),
+            "textDocument/documentSymbol" => Some(self.document_symbols(&uri)),
+            _ if method.starts_with("$/") => Some(Json::Null),
+            _ => None,
+        }
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) {
+        let text = self.documents.get(uri).cloned().unwrap_or_default();
+        let (program, mut diagnostics) = analyze(&text);
+        if let Some(program) = program {
+            for (kind, message, line) in lint(&program) {
+                diagnostics.push(diagnostic(&text, line, 2.0, kind, &message));
+            }
+        }
+        let params = Json::object(vec![
+            ("uri", Json::string(uri)),
+            ("diagnostics", Json::Array(diagnostics)),
+        ]);
+        self.notify("textDocument/publishDiagnostics", params);
+    }
+
+    // The declaration the identifier at `position` refers to
+    fn lookup(&self, uri: &str, position: &Json) -> Option<(Definition, String)> {
+        let text = self.documents.get(uri)?;
+        let line = position.get("line").as_usize()?;
+        let character = position.get("character").as_usize()?;
+        let source_line = text.lines().nth(line)?;
+        let (start, name) = word_at(source_line, character)?;
+        // `obj.name` refers to a field or method
+        let before = source_line.chars().take(start).collect::<String>();
+        let member = before.trim_end().ends_with('.');
+
+        let (program, _) = analyze(text);
+        let mut collector = Collector { definitions: vec![] };
+        collector.stmts(&program?, usize::MAX);
+        let line = line + 1;
+        let definition = collector
+            .definitions
+            .into_iter()
+            .filter(|definition| {
+                definition.name == name
+                    && definition.member == member
+                    && definition.visible.0 <= line
+                    && line <= definition.visible.1
+            })
+            // The innermost declaration shadows the others
+            .max_by_key(|definition| definition.visible.0);
+        match definition {
+            Some(definition) => Some((definition, name)),
+            None if !member && native_names().any(|native| native == name) => Some((
+                Definition {
+                    name: name.clone(),
+                    line: 0,
+                    detail: format!("native function {}", name),
+                    member: false,
+                    visible: (0, usize::MAX),
+                },
+                name,
+            )),
+            None => None,
+        }
+    }
+
+    fn definition(&self, uri: &str, position: &Json) -> Json {
+        let Some((definition, name)) = self.lookup(uri, position) else {
+            return Json::Null;
+        };
+        // Natives are not declared anywhere in the document
+        if definition.line == 0 {
+            return Json::Null;
+        }
+        let text = &self.documents[uri];
+        Json::object(vec![
+            ("uri", Json::string(uri)),
+            ("range", name_range(text, definition.line, &name)),
+        ])
+    }
+
+    fn hover(&self, uri: &str, position: &Json) -> Json {
+        let Some((definition, _)) = self.lookup(uri, position) else {
+            return Json::Null;
+        };
+        Json::object(vec![(
+            "contents",
+            Json::object(vec![
+                ("kind", Json::string("markdown")),
+                ("value", Json::String(format!("```lox\n{}\n```", definition.detail))),
+            ]),
+        )])
+    }
+
+    fn document_symbols(&self, uri: &str) -> Json {
+        let Some(text) = self.documents.get(uri) else {
+            return Json::Array(vec![]);
+        };
+        let Some(program) = analyze(text).0 else {
+            return Json::Array(vec![]);
+        };
+        let mut symbols = vec![];
+        for stmt in &program {
+            match stmt {
+                Stmt::Function(declaration) => {
+                    symbols.push(symbol(text, &declaration.name, &function_detail("", declaration), FUNCTION, declaration.line, vec![]))
+                }
+                Stmt::VarDeclaration(declaration) => {
+                    let kind = if declaration.constant { CONSTANT } else { VARIABLE };
+                    symbols.push(symbol(text, &declaration.identifier, &var_detail(declaration, ""), kind, declaration.line, vec![]))
+                }
+                Stmt::VarUnpack(names, constant, _, line) => {
+                    let (kind, keyword) = if *constant { (CONSTANT, "const") } else { (VARIABLE, "var") };
+                    for name in names {
+                        symbols.push(symbol(text, name, &format!("{} {}", keyword, name), kind, *line, vec![]));
+                    }
+                }
+                Stmt::Class(class) => {
+                    let mut members = vec![];
+                    for field in class.static_fields.iter().chain(class.instance_fields.iter()) {
+                        members.push((field.line, symbol(text, &field.identifier, &var_detail(field, ""), FIELD, field.line, vec![])));
+                    }
+                    for method in class.methods.values().chain(class.static_methods.values()) {
+                        members.push((method.line, symbol(text, &method.name, &function_detail("", method), METHOD, method.line, vec![])));
+                    }
+                    members.sort_by_key(|(line, _)| *line);
+                    let children = members.into_iter().map(|(_, member)| member).collect();
+                    symbols.push(symbol(text, &class.name, &class_detail(class), CLASS, class.line, children));
+                }
+                Stmt::Mixin(mixin) => {
+                    let mut methods: Vec<_> = mixin.methods.values().collect();
+                    methods.sort_by_key(|method| method.line);
+                    let children = methods
+                        .into_iter()
+                        .map(|method| symbol(text, &method.name, &function_detail("", method), METHOD, method.line, vec![]))
+                        .collect();
+                    let detail = format!("mixin {}", mixin.name);
+                    symbols.push(symbol(text, &mixin.name, &detail, CLASS, mixin.line, children));
+                }
+                Stmt::Interface(interface) => {
+                    let children = interface
+                        .methods
+                        .iter()
+                        .map(|(name, parameters, line)| {
+                            let detail = interface_method_detail(interface, name, parameters);
+                            symbol(text, name, &detail, METHOD, *line, vec![])
+                        })
+                        .collect();
+                    let detail = format!("interface {}", interface.name);
+                    symbols.push(symbol(text, &interface.name, &detail, INTERFACE, interface.line, children));
+                }
+                _ => {}
+            }
+        }
+        Json::Array(symbols)
+    }
+}
+
+// Parses a document, returning its program if it has no compile errors and
+// the errors as LSP diagnostics otherwise. A document with a top level
+// `main` function is checked as a program, anything else as a script.
+fn analyze(text: &str) -> (Option<Vec<Stmt>>, Vec<Json>) {
+    let serialized_code = crate::serialize_source_code(text);
+    let mut errors = vec![];
+    let mode = if crate::declares_main(text) { Mode::Main } else { Mode::Script };
+    let program = crate::parse(text, &serialized_code, &mut errors, mode);
+    let diagnostics = String::from_utf8_lossy(&errors)
+        .lines()
+        .filter_map(Json::parse)
+        .map(|error| {
+            let severity = match error.get("severity").as_str() {
+                Some("warning") => 2.0,
+                Some("note") => 3.0,
+                _ => 1.0,
+            };
+            diagnostic(
+                text,
+                error.get("line").as_usize().unwrap_or(1),
+                severity,
+                error.get("code").as_str().unwrap_or_default(),
+                error.get("message").as_str().unwrap_or_default(),
+            )
+        })
+        .collect();
+    (program, diagnostics)
+}
+
+fn position(line: usize, character: usize) -> Json {
+    Json::object(vec![
+        ("line", Json::Number(line as f64)),
+        ("character", Json::Number(character as f64)),
+    ])
+}
+
+// Diagnostics only know their line, so they cover the code on all of it
+fn diagnostic(text: &str, line: usize, severity: f64, code: &str, message: &str) -> Json {
+    let line = line.max(1) - 1;
+    let source_line = text.lines().nth(line).unwrap_or_default();
+    let start = source_line.chars().take_while(|c| c.is_whitespace()).count();
+    let end = source_line.trim_end().chars().count().max(start);
+    Json::object(vec![
+        (
+            "range",
+            Json::object(vec![("start", position(line, start)), ("end", position(line, end))]),
+        ),
+        ("severity", Json::Number(severity)),
+        ("code", Json::string(code)),
+        ("source", Json::string("lox")),
+        ("message", Json::string(message)),
+    ])
+}
+
+// Range of `name` on `line`, or of the line's start if it is not found
+fn name_range(text: &str, line: usize, name: &str) -> Json {
+    let line = line - 1;
+    let source_line = text.lines().nth(line).unwrap_or_default();
+    let start = find_word(source_line, name).unwrap_or(0);
+    let end = start + name.chars().count();
+    Json::object(vec![("start", position(line, start)), ("end", position(line, end))])
+}
+
+fn symbol(text: &str, name: &str, detail: &str, kind: f64, line: usize, children: Vec<Json>) -> Json {
+    let range = name_range(text, line, name);
+    Json::object(vec![
+        ("name", Json::string(name)),
+        ("detail", Json::string(detail)),
+        ("kind", Json::Number(kind)),
+        ("range", range.clone()),
+        ("selectionRange", range),
+        ("children", Json::Array(children)),
+    ])
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// The identifier touching `character` and the column it starts at
+fn word_at(line: &str, character: usize) -> Option<(usize, String)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut start = character.min(chars.len());
+    while start > 0 && is_word_char(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = start;
+    while end < chars.len() && is_word_char(chars[end]) {
+        end += 1;
+    }
+    if start == end || chars[start].is_ascii_digit() {
+        return None;
+    }
+    Some((start, chars[start..end].iter().collect()))
+}
+
+// Column of the first whole-word occurrence of `name` in `line`
+fn find_word(line: &str, name: &str) -> Option<usize> {
+    let chars: Vec<char> = line.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    (0..chars.len()).find(|&start| {
+        chars[start..].starts_with(&name)
+            && (start == 0 || !is_word_char(chars[start - 1]))
+            && chars.get(start + name.len()).is_none_or(|c| !is_word_char(*c))
+    })
+}
+
+fn function_detail(owner: &str, declaration: &FunctionDeclaration) -> String {
+    let prefix = if declaration.asynchronous { "async " } else { "" };
+    format!("{}fun {}{}({})", prefix, owner, declaration.name, declaration.parameters.join(", "))
+}
+
+fn class_detail(class: &ClassDeclaration) -> String {
+    let mut detail = match &class.superclass {
+        Some(superclass) => format!("class {} < {}", class.name, superclass),
+        None => format!("class {}", class.name),
+    };
+    if !class.mixins.is_empty() {
+        detail.push_str(&format!(" with {}", class.mixins.join(", ")));
+    }
+    if !class.interfaces.is_empty() {
+        detail.push_str(&format!(" : {}", class.interfaces.join(", ")));
+    }
+    detail
+}
+
+fn interface_method_detail(interface: &InterfaceDeclaration, name: &str, parameters: &[String]) -> String {
+    format!("{}.{}({})", interface.name, name, parameters.join(", "))
+}
+
+// Declarations whose initializer is a literal show the type it has
+fn var_detail(declaration: &VarDeclaration, owner: &str) -> String {
+    let keyword = if declaration.constant { "const" } else { "var" };
+    let kind = match declaration.value.as_ref() {
+        Expr::NumericLiteral(..) => Some("Number"),
+        Expr::StringLiteral(..) => Some("String"),
+        Expr::BoolLiteral(..) => Some("Bool"),
+        Expr::Null(_) => Some("Nil"),
+        Expr::Array(..) => Some("Array"),
+        Expr::ObjectLiteral { .. } => Some("Object"),
+        Expr::Range { .. } => Some("Range"),
+        _ => None,
+    };
+    match kind {
+        Some(kind) => format!("{} {}{}: {}", keyword, owner, declaration.identifier, kind),
+        None => format!("{} {}{}", keyword, owner, declaration.identifier),
+    }
+}
+
+struct Definition {
+    name: String,
+    line: usize,
+    detail: String,
+    // Fields and methods are reached through `.`, everything else by name
+    member: bool,
+    // First and last line the name can be used on
+    visible: (usize, usize),
+}
+
+struct Collector {
+    definitions: Vec<Definition>,
+}
+
+impl Collector {
+    fn define(&mut self, name: &str, line: usize, detail: String, member: bool, end: usize) {
+        // Globals can be used anywhere, functions are declared before `main` runs
+        let start = if end == usize::MAX { 0 } else { line };
+        self.definitions.push(Definition {
+            name: name.to_string(),
+            line,
+            detail,
+            member,
+            visible: (start, end),
+        });
+    }
+
+    // `end` is the last line of the body the statements are in
+    fn stmts(&mut self, stmts: &[Stmt], end: usize) {
+        for stmt in stmts {
+            self.stmt(stmt, end);
+        }
+    }
+
+    fn body(&mut self, stmts: &[Stmt], line: usize) {
+        self.stmts(stmts, last_line(stmts).max(line));
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, end: usize) {
+        match stmt {
+            Stmt::VarDeclaration(declaration) => {
+                let detail = var_detail(declaration, "");
+                self.define(&declaration.identifier, declaration.line, detail, false, end);
+            }
+            Stmt::VarUnpack(names, constant, _, line) => {
+                let kind = if *constant { "const" } else { "var" };
+                for name in names {
+                    self.define(name, *line, format!("{} {}", kind, name), false, end);
+                }
+            }
+            // A global declared from a function is visible everywhere after it
+            Stmt::Global(name, _, line) => {
+                self.define(name, *line, format!("var {} (global)", name), false, usize::MAX);
+            }
+            Stmt::Function(declaration) => {
+                let detail = function_detail("", declaration);
+                self.define(&declaration.name, declaration.line, detail, false, end);
+                self.function(declaration);
+            }
+            Stmt::Class(class) => {
+                self.define(&class.name, class.line, class_detail(class), false, end);
+                let owner = format!("{}.", class.name);
+                for field in &class.static_fields {
+                    let detail = format!("static {}", var_detail(field, &owner));
+                    self.define(&field.identifier, field.line, detail, true, usize::MAX);
+                }
+                for field in class.instance_fields.iter() {
+                    self.define(&field.identifier, field.line, var_detail(field, &owner), true, usize::MAX);
+                }
+                for (prefix, methods) in [("", &class.methods), ("static ", &class.static_methods)] {
+                    for method in methods.values() {
+                        let detail = format!("{}{}", prefix, function_detail(&owner, method));
+                        self.define(&method.name, method.line, detail, true, usize::MAX);
+                        self.function(method);
+                    }
+                }
+            }
+            Stmt::Mixin(mixin) => {
+                self.define(&mixin.name, mixin.line, format!("mixin {}", mixin.name), false, end);
+                let owner = format!("{}.", mixin.name);
+                for method in mixin.methods.values() {
+                    self.define(&method.name, method.line, function_detail(&owner, method), true, usize::MAX);
+                    self.function(method);
+                }
+            }
+            Stmt::Interface(interface) => {
+                self.define(&interface.name, interface.line, format!("interface {}", interface.name), false, end);
+                for (name, parameters, line) in &interface.methods {
+                    let detail = interface_method_detail(interface, name, parameters);
+                    self.define(name, *line, detail, true, usize::MAX);
+                }
+            }
+            Stmt::IfElse(branches) => {
+                for (_, body, line) in branches {
+                    self.body(body, *line);
+                }
+            }
+            Stmt::For((initializer, _, _), body, line) => {
+                let end = last_line(body).max(*line);
+                if let Some(initializer) = initializer {
+                    self.stmt(initializer, end);
+                }
+                self.stmts(body, end);
+            }
+            Stmt::ForIn(names, _, body, line) => {
+                let end = last_line(body).max(*line);
+                for name in names {
+                    self.define(name, *line, format!("var {} (loop variable)", name), false, end);
+                }
+                self.stmts(body, end);
+            }
+            Stmt::While(_, body, line) | Stmt::Loop(body, line) => self.body(body, *line),
+            Stmt::Try(body, name, handler, line) => {
+                self.body(body, *line);
+                let end = last_line(handler).max(last_line(body)).max(*line);
+                self.define(name, *line, format!("var {}: Object (caught error)", name), false, end);
+                self.stmts(handler, end);
+            }
+            Stmt::Match(_, arms, _) => {
+                for arm in arms {
+                    let end = last_line(&arm.body).max(arm.line);
+                    for name in arm.pattern.names() {
+                        self.define(name, arm.line, format!("var {} (match binding)", name), false, end);
+                    }
+                    self.stmts(&arm.body, end);
+                }
+            }
+            Stmt::With(_, name, body, line) => {
+                let end = last_line(body).max(*line);
+                self.define(name, *line, format!("const {} (with value)", name), false, end);
+                self.stmts(body, end);
+            }
+            Stmt::Block(stmts) => self.stmts(stmts, last_line(stmts)),
+            Stmt::Labeled(_, body) => self.stmt(body, end),
+            _ => {}
+        }
+    }
+
+    fn function(&mut self, declaration: &FunctionDeclaration) {
+        let end = last_line(&declaration.body).max(declaration.line);
+        for parameter in &declaration.parameters {
+            let detail = format!("parameter {} of {}", parameter, declaration.name);
+            self.define(parameter, declaration.line, detail, false, end);
+        }
+        self.stmts(&declaration.body, end);
+    }
+}
+
+// Last line any of the statements reaches
+fn last_line(stmts: &[Stmt]) -> usize {
+    stmts.iter().map(stmt_last_line).max().unwrap_or(0)
+}
+
+fn stmt_last_line(stmt: &Stmt) -> usize {
+    let own = stmt.line().unwrap_or(0);
+    let nested = match stmt {
+        Stmt::Expression(value) | Stmt::Return(value, _) | Stmt::Yield(value) => expr_last_line(value),
+        Stmt::VarDeclaration(declaration) => expr_last_line(&declaration.value),
+        Stmt::Global(_, value, _) => value.as_ref().map_or(0, expr_last_line),
+        Stmt::VarUnpack(_, _, value, _) => expr_last_line(value),
+        Stmt::Print(values, _, _) => values.iter().flatten().map(expr_last_line).max().unwrap_or(0),
+        Stmt::IfElse(branches) => branches
+            .iter()
+            .map(|(condition, body, _)| expr_last_line(condition).max(last_line(body)))
+            .max()
+            .unwrap_or(0),
+        Stmt::For((initializer, condition, step), body, _) => initializer
+            .as_deref()
+            .map_or(0, stmt_last_line)
+            .max(condition.as_ref().map_or(0, expr_last_line))
+            .max(step.as_ref().map_or(0, expr_last_line))
+            .max(last_line(body)),
+        Stmt::ForIn(_, iterable, body, _) => expr_last_line(iterable).max(last_line(body)),
+        Stmt::While(condition, body, _) => expr_last_line(condition).max(last_line(body)),
+        Stmt::Loop(body, _) => last_line(body),
+        Stmt::Try(body, _, handler, _) => last_line(body).max(last_line(handler)),
+        Stmt::With(value, _, body, _) => expr_last_line(value).max(last_line(body)),
+        Stmt::Match(value, arms, _) => arms
+            .iter()
+            .map(|arm| last_line(&arm.body).max(arm.line))
+            .max()
+            .unwrap_or(0)
+            .max(expr_last_line(value)),
+        Stmt::Block(stmts) => last_line(stmts),
+        Stmt::Function(declaration) => last_line(&declaration.body),
+        Stmt::Class(class) => class_last_line(class),
+        Stmt::Mixin(mixin) => mixin
+            .methods
+            .values()
+            .map(|method| last_line(&method.body).max(method.line))
+            .max()
+            .unwrap_or(0),
+        Stmt::Interface(interface) => interface.methods.iter().map(|(_, _, line)| *line).max().unwrap_or(0),
+        Stmt::Labeled(_, body) => stmt_last_line(body),
+        Stmt::Break(_) | Stmt::Continue(_) | Stmt::Comment(..) => 0,
+    };
+    own.max(nested)
+}
+
+fn class_last_line(class: &ClassDeclaration) -> usize {
+    class
+        .methods
+        .values()
+        .chain(class.static_methods.values())
+        .map(|method| last_line(&method.body).max(method.line))
+        .chain(class.static_fields.iter().chain(class.instance_fields.iter()).map(|field| field.line))
+        .max()
+        .unwrap_or(0)
+}
+
+fn expr_last_line(value: &Expr) -> usize {
+    let own = value.line().unwrap_or(0);
+    let nested = match value {
+        Expr::Array(elements, _) => elements.iter().map(expr_last_line).max().unwrap_or(0),
+        Expr::Range { start, end, .. } => expr_last_line(start).max(expr_last_line(end)),
+        Expr::Member { object, property, .. } => expr_last_line(object).max(expr_last_line(property)),
+        Expr::Slice {
+            object, start, end, ..
+        } => start
+            .iter()
+            .chain(end)
+            .map(|bound| expr_last_line(bound))
+            .max()
+            .unwrap_or(0)
+            .max(expr_last_line(object)),
+        Expr::Call { args, caller, .. } => args.iter().map(expr_last_line).max().unwrap_or(0).max(expr_last_line(caller)),
+        Expr::Unary { right, .. } | Expr::Await { value: right, .. } => expr_last_line(right),
+        Expr::BinaryExpr { left, right, .. } | Expr::ComparisonLiteral { left, right, .. } => {
+            expr_last_line(left).max(expr_last_line(right))
+        }
+        Expr::AssignmentExpr { assignee, value, .. } => expr_last_line(assignee).max(expr_last_line(value)),
+        Expr::If { branches, .. } => branches
+            .iter()
+            .map(|(condition, body, value)| {
+                expr_last_line(condition).max(last_line(body)).max(expr_last_line(value))
+            })
+            .max()
+            .unwrap_or(0),
+        Expr::ObjectLiteral { properties } => properties
+            .iter()
+            .map(|property| property.value.as_deref().map_or(property.line, expr_last_line))
+            .max()
+            .unwrap_or(0),
+        Expr::Class(class) => class_last_line(class),
+        _ => 0,
+    };
+    own.max(nested)
+}
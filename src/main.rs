@@ -1,17 +1,175 @@
 use lox::*;
 use std::env;
+use std::error::Error;
+use std::io::{self, IsTerminal};
 use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: lox [error options] [run] [--script] [--trace] [--profile] [--warn] [--cache] [--force | --lang lox] [--watch] [file.lox | - | -e code [arguments]]\n       lox [error options] fmt [--check] file.lox...\n       lox [error options] check [--script] file.lox...\n       lox [error options] bench [--script] [--runs N | --time SECONDS] file.lox [arguments]\n       lox lsp\nError options: --no-color, --error-format=human|json";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        let _ = run_prompt();
+    let mut args: Vec<String> = env::args().collect();
+    // Options for how errors are shown come before everything else. Errors
+    // are colored on a terminal unless `--no-color` is given or NO_COLOR is set.
+    let mut no_color = false;
+    while args.len() > 1 {
+        match &args[1][..] {
+            "--no-color" => no_color = true,
+            "--error-format=human" => set_error_format(ErrorFormat::Human),
+            "--error-format=json" => set_error_format(ErrorFormat::Json),
+            _ => break,
+        }
+        args.remove(1);
+    }
+    let color_allowed = env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+    set_color(!no_color && color_allowed && io::stderr().is_terminal());
+    if args.len() > 1 && args[1] == "lsp" {
+        process::exit(run_language_server());
+    }
+    if args.len() > 1 && args[1] == "fmt" {
+        let check = args.len() > 2 && args[2] == "--check";
+        let mut paths = vec![];
+        paths.extend(args.iter().skip(if check { 3 } else { 2 }).map(|arg| arg.as_str()));
+        if paths.is_empty() {
+            println!("Usage: lox fmt [--check] file.lox...");
+            process::exit(1);
+        }
+        exit_with(format_files(&paths, check));
+    }
+    if args.len() > 1 && args[1] == "check" {
+        let script = args.len() > 2 && args[2] == "--script";
+        let mut paths = vec![];
+        paths.extend(args.iter().skip(if script { 3 } else { 2 }).map(|arg| arg.as_str()));
+        if paths.is_empty() {
+            println!("Usage: lox check [--script] file.lox...");
+            process::exit(1);
+        }
+        let mode = if script { Mode::Script } else { Mode::Main };
+        exit_with(check_files(&paths, mode));
+    }
+    // Everything that runs a program lets Ctrl+C stop it gracefully
+    catch_interrupts();
+    if args.len() > 1 && args[1] == "bench" {
+        bench(&args[2..]);
+    }
+    // `lox run file.lox` is the same as `lox file.lox`
+    if args.len() > 1 && args[1] == "run" {
+        args.remove(1);
+    }
+    let mut options = RunOptions::default();
+    let mut watch = false;
+    let mut index = 1;
+    while index < args.len() && args[index].starts_with("--") {
+        match &args[index][..] {
+            "--script" => options.mode = Mode::Script,
+            "--trace" => options.trace = true,
+            "--profile" => options.profile = true,
+            "--warn" => options.warnings = true,
+            "--cache" => options.cache = true,
+            "--force" => options.any_extension = true,
+            // The only language there is, for files named otherwise
+            "--lang" if args.get(index + 1).is_some_and(|lang| lang == "lox") => {
+                options.any_extension = true;
+                index += 1;
+            }
+            "--watch" => watch = true,
+            flag => {
+                println!("Unknown option '{flag}'\n{USAGE}");
+                process::exit(1);
+            }
+        }
+        index += 1;
+    }
+
+    if index < args.len() && args[index] == "-e" {
+        let Some(code) = args.get(index + 1).filter(|_| !watch) else {
+            println!("-e needs code to run and cannot be watched\n{USAGE}");
+            process::exit(1);
+        };
+        let mut command_line_args = vec![];
+        command_line_args.extend(args.iter().skip(index + 2).map(|arg| arg.as_str()));
+        process::exit(run_inline(code, &command_line_args, options));
+    }
+    if index >= args.len() {
+        if watch {
+            println!("--watch needs a file to run\n{USAGE}");
+            process::exit(1);
+        }
+        process::exit(run_prompt_with_options(options));
     } else {
         let mut command_line_args = vec![];
-        command_line_args.extend(args.iter().skip(2).map(|arg| arg.as_str()));
-        if let Err(e) = run_file(&args[1], &command_line_args) {
-            println!("File error: {e}");
+        command_line_args.extend(args.iter().skip(index + 1).map(|arg| arg.as_str()));
+        if watch && args[index] == "-" {
+            println!("--watch needs a file to run\n{USAGE}");
             process::exit(1);
+        } else if watch {
+            exit_with(watch_file(&args[index], &command_line_args, options));
+        } else if args[index] == "-" {
+            // `cat program.lox | lox -` runs what is piped in
+            exit_with(run_stdin(&command_line_args, options));
+        } else {
+            exit_with(run_file_with_options(&args[index], &command_line_args, options));
+        }
+    }
+}
+
+// `lox bench` runs the file 10 times unless told otherwise
+fn bench(args: &[String]) {
+    let mut options = RunOptions::default();
+    let mut limit = BenchLimit::Runs(10);
+    let mut index = 0;
+    while index < args.len() && args[index].starts_with("--") {
+        let value = args.get(index + 1).map(|value| value.as_str()).unwrap_or("");
+        match &args[index][..] {
+            "--script" => options.mode = Mode::Script,
+            "--runs" => match value.parse::<usize>() {
+                Ok(runs) if runs > 0 => {
+                    limit = BenchLimit::Runs(runs);
+                    index += 1;
+                }
+                _ => {
+                    println!("--runs needs a whole number of runs greater than 0\n{USAGE}");
+                    process::exit(1);
+                }
+            },
+            "--time" => match value.parse::<f64>() {
+                Ok(seconds) if seconds > 0.0 && seconds.is_finite() => {
+                    limit = BenchLimit::Time(Duration::from_secs_f64(seconds));
+                    index += 1;
+                }
+                _ => {
+                    println!("--time needs a number of seconds greater than 0\n{USAGE}");
+                    process::exit(1);
+                }
+            },
+            flag => {
+                println!("Unknown option '{flag}'\n{USAGE}");
+                process::exit(1);
+            }
         }
+        index += 1;
+    }
+    if index >= args.len() {
+        println!("bench needs a file to run\n{USAGE}");
+        process::exit(1);
+    }
+    let mut command_line_args = vec![];
+    command_line_args.extend(args.iter().skip(index + 1).map(|arg| arg.as_str()));
+    exit_with(bench_file(&args[index], &command_line_args, options, limit));
+}
+
+fn exit_with(status: Result<i32, Box<dyn Error>>) {
+    match status {
+        Ok(code) => process::exit(code),
+        Err(e) => match e.downcast_ref::<FileError>() {
+            Some(error) => {
+                error.report();
+                process::exit(EXIT_FILE_ERROR);
+            }
+            None => {
+                println!("File error: {e}");
+                process::exit(1);
+            }
+        },
     }
 }
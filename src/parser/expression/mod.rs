@@ -1,13 +1,13 @@
 use crate::ast::*;
-use crate::environment::Scope;
+use crate::environment::{Mode, Scope};
 use crate::handle_errors::*;
 use crate::lexer::*;
 use crate::parser::parser::*;
 
 impl Parser {
     pub fn parse_expr(&mut self) -> Result<Expr, ParserError> {
-        let result = self.parse_assignment_expr()?;
-        if self.scope.last().unwrap() == &Scope::Global && !self.is_repl {
+        let result = self.nested(|parser| parser.parse_assignment_expr())?;
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
             return Err(ParserError::ScopeError(
                 "Invalid expression in global scope. Only declarations are allowed.".to_string(),
                 self.at().line,
@@ -26,6 +26,23 @@ impl Parser {
     fn parse_assignment_expr(&mut self) -> Result<Expr, ParserError> {
         let left = self.parse_obj_expr()?;
 
+        if let Expr::Member { optional: true, line, .. } = left
+            && matches!(
+                self.at().token_type,
+                TokenType::EQUAL
+                    | TokenType::MINUSEQUAL
+                    | TokenType::MODULUSEQUAL
+                    | TokenType::PLUSEQUAL
+                    | TokenType::SLASHEQUAL
+                    | TokenType::STAREQUAL
+            )
+        {
+            return Err(ParserError::UnExpectedToken(
+                "Cannot assign to an optional member access ('?.' or '?[')".to_string(),
+                line,
+            ));
+        }
+
         if self.at().token_type == TokenType::EQUAL {
             let line = self.eat().line;
             let value = self.parse_assignment_expr()?;
@@ -44,7 +61,8 @@ impl Parser {
             TokenType::STAREQUAL => (TokenType::STAREQUAL, String::from("*")),
             _ => return Ok(left),
         };
-        let line = self.eat().line;
+        let operator = self.eat();
+        let line = operator.line;
         let value = self.parse_expr()?;
 
         Ok(Expr::AssignmentExpr {
@@ -55,6 +73,7 @@ impl Parser {
                     token_type: token,
                     lexeme,
                     line,
+                    span: operator.span,
                 },
                 right: Box::new(value),
                 line,
@@ -65,7 +84,7 @@ impl Parser {
 
     fn parse_obj_expr(&mut self) -> Result<Expr, ParserError> {
         if self.at().token_type != TokenType::LEFTBRACE {
-            return self.parse_logical_expr();
+            return self.parse_nil_coalescing_expr();
         }
 
         let _ = self.eat();
@@ -80,24 +99,19 @@ impl Parser {
                     self.at().line,
                 ));
             }
-            let key = self.eat();
-
-            if self.at().token_type == TokenType::COMMA {
-                let line = self.eat().line;
+            if self.peek(1).token_type != TokenType::COLON {
+                let key = self.eat();
                 properties.push(Property {
                     key: key.lexeme,
                     value: None,
-                    line,
-                });
-                continue;
-            } else if self.at().token_type == TokenType::RIGHTBRACE {
-                properties.push(Property {
-                    key: key.lexeme,
-                    value: None,
-                    line: self.at().line,
+                    line: key.line,
                 });
+                if self.at().token_type != TokenType::RIGHTBRACE {
+                    let _ = self.expect(TokenType::COMMA, "Missing ',' or '}' after object fields")?;
+                }
                 continue;
             }
+            let key = self.eat();
             let _ = self.expect(
                 TokenType::COLON,
                 "Missing ':' for declaring value of object fields",
@@ -122,6 +136,23 @@ impl Parser {
         })
     }
 
+    fn parse_nil_coalescing_expr(&mut self) -> Result<Expr, ParserError> {
+        let mut left = self.parse_logical_expr()?;
+
+        while self.at().token_type == TokenType::QUESTIONQUESTION {
+            let operator = self.eat();
+            let line = operator.line;
+            let right = self.parse_logical_expr()?;
+            left = Expr::ComparisonLiteral {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+                line,
+            };
+        }
+        Ok(left)
+    }
+
     fn parse_logical_expr(&mut self) -> Result<Expr, ParserError> {
         let mut left = self.parse_equality_expr()?;
 
@@ -159,7 +190,7 @@ impl Parser {
     }
 
     fn parse_comparison_expr(&mut self) -> Result<Expr, ParserError> {
-        let mut left = self.parse_additive_expr()?;
+        let mut left = self.parse_range_expr()?;
 
         while self.at().token_type == TokenType::GREATER
             || self.at().token_type == TokenType::GREATEREQUAL
@@ -168,7 +199,7 @@ impl Parser {
         {
             let operator = self.eat();
             let line = operator.line;
-            let right = self.parse_additive_expr()?;
+            let right = self.parse_range_expr()?;
             left = Expr::ComparisonLiteral {
                 left: Box::new(left),
                 operator,
@@ -179,6 +210,24 @@ impl Parser {
         Ok(left)
     }
 
+    fn parse_range_expr(&mut self) -> Result<Expr, ParserError> {
+        let start = self.parse_additive_expr()?;
+
+        if self.at().token_type != TokenType::DOTDOT
+            && self.at().token_type != TokenType::DOTDOTEQUAL
+        {
+            return Ok(start);
+        }
+        let operator = self.eat();
+        let end = self.parse_additive_expr()?;
+        Ok(Expr::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            inclusive: operator.token_type == TokenType::DOTDOTEQUAL,
+            line: operator.line,
+        })
+    }
+
     fn parse_additive_expr(&mut self) -> Result<Expr, ParserError> {
         let mut left = self.parse_multiplicative_expr()?;
 
@@ -213,32 +262,76 @@ impl Parser {
         Ok(left)
     }
 
+    // A unary operator applies to the operand right after it, so `!a == b`
+    // is `(!a) == b`
     fn parse_unary_expr(&mut self) -> Result<Expr, ParserError> {
-        if self.at().token_type == TokenType::BANG || self.at().token_type == TokenType::MINUS {
-            let operator = self.eat();
+        if matches!(
+            self.at().token_type,
+            TokenType::BANG | TokenType::NOT | TokenType::MINUS
+        ) {
+            let mut operator = self.eat();
+            // `not` is spelled out but behaves exactly like `!`
+            if operator.token_type == TokenType::NOT {
+                operator.token_type = TokenType::BANG;
+            }
             let line = operator.line;
-            let right = self.parse_expr()?;
+            let right = self.nested(|p| p.parse_unary_expr())?;
             Ok(Expr::Unary {
                 operator,
                 right: Box::new(right),
                 line,
             })
+        } else if self.at().token_type == TokenType::AWAIT {
+            let line = self.eat().line;
+            let value = self.nested(|p| p.parse_unary_expr())?;
+            Ok(Expr::Await {
+                value: Box::new(value),
+                line,
+            })
         } else {
             self.parse_call_member_expr()
         }
     }
 
+    // Calls, `.` access and `[...]` indexing may follow each other in any
+    // order: `f()(x)[0].y`
     fn parse_call_member_expr(&mut self) -> Result<Expr, ParserError> {
-        let member = self.parse_member_expr()?;
-
-        if self.at().token_type == TokenType::LEFTPAREN {
-            return self.parse_call_expr(member);
+        let mut expr = self.parse_primary_expr()?;
+        loop {
+            expr = match self.at().token_type {
+                TokenType::LEFTPAREN => self.parse_call_expr(expr)?,
+                TokenType::DOT
+                | TokenType::LEFTBRACKET
+                | TokenType::QUESTIONDOT
+                | TokenType::QUESTIONBRACKET => self.parse_member_expr(expr)?,
+                _ => return Ok(expr),
+            };
         }
-        Ok(member)
     }
 
     fn parse_call_expr(&mut self, caller: Expr) -> Result<Expr, ParserError> {
-        if self.scope.last().unwrap() == &Scope::Global && !self.is_repl {
+        if let Expr::Super(_, line) = caller {
+            let in_constructor = matches!(
+                self.scope.iter().rev().find(|scope| {
+                    matches!(
+                        scope,
+                        Scope::Function(_)
+                            | Scope::Method(_)
+                            | Scope::StaticMethod(_)
+                            | Scope::Constructor(_)
+                    )
+                }),
+                Some(Scope::Constructor(_))
+            );
+            if !in_constructor {
+                return Err(ParserError::ScopeError(
+                    "'super(...)' can only be called inside a constructor".to_string(),
+                    line,
+                ));
+            }
+        }
+
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
             return Err(ParserError::UnExpectedToken("Unexpected function call expression in global scope. Did you forget to declare it using 'fun'?".to_string(), self.at().line));
         }
 
@@ -252,17 +345,11 @@ impl Parser {
             ));
         }
         let (args, line) = self.parse_args()?;
-        let mut call_expr = Expr::Call {
+        Ok(Expr::Call {
             args,
             caller: Box::new(caller),
             line,
-        };
-
-        if self.at().token_type == TokenType::LEFTPAREN {
-            call_expr = self.parse_call_expr(call_expr)?;
-        }
-
-        Ok(call_expr)
+        })
     }
 
     fn parse_args(&mut self) -> Result<(Vec<Expr>, usize), ParserError> {
@@ -283,47 +370,218 @@ impl Parser {
 
         while self.at().token_type == TokenType::COMMA {
             let _ = self.eat();
+            // A trailing comma before ')' is allowed
+            if self.at().token_type == TokenType::RIGHTPAREN {
+                break;
+            }
             args.push(self.parse_assignment_expr()?);
         }
 
         Ok(args)
     }
 
-    fn parse_member_expr(&mut self) -> Result<Expr, ParserError> {
-        let mut object = self.parse_primary_expr()?;
+    fn parse_member_expr(&mut self, object: Expr) -> Result<Expr, ParserError> {
+        let operator = self.eat();
+        let property;
+        let computed;
+        let optional = matches!(
+            operator.token_type,
+            TokenType::QUESTIONDOT | TokenType::QUESTIONBRACKET
+        );
+
+        if operator.token_type == TokenType::DOT || operator.token_type == TokenType::QUESTIONDOT {
+            computed = false;
+            property = self.parse_primary_expr()?;
+
+            match property {
+                Expr::Identifier(..) | Expr::This(_) | Expr::Super(_, _) => {}
+                _ => return Err(ParserError::MemberExpr(operator.line)),
+            }
+        } else {
+            let start = if self.at().token_type == TokenType::COLON {
+                None
+            } else {
+                Some(self.parse_expr()?)
+            };
+            if self.at().token_type == TokenType::COLON {
+                return self.parse_slice_expr(object, start, optional, operator.line);
+            }
+            computed = true;
+            property = start.ok_or(ParserError::MemberExpr(operator.line))?;
+            let _ = self.expect(
+                TokenType::RIGHTBRACKET,
+                "Missing closing ']' in member expression",
+            )?;
+        }
+        Ok(Expr::Member {
+            object: Box::new(object),
+            property: Box::new(property),
+            computed,
+            optional,
+            line: operator.line,
+        })
+    }
 
-        while self.at().token_type == TokenType::DOT
-            || self.at().token_type == TokenType::LEFTBRACKET
-        {
-            let operator = self.eat();
-            let property;
-            let computed;
+    // `object[start:end]`, with the start already parsed and the ':' next
+    fn parse_slice_expr(
+        &mut self,
+        object: Expr,
+        start: Option<Expr>,
+        optional: bool,
+        line: usize,
+    ) -> Result<Expr, ParserError> {
+        let _ = self.eat();
+        let end = if self.at().token_type == TokenType::RIGHTBRACKET {
+            None
+        } else {
+            Some(self.parse_expr()?)
+        };
+        let _ = self.expect(
+            TokenType::RIGHTBRACKET,
+            "Missing closing ']' in slice expression",
+        )?;
+        Ok(Expr::Slice {
+            object: Box::new(object),
+            start: start.map(Box::new),
+            end: end.map(Box::new),
+            optional,
+            line,
+        })
+    }
 
-            if operator.token_type == TokenType::DOT {
-                computed = false;
-                property = self.parse_primary_expr()?;
+    // `if` has already been read
+    fn parse_if_expr(&mut self, line: usize) -> Result<Expr, ParserError> {
+        match self.parse_if_chain(line)? {
+            Ok(value) => Ok(value),
+            Err(_) => Err(ParserError::UnExpectedToken(
+                "Every branch of an if expression must end with its value".to_string(),
+                line,
+            )),
+        }
+    }
 
-                match property {
-                    Expr::Identifier(..) | Expr::This(_) | Expr::Super(_, _) => {}
-                    _ => return Err(ParserError::MemberExpr(operator.line)),
-                }
+    // An `if` inside a branch of an if expression is an expression when all
+    // of its branches end with a value and a statement when none do
+    fn parse_if_chain(&mut self, line: usize) -> Result<Result<Expr, Stmt>, ParserError> {
+        let condition = self.parse_expr()?;
+        let (statements, value) = self.nested(|parser| parser.parse_value_block("if"))?;
+        let mut branches = vec![(condition, statements, value, line)];
+        let mut has_else = false;
+        while self.at().token_type == TokenType::ELSE {
+            let else_line = self.eat().line;
+            if self.at().token_type == TokenType::IF {
+                let _ = self.eat();
+                let condition = self.parse_expr()?;
+                let (statements, value) = self.nested(|parser| parser.parse_value_block("else if"))?;
+                branches.push((condition, statements, value, else_line));
             } else {
-                computed = true;
-                property = self.parse_expr()?;
-                let _ = self.expect(
-                    TokenType::RIGHTBRACKET,
-                    "Missing closing ']' in member expression",
-                )?;
+                let (statements, value) = self.nested(|parser| parser.parse_value_block("else"))?;
+                branches.push((Expr::BoolLiteral(true, else_line), statements, value, else_line));
+                has_else = true;
+                break;
             }
-            object = Expr::Member {
-                object: Box::new(object),
-                property: Box::new(property),
-                computed,
-                line: operator.line,
+        }
+
+        if branches.iter().all(|(_, _, value, _)| value.is_none()) {
+            let branches = branches
+                .into_iter()
+                .map(|(condition, statements, _, line)| (condition, statements, line))
+                .collect();
+            return Ok(Err(Stmt::IfElse(branches)));
+        }
+        if !has_else {
+            return Err(ParserError::UnExpectedToken(
+                "Missing 'else' branch. An if expression needs a value for every case".to_string(),
+                self.at().line,
+            ));
+        }
+        let mut expr_branches = vec![];
+        for (condition, statements, value, line) in branches {
+            let Some(value) = value else {
+                return Err(ParserError::UnExpectedToken(
+                    "Every branch of an if expression must end with its value".to_string(),
+                    line,
+                ));
             };
+            expr_branches.push((condition, statements, value));
         }
+        Ok(Ok(Expr::If {
+            branches: expr_branches,
+            line,
+        }))
+    }
 
-        Ok(object)
+    // `{ statements value }`, a branch of an if expression, with the value
+    // left out when the branch ends with a statement. Its statements cannot
+    // `return`, `break` or `continue` past the value.
+    fn parse_value_block(&mut self, branch: &str) -> Result<(Vec<Stmt>, Option<Expr>), ParserError> {
+        let _ = self.expect(
+            TokenType::LEFTBRACE,
+            format!("Missing '{{' to start the body of the {} branch", branch).as_str(),
+        )?;
+        self.scope.push(Scope::IfExpression);
+        let mut statements = vec![];
+        let mut value = None;
+        while !self.at_block_end() {
+            if !self.not_eof() {
+                return Err(ParserError::EOF);
+            }
+            if let Some(comment) = self.take_comment() {
+                statements.push(comment);
+                continue;
+            }
+            let expr = match self.at().token_type {
+                TokenType::IF => {
+                    let line = self.eat().line;
+                    match self.nested(|parser| parser.parse_if_chain(line))? {
+                        Ok(expr) => expr,
+                        Err(stmt) => {
+                            statements.push(stmt);
+                            continue;
+                        }
+                    }
+                }
+                TokenType::PRINT | TokenType::PRINTLN if !self.print_call() => {
+                    statements.push(self.parse_stmt()?);
+                    continue;
+                }
+                TokenType::IDENTIFIER if self.peek(1).token_type == TokenType::COLON => {
+                    statements.push(self.parse_stmt()?);
+                    continue;
+                }
+                TokenType::VAR
+                | TokenType::CONST
+                | TokenType::LEFTBRACE
+                | TokenType::TRY
+                | TokenType::WITH
+                | TokenType::WHILE
+                | TokenType::LOOP
+                | TokenType::FOR
+                | TokenType::FUN
+                | TokenType::ASYNC
+                | TokenType::CLASS
+                | TokenType::RETURN
+                | TokenType::YIELD
+                | TokenType::BREAK
+                | TokenType::CONTINUE => {
+                    statements.push(self.parse_stmt()?);
+                    continue;
+                }
+                _ => self.parse_expr()?,
+            };
+            if self.at().token_type == TokenType::RIGHTBRACE {
+                value = Some(expr);
+                break;
+            }
+            let _ = self.expect(TokenType::SEMICOLON, "Missing ';' at the end of expression")?;
+            statements.push(Stmt::Expression(expr));
+        }
+        self.scope.pop();
+        let _ = self.expect(
+            TokenType::RIGHTBRACE,
+            format!("Missing '}}' to end the body of the {} branch", branch).as_str(),
+        )?;
+        Ok((statements, value))
     }
 
     fn parse_primary_expr(&mut self) -> Result<Expr, ParserError> {
@@ -331,28 +589,34 @@ impl Parser {
         let line = tk.line;
 
         match tk.token_type {
-            TokenType::IDENTIFIER => Ok(Expr::Identifier(tk.lexeme, line)),
-            TokenType::STRING => Ok(Expr::StringLiteral(tk.lexeme, line)),
+            // `print` and `println` are also natives when used as values.
+            TokenType::IDENTIFIER | TokenType::PRINT | TokenType::PRINTLN => {
+                Ok(Expr::Identifier(tk.lexeme, line))
+            }
+            TokenType::STRING => Ok(Expr::StringLiteral(tk.lexeme.into(), line)),
             TokenType::NUMBER => Ok(Expr::NumericLiteral(
                 tk.lexeme.parse::<f64>().unwrap(),
                 line,
             )),
-            TokenType::THIS => {
-                let valid = self.scope.iter().rev().any(|scope| match scope {
-                    Scope::Class(_) | Scope::Method(_) | Scope::Constructor(_) => true,
-                    _ => false,
-                });
-                if !valid {
-                    Err(ParserError::ScopeError(
-                        "'this' keyword is only allowed inside class methods or constructors"
-                            .to_string(),
+            TokenType::THIS => match self.enclosing_class_member() {
+                Some(Scope::StaticMethod(name)) => Err(ParserError::ScopeError(
+                    format!("'this' cannot be used inside static method '{}'", name),
+                    line,
+                )),
+                Some(_) => Ok(Expr::This(line)),
+                None => Err(ParserError::ScopeError(
+                    "'this' keyword is only allowed inside class methods or constructors"
+                        .to_string(),
+                    line,
+                )),
+            },
+            TokenType::SUPER => {
+                if let Some(Scope::StaticMethod(name)) = self.enclosing_class_member() {
+                    return Err(ParserError::ScopeError(
+                        format!("'super' cannot be used inside static method '{}'", name),
                         line,
-                    ))
-                } else {
-                    Ok(Expr::This(line))
+                    ));
                 }
-            }
-            TokenType::SUPER => {
                 if let Some(Scope::Class(class_name)) = self
                     .scope
                     .iter()
@@ -368,6 +632,15 @@ impl Parser {
                     ))
                 }
             }
+            TokenType::IF => self.parse_if_expr(line),
+            // `class < Base { ... }` builds a class at runtime
+            TokenType::CLASS => {
+                let name = match self.at().token_type {
+                    TokenType::IDENTIFIER => self.eat().lexeme,
+                    _ => ANONYMOUS_CLASS.to_string(),
+                };
+                Ok(Expr::Class(Box::new(self.parse_class(name, line)?)))
+            }
             TokenType::TRUE => Ok(Expr::BoolLiteral(true, line)),
             TokenType::FALSE => Ok(Expr::BoolLiteral(false, line)),
             TokenType::NIL => Ok(Expr::Null(line)),
@@ -383,7 +656,8 @@ impl Parser {
                 let mut value = vec![];
 
                 while self.at().token_type != TokenType::RIGHTBRACKET {
-                    value.push(self.parse_primary_expr()?);
+                    value.push(self.nested(|parser| parser.parse_assignment_expr())?);
+                    // A trailing comma before ']' is allowed
                     if self.at().token_type == TokenType::RIGHTBRACKET {
                         break;
                     }
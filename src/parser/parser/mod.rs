@@ -1,32 +1,121 @@
+use std::ops::Range;
+
 use crate::ast::*;
-use crate::environment::Scope;
+use crate::environment::{Mode, Scope};
 use crate::handle_errors::*;
 use crate::lexer::*;
+
+/// How deeply statements and expressions may nest inside each other.
+pub const MAX_NESTING_DEPTH: usize = 256;
+
 pub struct Parser {
     tokens: Vec<Token>,
+    // Comments taken out of the token stream, each with the index of the
+    // token it comes before and whether it trails code on the same line
+    comments: Vec<(usize, Token, bool)>,
+    next_comment: usize,
+    current: usize,
+    depth: usize,
     pub scope: Vec<Scope>,
-    pub is_repl: bool,
+    pub mode: Mode,
+    // Label for the loop about to be parsed
+    pub label: Option<String>,
+    // Whether the function being parsed has yielded so far
+    pub generator: bool,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>, is_repl: bool) -> Self {
+    pub fn new(tokens: Vec<Token>, mode: Mode) -> Self {
+        let mut code = vec![];
+        let mut comments = vec![];
+        for token in tokens {
+            if token.token_type == TokenType::COMMENT {
+                let trailing = code.last().is_some_and(|previous: &Token| previous.line == token.line);
+                comments.push((code.len(), token, trailing));
+            } else {
+                code.push(token);
+            }
+        }
         Parser {
-            tokens,
+            tokens: code,
+            comments,
+            next_comment: 0,
+            current: 0,
+            depth: 0,
             scope: vec![Scope::Global],
-            is_repl,
+            mode,
+            label: None,
+            generator: false,
         }
     }
 
     pub fn at(&self) -> &Token {
-        &self.tokens[0]
+        &self.tokens[self.current]
+    }
+
+    /// Looks `n` tokens past the current one, clamping to the trailing EOF.
+    pub fn peek(&self, n: usize) -> &Token {
+        let index = (self.current + n).min(self.tokens.len() - 1);
+        &self.tokens[index]
+    }
+
+    /// Whether the `print` or `println` at the current token is a call of
+    /// the native, `println(a, b);`, rather than a print statement. Only a
+    /// call ends right after its closing parenthesis, so `println (1) + 1;`
+    /// stays a statement printing 2.
+    pub fn print_call(&self) -> bool {
+        if self.peek(1).token_type != TokenType::LEFTPAREN {
+            return false;
+        }
+        let mut depth = 0;
+        for (index, token) in self.tokens.iter().enumerate().skip(self.current + 1) {
+            match token.token_type {
+                TokenType::LEFTPAREN => depth += 1,
+                TokenType::RIGHTPAREN if depth == 1 => {
+                    // The REPL lets the last statement go without a semicolon
+                    let next = self.tokens.get(index + 1).map(|token| &token.token_type);
+                    return matches!(next, Some(TokenType::SEMICOLON | TokenType::EOF));
+                }
+                TokenType::RIGHTPAREN => depth -= 1,
+                TokenType::EOF => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// The span of the token an error on `line` points at: the current
+    /// token, or the one before it when the error is reported on its line,
+    /// as a missing ';' is.
+    pub fn error_span(&self, line: usize) -> Option<Range<usize>> {
+        [self.at(), self.previous()]
+            .into_iter()
+            .find(|token| token.line == line && token.token_type != TokenType::EOF)
+            .map(|token| token.span.clone())
+    }
+
+    pub fn previous(&self) -> &Token {
+        &self.tokens[self.current.saturating_sub(1)]
     }
 
     pub fn eat(&mut self) -> Token {
-        let token = self.tokens.remove(0);
+        let token = self.tokens[self.current].clone();
+        if self.current < self.tokens.len() - 1 {
+            self.current += 1;
+        }
         token
     }
 
     pub fn expect(&mut self, token: TokenType, message: &str) -> Result<Token, ParserError> {
+        // A missing ';' is reported on the line of the statement it ends
+        // rather than on whatever comes next, even the end of the file
+        if token == TokenType::SEMICOLON
+            && self.current > 0
+            && self.at().token_type != TokenType::SEMICOLON
+            && (!self.not_eof() || self.at().line > self.previous().line)
+        {
+            return Err(ParserError::UnExpectedToken(message.to_string(), self.previous().line));
+        }
         if !self.not_eof() {
             return Err(ParserError::EOF);
         }
@@ -37,8 +126,90 @@ impl Parser {
         Ok(self.eat())
     }
 
+    /// Whether a comment appears before the current token and has not been
+    /// turned into a statement yet.
+    pub fn comment_pending(&self) -> bool {
+        self.comments
+            .get(self.next_comment)
+            .is_some_and(|(index, _, _)| *index <= self.current)
+    }
+
+    /// Takes the next pending comment as a statement. Comments found inside
+    /// an expression come out at the next statement boundary.
+    pub fn take_comment(&mut self) -> Option<Stmt> {
+        if !self.comment_pending() {
+            return None;
+        }
+        let (_, token, trailing) = self.comments[self.next_comment].clone();
+        self.next_comment += 1;
+        Some(Stmt::Comment(token.lexeme, trailing, token.line))
+    }
+
+    /// Whether a statement list ends at the current token. Comments right
+    /// before the closing brace still belong to the list.
+    pub fn at_block_end(&self) -> bool {
+        self.at().token_type == TokenType::RIGHTBRACE && !self.comment_pending()
+    }
+
+    pub fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParserError>,
+    ) -> Result<T, ParserError> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(ParserError::NestingTooDeep(self.at().line));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// The innermost class, method or constructor scope being parsed, if any.
+    pub fn enclosing_class_member(&self) -> Option<&Scope> {
+        self.scope.iter().rev().find(|scope| {
+            matches!(
+                scope,
+                Scope::Class(_) | Scope::Method(_) | Scope::StaticMethod(_) | Scope::Constructor(_)
+            )
+        })
+    }
+
+    /// Whether a loop labeled `label` encloses the current statement within
+    /// the same function. Loops outside it cannot be left with `break`.
+    pub fn enclosing_loop(&self, label: &str) -> bool {
+        self.scope
+            .iter()
+            .rev()
+            .take_while(|scope| matches!(scope, Scope::Loop(_)))
+            .any(|scope| matches!(scope, Scope::Loop(Some(name)) if name == label))
+    }
+
+    // Parses the optional label after `break` or `continue`
+    fn jump_label(&mut self, keyword: &str) -> Result<Option<String>, ParserError> {
+        if self.at().token_type != TokenType::IDENTIFIER {
+            return Ok(None);
+        }
+        let label = self.eat();
+        if !self.enclosing_loop(&label.lexeme) {
+            return Err(ParserError::ScopeError(
+                format!("No enclosing loop is labeled '{}' for '{}'", label.lexeme, keyword),
+                label.line,
+            ));
+        }
+        Ok(Some(label.lexeme))
+    }
+
+    fn parse_expression_statement(&mut self) -> Result<Stmt, ParserError> {
+        let stmt = Stmt::Expression(self.parse_expr()?);
+        // The ';' is optional at the REPL prompt
+        if self.mode != Mode::Repl || self.at().token_type == TokenType::SEMICOLON {
+            let _ = self.expect(TokenType::SEMICOLON, "Missing ';' at the end of expression")?;
+        }
+        Ok(stmt)
+    }
+
     pub fn not_eof(&self) -> bool {
-        match self.tokens[0].token_type {
+        match self.at().token_type {
             TokenType::EOF => false,
             _ => true,
         }
@@ -47,7 +218,7 @@ impl Parser {
     pub fn produce_ast(&mut self) -> Result<Vec<Stmt>, ParserError> {
         let mut program = vec![];
 
-        while self.not_eof() {
+        while self.not_eof() || self.comment_pending() {
             program.push(self.parse_stmt()?);
         }
 
@@ -55,8 +226,26 @@ impl Parser {
     }
 
     pub fn parse_stmt(&mut self) -> Result<Stmt, ParserError> {
+        if let Some(comment) = self.take_comment() {
+            return Ok(comment);
+        }
+        self.parse_clause_stmt()
+    }
+
+    /// Parses a statement that is part of another one, such as the
+    /// initializer of a for loop, leaving pending comments for the
+    /// enclosing statement list.
+    pub fn parse_clause_stmt(&mut self) -> Result<Stmt, ParserError> {
+        self.nested(|parser| parser.parse_nested_stmt())
+    }
+
+    fn parse_nested_stmt(&mut self) -> Result<Stmt, ParserError> {
         match self.at().token_type {
             TokenType::VAR | TokenType::CONST => self.parse_var_declaration(),
+            TokenType::GLOBAL => self.parse_global_statement(),
+            TokenType::IDENTIFIER if self.peek(1).token_type == TokenType::COLON => {
+                self.parse_labeled_statement()
+            }
             TokenType::IDENTIFIER
             | TokenType::NUMBER
             | TokenType::NIL
@@ -66,22 +255,24 @@ impl Parser {
             | TokenType::STRING
             | TokenType::THIS
             | TokenType::SUPER
-            | TokenType::LEFTPAREN => {
-                let stmt = Stmt::Expression(self.parse_expr()?);
-                if !self.is_repl {
-                    let _ =
-                        self.expect(TokenType::SEMICOLON, "Missing ';' at the end of expression")?;
-                }
-                Ok(stmt)
-            }
+            | TokenType::AWAIT
+            | TokenType::LEFTPAREN => self.parse_expression_statement(),
+            TokenType::PRINT | TokenType::PRINTLN if self.print_call() => self.parse_expression_statement(),
             TokenType::LEFTBRACE => self.parse_block_statement(),
+            TokenType::TRY => self.parse_try_statement(),
+            TokenType::WITH => self.parse_with_statement(),
             TokenType::PRINT => self.parse_print_statement(false),
             TokenType::PRINTLN => self.parse_print_statement(true),
             TokenType::IF => self.parse_if_else_statement(),
+            TokenType::MATCH => self.parse_match_statement(),
             TokenType::WHILE => self.parse_while_statement(),
+            TokenType::LOOP => self.parse_loop_statement(),
             TokenType::FOR => self.parse_for_statement(),
-            TokenType::FUN => self.parse_functional_statement(),
+            TokenType::FUN | TokenType::ASYNC => self.parse_functional_statement(),
             TokenType::CLASS => self.parse_class_statement(),
+            TokenType::INTERFACE => self.parse_interface_statement(),
+            TokenType::MIXIN => self.parse_mixin_statement(),
+            TokenType::YIELD => self.parse_yield_statement(),
             TokenType::RETURN => {
                 let line = self.eat().line;
                 match self.scope.last().unwrap() {
@@ -97,67 +288,93 @@ impl Parser {
                             line,
                         ));
                     }
-                    Scope::Constructor(name) => {
+                    _ => {}
+                }
+                let callable = self.scope.iter().rev().find(|scope| {
+                    matches!(
+                        scope,
+                        Scope::Function(_)
+                            | Scope::Method(_)
+                            | Scope::StaticMethod(_)
+                            | Scope::Constructor(_)
+                            | Scope::IfExpression
+                    )
+                });
+                let constructor = match callable {
+                    Some(Scope::IfExpression) => {
+                        return Err(ParserError::ScopeError("Invalid return statement in an if expression. A branch must end with its value".to_string(), line));
+                    }
+                    Some(Scope::Constructor(class_name)) => Some(class_name.clone()),
+                    Some(_) => None,
+                    None => {
+                        return Err(ParserError::ScopeError("Invalid return statement inside loop. Must be within a function or method.".to_string(), line));
+                    }
+                };
+                let mut expr = Expr::Null(line);
+                let mut several = false;
+                if self.at().token_type != TokenType::SEMICOLON {
+                    if let Some(class_name) = constructor {
                         return Err(ParserError::ScopeError(
                             format!(
-                                "Invalid return statement in constructor of class '{}'. Use return only inside functions or methods.",
-                                name
+                                "Cannot return a value from the constructor of class '{}'. Use 'return;' to exit early.",
+                                class_name
                             ),
                             line,
                         ));
                     }
-                    Scope::Loop => {
-                        let valid = self.scope.iter().rev().any(|scope| match scope {
-                            Scope::Function(_) | Scope::Method(_) => true,
-                            _ => false,
-                        });
-                        if !valid {
-                            return Err(ParserError::ScopeError("Invalid return statement inside loop. Must be within a function or method.".to_string(), line));
+                    expr = self.parse_expr()?;
+                    // `return a, b;` returns the array [a, b]
+                    if self.at().token_type == TokenType::COMMA {
+                        let mut values = vec![expr];
+                        while self.at().token_type == TokenType::COMMA {
+                            let _ = self.eat();
+                            values.push(self.parse_expr()?);
                         }
+                        expr = Expr::Array(values, line);
+                        several = true;
                     }
-                    _ => {}
-                }
-                let mut expr = Expr::Null(line);
-                if self.at().token_type != TokenType::SEMICOLON {
-                    expr = self.parse_expr()?;
                 }
                 let _ = self.expect(
                     TokenType::SEMICOLON,
                     "Missing ';' at end of return statement",
                 )?;
-                Ok(Stmt::Return(expr))
+                Ok(Stmt::Return(expr, several))
             }
             TokenType::BREAK => {
                 let line = self.eat().line;
                 match self.scope.last().unwrap() {
                     Scope::Global => {return Err(ParserError::ScopeError("Invalid use of 'break' at global scope. 'break' is only allowed inside loops.".to_string(), line))},
                     Scope::Class(class_name) => {return Err(ParserError::ScopeError(format!("Invalid use of 'break' in class '{}'. 'break' is only allowed inside loops", class_name), line))},
-                    Scope::Method(name) => {return Err(ParserError::ScopeError(format!("Invalid use of 'break' in method '{}'. 'break' is only allowed inside loops", name), line))},
+                    Scope::Method(name) | Scope::StaticMethod(name) => {return Err(ParserError::ScopeError(format!("Invalid use of 'break' in method '{}'. 'break' is only allowed inside loops", name), line))},
                     Scope::Constructor(name) => {return Err(ParserError::ScopeError(format!("Invalid use of 'break' in constructor of class '{}'. 'break' is only allowed inside loops", name), line))},
                     Scope::Function(name) => {return Err(ParserError::ScopeError(format!("Invalid use of 'break' in function '{}'. 'break' is only allowed inside loops", name), line))},
+                    Scope::IfExpression => {return Err(ParserError::ScopeError("Invalid use of 'break' in an if expression. A branch must end with its value".to_string(), line))},
                     _ => {},
                 }
+                let label = self.jump_label("break")?;
                 let _ = self.expect(
                     TokenType::SEMICOLON,
                     "Missing ';' at end of break statement",
                 )?;
-                Ok(Stmt::Break)
+                Ok(Stmt::Break(label))
             }
             TokenType::CONTINUE => {
                 let line = self.eat().line;
                 match self.scope.last().unwrap() {
                     Scope::Global => {return Err(ParserError::ScopeError("Invalid use of 'continue' at global scope. 'continue' is only allowed inside loops.".to_string(), line))},
                     Scope::Class(class_name) => {return Err(ParserError::ScopeError(format!("Invalid use of 'continue' in class '{}'. 'continue' is only allowed inside loops", class_name), line))},
-                    Scope::Method(name) => {return Err(ParserError::ScopeError(format!("Invalid use of 'continue' in method '{}'. 'continue' is only allowed inside loops", name), line))},
+                    Scope::Method(name) | Scope::StaticMethod(name) => {return Err(ParserError::ScopeError(format!("Invalid use of 'continue' in method '{}'. 'continue' is only allowed inside loops", name), line))},
                     Scope::Constructor(name) => {return Err(ParserError::ScopeError(format!("Invalid use of 'continue' in constructor of class '{}'. 'continue' is only allowed inside loops", name), line))},
                     Scope::Function(name) => {return Err(ParserError::ScopeError(format!("Invalid use of 'continue' in function '{}'. 'continue' is only allowed inside loops", name), line))},
+                    Scope::IfExpression => {return Err(ParserError::ScopeError("Invalid use of 'continue' in an if expression. A branch must end with its value".to_string(), line))},
                     _ => {},
                 }
+                let label = self.jump_label("continue")?;
                 let _ = self.expect(
                     TokenType::SEMICOLON,
                     "Missing ';' at end of continue statement",
                 )?;
-                Ok(Stmt::Continue)
+                Ok(Stmt::Continue(label))
             }
             _ => Err(ParserError::UnExpectedToken(
                 format!("Invalid statement. Found {}", self.at().lexeme),
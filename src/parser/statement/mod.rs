@@ -1,7 +1,8 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use crate::ast::*;
-use crate::environment::Scope;
+use crate::environment::{Mode, Scope};
 use crate::handle_errors::*;
 use crate::lexer::*;
 use crate::parser::parser::*;
@@ -17,16 +18,19 @@ impl Parser {
             )?
             .lexeme;
 
+        if self.at().token_type == TokenType::COMMA {
+            return self.parse_var_unpack(identifier, is_constant, line);
+        }
         if self.at().token_type == TokenType::SEMICOLON {
-            let _ = self.eat().line;
+            let _ = self.eat();
             if is_constant {
-                return Err(ParserError::ConstValueNull(self.at().line));
+                return Err(ParserError::ConstValueNull(self.previous().line));
             }
 
             return Ok(Stmt::VarDeclaration(VarDeclaration {
                 constant: false,
                 identifier,
-                value: Box::new(Expr::Null(self.at().line)),
+                value: Box::new(Expr::Null(self.previous().line)),
                 line,
             }));
         }
@@ -50,8 +54,78 @@ impl Parser {
         Ok(declaration)
     }
 
+    // Parses the rest of `var a, b = value;` after its first name
+    fn parse_var_unpack(&mut self, first: String, is_constant: bool, line: usize) -> Result<Stmt, ParserError> {
+        if let Scope::Class(class_name) = self.scope.last().unwrap() {
+            return Err(ParserError::ScopeError(
+                format!("Fields of class '{}' must be declared one at a time", class_name),
+                line,
+            ));
+        }
+        let mut names = vec![first];
+        while self.at().token_type == TokenType::COMMA {
+            let _ = self.eat();
+            let name = self
+                .expect(TokenType::IDENTIFIER, "Expected identifier name following ',' in variable declaration")?
+                .lexeme;
+            if names.contains(&name) {
+                return Err(ParserError::UnExpectedToken(
+                    format!("Variable '{}' is declared twice", name),
+                    line,
+                ));
+            }
+            names.push(name);
+        }
+        let _ = self.expect(
+            TokenType::EQUAL,
+            "Expected '=' to give the values of several variables",
+        )?;
+        self.scope.push(Scope::VarDeclaration);
+        let value = self.parse_expr()?;
+        self.scope.pop();
+        let _ = self.expect(
+            TokenType::SEMICOLON,
+            "Expected ';' at the end of variable declaration",
+        )?;
+        Ok(Stmt::VarUnpack(names, is_constant, value, line))
+    }
+
+    pub fn parse_global_statement(&mut self) -> Result<Stmt, ParserError> {
+        let line = self.eat().line;
+        match self.scope.last().unwrap() {
+            Scope::Global => {
+                return Err(ParserError::ScopeError(
+                    "'global' is only needed inside a function. Use 'var' in global scope".to_string(),
+                    line,
+                ));
+            }
+            Scope::Class(class_name) => {
+                return Err(ParserError::ScopeError(
+                    format!(
+                        "Invalid 'global' statement inside class '{}'. Only method and field declarations are allowed.",
+                        class_name
+                    ),
+                    line,
+                ));
+            }
+            _ => {}
+        }
+        let identifier = self
+            .expect(TokenType::IDENTIFIER, "Expected identifier name following 'global' keyword")?
+            .lexeme;
+        let mut value = None;
+        if self.at().token_type == TokenType::EQUAL {
+            let _ = self.eat();
+            self.scope.push(Scope::VarDeclaration);
+            value = Some(self.parse_expr()?);
+            self.scope.pop();
+        }
+        let _ = self.expect(TokenType::SEMICOLON, "Expected ';' at the end of global statement")?;
+        Ok(Stmt::Global(identifier, value, line))
+    }
+
     pub fn parse_print_statement(&mut self, new_line: bool) -> Result<Stmt, ParserError> {
-        if self.scope.last().unwrap() == &Scope::Global && !self.is_repl {
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
             return Err(ParserError::ScopeError(
                 "Print statement not allowed in global scope".to_string(),
                 self.at().line,
@@ -66,10 +140,10 @@ impl Parser {
                 self.at().line,
             ));
         }
-        let _ = self.eat();
+        let line = self.eat().line;
         if self.at().token_type == TokenType::SEMICOLON {
             let _ = self.eat();
-            return Ok(Stmt::Print(None, new_line));
+            return Ok(Stmt::Print(None, new_line, line));
         }
         let expr = self.parse_expr()?;
         let mut expressions = vec![expr];
@@ -83,12 +157,12 @@ impl Parser {
             TokenType::SEMICOLON,
             "Expected ';' at end of print statement",
         )?;
-        Ok(Stmt::Print(Some(expressions), new_line))
+        Ok(Stmt::Print(Some(expressions), new_line, line))
     }
 
     pub fn parse_if_else_statement(&mut self) -> Result<Stmt, ParserError> {
         let line = self.at().line;
-        if self.scope.last().unwrap() == &Scope::Global && !self.is_repl {
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
             return Err(ParserError::ScopeError(
                 "if-else statements not allowed in global scope".to_string(),
                 self.at().line,
@@ -110,7 +184,7 @@ impl Parser {
             "Missing '{' to start the body of the if block",
         )?;
         let mut statements = vec![];
-        while self.at().token_type != TokenType::RIGHTBRACE {
+        while !self.at_block_end() {
             statements.push(self.parse_stmt()?);
         }
         let _ = self.expect(
@@ -146,7 +220,7 @@ impl Parser {
 
             let _ = self.expect(TokenType::LEFTBRACE, messages1[is_else_block])?;
             let mut statements = vec![];
-            while self.at().token_type != TokenType::RIGHTBRACE {
+            while !self.at_block_end() {
                 match self.parse_stmt() {
                     Ok(s) => statements.push(s),
                     Err(e) => return Err(e),
@@ -158,8 +232,149 @@ impl Parser {
         Ok(Stmt::IfElse(if_collection))
     }
 
+    pub fn parse_match_statement(&mut self) -> Result<Stmt, ParserError> {
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
+            return Err(ParserError::ScopeError(
+                "match statements not allowed in global scope".to_string(),
+                self.at().line,
+            ));
+        }
+        if let Scope::Class(class_name) = self.scope.last().unwrap() {
+            return Err(ParserError::ScopeError(
+                format!(
+                    "Invalid match statement inside class '{}'. Only method and field declarations are allowed.",
+                    class_name
+                ),
+                self.at().line,
+            ));
+        }
+        let line = self.eat().line;
+        let value = self.parse_expr()?;
+        let _ = self.expect(
+            TokenType::LEFTBRACE,
+            "Missing '{' to start the arms of the match statement",
+        )?;
+        let mut arms = vec![];
+        while !self.at_block_end() {
+            let arm_line = self
+                .expect(TokenType::CASE, "Expected 'case' to start an arm of the match statement")?
+                .line;
+            let mut names = vec![];
+            let pattern = self.parse_pattern(&mut names)?;
+            let _ = self.expect(TokenType::COLON, "Missing ':' after the pattern of a match arm")?;
+            // An arm runs up to the next `case` or the end of the match
+            let mut body = vec![];
+            while !self.at_block_end() && self.at().token_type != TokenType::CASE {
+                body.push(self.parse_stmt()?);
+            }
+            arms.push(MatchArm { pattern, body, line: arm_line });
+        }
+        let _ = self.expect(
+            TokenType::RIGHTBRACE,
+            "Missing '}' to end the match statement",
+        )?;
+        Ok(Stmt::Match(value, arms, line))
+    }
+
+    // `names` collects the names bound so far, which may not repeat within
+    // one pattern
+    fn parse_pattern(&mut self, names: &mut Vec<String>) -> Result<Pattern, ParserError> {
+        self.nested(|parser| {
+            let tk = parser.eat();
+            match tk.token_type {
+                TokenType::IDENTIFIER if tk.lexeme == "_" => Ok(Pattern::Wildcard),
+                TokenType::IDENTIFIER => {
+                    if names.contains(&tk.lexeme) {
+                        return Err(ParserError::UnExpectedToken(
+                            format!("Name '{}' is bound twice in the same pattern", tk.lexeme),
+                            tk.line,
+                        ));
+                    }
+                    names.push(tk.lexeme.clone());
+                    Ok(Pattern::Binding(tk.lexeme))
+                }
+                TokenType::NUMBER => Ok(Pattern::Literal(Expr::NumericLiteral(
+                    tk.lexeme.parse::<f64>().unwrap(),
+                    tk.line,
+                ))),
+                TokenType::MINUS if parser.at().token_type == TokenType::NUMBER => {
+                    let number = parser.eat();
+                    Ok(Pattern::Literal(Expr::NumericLiteral(
+                        -number.lexeme.parse::<f64>().unwrap(),
+                        tk.line,
+                    )))
+                }
+                TokenType::STRING => Ok(Pattern::Literal(Expr::StringLiteral(tk.lexeme.into(), tk.line))),
+                TokenType::TRUE => Ok(Pattern::Literal(Expr::BoolLiteral(true, tk.line))),
+                TokenType::FALSE => Ok(Pattern::Literal(Expr::BoolLiteral(false, tk.line))),
+                TokenType::NIL => Ok(Pattern::Literal(Expr::Null(tk.line))),
+                TokenType::LEFTBRACKET => {
+                    let mut elements = vec![];
+                    while parser.at().token_type != TokenType::RIGHTBRACKET {
+                        elements.push(parser.parse_pattern(names)?);
+                        // A trailing comma before ']' is allowed
+                        if parser.at().token_type == TokenType::RIGHTBRACKET {
+                            break;
+                        }
+                        let _ = parser.expect(TokenType::COMMA, "Missing ',' after array pattern element")?;
+                    }
+                    let _ = parser.eat();
+                    Ok(Pattern::Array(elements))
+                }
+                TokenType::LEFTBRACE => {
+                    let mut entries: Vec<(String, Pattern)> = vec![];
+                    while parser.at().token_type != TokenType::RIGHTBRACE {
+                        let key = parser.eat();
+                        let pattern = match key.token_type {
+                            TokenType::IDENTIFIER | TokenType::STRING
+                                if parser.at().token_type == TokenType::COLON =>
+                            {
+                                let _ = parser.eat();
+                                parser.parse_pattern(names)?
+                            }
+                            // `{r}` is short for `{r: r}`
+                            TokenType::IDENTIFIER => {
+                                if names.contains(&key.lexeme) {
+                                    return Err(ParserError::UnExpectedToken(
+                                        format!("Name '{}' is bound twice in the same pattern", key.lexeme),
+                                        key.line,
+                                    ));
+                                }
+                                names.push(key.lexeme.clone());
+                                Pattern::Binding(key.lexeme.clone())
+                            }
+                            _ => {
+                                return Err(ParserError::ObjectKey(
+                                    format!("Found '{}'", key.lexeme),
+                                    key.line,
+                                ));
+                            }
+                        };
+                        if entries.iter().any(|(existing, _)| *existing == key.lexeme) {
+                            return Err(ParserError::UnExpectedToken(
+                                format!("Key '{}' appears twice in the same pattern", key.lexeme),
+                                key.line,
+                            ));
+                        }
+                        entries.push((key.lexeme, pattern));
+                        if parser.at().token_type != TokenType::RIGHTBRACE {
+                            let _ = parser.expect(TokenType::COMMA, "Missing ',' or '}' after object pattern entry")?;
+                        }
+                    }
+                    let _ = parser.eat();
+                    Ok(Pattern::Object(entries))
+                }
+                TokenType::EOF => Err(ParserError::EOF),
+                _ => Err(ParserError::UnExpectedToken(
+                    format!("Expected a pattern, found '{}'", tk.lexeme),
+                    tk.line,
+                )),
+            }
+        })
+    }
+
     pub fn parse_for_statement(&mut self) -> Result<Stmt, ParserError> {
-        if self.scope.last().unwrap() == &Scope::Global && !self.is_repl {
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
             return Err(ParserError::ScopeError(
                 "for loop not allowed in global scope".to_string(),
                 self.at().line,
@@ -174,33 +389,89 @@ impl Parser {
                 self.at().line,
             ));
         }
-        self.scope.push(Scope::Loop);
+        self.scope.push(Scope::Loop(self.label.take()));
         let line = self.eat().line;
 
-        if self.at().token_type == TokenType::SEMICOLON {
-            return Err(ParserError::ForLoopDeclaration(
-                "".to_string(),
-                self.at().line,
-            ));
+        if self.for_in_ahead() {
+            return self.parse_for_in_statement(line);
         }
-        let var_stmt = self.parse_stmt()?;
 
-        if self.at().token_type == TokenType::SEMICOLON {
+        // Every clause may be left out: `for ; i < 3; {}`, `for ;; {}`
+        let var_stmt = if self.at().token_type == TokenType::SEMICOLON {
+            let _ = self.eat();
+            None
+        } else {
+            Some(Box::new(self.parse_clause_stmt()?))
+        };
+
+        let expr1 = if self.at().token_type == TokenType::SEMICOLON {
+            None
+        } else {
+            Some(self.parse_expr()?)
+        };
+        if self.at().token_type != TokenType::SEMICOLON {
             return Err(ParserError::ForLoopDeclaration(
-                "".to_string(),
+                "Missing ';' after the condition".to_string(),
                 self.at().line,
             ));
         }
-        let expr1 = self.parse_expr()?;
         let _ = self.eat();
 
-        if self.at().token_type == TokenType::LEFTBRACE {
-            return Err(ParserError::ForLoopDeclaration(
-                "".to_string(),
-                self.at().line,
-            ));
+        let expr2 = if self.at().token_type == TokenType::LEFTBRACE {
+            None
+        } else {
+            Some(self.parse_expr()?)
+        };
+
+        let _ = self.expect(
+            TokenType::LEFTBRACE,
+            "Missing '{' to start the body of the for loop",
+        )?;
+
+        let mut stmt = vec![];
+        while !self.at_block_end() {
+            stmt.push(self.parse_stmt()?);
         }
-        let expr2 = self.parse_expr()?;
+
+        let _ = self.expect(
+            TokenType::RIGHTBRACE,
+            "Missing '}' to end the body of the for loop",
+        )?;
+
+        self.scope.pop();
+        Ok(Stmt::For((var_stmt, expr1, expr2), stmt, line))
+    }
+
+    // Whether the loop variables of a for-in loop, `x in` or `i, x in`,
+    // come next
+    fn for_in_ahead(&self) -> bool {
+        let mut n = 0;
+        while self.peek(n).token_type == TokenType::IDENTIFIER {
+            match self.peek(n + 1).token_type {
+                TokenType::IN => return true,
+                TokenType::COMMA => n += 2,
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    fn parse_for_in_statement(&mut self, line: usize) -> Result<Stmt, ParserError> {
+        let mut names: Vec<String> = vec![];
+        loop {
+            let name = self.eat();
+            if names.contains(&name.lexeme) {
+                return Err(ParserError::ForLoopDeclaration(
+                    format!("Loop variable '{}' is declared twice", name.lexeme),
+                    name.line,
+                ));
+            }
+            names.push(name.lexeme);
+            if self.eat().token_type == TokenType::IN {
+                break;
+            }
+        }
+        let iterable = self.parse_expr()?;
 
         let _ = self.expect(
             TokenType::LEFTBRACE,
@@ -208,7 +479,7 @@ impl Parser {
         )?;
 
         let mut stmt = vec![];
-        while self.at().token_type != TokenType::RIGHTBRACE {
+        while !self.at_block_end() {
             stmt.push(self.parse_stmt()?);
         }
 
@@ -218,11 +489,35 @@ impl Parser {
         )?;
 
         self.scope.pop();
-        Ok(Stmt::For((Box::new(var_stmt), expr1, expr2), stmt, line))
+        Ok(Stmt::ForIn(names, iterable, stmt, line))
+    }
+
+    pub fn parse_labeled_statement(&mut self) -> Result<Stmt, ParserError> {
+        let label = self.eat();
+        let _ = self.eat();
+        if !matches!(self.at().token_type, TokenType::WHILE | TokenType::LOOP | TokenType::FOR) {
+            return Err(ParserError::UnExpectedToken(
+                format!("Only loops can have a label. Found {} after '{}:'", self.at().lexeme, label.lexeme),
+                label.line,
+            ));
+        }
+        if self.enclosing_loop(&label.lexeme) {
+            return Err(ParserError::ScopeError(
+                format!("Label '{}' is already used by an enclosing loop", label.lexeme),
+                label.line,
+            ));
+        }
+        self.label = Some(label.lexeme.clone());
+        let body = match self.at().token_type {
+            TokenType::WHILE => self.parse_while_statement()?,
+            TokenType::LOOP => self.parse_loop_statement()?,
+            _ => self.parse_for_statement()?,
+        };
+        Ok(Stmt::Labeled(label.lexeme, Box::new(body)))
     }
 
     pub fn parse_while_statement(&mut self) -> Result<Stmt, ParserError> {
-        if self.scope.last().unwrap() == &Scope::Global && !self.is_repl {
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
             return Err(ParserError::ScopeError(
                 "while loop not allowed in global scope".to_string(),
                 self.at().line,
@@ -238,7 +533,7 @@ impl Parser {
                 self.at().line,
             ));
         }
-        self.scope.push(Scope::Loop);
+        self.scope.push(Scope::Loop(self.label.take()));
         let line = self.eat().line;
         let expr = self.parse_expr()?;
         let _ = self.expect(
@@ -247,7 +542,7 @@ impl Parser {
         )?;
 
         let mut stmt = vec![];
-        while self.at().token_type != TokenType::RIGHTBRACE {
+        while !self.at_block_end() {
             match self.parse_stmt() {
                 Ok(s) => stmt.push(s),
                 Err(e) => return Err(e),
@@ -262,10 +557,10 @@ impl Parser {
         Ok(Stmt::While(expr, stmt, line))
     }
 
-    pub fn parse_block_statement(&mut self) -> Result<Stmt, ParserError> {
-        if self.scope.last().unwrap() == &Scope::Global && !self.is_repl {
+    pub fn parse_loop_statement(&mut self) -> Result<Stmt, ParserError> {
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
             return Err(ParserError::ScopeError(
-                "block statements not allowed in global scope".to_string(),
+                "loop not allowed in global scope".to_string(),
                 self.at().line,
             ));
         }
@@ -273,44 +568,158 @@ impl Parser {
         if let Scope::Class(class_name) = self.scope.last().unwrap() {
             return Err(ParserError::ScopeError(
                 format!(
-                    "Invalid block statement inside class '{}'. Only method and field declarations are allowed.",
+                    "Invalid loop inside class '{}'. Only method and field declarations are allowed.",
                     class_name
                 ),
                 self.at().line,
             ));
         }
-        let _ = self.eat();
-        let mut stmts = vec![];
-        while self.at().token_type != TokenType::RIGHTBRACE {
-            stmts.push(self.parse_stmt()?);
+        self.scope.push(Scope::Loop(self.label.take()));
+        let line = self.eat().line;
+        let _ = self.expect(
+            TokenType::LEFTBRACE,
+            "Missing '{' to start the body of the loop",
+        )?;
+
+        let mut stmt = vec![];
+        while !self.at_block_end() {
+            stmt.push(self.parse_stmt()?);
         }
+
         let _ = self.expect(
             TokenType::RIGHTBRACE,
-            "Missing '}' to end the body of the block statement",
+            "Missing '}' to end the body of the loop",
         )?;
-        Ok(Stmt::Block(stmts))
+        self.scope.pop();
+        Ok(Stmt::Loop(stmt, line))
     }
 
-    pub fn parse_functional_statement(&mut self) -> Result<Stmt, ParserError> {
+    pub fn parse_try_statement(&mut self) -> Result<Stmt, ParserError> {
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
+            return Err(ParserError::ScopeError(
+                "try statement not allowed in global scope".to_string(),
+                self.at().line,
+            ));
+        }
+
+        if let Scope::Class(class_name) = self.scope.last().unwrap() {
+            return Err(ParserError::ScopeError(
+                format!(
+                    "Invalid try statement inside class '{}'. Only method and field declarations are allowed.",
+                    class_name
+                ),
+                self.at().line,
+            ));
+        }
         let line = self.eat().line;
+        let _ = self.expect(
+            TokenType::LEFTBRACE,
+            "Missing '{' to start the body of the try statement",
+        )?;
+        let mut body = vec![];
+        while !self.at_block_end() {
+            body.push(self.parse_stmt()?);
+        }
+        let _ = self.expect(
+            TokenType::RIGHTBRACE,
+            "Missing '}' to end the body of the try statement",
+        )?;
 
+        let _ = self.expect(TokenType::CATCH, "Expected 'catch' after the body of the try statement")?;
         let name = self
             .expect(
                 TokenType::IDENTIFIER,
-                "Expected function name after 'fun' keyword",
+                "Expected a variable name for the error after 'catch'",
             )?
             .lexeme;
+        let _ = self.expect(
+            TokenType::LEFTBRACE,
+            "Missing '{' to start the body of the catch block",
+        )?;
+        let mut handler = vec![];
+        while !self.at_block_end() {
+            handler.push(self.parse_stmt()?);
+        }
+        let _ = self.expect(
+            TokenType::RIGHTBRACE,
+            "Missing '}' to end the body of the catch block",
+        )?;
+
+        Ok(Stmt::Try(body, name, handler, line))
+    }
+
+    pub fn parse_with_statement(&mut self) -> Result<Stmt, ParserError> {
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
+            return Err(ParserError::ScopeError(
+                "with statement not allowed in global scope".to_string(),
+                self.at().line,
+            ));
+        }
 
         if let Scope::Class(class_name) = self.scope.last().unwrap() {
-            if class_name == &name {
-                self.scope.push(Scope::Constructor(name.clone()));
-            } else {
-                self.scope.push(Scope::Method(name.clone()));
-            }
-        } else {
-            self.scope.push(Scope::Function(name.clone()));
+            return Err(ParserError::ScopeError(
+                format!(
+                    "Invalid with statement inside class '{}'. Only method and field declarations are allowed.",
+                    class_name
+                ),
+                self.at().line,
+            ));
+        }
+        let line = self.eat().line;
+        let value = self.parse_expr()?;
+        let _ = self.expect(TokenType::AS, "Expected 'as' after the value of the with statement")?;
+        let name = self
+            .expect(
+                TokenType::IDENTIFIER,
+                "Expected a variable name for the value after 'as'",
+            )?
+            .lexeme;
+        let _ = self.expect(
+            TokenType::LEFTBRACE,
+            "Missing '{' to start the body of the with statement",
+        )?;
+        let mut body = vec![];
+        while !self.at_block_end() {
+            body.push(self.parse_stmt()?);
+        }
+        let _ = self.expect(
+            TokenType::RIGHTBRACE,
+            "Missing '}' to end the body of the with statement",
+        )?;
+        Ok(Stmt::With(value, name, body, line))
+    }
+
+    pub fn parse_block_statement(&mut self) -> Result<Stmt, ParserError> {
+        if self.scope.last().unwrap() == &Scope::Global && self.mode == Mode::Main {
+            return Err(ParserError::ScopeError(
+                "block statements not allowed in global scope".to_string(),
+                self.at().line,
+            ));
         }
 
+        if let Scope::Class(class_name) = self.scope.last().unwrap() {
+            return Err(ParserError::ScopeError(
+                format!(
+                    "Invalid block statement inside class '{}'. Only method and field declarations are allowed.",
+                    class_name
+                ),
+                self.at().line,
+            ));
+        }
+        let _ = self.eat();
+        let mut stmts = vec![];
+        while !self.at_block_end() {
+            stmts.push(self.parse_stmt()?);
+        }
+        let _ = self.expect(
+            TokenType::RIGHTBRACE,
+            "Missing '}' to end the body of the block statement",
+        )?;
+        Ok(Stmt::Block(stmts))
+    }
+
+    // `(a, b)` after the name of a function, method or interface method
+    fn parse_parameters(&mut self, name: &str) -> Result<Vec<String>, ParserError> {
         let _ = self.expect(
             TokenType::LEFTPAREN,
             format!("Missing '(' to declare parameters of function {}", name).as_str(),
@@ -319,13 +728,17 @@ impl Parser {
         let mut parameters = vec![];
 
         while self.at().token_type != TokenType::RIGHTPAREN {
-            parameters.push(
-                self.expect(
-                    TokenType::IDENTIFIER,
-                    format!("Expected parameter name in function '{}'", name).as_str(),
-                )?
-                .lexeme,
-            );
+            let parameter = self.expect(
+                TokenType::IDENTIFIER,
+                format!("Expected parameter name in function '{}'", name).as_str(),
+            )?;
+            if parameters.contains(&parameter.lexeme) {
+                return Err(ParserError::UnExpectedToken(
+                    format!("Parameter '{}' is declared twice in function '{}'", parameter.lexeme, name),
+                    parameter.line,
+                ));
+            }
+            parameters.push(parameter.lexeme);
             if self.at().token_type != TokenType::COMMA
                 && self.at().token_type != TokenType::RIGHTPAREN
             {
@@ -343,6 +756,43 @@ impl Parser {
             TokenType::RIGHTPAREN,
             format!("Missing ')' for parameter declaration in function {}", name).as_str(),
         )?;
+        Ok(parameters)
+    }
+
+    pub fn parse_functional_statement(&mut self) -> Result<Stmt, ParserError> {
+        let is_static = self.previous().token_type == TokenType::STATIC;
+        let asynchronous = self.at().token_type == TokenType::ASYNC;
+        if asynchronous {
+            let _ = self.eat();
+        }
+        let line = self.expect(TokenType::FUN, "Expected 'fun' after 'async' keyword")?.line;
+
+        let name = self
+            .expect(
+                TokenType::IDENTIFIER,
+                "Expected function name after 'fun' keyword",
+            )?
+            .lexeme;
+
+        if let Scope::Class(class_name) = self.scope.last().unwrap() {
+            if is_static {
+                self.scope.push(Scope::StaticMethod(name.clone()));
+            } else if class_name == &name || name == "init" {
+                if asynchronous {
+                    return Err(ParserError::ScopeError(
+                        format!("The constructor of class '{}' cannot be async", class_name),
+                        line,
+                    ));
+                }
+                self.scope.push(Scope::Constructor(class_name.clone()));
+            } else {
+                self.scope.push(Scope::Method(name.clone()));
+            }
+        } else {
+            self.scope.push(Scope::Function(name.clone()));
+        }
+
+        let parameters = self.parse_parameters(&name)?;
 
         let mut body = vec![];
         let _ = self.expect(
@@ -350,9 +800,18 @@ impl Parser {
             format!("Missing '{{' to start the body of function {}", name).as_str(),
         )?;
 
-        while self.at().token_type != TokenType::RIGHTBRACE {
+        // Functions declared inside have their own say on yielding
+        let outer_generator = std::mem::replace(&mut self.generator, false);
+        while !self.at_block_end() {
             body.push(self.parse_stmt()?);
         }
+        let generator = std::mem::replace(&mut self.generator, outer_generator);
+        if generator && asynchronous {
+            return Err(ParserError::ScopeError(
+                format!("Async function '{}' cannot yield", name),
+                line,
+            ));
+        }
 
         let _ = self.expect(
             TokenType::RIGHTBRACE,
@@ -360,18 +819,210 @@ impl Parser {
         )?;
         self.scope.pop();
 
-        Ok(Stmt::Function(FunctionDeclaration {
+        Ok(Stmt::Function(Rc::new(FunctionDeclaration {
             name,
             parameters,
             body,
+            generator,
+            asynchronous,
             line,
-        }))
+        })))
     }
 
-    pub fn parse_class_statement(&mut self) -> Result<Stmt, ParserError> {
+    pub fn parse_yield_statement(&mut self) -> Result<Stmt, ParserError> {
+        let line = self.eat().line;
+        let callable = self.scope.iter().rev().find(|scope| {
+            matches!(
+                scope,
+                Scope::Function(_)
+                    | Scope::Method(_)
+                    | Scope::StaticMethod(_)
+                    | Scope::Constructor(_)
+                    | Scope::Class(_)
+                    | Scope::IfExpression
+            )
+        });
+        match callable {
+            Some(Scope::Function(_) | Scope::Method(_) | Scope::StaticMethod(_)) => {}
+            Some(Scope::Constructor(class_name)) => {
+                return Err(ParserError::ScopeError(
+                    format!("Cannot yield from the constructor of class '{}'", class_name),
+                    line,
+                ));
+            }
+            Some(Scope::IfExpression) => {
+                return Err(ParserError::ScopeError(
+                    "Invalid yield statement in an if expression. A branch must end with its value".to_string(),
+                    line,
+                ));
+            }
+            _ => {
+                return Err(ParserError::ScopeError(
+                    "Yield statement not allowed outside a function or method".to_string(),
+                    line,
+                ));
+            }
+        }
+        self.generator = true;
+        let mut expr = Expr::Null(line);
+        if self.at().token_type != TokenType::SEMICOLON {
+            expr = self.parse_expr()?;
+        }
+        let _ = self.expect(
+            TokenType::SEMICOLON,
+            "Missing ';' at end of yield statement",
+        )?;
+        Ok(Stmt::Yield(expr))
+    }
+
+    // The comma separated mixins or interfaces in the header of a class
+    fn parse_class_list(&mut self, kind: &str, class_name: &str) -> Result<Vec<String>, ParserError> {
+        let mut names = vec![];
+        loop {
+            let name = self.expect(
+                TokenType::IDENTIFIER,
+                format!("Expected {} name in the header of class '{}'", kind.to_lowercase(), class_name).as_str(),
+            )?;
+            if names.contains(&name.lexeme) {
+                return Err(ParserError::UnExpectedToken(
+                    format!("{} '{}' is listed twice by class '{}'", kind, name.lexeme, class_name),
+                    name.line,
+                ));
+            }
+            names.push(name.lexeme);
+            if self.at().token_type != TokenType::COMMA {
+                return Ok(names);
+            }
+            let _ = self.eat();
+        }
+    }
+
+    pub fn parse_mixin_statement(&mut self) -> Result<Stmt, ParserError> {
+        if self.scope.last().unwrap() != &Scope::Global {
+            return Err(ParserError::ScopeError(
+                "Mixin declarations are only allowed in the global scope".to_string(),
+                self.at().line,
+            ));
+        }
+        let line = self.eat().line;
+        let name = self
+            .expect(
+                TokenType::IDENTIFIER,
+                "Expected mixin name after 'mixin' keyword",
+            )?
+            .lexeme;
+        let _ = self.expect(
+            TokenType::LEFTBRACE,
+            format!("Missing '{{' to start the body of mixin {}", name).as_str(),
+        )?;
+        // Methods are parsed as those of a class, which is what they end up in
+        self.scope.push(Scope::Class(name.clone()));
+        let mut methods = HashMap::new();
+        let mut comments = vec![];
+        while !self.at_block_end() {
+            if let Some(Stmt::Comment(text, trailing, line)) = self.take_comment() {
+                comments.push((text, trailing, line));
+                continue;
+            }
+            let asynchronous = self.at().token_type == TokenType::ASYNC;
+            if self.peek(asynchronous as usize).token_type != TokenType::FUN {
+                return Err(ParserError::ScopeError(
+                    format!("Only methods can be declared in mixin '{}'", name),
+                    self.at().line,
+                ));
+            }
+            let method_line = self.at().line;
+            let method_name = self.peek(asynchronous as usize + 1).lexeme.clone();
+            if method_name == "init" || method_name == name {
+                return Err(ParserError::ScopeError(
+                    format!("Mixin '{}' cannot declare a constructor", name),
+                    method_line,
+                ));
+            }
+            if let Stmt::Function(method) = self.parse_functional_statement()? {
+                if methods.contains_key(&method.name) {
+                    return Err(ParserError::ScopeError(
+                        format!("Method '{}' is declared twice in mixin '{}'", method.name, name),
+                        method.line,
+                    ));
+                }
+                methods.insert(method.name.clone(), method);
+            }
+        }
+        self.scope.pop();
+        let _ = self.expect(
+            TokenType::RIGHTBRACE,
+            format!("Missing '}}' to end the body of mixin {}", name).as_str(),
+        )?;
+        Ok(Stmt::Mixin(Rc::new(MixinDeclaration {
+            name,
+            methods,
+            comments,
+            line,
+        })))
+    }
+
+    pub fn parse_interface_statement(&mut self) -> Result<Stmt, ParserError> {
         if self.scope.last().unwrap() != &Scope::Global {
             return Err(ParserError::ScopeError(
-                "Class declarations are only allowed in the global scope".to_string(),
+                "Interface declarations are only allowed in the global scope".to_string(),
+                self.at().line,
+            ));
+        }
+        let line = self.eat().line;
+        let name = self
+            .expect(
+                TokenType::IDENTIFIER,
+                "Expected interface name after 'interface' keyword",
+            )?
+            .lexeme;
+        let _ = self.expect(
+            TokenType::LEFTBRACE,
+            format!("Missing '{{' to start the body of interface {}", name).as_str(),
+        )?;
+        let mut methods: Vec<(String, Vec<String>, usize)> = vec![];
+        let mut comments = vec![];
+        while !self.at_block_end() {
+            if let Some(Stmt::Comment(text, trailing, line)) = self.take_comment() {
+                comments.push((text, trailing, line));
+                continue;
+            }
+            let method = self.expect(
+                TokenType::IDENTIFIER,
+                format!("Expected a method name in interface '{}'", name).as_str(),
+            )?;
+            if methods.iter().any(|(existing, _, _)| *existing == method.lexeme) {
+                return Err(ParserError::ScopeError(
+                    format!("Method '{}' is declared twice in interface '{}'", method.lexeme, name),
+                    method.line,
+                ));
+            }
+            let parameters = self.parse_parameters(&method.lexeme)?;
+            let _ = self.expect(
+                TokenType::SEMICOLON,
+                "Expected ';' after a method of an interface",
+            )?;
+            methods.push((method.lexeme, parameters, method.line));
+        }
+        let _ = self.expect(
+            TokenType::RIGHTBRACE,
+            format!("Missing '}}' to end the body of interface {}", name).as_str(),
+        )?;
+        Ok(Stmt::Interface(Rc::new(InterfaceDeclaration {
+            name,
+            methods,
+            comments,
+            line,
+        })))
+    }
+
+    pub fn parse_class_statement(&mut self) -> Result<Stmt, ParserError> {
+        if let Scope::Class(class_name) = self.scope.last().unwrap() {
+            return Err(ParserError::ScopeError(
+                format!(
+                    "Invalid class declaration inside class '{}'. Only method and field declarations are allowed.",
+                    class_name
+                ),
                 self.at().line,
             ));
         }
@@ -383,6 +1034,13 @@ impl Parser {
                 "Expected class name after 'class' keyword",
             )?
             .lexeme;
+        Ok(Stmt::Class(self.parse_class(name, line)?))
+    }
+
+    /// Parses a class from its optional superclass, mixins and interfaces to
+    /// the end of its body, after the name of a declaration or class
+    /// expression.
+    pub fn parse_class(&mut self, name: String, line: usize) -> Result<ClassDeclaration, ParserError> {
         self.scope.push(Scope::Class(name.clone()));
 
         let mut superclass = None;
@@ -395,33 +1053,71 @@ impl Parser {
             );
         }
 
+        // `class Sprite with Movable, Drawable` takes the methods of mixins
+        let mut mixins = vec![];
+        if self.at().token_type == TokenType::WITH {
+            let _ = self.eat();
+            mixins = self.parse_class_list("Mixin", &name)?;
+        }
+
+        // `class Circle : Drawable, Sized` lists the interfaces it implements
+        let mut interfaces = vec![];
+        if self.at().token_type == TokenType::COLON {
+            let _ = self.eat();
+            interfaces = self.parse_class_list("Interface", &name)?;
+        }
+
         let mut var = vec![];
+        let mut instance_fields = vec![];
         let mut methods = HashMap::new();
+        let mut static_methods = HashMap::new();
+        let mut comments = vec![];
 
         let _ = self.expect(
             TokenType::LEFTBRACE,
             format!("Missing '{{' to start the body of class {}", name).as_str(),
         )?;
 
-        while self.at().token_type != TokenType::RIGHTBRACE {
-            let stmt = match self.parse_stmt() {
-                Ok(s) => s,
-                Err(e) => return match e {
-                    ParserError::ScopeError(message, line) => {
-                        Err(ParserError::ScopeError(
+        while !self.at_block_end() {
+            if let Some(Stmt::Comment(text, trailing, line)) = self.take_comment() {
+                comments.push((text, trailing, line));
+                continue;
+            }
+            if self.at().token_type == TokenType::STATIC {
+                let _ = self.eat();
+                match self.at().token_type {
+                    TokenType::VAR | TokenType::CONST => {
+                        if let Stmt::VarDeclaration(var_stmt) = self.parse_var_declaration()? {
+                            var.push(var_stmt);
+                        }
+                    }
+                    TokenType::FUN | TokenType::ASYNC => {
+                        if let Stmt::Function(method_stmt) = self.parse_functional_statement()? {
+                            if static_methods.contains_key(&method_stmt.name) {
+                                return Err(method_declared_twice(&method_stmt, &name));
+                            }
+                            static_methods.insert(method_stmt.name.clone(), method_stmt);
+                        }
+                    }
+                    _ => {
+                        return Err(ParserError::UnExpectedToken(
                             format!(
-                                "Invalid {} inside class body. Only method and field declarations are allowed.",
-                                message
+                                "Expected 'var', 'const' or 'fun' after 'static' in class '{}'",
+                                name
                             ),
-                            line,
-                        ))
+                            self.at().line,
+                        ));
                     }
-                    _ => Err(e),
-                },
-            };
+                }
+                continue;
+            }
+            let stmt = self.parse_stmt()?;
             match stmt {
-                Stmt::VarDeclaration(var_stmt) => var.push(var_stmt),
+                Stmt::VarDeclaration(var_stmt) => instance_fields.push(var_stmt),
                 Stmt::Function(method_stmt) => {
+                    if methods.contains_key(&method_stmt.name) {
+                        return Err(method_declared_twice(&method_stmt, &name));
+                    }
                     methods.insert(method_stmt.name.clone(), method_stmt);
                 }
                 _ => {}
@@ -434,12 +1130,29 @@ impl Parser {
         )?;
 
         self.scope.pop();
-        Ok(Stmt::Class(ClassDeclaration {
+        Ok(ClassDeclaration {
             name,
             static_fields: var,
+            instance_fields: Rc::new(instance_fields),
             methods,
+            static_methods,
             superclass,
+            mixins,
+            interfaces,
+            comments,
             line,
-        }))
+        })
     }
 }
+
+// Unlike functions, methods are looked up by name alone and cannot be
+// overloaded
+fn method_declared_twice(method: &FunctionDeclaration, class_name: &str) -> ParserError {
+    ParserError::ScopeError(
+        format!(
+            "Method '{}' is declared twice in class '{}'. Methods cannot be overloaded",
+            method.name, class_name
+        ),
+        method.line,
+    )
+}
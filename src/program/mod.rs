@@ -0,0 +1,196 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::embed::{LoxError, Value, mismatch};
+use crate::environment::*;
+use crate::handle_errors::*;
+use crate::interpreter::interpreter::{Interpreter, Output};
+use crate::values::*;
+use crate::{INTERPRETER_STACK_SIZE, RunOptions, configure, parse, report_warnings, read_program, serialize_source_code};
+
+// A call the host made: the callee's name, its arguments and where to send
+// the result
+type Call = (String, Vec<Value>, mpsc::Sender<Result<Value, LoxError>>);
+
+/// A program loaded once so the host can call its functions by name, for
+/// example as plugin hooks. Loading declares the program's functions and
+/// classes without calling `main`; a script runs its top-level statements.
+///
+/// The program lives on a thread of its own, with the stack size `run_file`
+/// uses, and calls are queued to it one at a time. `Program` is `Send` and
+/// `Sync`, so it can be shared between the threads of a server through an
+/// `Arc`, and every call sees the state earlier calls left behind.
+pub struct Program {
+    calls: Option<mpsc::Sender<Call>>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl Program {
+    pub fn load(
+        file_path: &str,
+        command_line_args: &[&str],
+        options: RunOptions,
+    ) -> Result<Program, LoxError> {
+        let contents = read_program(file_path, &options).map_err(|e| LoxError::File(e.to_string()))?;
+        let input = Box::new(BufReader::new(io::stdin()));
+        Program::start(contents, Some(file_path), command_line_args, options, Output::default(), input)
+    }
+
+    /// Loads a program given as source text, with its output going to
+    /// `output` and `scan` reading from `input`.
+    pub fn from_source(
+        source: &str,
+        command_line_args: &[&str],
+        options: RunOptions,
+        output: Output,
+        input: Box<dyn BufRead + Send>,
+    ) -> Result<Program, LoxError> {
+        Program::start(source.to_string(), None, command_line_args, options, output, input)
+    }
+
+    fn start(
+        source: String,
+        file: Option<&str>,
+        command_line_args: &[&str],
+        options: RunOptions,
+        output: Output,
+        input: Box<dyn BufRead + Send>,
+    ) -> Result<Program, LoxError> {
+        let file = file.map(str::to_string);
+        let command_line_args: Vec<String> = command_line_args.iter().map(|arg| arg.to_string()).collect();
+        let (calls, requests) = mpsc::channel::<Call>();
+        let (loaded_sender, loaded) = mpsc::channel();
+        let worker = thread::Builder::new()
+            .stack_size(INTERPRETER_STACK_SIZE)
+            .spawn(move || {
+                with_file(file.as_deref(), || {
+                    let serialized_code = serialize_source_code(&source);
+                    let mut diagnostics = vec![];
+                    let Some(program) = parse(&source, &serialized_code, &mut diagnostics, options.mode) else {
+                        let diagnostics = String::from_utf8_lossy(&diagnostics).into_owned();
+                        let _ = loaded_sender.send(Err(LoxError::Compile(diagnostics)));
+                        return;
+                    };
+                    let env = Environment::new_global(&options.access);
+                    let mut interpreter = Interpreter::new(&env, output, input);
+                    configure(&mut interpreter, &options, Some(&source));
+                    if interpreter.warnings {
+                        report_warnings(&program, &serialized_code, &mut interpreter.output.stderr);
+                    }
+                    let args: Vec<&str> = command_line_args.iter().map(|arg| &arg[..]).collect();
+                    let result = interpreter.load_program(&program, &env, &args, options.mode);
+                    let failed = result.is_err();
+                    let _ = loaded_sender.send(result.map_err(LoxError::from));
+                    if failed {
+                        return;
+                    }
+                    // Runs until the host drops the program
+                    for (name, args, reply) in requests {
+                        let _ = reply.send(interpreter.call_by_name(&env, &name, &args));
+                    }
+                    interpreter.write_profile();
+                })
+            })
+            .map_err(|e| LoxError::File(format!("Failed to start the interpreter: {}", e)))?;
+        let program = Program {
+            calls: Some(calls),
+            worker: Some(worker),
+        };
+        loaded.recv().unwrap_or_else(|_| Err(stopped()))?;
+        Ok(program)
+    }
+
+    /// Calls the global function, class or native `name` with `args` and
+    /// returns its result. Calls from several threads run one after another.
+    pub fn call(&self, name: &str, args: &[Value]) -> Result<Value, LoxError> {
+        let (reply, result) = mpsc::channel();
+        if let Some(calls) = &self.calls {
+            let _ = calls.send((name.to_string(), args.to_vec(), reply));
+        }
+        result.recv().unwrap_or_else(|_| Err(stopped()))
+    }
+}
+
+// The worker only goes away without answering if the interpreter panicked
+fn stopped() -> LoxError {
+    LoxError::Conversion("The interpreter has stopped".to_string())
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        // Closing the channel ends the worker's loop
+        self.calls = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Interpreter {
+    /// Copies a host value into the interpreter. Values the host only has
+    /// the printed form of cannot be passed back in.
+    pub fn runtime_value(&mut self, value: &Value) -> Result<RuntimeVal, LoxError> {
+        Ok(match value {
+            Value::Nil => RuntimeVal::Nil,
+            Value::Bool(bit) => RuntimeVal::Bool(*bit),
+            Value::Number(num) => RuntimeVal::Number(*num),
+            Value::String(s) => RuntimeVal::String(Rc::from(&s[..])),
+            Value::Array(elements) => {
+                let mut values = vec![];
+                for element in elements {
+                    values.push(self.runtime_value(element)?);
+                }
+                RuntimeVal::Array(values)
+            }
+            Value::Object(properties) => {
+                let mut values = HashMap::new();
+                for (name, property) in properties {
+                    values.insert(name.clone(), self.runtime_value(property)?);
+                }
+                RuntimeVal::Object(values)
+            }
+            Value::Map(entries) => {
+                let mut map = HashMap::new();
+                for (key, entry) in entries {
+                    map.insert(self.map_key(key)?, self.runtime_value(entry)?);
+                }
+                RuntimeVal::Map(map)
+            }
+            Value::Set(elements) => {
+                let mut set = HashSet::new();
+                for element in elements {
+                    set.insert(self.map_key(element)?);
+                }
+                RuntimeVal::Set(set)
+            }
+            Value::Other { .. } => return Err(mismatch("data", value)),
+        })
+    }
+
+    fn map_key(&mut self, key: &Value) -> Result<MapKey, LoxError> {
+        let val = self.runtime_value(key)?;
+        MapKey::from_value(&val).ok_or_else(|| mismatch("Bool, Number or String key", key))
+    }
+
+    fn call_by_name(
+        &mut self,
+        env: &Rc<RefCell<Environment>>,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, LoxError> {
+        let Ok(callee) = lookup_var(env, name) else {
+            let message = format!("'{}' is not declared.{}", name, suggest_name(env, name));
+            return Err(RuntimeError::EnvironmentError(message, 0).into());
+        };
+        let mut values = vec![];
+        for arg in args {
+            values.push(self.runtime_value(arg)?);
+        }
+        let result = self.call(callee, &values, 0)?;
+        Ok(self.host_value(&result)?)
+    }
+}
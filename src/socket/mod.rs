@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// An open network connection, or a listener waiting for connections,
+/// behind the `Socket` value type. Closing it, or dropping the last copy of
+/// the value, closes the underlying socket.
+pub struct Socket {
+    address: String,
+    connection: RefCell<Option<Connection>>,
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    TcpListener(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(unix)]
+    UnixListener(UnixListener),
+}
+
+impl Socket {
+    fn new(address: String, connection: Connection) -> Socket {
+        Socket {
+            address,
+            connection: RefCell::new(Some(connection)),
+        }
+    }
+
+    pub fn tcp_connect(host: &str, port: u16) -> io::Result<Socket> {
+        let stream = TcpStream::connect((host, port))?;
+        Ok(Socket::new(format!("{}:{}", host, port), Connection::Tcp(stream)))
+    }
+
+    // Port 0 lets the system pick a free port, which the address then shows
+    pub fn tcp_listen(host: &str, port: u16) -> io::Result<Socket> {
+        let listener = TcpListener::bind((host, port))?;
+        let address = listener.local_addr()?.to_string();
+        Ok(Socket::new(address, Connection::TcpListener(listener)))
+    }
+
+    #[cfg(unix)]
+    pub fn unix_connect(path: &str) -> io::Result<Socket> {
+        let stream = UnixStream::connect(path)?;
+        Ok(Socket::new(path.to_string(), Connection::Unix(stream)))
+    }
+
+    #[cfg(not(unix))]
+    pub fn unix_connect(_path: &str) -> io::Result<Socket> {
+        Err(unix_unsupported())
+    }
+
+    #[cfg(unix)]
+    pub fn unix_listen(path: &str) -> io::Result<Socket> {
+        let listener = UnixListener::bind(path)?;
+        Ok(Socket::new(path.to_string(), Connection::UnixListener(listener)))
+    }
+
+    #[cfg(not(unix))]
+    pub fn unix_listen(_path: &str) -> io::Result<Socket> {
+        Err(unix_unsupported())
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Waits for the next connection to a listener.
+    pub fn accept(&self) -> io::Result<Socket> {
+        match &*self.connection.borrow() {
+            Some(Connection::TcpListener(listener)) => {
+                let (stream, peer) = listener.accept()?;
+                Ok(Socket::new(peer.to_string(), Connection::Tcp(stream)))
+            }
+            #[cfg(unix)]
+            Some(Connection::UnixListener(listener)) => {
+                let (stream, _) = listener.accept()?;
+                Ok(Socket::new(self.address.clone(), Connection::Unix(stream)))
+            }
+            Some(_) => Err(invalid("only a listening socket can accept connections")),
+            None => Err(closed()),
+        }
+    }
+
+    pub fn send(&self, data: &[u8]) -> io::Result<()> {
+        match &mut *self.connection.borrow_mut() {
+            Some(Connection::Tcp(stream)) => stream.write_all(data),
+            #[cfg(unix)]
+            Some(Connection::Unix(stream)) => stream.write_all(data),
+            Some(_) => Err(invalid("a listening socket cannot send data")),
+            None => Err(closed()),
+        }
+    }
+
+    /// Waits for data and returns at most `max` bytes of it. An empty result
+    /// means the other end closed the connection.
+    pub fn recv(&self, max: usize) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0; max];
+        let count = match &mut *self.connection.borrow_mut() {
+            Some(Connection::Tcp(stream)) => stream.read(&mut buffer)?,
+            #[cfg(unix)]
+            Some(Connection::Unix(stream)) => stream.read(&mut buffer)?,
+            Some(_) => return Err(invalid("a listening socket cannot receive data")),
+            None => return Err(closed()),
+        };
+        buffer.truncate(count);
+        Ok(buffer)
+    }
+
+    /// Closes the socket. Closing it again does nothing.
+    pub fn close(&self) {
+        self.connection.borrow_mut().take();
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "the socket is closed")
+}
+
+#[cfg(not(unix))]
+fn unix_unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not available on this platform")
+}
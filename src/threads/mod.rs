@@ -0,0 +1,351 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, BufReader};
+use std::rc::Rc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::environment::*;
+use crate::handle_errors::RuntimeError;
+use crate::interpreter::interpreter::{Interpreter, Output};
+use crate::values::*;
+use crate::{INTERPRETER_STACK_SIZE, parse, serialize_source_code};
+
+/// The source of a running program and how it was run, kept so a thread
+/// can declare the program's functions and classes again in an
+/// interpreter of its own.
+pub struct ProgramSource {
+    pub text: String,
+    pub mode: Mode,
+    pub access: NativeAccess,
+}
+
+/// A value on its way from one thread's interpreter to another's. Channels
+/// are shared, everything else is copied deeply.
+#[derive(Clone)]
+pub enum Message {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Range { start: f64, end: f64, inclusive: bool },
+    Array(Vec<Message>),
+    Object(Vec<(String, Message)>),
+    Map(Vec<(Message, Message)>),
+    Set(Vec<Message>),
+    Channel(Arc<Channel>),
+    Mutex(Arc<SharedValue>),
+}
+
+/// A queue of values that threads hand to each other, behind the `Channel`
+/// value type. Any thread holding it can send and receive.
+pub struct Channel {
+    // The values sent and not yet received, and whether it was closed
+    queue: Mutex<(VecDeque<Message>, bool)>,
+    sent: Condvar,
+}
+
+impl Channel {
+    pub fn new() -> Self {
+        Channel {
+            queue: Mutex::new((VecDeque::new(), false)),
+            sent: Condvar::new(),
+        }
+    }
+
+    /// Queues a message. Returns false, dropping it, once the channel is
+    /// closed.
+    pub fn send(&self, message: Message) -> bool {
+        let mut queue = self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if queue.1 {
+            return false;
+        }
+        queue.0.push_back(message);
+        self.sent.notify_one();
+        true
+    }
+
+    /// Takes the oldest message, waiting for one to be sent if there is
+    /// none. Returns `None` once the channel is closed and empty.
+    pub fn recv(&self) -> Option<Message> {
+        let mut queue = self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        loop {
+            if let Some(message) = queue.0.pop_front() {
+                return Some(message);
+            }
+            if queue.1 {
+                return None;
+            }
+            queue = self.sent.wait(queue).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// Stops the channel taking messages, and wakes every receiver waiting
+    /// on it. Messages already sent can still be received.
+    pub fn close(&self) {
+        let mut queue = self.queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        queue.1 = true;
+        self.sent.notify_all();
+    }
+}
+
+/// A value threads share, behind the `Mutex` value type. Only one thread
+/// at a time can read or replace it.
+pub struct SharedValue {
+    value: Mutex<Message>,
+}
+
+impl SharedValue {
+    pub fn new(message: Message) -> Self {
+        SharedValue {
+            value: Mutex::new(message),
+        }
+    }
+}
+
+thread_local! {
+    // Mutexes the calling thread holds the lock of, by address
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(vec![]) };
+}
+
+/// A function running on a thread of its own, behind the `Thread` value.
+pub struct Thread {
+    name: String,
+    handle: RefCell<Option<JoinHandle<Result<Message, RuntimeError>>>>,
+    // What the function returned or failed with, once joined
+    outcome: RefCell<Option<Result<Message, RuntimeError>>>,
+}
+
+impl Thread {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn wait(&self) -> Result<Message, RuntimeError> {
+        if let Some(handle) = self.handle.borrow_mut().take() {
+            let outcome = handle.join().unwrap_or_else(|_| {
+                Err(RuntimeError::Panic(format!("Thread '{}' stopped unexpectedly", self.name), 0))
+            });
+            *self.outcome.borrow_mut() = Some(outcome);
+        }
+        self.outcome.borrow().clone().unwrap_or(Err(RuntimeError::InternalError))
+    }
+}
+
+impl Interpreter {
+    /// Runs a function declared at the top of the program on a new thread,
+    /// with deep copies of `args`.
+    pub(crate) fn spawn_thread(
+        &mut self,
+        function: &RuntimeVal,
+        args: &[RuntimeVal],
+        line: usize,
+    ) -> Result<Rc<Thread>, RuntimeError> {
+        let name = callable_name(function);
+        let top_level = match (function, lookup_var(&self.globals, &name)) {
+            (RuntimeVal::Function { declaration, .. }, Ok(RuntimeVal::Function { declaration: own, .. })) => {
+                Rc::ptr_eq(declaration, &own)
+            }
+            (RuntimeVal::Overloaded { declarations, .. }, Ok(RuntimeVal::Overloaded { declarations: own, .. })) => {
+                Rc::ptr_eq(declarations, &own)
+            }
+            _ => false,
+        };
+        if !top_level {
+            return Err(RuntimeError::TypeMismatch(
+                "Only functions declared at the top level of the program can run on a thread".to_string(),
+                line,
+            ));
+        }
+        let Some(source) = self.source.clone() else {
+            return Err(RuntimeError::IoError(
+                "Threads can only be started by programs run from a file or source text".to_string(),
+                line,
+            ));
+        };
+        let mut messages = vec![];
+        for arg in args {
+            messages.push(self.message(arg, line)?);
+        }
+        let thread_name = name.clone();
+        let handle = thread::Builder::new()
+            .name(name.clone())
+            .stack_size(INTERPRETER_STACK_SIZE)
+            .spawn(move || run_thread(source, &thread_name, messages))
+            .map_err(|e| RuntimeError::IoError(format!("Failed to start a thread: {}", e), line))?;
+        let thread = Rc::new(Thread {
+            name,
+            handle: RefCell::new(Some(handle)),
+            outcome: RefCell::new(None),
+        });
+        self.threads.push(Rc::clone(&thread));
+        Ok(thread)
+    }
+
+    /// Waits for a thread to finish and gives what its function returned,
+    /// or raises the error it failed with.
+    pub(crate) fn join_thread(&mut self, thread: &Thread) -> Result<RuntimeVal, RuntimeError> {
+        self.threads.retain(|own| !std::ptr::eq(&**own, thread));
+        thread.wait().map(|message| from_message(&message))
+    }
+
+    /// Waits for every thread the program did not join, then reports the
+    /// first error one of them failed with.
+    pub(crate) fn finish_threads(&mut self) -> Result<(), RuntimeError> {
+        let mut result = Ok(());
+        for thread in std::mem::take(&mut self.threads) {
+            if let (Ok(()), Err(error)) = (&result, thread.wait()) {
+                result = Err(error);
+            }
+        }
+        result
+    }
+
+    /// Calls `update` with the value a mutex holds while holding its lock,
+    /// and stores a copy of what it returns in its place. Locking a mutex
+    /// the thread already holds is an error rather than a deadlock.
+    pub(crate) fn lock_value(
+        &mut self,
+        shared: &SharedValue,
+        line: usize,
+        update: impl FnOnce(&mut Self, RuntimeVal) -> Result<RuntimeVal, RuntimeError>,
+    ) -> Result<RuntimeVal, RuntimeError> {
+        let address = shared as *const SharedValue as usize;
+        if HELD.with(|held| held.borrow().contains(&address)) {
+            return Err(RuntimeError::InvalidCall(
+                "The mutex is already locked by this thread".to_string(),
+                line,
+            ));
+        }
+        let mut value = shared.value.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        HELD.with(|held| held.borrow_mut().push(address));
+        let result = update(self, from_message(&value)).and_then(|updated| {
+            *value = self.message(&updated, line)?;
+            Ok(updated)
+        });
+        HELD.with(|held| held.borrow_mut().retain(|own| *own != address));
+        result
+    }
+
+    /// Copies a value so it can be handed to another thread.
+    pub(crate) fn message(&mut self, val: &RuntimeVal, line: usize) -> Result<Message, RuntimeError> {
+        Ok(match val {
+            RuntimeVal::Nil => Message::Nil,
+            RuntimeVal::Bool(bit) => Message::Bool(*bit),
+            RuntimeVal::Number(num) => Message::Number(*num),
+            RuntimeVal::String(s) => Message::String(s.to_string()),
+            RuntimeVal::Bytes(data) => Message::Bytes(data.clone()),
+            RuntimeVal::Range { start, end, inclusive } => Message::Range {
+                start: *start,
+                end: *end,
+                inclusive: *inclusive,
+            },
+            RuntimeVal::Array(elements) => {
+                let mut messages = vec![];
+                for element in elements {
+                    messages.push(self.message(element, line)?);
+                }
+                Message::Array(messages)
+            }
+            RuntimeVal::Object(properties) => {
+                let mut messages = vec![];
+                for (key, property) in properties {
+                    messages.push((key.clone(), self.message(property, line)?));
+                }
+                Message::Object(messages)
+            }
+            RuntimeVal::Map(map) => {
+                let mut entries = vec![];
+                for (key, entry) in map {
+                    entries.push((self.message(&key.to_value(), line)?, self.message(entry, line)?));
+                }
+                Message::Map(entries)
+            }
+            RuntimeVal::Set(set) => {
+                let mut elements = vec![];
+                for element in set {
+                    elements.push(self.message(&element.to_value(), line)?);
+                }
+                Message::Set(elements)
+            }
+            RuntimeVal::Channel(channel) => Message::Channel(Arc::clone(channel)),
+            RuntimeVal::Mutex(shared) => Message::Mutex(Arc::clone(shared)),
+            _ => {
+                return Err(RuntimeError::TypeMismatch(
+                    format!("Values of type {} cannot be sent to another thread", type_name(val)),
+                    line,
+                ));
+            }
+        })
+    }
+}
+
+/// Rebuilds a value another thread sent.
+pub fn from_message(message: &Message) -> RuntimeVal {
+    match message {
+        Message::Nil => RuntimeVal::Nil,
+        Message::Bool(bit) => RuntimeVal::Bool(*bit),
+        Message::Number(num) => RuntimeVal::Number(*num),
+        Message::String(s) => make_string(s),
+        Message::Bytes(data) => RuntimeVal::Bytes(data.clone()),
+        Message::Range { start, end, inclusive } => RuntimeVal::Range {
+            start: *start,
+            end: *end,
+            inclusive: *inclusive,
+        },
+        Message::Array(elements) => RuntimeVal::Array(elements.iter().map(from_message).collect()),
+        Message::Object(properties) => RuntimeVal::Object(
+            properties
+                .iter()
+                .map(|(key, property)| (key.clone(), from_message(property)))
+                .collect(),
+        ),
+        Message::Map(entries) => {
+            let mut map = HashMap::new();
+            for (key, entry) in entries {
+                if let Some(key) = MapKey::from_value(&from_message(key)) {
+                    map.insert(key, from_message(entry));
+                }
+            }
+            RuntimeVal::Map(map)
+        }
+        Message::Set(elements) => {
+            let mut set = HashSet::new();
+            for element in elements {
+                if let Some(element) = MapKey::from_value(&from_message(element)) {
+                    set.insert(element);
+                }
+            }
+            RuntimeVal::Set(set)
+        }
+        Message::Channel(channel) => RuntimeVal::Channel(Arc::clone(channel)),
+        Message::Mutex(shared) => RuntimeVal::Mutex(Arc::clone(shared)),
+    }
+}
+
+// The body of a thread: declares the program's functions and classes in a
+// fresh interpreter, without running its statements or `main`, and calls
+// the function named `name`. Output goes to the process's stdout.
+fn run_thread(source: Arc<ProgramSource>, name: &str, args: Vec<Message>) -> Result<Message, RuntimeError> {
+    let serialized_code = serialize_source_code(&source.text);
+    let Some(program) = parse(&source.text, &serialized_code, &mut io::sink(), source.mode) else {
+        return Err(RuntimeError::InternalError);
+    };
+    let env = Environment::new_global(&source.access);
+    let mut interpreter = Interpreter::new(&env, Output::default(), Box::new(BufReader::new(io::stdin())));
+    interpreter.source = Some(Arc::clone(&source));
+    interpreter.interruptible = false;
+    interpreter.evaluate_first_pass(&program, &env)?;
+    let function = lookup_var(&env, name).map_err(|_| RuntimeError::InternalError)?;
+    let args: Vec<RuntimeVal> = args.iter().map(from_message).collect();
+    let mut result = interpreter.call(function, &args, 0)?;
+    // An async function runs to its end on the thread
+    if let RuntimeVal::Task(task) = &result {
+        interpreter.run_until(task, 0)?;
+        result = task.result()?;
+    }
+    interpreter.finish_tasks(0)?;
+    interpreter.finish_threads()?;
+    interpreter.message(&result, 0)
+}
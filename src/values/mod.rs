@@ -1,16 +1,25 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::{Rc, Weak}, sync::Arc};
 use crate::handle_errors::RuntimeError;
 
 use crate::{
-    ast::Stmt,
-    environment::Environment,
+    ast::{ClassDeclaration, FunctionDeclaration, InterfaceDeclaration, MixinDeclaration, VarDeclaration},
+    environment::{Environment, declare_var},
+    file::File,
+    interpreter::generator::Generator,
+    interpreter::interpreter::Interpreter,
+    interpreter::scheduler::AsyncTask,
+    socket::Socket,
+    threads::{Channel, SharedValue, Thread},
 };
 
+pub type NativeFn = fn(&mut Interpreter, &[RuntimeVal], usize) -> Result<RuntimeVal, RuntimeError>;
+
 pub enum EvalResult {
     Value(RuntimeVal),
     Return(RuntimeVal),
-    Break,
-    Continue,
+    // The label of the loop to leave, or `None` for the innermost one
+    Break(Option<String>),
+    Continue(Option<String>),
     NoDisplay,
 }
 
@@ -19,33 +28,182 @@ pub enum RuntimeVal {
     Bool(bool),
     Nil,
     Number(f64),
-    String(String),
+    String(Rc<str>),
     Object(HashMap<String, RuntimeVal>),
     Array(Vec<RuntimeVal>),
+    Bytes(Vec<u8>),
+    Map(HashMap<MapKey, RuntimeVal>),
+    Set(HashSet<MapKey>),
+    Range {
+        start: f64,
+        end: f64,
+        inclusive: bool,
+    },
     Function {
-        name: String,
-        params: Vec<String>,
-        body: Vec<Stmt>,
+        declaration: Rc<FunctionDeclaration>,
         closure: Rc<RefCell<Environment>>,
     },
-    NativeFunction(fn(&[RuntimeVal], usize) -> Result<RuntimeVal, RuntimeError>, String),
+    // Functions of one name declared in the same scope, each taking a
+    // different number of parameters. A call runs the one that takes as
+    // many as it passes arguments.
+    Overloaded {
+        declarations: Rc<Vec<Rc<FunctionDeclaration>>>,
+        closure: Rc<RefCell<Environment>>,
+    },
+    NativeFunction(NativeFn, String),
     Method {
-        name: String,
-        params: Vec<String>,
-        body: Vec<Stmt>,
+        declaration: Rc<FunctionDeclaration>,
         closure: Rc<RefCell<Environment>>,
-        instance: Box<RuntimeVal>,
     },
     Class {
         name: String,
-        static_fields: HashMap<String, RuntimeVal>,
+        static_fields: Rc<RefCell<Environment>>,
+        instance_fields: Rc<Vec<VarDeclaration>>,
         methods: HashMap<String, RuntimeVal>,
+        static_methods: HashMap<String, RuntimeVal>,
         superclass: Option<String>,
+        interfaces: Rc<Vec<String>>,
+        closure: Rc<RefCell<Environment>>,
+        // Instance methods resolved so far by name, own or inherited. Shared
+        // by every copy of the class value.
+        method_cache: Rc<RefCell<MethodCache>>,
+    },
+    Interface(Rc<InterfaceDeclaration>),
+    Mixin {
+        declaration: Rc<MixinDeclaration>,
+        closure: Rc<RefCell<Environment>>,
     },
     Instance {
         class_name: String,
+        class: Rc<RuntimeVal>,
         instance_env: Rc<RefCell<Environment>>,
     },
+    // An instance that is not kept alive by this reference
+    WeakRef {
+        class_name: String,
+        class: Rc<RuntimeVal>,
+        instance_env: Weak<RefCell<Environment>>,
+    },
+    // Copies share the paused call, so resuming one advances them all
+    Generator(Rc<Generator>),
+    // Copies share the task, so awaiting one awaits them all
+    Task(Rc<AsyncTask>),
+    // Copies share the connection, so closing one closes them all
+    Socket(Rc<Socket>),
+    // Copies share the open file, so closing one closes them all
+    File(Rc<File>),
+    // Shared by every thread holding it
+    Channel(Arc<Channel>),
+    // Shared by every thread holding it
+    Mutex(Arc<SharedValue>),
+    // Copies share the running thread, so joining one joins them all
+    Thread(Rc<Thread>),
+}
+
+/// A key of a `Map` value, or an element of a `Set`. Numbers are stored by their bits (with -0
+/// folded into 0) so they can be hashed.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Bool(bool),
+    Number(u64),
+    String(Rc<str>),
+}
+
+impl MapKey {
+    pub fn from_value(value: &RuntimeVal) -> Option<MapKey> {
+        match value {
+            RuntimeVal::Bool(bit) => Some(MapKey::Bool(*bit)),
+            RuntimeVal::Number(num) if !num.is_nan() => {
+                Some(MapKey::Number(if *num == 0.0 { 0.0f64 } else { *num }.to_bits()))
+            }
+            RuntimeVal::String(s) => Some(MapKey::String(Rc::clone(s))),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self) -> RuntimeVal {
+        match self {
+            MapKey::Bool(bit) => RuntimeVal::Bool(*bit),
+            MapKey::Number(bits) => RuntimeVal::Number(f64::from_bits(*bits)),
+            MapKey::String(s) => RuntimeVal::String(Rc::clone(s)),
+        }
+    }
+}
+
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        match (self, other) {
+            (MapKey::Number(a), MapKey::Number(b)) => f64::from_bits(*a).total_cmp(&f64::from_bits(*b)),
+            (MapKey::String(a), MapKey::String(b)) => a.cmp(b),
+            (MapKey::Bool(a), MapKey::Bool(b)) => a.cmp(b),
+            (MapKey::Bool(_), _) | (MapKey::Number(_), MapKey::String(_)) => std::cmp::Ordering::Less,
+            _ => std::cmp::Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Keys of a map in a stable order: bools, then numbers, then strings.
+pub fn sorted_map_keys(map: &HashMap<MapKey, RuntimeVal>) -> Vec<&MapKey> {
+    let mut keys: Vec<&MapKey> = map.keys().collect();
+    keys.sort();
+    keys
+}
+
+/// Name of a value's type as reported by `type_of`.
+pub fn type_name(val: &RuntimeVal) -> &'static str {
+    match val {
+        RuntimeVal::Number(_) => "Number",
+        RuntimeVal::Bool(_) => "Bool",
+        RuntimeVal::Nil => "Nil",
+        RuntimeVal::String(_) => "String",
+        RuntimeVal::Object(_) => "Object",
+        RuntimeVal::Array(_) => "Array",
+        RuntimeVal::Bytes(_) => "Bytes",
+        RuntimeVal::Range { .. } => "Range",
+        RuntimeVal::Map(_) => "Map",
+        RuntimeVal::Set(_) => "Set",
+        RuntimeVal::Function { .. } | RuntimeVal::Overloaded { .. } => "Function",
+        RuntimeVal::NativeFunction(_, _) => "Native function",
+        RuntimeVal::Method { .. } => "Method",
+        RuntimeVal::Class { .. } => "Class",
+        RuntimeVal::Interface(_) => "Interface",
+        RuntimeVal::Mixin { .. } => "Mixin",
+        RuntimeVal::Instance { .. } => "Instance",
+        RuntimeVal::WeakRef { .. } => "WeakRef",
+        RuntimeVal::Generator(_) => "Generator",
+        RuntimeVal::Task(_) => "Task",
+        RuntimeVal::Socket(_) => "Socket",
+        RuntimeVal::File(_) => "File",
+        RuntimeVal::Channel(_) => "Channel",
+        RuntimeVal::Mutex(_) => "Mutex",
+        RuntimeVal::Thread(_) => "Thread",
+    }
+}
+
+/// Name a callable value is reported under by tracing and profiling.
+pub fn callable_name(val: &RuntimeVal) -> String {
+    match val {
+        RuntimeVal::Function { declaration, .. } | RuntimeVal::Method { declaration, .. } => {
+            declaration.name.clone()
+        }
+        RuntimeVal::Overloaded { declarations, .. } => declarations[0].name.clone(),
+        RuntimeVal::NativeFunction(_, name) => name.clone(),
+        RuntimeVal::Class { name, .. } => name.clone(),
+        _ => format!("<{}>", type_name(val)),
+    }
+}
+
+/// Elements of a set in the same stable order as map keys.
+pub fn sorted_set_elements(set: &HashSet<MapKey>) -> Vec<&MapKey> {
+    let mut elements: Vec<&MapKey> = set.iter().collect();
+    elements.sort();
+    elements
 }
 
 pub fn make_number(num: f64) -> RuntimeVal {
@@ -61,7 +219,11 @@ pub fn make_nil() -> RuntimeVal {
 }
 
 pub fn make_string(str: &str) -> RuntimeVal {
-    RuntimeVal::String(str.to_string())
+    RuntimeVal::String(Rc::from(str))
+}
+
+pub fn make_shared_string(str: &Rc<str>) -> RuntimeVal {
+    RuntimeVal::String(Rc::clone(str))
 }
 
 pub fn make_obj(map: &HashMap<String, RuntimeVal>) -> RuntimeVal {
@@ -72,59 +234,134 @@ pub fn make_arr(arr: &Vec<RuntimeVal>) -> RuntimeVal {
     RuntimeVal::Array(arr.clone())
 }
 
+pub fn make_range(start: f64, end: f64, inclusive: bool) -> RuntimeVal {
+    RuntimeVal::Range { start, end, inclusive }
+}
+
 pub fn make_function(
-    name: &str,
-    params: &Vec<String>,
-    body: &Vec<Stmt>,
+    declaration: &Rc<FunctionDeclaration>,
     env: &Rc<RefCell<Environment>>,
 ) -> RuntimeVal {
     RuntimeVal::Function {
-        name: name.to_string(),
-        params: params.clone(),
-        body: body.clone(),
-        closure: Rc::clone(&env),
+        declaration: Rc::clone(declaration),
+        closure: Rc::clone(env),
+    }
+}
+
+/// Adds a function declared in `env` to the function or overloads already
+/// declared there under its name. One taking as many parameters is replaced
+/// when `replace` is set. `None` when `existing` is not a function declared
+/// in `env`, or one of them takes as many parameters and cannot be replaced.
+pub fn add_overload(
+    existing: RuntimeVal,
+    declaration: &Rc<FunctionDeclaration>,
+    env: &Rc<RefCell<Environment>>,
+    replace: bool,
+) -> Option<RuntimeVal> {
+    let mut declarations = match existing {
+        RuntimeVal::Function { declaration, closure } if Rc::ptr_eq(&closure, env) => vec![declaration],
+        RuntimeVal::Overloaded { declarations, closure } if Rc::ptr_eq(&closure, env) => declarations.to_vec(),
+        _ => return None,
+    };
+    let arity = declaration.parameters.len();
+    match declarations.iter().position(|other| other.parameters.len() == arity) {
+        Some(index) if replace => {
+            declarations[index] = Rc::clone(declaration);
+        }
+        Some(_) => return None,
+        None => declarations.push(Rc::clone(declaration)),
+    }
+    if let [declaration] = &declarations[..] {
+        return Some(make_function(declaration, env));
     }
+    Some(RuntimeVal::Overloaded {
+        declarations: Rc::new(declarations),
+        closure: Rc::clone(env),
+    })
 }
 
-pub fn make_native_function(func: fn(&[RuntimeVal], usize) -> Result<RuntimeVal, RuntimeError>, name: &str) -> RuntimeVal {
+pub fn make_native_function(func: NativeFn, name: &str) -> RuntimeVal {
     RuntimeVal::NativeFunction(func, name.to_string())
 }
 
-pub fn make_method(name: &str,
-                   params: &[String],
-                   body: &[Stmt],
-                   closure: &Rc<RefCell<Environment>>, instance_var: RuntimeVal) -> RuntimeVal {
-    RuntimeVal::Method { name: name.to_string(), params: params.to_vec(), body: body.to_vec(), closure: Rc::clone(&closure), instance: Box::new(instance_var) }
+/// Binds a method to its instance. `this` lives in an environment wrapped
+/// around the method's closure, so the binding survives the method value being
+/// stored, passed around or returned, and is visible to functions nested in it.
+pub fn make_method(
+    declaration: &Rc<FunctionDeclaration>,
+    closure: &Rc<RefCell<Environment>>,
+    instance_var: RuntimeVal,
+) -> RuntimeVal {
+    let bound_env = Environment::new(Some(Rc::clone(closure)));
+    let _ = declare_var(&bound_env, "this", instance_var, true);
+    RuntimeVal::Method {
+        declaration: Rc::clone(declaration),
+        closure: bound_env,
+    }
 }
 
+/// Binds a method looked up on a class to an instance.
+pub fn bind_method(method: RuntimeVal, instance: RuntimeVal) -> RuntimeVal {
+    match method {
+        RuntimeVal::Function { declaration, closure } => make_method(&declaration, &closure, instance),
+        method => method,
+    }
+}
+
+/// The methods a class has resolved, as of the class generation they were
+/// resolved in. Superclasses are looked up by name, so replacing a class
+/// makes what was resolved before stale.
+#[derive(Default)]
+pub struct MethodCache {
+    pub generation: usize,
+    pub methods: HashMap<String, RuntimeVal>,
+}
+
+/// The value of a declared class. Its methods and static fields are made
+/// by the caller, the rest is taken from the declaration.
 pub fn make_class(
-    name: &str,
-    static_fields: HashMap<String, RuntimeVal>,
+    class: &ClassDeclaration,
+    static_fields: Rc<RefCell<Environment>>,
     methods: HashMap<String, RuntimeVal>,
-    superclass: Option<String>,
+    static_methods: HashMap<String, RuntimeVal>,
+    closure: &Rc<RefCell<Environment>>,
 ) -> RuntimeVal {
     RuntimeVal::Class {
-        name: name.to_string(),
+        name: class.name.clone(),
         static_fields,
+        instance_fields: Rc::clone(&class.instance_fields),
         methods,
-        superclass,
+        static_methods,
+        superclass: class.superclass.clone(),
+        interfaces: Rc::new(class.interfaces.clone()),
+        closure: Rc::clone(closure),
+        method_cache: Rc::new(RefCell::new(MethodCache::default())),
     }
 }
 
-pub fn make_instance(name: &str, env: Rc<RefCell<Environment>>) -> RuntimeVal {
-    RuntimeVal::Instance { class_name: name.to_string(), instance_env: env }
+pub fn make_instance(name: &str, class: Rc<RuntimeVal>, env: Rc<RefCell<Environment>>) -> RuntimeVal {
+    RuntimeVal::Instance { class_name: name.to_string(), class, instance_env: env }
+}
+
+/// Caught errors are handed to a catch block as `{ kind, message, line }`.
+pub fn make_error(kind: &str, message: &str, line: usize) -> RuntimeVal {
+    let mut fields = HashMap::new();
+    fields.insert("kind".to_string(), make_string(kind));
+    fields.insert("message".to_string(), make_string(message));
+    fields.insert("line".to_string(), make_number(line as f64));
+    RuntimeVal::Object(fields)
 }
 
 pub fn make_return(expr_value: RuntimeVal) -> EvalResult {
     EvalResult::Return(expr_value)
 }
 
-pub fn make_break() -> EvalResult {
-    EvalResult::Break
+pub fn make_break(label: &Option<String>) -> EvalResult {
+    EvalResult::Break(label.clone())
 }
 
-pub fn make_continue() -> EvalResult {
-    EvalResult::Continue
+pub fn make_continue(label: &Option<String>) -> EvalResult {
+    EvalResult::Continue(label.clone())
 }
 
 pub fn make_none() -> EvalResult {
@@ -0,0 +1,76 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Mode, Output, RunOptions, declares_main, run_source};
+
+// Where the running program's output goes, set for the length of one call
+enum Sink {
+    Buffer(String),
+    Callback(js_sys::Function),
+}
+
+thread_local! {
+    static SINK: RefCell<Sink> = RefCell::new(Sink::Buffer(String::new()));
+}
+
+// Program output and errors both go to the current sink, in order
+struct Console;
+
+impl Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        let callback = SINK.with(|sink| match &mut *sink.borrow_mut() {
+            Sink::Buffer(output) => {
+                output.push_str(&text);
+                None
+            }
+            Sink::Callback(callback) => Some(callback.clone()),
+        });
+        // Called outside the borrow so the callback may run another program
+        if let Some(callback) = callback {
+            let _ = callback.call1(&JsValue::NULL, &JsValue::from_str(&text));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs a program and returns everything it printed, errors included. A
+/// program with a top-level `main` function starts there, anything else
+/// runs as a script. `scan` always sees the end of input.
+#[wasm_bindgen]
+pub fn eval(source: &str) -> String {
+    let previous = SINK.with(|sink| sink.replace(Sink::Buffer(String::new())));
+    run(source);
+    match SINK.with(|sink| sink.replace(previous)) {
+        Sink::Buffer(output) => output,
+        Sink::Callback(_) => String::new(),
+    }
+}
+
+/// Runs a program like `eval`, passing its output to `on_output` as it is
+/// printed. Returns the exit status `run_source` would.
+#[wasm_bindgen]
+pub fn eval_with_output(source: &str, on_output: js_sys::Function) -> i32 {
+    let previous = SINK.with(|sink| sink.replace(Sink::Callback(on_output)));
+    let code = run(source);
+    SINK.with(|sink| sink.replace(previous));
+    code
+}
+
+fn run(source: &str) -> i32 {
+    let options = RunOptions {
+        mode: if declares_main(source) { Mode::Main } else { Mode::Script },
+        ..RunOptions::default()
+    };
+    let output = Output {
+        stdout: Box::new(Console),
+        stderr: Box::new(Console),
+    };
+    run_source(source, &[], options, output, Box::new(io::empty()))
+}
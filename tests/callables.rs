@@ -0,0 +1,99 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn functions_passed_and_returned_stay_equal() {
+    let output = run(r#"
+        fun identity(value) { return value; }
+        fun add(a, b) { return a + b; }
+        fun sum(a, b) { return a + b; }
+
+        fun main() {
+            var passed = identity(add);
+            println(passed == add, " ", passed != sum, " ", passed(1, 2));
+        }
+    "#);
+    assert_eq!(output, "true true 3\n");
+}
+
+#[test]
+fn closures_of_separate_calls_differ() {
+    let output = run(r#"
+        fun make() {
+            fun inner() { return 1; }
+            return inner;
+        }
+
+        fun main() {
+            var first = make();
+            println(first == first, " ", first != make());
+        }
+    "#);
+    assert_eq!(output, "true true\n");
+}
+
+#[test]
+fn natives_passed_and_returned_stay_equal() {
+    let output = run(r#"
+        fun identity(value) { return value; }
+
+        fun main() {
+            var passed = identity(len);
+            println(passed == len, " ", passed != size, " ", passed([1, 2]));
+        }
+    "#);
+    assert_eq!(output, "true true 2\n");
+}
+
+#[test]
+fn classes_passed_and_returned_stay_equal() {
+    let output = run(r#"
+        class Point {
+            fun init(x) { this.x = x; }
+        }
+        class Other {}
+        fun identity(value) { return value; }
+
+        fun main() {
+            var passed = identity(Point);
+            println(passed == Point, " ", passed != Other, " ", passed(4).x);
+        }
+    "#);
+    assert_eq!(output, "true true 4\n");
+}
+
+#[test]
+fn bound_methods_equal_only_on_the_same_instance() {
+    let output = run(r#"
+        class Point {
+            fun init(x) { this.x = x; }
+            fun get() { return this.x; }
+        }
+        fun identity(value) { return value; }
+
+        fun main() {
+            var p = Point(1);
+            var q = Point(2);
+            var method = identity(p.get);
+            println(method == p.get, " ", p.get != q.get, " ", method(), " ", identity(q.get)());
+        }
+    "#);
+    assert_eq!(output, "true true 1 2\n");
+}
+
+#[test]
+fn callables_of_different_kinds_differ() {
+    let output = run(r#"
+        class Point {
+            fun get() { return 0; }
+        }
+        fun add(a, b) { return a + b; }
+
+        fun main() {
+            var p = Point();
+            println(add == len, " ", Point == add, " ", p.get == add, " ", Point != len);
+        }
+    "#);
+    assert_eq!(output, "false false false true\n");
+}
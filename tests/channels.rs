@@ -0,0 +1,82 @@
+mod common;
+
+use common::{run, run_with};
+use lox::{NativeAccess, RunOptions};
+
+#[test]
+fn values_are_received_in_the_order_they_were_sent() {
+    let output = run(r#"
+        fun main() {
+            var c = channel();
+            channel_send(c, 1);
+            channel_send(c, "two");
+            println(channel_recv(c), " ", channel_recv(c));
+        }
+    "#);
+    assert_eq!(output, "1 two\n");
+}
+
+#[test]
+fn closed_channel_gives_its_remaining_values_then_nil() {
+    let output = run(r#"
+        fun main() {
+            var c = channel();
+            channel_send(c, 1);
+            channel_close(c);
+            println(channel_recv(c), " ", channel_recv(c));
+            try { channel_send(c, 2); } catch e { println(e.message); }
+        }
+    "#);
+    assert_eq!(output, "1 nil\nCannot send to a closed channel\n");
+}
+
+#[test]
+fn channel_carries_values_between_threads() {
+    let output = run(r#"
+        fun produce(jobs, count) {
+            for i in 0..count { channel_send(jobs, i * 10); }
+            channel_close(jobs);
+        }
+
+        fun main() {
+            var jobs = channel();
+            spawn_thread(produce, jobs, 3);
+            var value = channel_recv(jobs);
+            while type_of(value) != "Nil" {
+                print(value, " ");
+                value = channel_recv(jobs);
+            }
+        }
+    "#);
+    assert_eq!(output, "0 10 20 ");
+}
+
+#[test]
+fn with_closes_a_channel_however_its_body_ends() {
+    let output = run(r#"
+        fun main() {
+            var finished = channel();
+            with finished as c { channel_send(c, 1); }
+            println(channel_recv(finished), " ", channel_recv(finished));
+
+            var failed = channel();
+            try {
+                with failed as c { error("inside"); }
+            } catch e {
+                println(e.message);
+            }
+            try { channel_send(failed, 1); } catch e { println(e.message); }
+        }
+    "#);
+    assert_eq!(output, "1 nil\ninside\nCannot send to a closed channel\n");
+}
+
+#[test]
+fn channels_are_available_to_sandboxed_programs() {
+    let options = RunOptions {
+        access: NativeAccess::Sandboxed,
+        ..RunOptions::default()
+    };
+    let source = "fun main() { var c = channel(); channel_send(c, 1); println(channel_recv(c)); }";
+    assert_eq!(run_with(source, options).unwrap(), "1\n");
+}
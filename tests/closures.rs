@@ -0,0 +1,95 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn counter_keeps_its_own_count() {
+    let output = run(r#"
+        fun makeCounter() {
+            var count = 0;
+            fun increment() {
+                count += 1;
+                return count;
+            }
+            return increment;
+        }
+
+        fun main() {
+            var first = makeCounter();
+            var second = makeCounter();
+            println(first(), " ", first(), " ", second(), " ", first());
+        }
+    "#);
+    assert_eq!(output, "1 2 1 3\n");
+}
+
+#[test]
+fn while_body_closures_capture_each_iteration() {
+    let output = run(r#"
+        fun main() {
+            var closures = [];
+            var i = 0;
+            while i < 3 {
+                var captured = i;
+                fun get() { return captured; }
+                closures = concat(closures, [get]);
+                i += 1;
+            }
+            for get in closures { print(get(), " "); }
+        }
+    "#);
+    assert_eq!(output, "0 1 2 ");
+}
+
+#[test]
+fn for_body_closures_capture_each_iteration() {
+    let output = run(r#"
+        fun main() {
+            var closures = [];
+            for var i = 0; i < 3; i += 1 {
+                var captured = i * 10;
+                fun get() { return captured; }
+                closures = concat(closures, [get]);
+            }
+            for get in closures { print(get(), " "); }
+        }
+    "#);
+    assert_eq!(output, "0 10 20 ");
+}
+
+#[test]
+fn for_in_body_closures_capture_each_item() {
+    let output = run(r#"
+        fun main() {
+            var closures = [];
+            for item in ["a", "b", "c"] {
+                fun get() { return item; }
+                closures = concat(closures, [get]);
+            }
+            for get in closures { print(get(), " "); }
+        }
+    "#);
+    assert_eq!(output, "a b c ");
+}
+
+#[test]
+fn closures_share_one_upvalue() {
+    let output = run(r#"
+        fun makePair() {
+            var shared = 0;
+            fun increment() { shared += 1; }
+            fun read() { return shared; }
+            return [increment, read];
+        }
+
+        fun main() {
+            var pair = makePair();
+            var other = makePair();
+            pair[0]();
+            pair[0]();
+            other[0]();
+            println(pair[1](), " ", other[1]());
+        }
+    "#);
+    assert_eq!(output, "2 1\n");
+}
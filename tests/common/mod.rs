@@ -0,0 +1,49 @@
+// Each test file uses only some of these
+#![allow(dead_code)]
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use lox::{Output, RunOptions, eval_source};
+
+// Collects what a program prints, shared with the interpreter's thread
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Captured {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs a program starting at `main` and returns what it printed, failing
+/// the test with the program's error output if it does not finish.
+pub fn run(source: &str) -> String {
+    run_with(source, RunOptions::default()).unwrap_or_else(|error| panic!("{}", error))
+}
+
+/// Runs a program and returns what it printed, or its error along with
+/// everything it wrote to stderr.
+pub fn run_with(source: &str, options: RunOptions) -> Result<String, String> {
+    let stdout = Captured::default();
+    let stderr = Captured::default();
+    let output = Output {
+        stdout: Box::new(stdout.clone()),
+        stderr: Box::new(stderr.clone()),
+    };
+    match eval_source(source, &[], options, output, Box::new(io::empty())) {
+        Ok(_) => Ok(stdout.text()),
+        Err(error) => Err(format!("{}\n{}", error, stderr.text())),
+    }
+}
@@ -0,0 +1,20 @@
+use std::process::Command;
+
+// Runs `source` as a file and returns its JSON diagnostics
+fn json_errors(source: &str) -> String {
+    let path = std::env::temp_dir().join(format!("lox_diagnostics_{}.lox", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .arg("--error-format=json")
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn unterminated_string_is_reported_where_it_starts() {
+    let errors = json_errors("fun main() {\n  var s = \"abc\ndef\n}\n");
+    assert!(errors.contains(r#""line":2,"column":11,"#), "{}", errors);
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::{run, run_with};
+use lox::{Mode, RunOptions};
+
+// Top-level statements finalize what they released as soon as they end
+fn run_script(source: &str) -> String {
+    let options = RunOptions { mode: Mode::Script, ..RunOptions::default() };
+    run_with(source, options).unwrap_or_else(|error| panic!("{}", error))
+}
+
+const RESOURCE: &str = r#"
+    class Resource {
+        fun init(name) { this.name = name; }
+        fun __drop__() { println("drop ", this.name); }
+    }
+"#;
+
+#[test]
+fn locals_are_dropped_once_in_creation_order_when_the_call_ends() {
+    let output = run(&format!(
+        "{}{}",
+        RESOURCE,
+        r#"
+        fun scope() {
+            var a = Resource("a");
+            var b = Resource("b");
+            var c = Resource("c");
+            println("leaving");
+        }
+
+        fun main() {
+            scope();
+            println("after scope");
+            scope();
+        }
+        "#
+    ));
+    assert_eq!(
+        output,
+        "leaving\ndrop a\ndrop b\ndrop c\nafter scope\nleaving\ndrop a\ndrop b\ndrop c\n"
+    );
+}
+
+#[test]
+fn reassigning_the_last_reference_drops_the_instance() {
+    let output = run_script(&format!(
+        "{}{}",
+        RESOURCE,
+        r#"
+        var kept = Resource("kept");
+        var other = kept;
+        kept = nil;
+        println("still referenced");
+        other = Resource("next");
+        println("replaced");
+        other = nil;
+        "#
+    ));
+    assert_eq!(output, "still referenced\ndrop kept\nreplaced\ndrop next\n");
+}
+
+#[test]
+fn instances_a_finalizer_lets_go_of_are_dropped_after_it() {
+    let output = run(&format!(
+        "{}{}",
+        RESOURCE,
+        r#"
+        class Holder {
+            fun init(inner) { this.inner = inner; }
+            fun __drop__() { println("drop holder"); }
+        }
+
+        fun hold() {
+            var holder = Holder(Resource("inner"));
+        }
+
+        fun main() {
+            hold();
+            println("done");
+        }
+        "#
+    ));
+    assert_eq!(output, "drop holder\ndrop inner\ndone\n");
+}
+
+#[test]
+fn many_live_instances_are_not_dropped_early() {
+    let output = run_script(&format!(
+        "{}{}",
+        RESOURCE,
+        r#"
+        fun identity(x) { return x; }
+
+        var kept = [];
+        for i in 0..100 { kept = concat(kept, [Resource(i)]); }
+        for i in 0..1000 { identity(i); }
+        println("calls done");
+        kept = slice(kept, 99, 100);
+        println("released");
+        kept = nil;
+        "#
+    ));
+    let dropped: Vec<String> = (0..99).map(|i| format!("drop {}\n", i)).collect();
+    assert_eq!(output, format!("calls done\n{}released\ndrop 99\n", dropped.concat()));
+}
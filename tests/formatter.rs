@@ -0,0 +1,30 @@
+use lox::format_source;
+
+#[test]
+fn multi_line_strings_keep_their_lines_inside_functions() {
+    let source = "fun main() {\n  var plain = \"a\n  b\";\n  var triple = \"\"\"it's \"hi\"\nthere\"\"\";\n}\n";
+    let formatted = format_source(source).unwrap();
+    assert_eq!(
+        formatted,
+        "fun main() {\n    var plain = \"a\n  b\";\n    var triple = \"\"\"it's \"hi\"\nthere\"\"\";\n}\n"
+    );
+}
+
+#[test]
+fn print_statements_starting_with_parentheses_stay_statements() {
+    let source = "println (if true { 1 } else { 2 }) * 3;\n";
+    assert_eq!(format_source(source).unwrap(), source);
+}
+
+#[test]
+fn formatting_is_stable() {
+    let source = "fun f(a){if a{return [1,2];}\nvar s=\"x\ny\";return s;}\n";
+    let formatted = format_source(source).unwrap();
+    assert_eq!(format_source(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn returns_of_several_values_keep_their_form() {
+    let source = "fun pair() {\n    return 1, [2, 3];\n}\n\nfun array() {\n    return [1, 2];\n}\n";
+    assert_eq!(format_source(source).unwrap(), source);
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn yields_inside_loops() {
+    let output = run(r#"
+        fun numbers() {
+            var i = 0;
+            while i < 3 { yield i; i += 1; }
+            for var j = 10; j < 12; j += 1 { yield j; }
+            for k in 20..22 { yield k; }
+            outer: loop {
+                for m in [1, 2, 3] {
+                    if m == 2 { continue; }
+                    yield 30 + m;
+                    if m == 3 { break outer; }
+                }
+            }
+        }
+
+        fun main() {
+            for n in numbers() { print(n, " "); }
+        }
+    "#);
+    assert_eq!(output, "0 1 2 10 11 20 21 31 33 ");
+}
+
+#[test]
+fn yields_inside_try_and_catch() {
+    let output = run(r#"
+        fun guarded() {
+            try {
+                yield 1;
+                error("boom");
+                yield 2;
+            } catch e {
+                yield e.message;
+            }
+            yield 3;
+        }
+
+        fun main() {
+            for value in guarded() { print(value, " "); }
+        }
+    "#);
+    assert_eq!(output, "1 boom 3 ");
+}
+
+#[test]
+fn with_value_is_closed_once_its_body_finishes() {
+    let output = run(r#"
+        class Resource {
+            fun init(name) { this.name = name; }
+            fun close() { println("closed ", this.name); }
+        }
+
+        fun reading(name) {
+            with Resource(name) as resource {
+                yield 1;
+                yield 2;
+            }
+            yield 3;
+        }
+
+        fun main() {
+            for value in reading("a") { println(value); }
+            // Left paused inside the body, so never closed
+            println(next(reading("b")));
+        }
+    "#);
+    assert_eq!(output, "1\n2\nclosed a\n3\n1\n");
+}
+
+#[test]
+fn awaits_inside_loops_try_and_assignments() {
+    let output = run(r#"
+        async fun worker(name) {
+            await delay(0);
+            return name;
+        }
+
+        async fun fails() {
+            await delay(0);
+            error("lost");
+        }
+
+        async fun collect() {
+            var results = [];
+            for i in 0..2 {
+                var value = await worker(string(i));
+                results = concat(results, [value]);
+            }
+            try {
+                await fails();
+            } catch e {
+                results = concat(results, [e.message]);
+            }
+            var box = {value: nil};
+            box.value = await worker("box");
+            return concat(results, [box.value]);
+        }
+
+        fun main() {
+            for value in await collect() { print(value, " "); }
+        }
+    "#);
+    assert_eq!(output, "0 1 lost box ");
+}
@@ -0,0 +1,24 @@
+mod common;
+
+use common::{run, run_with};
+use lox::RunOptions;
+
+// `var x = if true { if true { ... 1 } else { 2 } ... } else { 2 };`
+fn nested_if_expressions(depth: usize) -> String {
+    format!(
+        "fun main() {{ var x = {}1{}; println(x); }}",
+        "if true { ".repeat(depth),
+        " } else { 2 }".repeat(depth)
+    )
+}
+
+#[test]
+fn nested_if_expressions_within_the_limit_run() {
+    assert_eq!(run(&nested_if_expressions(50)), "1\n");
+}
+
+#[test]
+fn deeply_nested_if_expressions_are_rejected() {
+    let error = run_with(&nested_if_expressions(100_000), RunOptions::default()).unwrap_err();
+    assert!(error.contains("Code is nested too deeply"), "{}", error);
+}
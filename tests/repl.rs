@@ -0,0 +1,28 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Types `lines` at the prompt and returns what the session printed
+fn repl(lines: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lox"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(lines.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    String::from_utf8(output.stdout).unwrap().replace("> ", "")
+}
+
+#[test]
+fn subclasses_inherit_from_a_redeclared_superclass() {
+    let output = repl(
+        r#"class A { fun m() { return "old"; } }
+class B < A {}
+println(B().m());
+class A { fun m() { return "new"; } }
+println(B().m());
+"#,
+    );
+    assert_eq!(output, "old\nnew\n");
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::{run, run_with};
+use lox::RunOptions;
+
+#[test]
+fn len_counts_characters() {
+    assert_eq!(run(r#"fun main() { println(len("héllo")); }"#), "5\n");
+}
+
+#[test]
+fn indexing_reads_and_writes_characters() {
+    let output = run(r#"
+        fun main() {
+            var s = "héllo";
+            println(s[1], s[2]);
+            s[1] = "e";
+            s[4] = "ö";
+            println(s);
+        }
+    "#);
+    assert_eq!(output, "él\nhellö\n");
+}
+
+#[test]
+fn indexing_past_the_last_character_is_an_error() {
+    for source in [r#"fun main() { println("é"[1]); }"#, r#"fun main() { var s = "é"; s[1] = "e"; }"#] {
+        let error = run_with(source, RunOptions::default()).unwrap_err();
+        assert!(error.contains("Array index is out of bounds"), "{}", error);
+    }
+}
@@ -0,0 +1,27 @@
+mod common;
+
+use common::run_with;
+use lox::RunOptions;
+
+fn error(source: &str) -> String {
+    run_with(source, RunOptions::default()).unwrap_err()
+}
+
+#[test]
+fn misspelled_local_is_suggested_over_an_equally_close_native() {
+    // `mapz` is one edit from both the local `maps` and the native `map`
+    let error = error("fun main() { var maps = []; println(mapz); }");
+    assert!(error.contains("Did you mean 'maps'?"), "{}", error);
+}
+
+#[test]
+fn misspelled_native_is_suggested() {
+    let error = error("fun main() { println(lenn([1])); }");
+    assert!(error.contains("Did you mean 'len'?"), "{}", error);
+}
+
+#[test]
+fn names_too_different_get_no_suggestion() {
+    let error = error("fun main() { println(quux); }");
+    assert!(!error.contains("Did you mean"), "{}", error);
+}
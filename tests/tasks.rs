@@ -0,0 +1,100 @@
+mod common;
+
+use common::{run, run_with};
+use lox::RunOptions;
+
+fn error(source: &str) -> String {
+    run_with(source, RunOptions::default()).unwrap_err()
+}
+
+#[test]
+fn calling_an_async_function_returns_a_task() {
+    let output = run(r#"
+        async fun one() { return 1; }
+
+        fun main() {
+            var task = one();
+            println(type_of(task));
+            println(await task, await task);
+        }
+    "#);
+    assert_eq!(output, "Task\n11\n");
+}
+
+#[test]
+fn spawned_tasks_take_turns() {
+    let output = run(r#"
+        async fun worker(name, times) {
+            for i in 0..times {
+                print(name, i, " ");
+                await delay(0);
+            }
+            return name;
+        }
+
+        fun main() {
+            var a = spawn(worker("a", 3));
+            var b = spawn(worker("b", 2));
+            println(await a, await b);
+        }
+    "#);
+    assert_eq!(output, "a0 b0 a1 b1 a2 ab\n");
+}
+
+#[test]
+fn async_main_is_awaited() {
+    let output = run(r#"
+        async fun main() {
+            await delay(0);
+            println("done");
+        }
+    "#);
+    assert_eq!(output, "done\n");
+}
+
+#[test]
+fn awaited_failure_can_be_caught() {
+    let output = run(r#"
+        async fun fails() { error("boom"); }
+
+        fun main() {
+            try { await fails(); } catch e { println(e.message); }
+        }
+    "#);
+    assert_eq!(output, "boom\n");
+}
+
+#[test]
+fn failure_nothing_awaited_is_reported_at_the_end() {
+    let error = error(r#"
+        async fun fails() { error("nobody saw"); }
+
+        fun main() {
+            spawn(fails());
+            println("done");
+        }
+    "#);
+    assert!(error.contains("nobody saw"), "{}", error);
+}
+
+#[test]
+fn only_tasks_can_be_awaited() {
+    let error = error("fun main() { await 5; }");
+    assert!(error.contains("Only tasks can be awaited, found Number"), "{}", error);
+}
+
+#[test]
+fn task_waiting_for_itself_is_an_error() {
+    let error = error(r#"
+        class Box {}
+
+        async fun waiter(box) { return await box.task; }
+
+        fun main() {
+            var box = Box();
+            box.task = waiter(box);
+            println(await box.task);
+        }
+    "#);
+    assert!(error.contains("Task 'waiter' can never finish"), "{}", error);
+}